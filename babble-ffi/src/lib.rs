@@ -0,0 +1,246 @@
+//! A C interface to babble, for calling library learning from other
+//! languages (through `ctypes`, `cffi`, or a C++ wrapper) without running the
+//! experiment binaries.
+//!
+//! The interface is a handful of `extern "C"` functions which take and
+//! return NUL-terminated strings: programs go in as s-expressions, in the
+//! syntax of [`babble::web::learn_library`], and results come out as JSON. A
+//! C header can be generated with `cbindgen --config cbindgen.toml`.
+//!
+//! From Python, through `ctypes`:
+//!
+//! ```python
+//! import ctypes, json
+//! babble = ctypes.CDLL("target/release/libbabble_ffi.so")
+//! babble.babble_compress.restype = ctypes.c_void_p
+//! result = babble.babble_compress(b"(f (g 1)) (f (g 2))", None)
+//! print(json.loads(ctypes.string_at(result)))
+//! babble.babble_string_free(ctypes.c_void_p(result))
+//! ```
+//!
+//! With the `python` feature, the crate is also a Python extension module,
+//! which returns Python objects instead of JSON; see the [`python`] module.
+
+#![warn(
+  clippy::all,
+  clippy::pedantic,
+  anonymous_parameters,
+  elided_lifetimes_in_paths,
+  missing_copy_implementations,
+  missing_debug_implementations,
+  single_use_lifetimes,
+  trivial_casts,
+  unreachable_pub,
+  unused_lifetimes
+)]
+
+use babble::{
+  compress, extract::split_libs, simple_lang::SimpleOp, web, AstNode, Config,
+  Expr, LibNames, PrettyOptions, Syntax,
+};
+use egg::RecExpr;
+use serde::Serialize;
+use std::{
+  any::Any,
+  ffi::{CStr, CString},
+  os::raw::c_char,
+  panic::{self, AssertUnwindSafe},
+  ptr,
+  time::Duration,
+};
+
+#[cfg(feature = "python")]
+pub mod python;
+
+/// The settings of [`babble_compress`]. Get the defaults with
+/// [`babble_config_default`] and override the settings which matter.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BabbleConfig {
+  /// The number of library selections kept at each e-class during the beam
+  /// search.
+  pub beam_size: usize,
+  /// The number of libraries learned at a time.
+  pub lps: usize,
+  /// The maximum arity of a library function, or 0 for no limit.
+  pub max_arity: usize,
+  /// Whether to learn "library functions" with no arguments.
+  pub learn_constants: bool,
+  /// The number of times to apply library rewrites.
+  pub lib_iter_limit: usize,
+  /// The time limit for each run of the e-graph, in milliseconds.
+  pub timeout_ms: u64,
+}
+
+impl Default for BabbleConfig {
+  fn default() -> Self {
+    Self {
+      beam_size: 400,
+      lps: 1,
+      max_arity: 0,
+      learn_constants: false,
+      lib_iter_limit: 1,
+      timeout_ms: 60_000,
+    }
+  }
+}
+
+impl<Op> From<BabbleConfig> for Config<Op> {
+  fn from(config: BabbleConfig) -> Self {
+    Config::default()
+      .beams(config.beam_size, config.beam_size)
+      .lps(config.lps)
+      .max_arity((config.max_arity > 0).then_some(config.max_arity))
+      .learn_constants(config.learn_constants)
+      .lib_iter_limit(config.lib_iter_limit)
+      .timeout(Duration::from_millis(config.timeout_ms))
+  }
+}
+
+/// The JSON result of a successful [`babble_compress`].
+#[derive(Debug, Serialize)]
+struct Output {
+  /// The compressed programs as a single s-expression, which defines the
+  /// learned library functions. Library functions are referred to by their
+  /// names.
+  expr: String,
+  /// The learned library functions, outermost first.
+  libs: Vec<OutputLib>,
+  /// The compressed programs, in the order of the input.
+  programs: Vec<String>,
+  /// The size of the input programs.
+  initial_cost: usize,
+  /// The size of the compressed programs.
+  final_cost: usize,
+}
+
+/// A learned library function in an [`Output`].
+#[derive(Debug, Serialize)]
+struct OutputLib {
+  /// A descriptive name for the library function; see [`LibNames`].
+  name: String,
+  /// The identifier of the library function, such as `l0`.
+  id: String,
+  definition: String,
+  recursive: bool,
+}
+
+/// The JSON result of a failed [`babble_compress`].
+#[derive(Debug, Serialize)]
+struct OutputError {
+  error: String,
+}
+
+/// The default settings of [`babble_compress`].
+#[must_use]
+#[no_mangle]
+pub extern "C" fn babble_config_default() -> BabbleConfig {
+  BabbleConfig::default()
+}
+
+/// Learns a library from `programs`, a NUL-terminated sequence of
+/// s-expressions, with the settings `config`, or the defaults if it is null.
+///
+/// Returns a NUL-terminated JSON object, which must be freed with
+/// [`babble_string_free`]. On success, it has the fields `expr`, the
+/// compressed programs as one s-expression defining the learned library
+/// functions; `libs`, the descriptive `name`, `id`, `definition` and
+/// `recursive` flag of each library function; `programs`, each compressed
+/// program; and `initial_cost` and `final_cost`. Library functions are
+/// referred to by their descriptive names. On failure, including if library
+/// learning panics, it only has an `error` field describing the problem.
+///
+/// # Safety
+///
+/// `programs` must point to a NUL-terminated string, and `config` must be
+/// null or point to a valid [`BabbleConfig`].
+#[no_mangle]
+pub unsafe extern "C" fn babble_compress(
+  programs: *const c_char,
+  config: *const BabbleConfig,
+) -> *mut c_char {
+  let config = config.as_ref().copied().unwrap_or_default();
+  let programs = if programs.is_null() {
+    Err("no programs given".to_string())
+  } else {
+    CStr::from_ptr(programs)
+      .to_str()
+      .map_err(|e| format!("programs are not valid UTF-8: {e}"))
+  };
+  let result = programs.and_then(|programs| {
+    panic::catch_unwind(AssertUnwindSafe(|| run(programs, config)))
+      .unwrap_or_else(|payload| Err(panic_message(&*payload)))
+  });
+  let json = match result {
+    Ok(output) => serde_json::to_string(&output),
+    Err(error) => serde_json::to_string(&OutputError { error }),
+  }
+  .unwrap_or_else(|e| format!("{{\"error\":\"{e}\"}}"));
+  // JSON escapes NUL characters, so this can't fail.
+  CString::new(json).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Frees a string returned by [`babble_compress`]. Does nothing if `s` is
+/// null.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by [`babble_compress`] which hasn't
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn babble_string_free(s: *mut c_char) {
+  if !s.is_null() {
+    drop(CString::from_raw(s));
+  }
+}
+
+/// Parses and compresses `programs` with `config`.
+fn run(programs: &str, config: BabbleConfig) -> Result<Output, String> {
+  compress_exprs(web::parse_programs(programs)?, config)
+}
+
+/// Compresses `exprs` with `config`.
+fn compress_exprs(
+  exprs: Vec<Expr<SimpleOp>>,
+  config: BabbleConfig,
+) -> Result<Output, String> {
+  let result = compress(exprs, &config.into()).map_err(|e| e.to_string())?;
+
+  let names = LibNames::from_expr(&result.expr);
+  let options = PrettyOptions::default().syntax(Syntax::Lisp);
+  let pretty = |expr: Expr<SimpleOp>| {
+    expr.pretty_with(options).with_lib_names(&names).to_string()
+  };
+  let rec_expr: RecExpr<AstNode<SimpleOp>> = result.expr.clone().into();
+  let applied = split_libs(&rec_expr);
+  Ok(Output {
+    expr: pretty(result.expr),
+    libs: applied
+      .libs
+      .into_iter()
+      .map(|lib| OutputLib {
+        name: names.label(lib.id),
+        id: lib.id.to_string(),
+        definition: pretty(lib.definition.into()),
+        recursive: lib.recursive,
+      })
+      .collect(),
+    programs: applied
+      .programs
+      .into_iter()
+      .map(|program| pretty(program.into()))
+      .collect(),
+    initial_cost: result.initial_cost,
+    final_cost: result.final_cost,
+  })
+}
+
+/// The message of a panic with `payload`.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+  let message = payload
+    .downcast_ref::<&str>()
+    .copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("unknown error");
+  format!("library learning panicked: {message}")
+}
@@ -0,0 +1,105 @@
+//! Python bindings, built with the `python` feature, which let a Python
+//! program such as Dream&shy;Coder's driver run library learning in-process:
+//!
+//! ```python
+//! import babble
+//! result = babble.compress(["(f (g 1))", "(f (g 2))"], beam_size=100)
+//! for lib in result["libs"]:
+//!     print(lib["name"], lib["definition"])
+//! ```
+//!
+//! The extension module is built with `maturin build --features python`.
+
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
+
+use super::{compress_exprs, BabbleConfig, Output};
+use babble::web;
+
+/// Learns a library from `programs`, a list of s-expressions in the syntax
+/// of `babble_compress`, and rewrites them to use it.
+///
+/// Returns a dict with the keys `expr`, the compressed programs as one
+/// s-expression defining the learned library functions; `libs`, a list of
+/// dicts with the descriptive `name`, `id`, `definition` and `recursive`
+/// flag of each library function; `programs`, the compressed programs in
+/// order; and `initial_cost` and `final_cost`.
+///
+/// Raises `ValueError` if a program isn't a single valid s-expression, or if
+/// there are no programs.
+#[pyfunction]
+#[pyo3(signature = (
+  programs,
+  *,
+  beam_size = 400,
+  lps = 1,
+  max_arity = None,
+  learn_constants = false,
+  lib_iter_limit = 1,
+  timeout_ms = 60_000,
+))]
+#[allow(clippy::too_many_arguments, clippy::needless_pass_by_value)]
+fn compress(
+  py: Python<'_>,
+  programs: Vec<String>,
+  beam_size: usize,
+  lps: usize,
+  max_arity: Option<usize>,
+  learn_constants: bool,
+  lib_iter_limit: usize,
+  timeout_ms: u64,
+) -> PyResult<PyObject> {
+  let mut exprs = Vec::with_capacity(programs.len());
+  for (i, program) in programs.iter().enumerate() {
+    let mut parsed = web::parse_programs(program)
+      .map_err(|e| PyValueError::new_err(format!("program {i}: {e}")))?;
+    if parsed.len() != 1 {
+      return Err(PyValueError::new_err(format!(
+        "program {i}: expected one s-expression, found {}",
+        parsed.len()
+      )));
+    }
+    exprs.append(&mut parsed);
+  }
+  let config = BabbleConfig {
+    beam_size,
+    lps,
+    max_arity: max_arity.unwrap_or(0),
+    learn_constants,
+    lib_iter_limit,
+    timeout_ms,
+  };
+  let output = py
+    .allow_threads(|| compress_exprs(exprs, config))
+    .map_err(PyValueError::new_err)?;
+  to_py(py, output)
+}
+
+/// Converts `output` into a Python dict.
+fn to_py(py: Python<'_>, output: Output) -> PyResult<PyObject> {
+  let libs = output
+    .libs
+    .into_iter()
+    .map(|lib| {
+      let dict = PyDict::new(py);
+      dict.set_item("name", lib.name)?;
+      dict.set_item("id", lib.id)?;
+      dict.set_item("definition", lib.definition)?;
+      dict.set_item("recursive", lib.recursive)?;
+      Ok(dict)
+    })
+    .collect::<PyResult<Vec<_>>>()?;
+  let dict = PyDict::new(py);
+  dict.set_item("expr", output.expr)?;
+  dict.set_item("libs", libs)?;
+  dict.set_item("programs", output.programs)?;
+  dict.set_item("initial_cost", output.initial_cost)?;
+  dict.set_item("final_cost", output.final_cost)?;
+  Ok(dict.into())
+}
+
+/// The `babble` Python module.
+#[pymodule]
+#[pyo3(name = "babble")]
+fn python_module(_py: Python<'_>, module: &PyModule) -> PyResult<()> {
+  module.add_function(wrap_pyfunction!(compress, module)?)
+}
@@ -16,8 +16,9 @@
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::parse_macro_input;
+use syn::{parse_macro_input, DeriveInput};
 
+mod op;
 mod parse;
 
 use parse::RewriteRules;
@@ -39,3 +40,58 @@ pub fn rewrite_rules(tokens: TokenStream) -> TokenStream {
   let output = quote! { #input };
   TokenStream::from(output)
 }
+
+/// Derives `Arity`, `Display`, `FromStr`, `Teachable`, `Printable`, and
+/// `DiscriminantEq` for an enum of operations.
+///
+/// Each variant is either a unit variant or has one unnamed field, and is
+/// configured with `#[babble(...)]` attributes:
+///
+/// - `lambda`, `apply`, `fix`, `list`: the unit variant for that construct.
+/// - `var`, `lib_var`, `lib`: the variant for de Bruijn-indexed variables
+///   (holding a `DeBruijnIndex`), library references, and library
+///   definitions (both holding a `LibId`).
+/// - `fallback`: a variant whose field is built with `From<&str>` from any
+///   operation that does not otherwise parse, such as identifiers.
+/// - `name = "..."`, `alias = "..."`: the name a unit variant is printed and
+///   parsed as (by default the variant's name in snake case), and further
+///   names it is parsed from.
+/// - `arity = N`, `variadic`: the minimum number of arguments, and whether
+///   more are allowed.
+/// - `precedence = N`: the variant's precedence when pretty-printing.
+///
+/// Every language needs exactly one variant for each of `lambda`, `apply`,
+/// `fix`, `var`, `lib`, `lib_var`, and `list`. Other variants with a field
+/// print their field and are parsed with its `FromStr`, trying variants in
+/// the order they are declared.
+///
+/// # Examples
+/// ```ignore
+/// #[derive(BabbleOp, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// enum Op {
+///     #[babble(name = "+", arity = 2, precedence = 30)]
+///     Add,
+///     Int(i32),
+///     #[babble(fallback)]
+///     Ident(Symbol),
+///     #[babble(lambda, name = "λ", alias = "lambda")]
+///     Lambda,
+///     #[babble(apply, name = "@")]
+///     Apply,
+///     #[babble(fix)]
+///     Fix,
+///     #[babble(var)]
+///     Var(DeBruijnIndex),
+///     #[babble(lib)]
+///     Lib(LibId),
+///     #[babble(lib_var)]
+///     LibVar(LibId),
+///     #[babble(list)]
+///     List,
+/// }
+/// ```
+#[proc_macro_derive(BabbleOp, attributes(babble))]
+pub fn derive_babble_op(tokens: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(tokens as DeriveInput);
+  op::derive(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
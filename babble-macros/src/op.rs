@@ -0,0 +1,444 @@
+//! The implementation of `#[derive(BabbleOp)]`.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+  spanned::Spanned, Data, DeriveInput, Error, Fields, Ident, Lit, Meta,
+  NestedMeta, Variant,
+};
+
+/// The role a variant plays in the language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+  Lambda,
+  Apply,
+  Fix,
+  Var,
+  Lib,
+  LibVar,
+  List,
+  /// A variant with one field which every otherwise unparsable operation is
+  /// converted into, such as an identifier.
+  Fallback,
+  /// Any other operation.
+  Plain,
+}
+
+impl Role {
+  /// The roles which every language must have exactly one variant for.
+  const REQUIRED: [(Self, &'static str); 7] = [
+    (Self::Lambda, "lambda"),
+    (Self::Apply, "apply"),
+    (Self::Fix, "fix"),
+    (Self::Var, "var"),
+    (Self::Lib, "lib"),
+    (Self::LibVar, "lib_var"),
+    (Self::List, "list"),
+  ];
+
+  fn from_flag(flag: &str) -> Option<Self> {
+    let role = match flag {
+      "lambda" => Self::Lambda,
+      "apply" => Self::Apply,
+      "fix" => Self::Fix,
+      "var" => Self::Var,
+      "lib" => Self::Lib,
+      "lib_var" => Self::LibVar,
+      "list" => Self::List,
+      "fallback" => Self::Fallback,
+      _ => return None,
+    };
+    Some(role)
+  }
+
+  fn has_field(self) -> bool {
+    matches!(self, Self::Var | Self::Lib | Self::LibVar | Self::Fallback)
+  }
+}
+
+/// A variant of the operation enum, along with its `#[babble(...)]`
+/// attributes.
+struct OpVariant {
+  ident: Ident,
+  has_field: bool,
+  role: Role,
+  name: String,
+  aliases: Vec<String>,
+  arity: usize,
+  variadic: bool,
+  precedence: u8,
+}
+
+impl OpVariant {
+  fn parse(variant: &Variant) -> syn::Result<Self> {
+    let has_field = match &variant.fields {
+      Fields::Unit => false,
+      Fields::Unnamed(fields) if fields.unnamed.len() == 1 => true,
+      fields => {
+        return Err(Error::new(
+          fields.span(),
+          "expected a unit variant or a variant with one unnamed field",
+        ))
+      }
+    };
+
+    let mut role = Role::Plain;
+    let mut name = None;
+    let mut aliases = Vec::new();
+    let mut arity = None;
+    let mut variadic = false;
+    let mut precedence = None;
+
+    for attr in &variant.attrs {
+      if !attr.path.is_ident("babble") {
+        continue;
+      }
+      let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        meta => return Err(Error::new(meta.span(), "expected #[babble(...)]")),
+      };
+      for nested in list.nested {
+        match nested {
+          NestedMeta::Meta(Meta::Path(path)) => {
+            let flag = path.get_ident().map(ToString::to_string);
+            if flag.as_deref() == Some("variadic") {
+              variadic = true;
+              continue;
+            }
+            match flag.as_deref().and_then(Role::from_flag) {
+              Some(flag_role) => role = flag_role,
+              None => return Err(Error::new(path.span(), "unknown attribute")),
+            }
+          }
+          NestedMeta::Meta(Meta::NameValue(pair)) => {
+            let key = pair.path.get_ident().map(ToString::to_string);
+            match (key.as_deref(), &pair.lit) {
+              (Some("name"), Lit::Str(lit)) => name = Some(lit.value()),
+              (Some("alias"), Lit::Str(lit)) => aliases.push(lit.value()),
+              (Some("arity"), Lit::Int(lit)) => {
+                arity = Some(lit.base10_parse()?);
+              }
+              (Some("precedence"), Lit::Int(lit)) => {
+                precedence = Some(lit.base10_parse()?);
+              }
+              _ => return Err(Error::new(pair.span(), "unknown attribute")),
+            }
+          }
+          nested => return Err(Error::new(nested.span(), "unknown attribute")),
+        }
+      }
+    }
+
+    if role.has_field() && !has_field {
+      return Err(Error::new(
+        variant.span(),
+        "this variant must have exactly one unnamed field",
+      ));
+    }
+    if matches!(role, Role::Lambda | Role::Apply | Role::Fix | Role::List)
+      && has_field
+    {
+      return Err(Error::new(variant.span(), "this variant must be a unit"));
+    }
+
+    let arity = arity.unwrap_or(match role {
+      Role::Lambda | Role::Fix => 1,
+      Role::Apply | Role::Lib => 2,
+      _ => 0,
+    });
+    let variadic = variadic || role == Role::List;
+    let name = name.unwrap_or_else(|| match role {
+      Role::Lambda => "lambda".to_string(),
+      Role::Apply => "apply".to_string(),
+      Role::Fix => "fix".to_string(),
+      Role::List => "list".to_string(),
+      _ => to_snake_case(&variant.ident.to_string()),
+    });
+    let precedence = precedence.unwrap_or(match role {
+      Role::Lambda | Role::Fix | Role::Lib => 10,
+      Role::Apply => 40,
+      Role::List => 50,
+      _ if arity == 0 && !variadic => 60,
+      _ => 30,
+    });
+
+    Ok(Self {
+      ident: variant.ident.clone(),
+      has_field,
+      role,
+      name,
+      aliases,
+      arity,
+      variadic,
+      precedence,
+    })
+  }
+
+  /// A pattern matching this variant, ignoring its field.
+  fn pattern(&self) -> TokenStream {
+    let ident = &self.ident;
+    if self.has_field {
+      quote!(Self::#ident(_))
+    } else {
+      quote!(Self::#ident)
+    }
+  }
+}
+
+fn to_snake_case(ident: &str) -> String {
+  let mut name = String::new();
+  for (i, c) in ident.chars().enumerate() {
+    if c.is_uppercase() {
+      if i > 0 {
+        name.push('_');
+      }
+      name.extend(c.to_lowercase());
+    } else {
+      name.push(c);
+    }
+  }
+  name
+}
+
+/// Generates the implementations of `Arity`, `Display`, `FromStr`,
+/// `Teachable`, `Printable`, and `DiscriminantEq` for an operation enum.
+pub(crate) fn derive(input: &DeriveInput) -> syn::Result<TokenStream> {
+  let ty = &input.ident;
+  let data = match &input.data {
+    Data::Enum(data) => data,
+    _ => {
+      return Err(Error::new(
+        input.span(),
+        "BabbleOp can only be derived for enums",
+      ))
+    }
+  };
+  if !input.generics.params.is_empty() {
+    return Err(Error::new(
+      input.generics.span(),
+      "BabbleOp cannot be derived for generic enums",
+    ));
+  }
+
+  let variants = data
+    .variants
+    .iter()
+    .map(OpVariant::parse)
+    .collect::<syn::Result<Vec<_>>>()?;
+
+  let mut roles = Vec::new();
+  for (role, flag) in Role::REQUIRED {
+    let mut matching = variants.iter().filter(|v| v.role == role);
+    match (matching.next(), matching.next()) {
+      (Some(variant), None) => roles.push(&variant.ident),
+      (None, _) => {
+        return Err(Error::new(
+          input.span(),
+          format!("missing a #[babble({flag})] variant"),
+        ))
+      }
+      (Some(_), Some(variant)) => {
+        return Err(Error::new(
+          variant.ident.span(),
+          format!("more than one #[babble({flag})] variant"),
+        ))
+      }
+    }
+  }
+  let [lambda, apply, fix, var, lib, lib_var, list] = match roles[..] {
+    [a, b, c, d, e, f, g] => [a, b, c, d, e, f, g],
+    _ => unreachable!(),
+  };
+
+  let mut fallbacks = variants.iter().filter(|v| v.role == Role::Fallback);
+  let fallback = fallbacks.next();
+  if let Some(variant) = fallbacks.next() {
+    return Err(Error::new(
+      variant.ident.span(),
+      "more than one #[babble(fallback)] variant",
+    ));
+  }
+
+  let min_arity = variants.iter().map(|v| {
+    let (pat, arity) = (v.pattern(), v.arity);
+    quote!(#pat => #arity)
+  });
+  let max_arity = variants.iter().map(|v| {
+    let (pat, arity) = (v.pattern(), v.arity);
+    if v.variadic {
+      quote!(#pat => ::std::option::Option::None)
+    } else {
+      quote!(#pat => ::std::option::Option::Some(#arity))
+    }
+  });
+
+  let display = variants.iter().map(|v| {
+    let (ident, name) = (&v.ident, &v.name);
+    match (v.role, v.has_field) {
+      (Role::Lib, _) => quote!(Self::#ident(x) => write!(f, "lib {}", x)),
+      (_, true) => quote!(Self::#ident(x) => ::std::fmt::Display::fmt(x, f)),
+      (_, false) => quote!(Self::#ident => f.write_str(#name)),
+    }
+  });
+
+  let parse_names = variants.iter().filter(|v| !v.has_field).map(|v| {
+    let ident = &v.ident;
+    let names = std::iter::once(&v.name).chain(&v.aliases);
+    quote!(#(#names)|* => return ::std::result::Result::Ok(Self::#ident))
+  });
+  let parse_fields = variants
+    .iter()
+    .filter(|v| v.has_field && v.role != Role::Fallback)
+    .map(|v| {
+      let ident = &v.ident;
+      let parsed = if v.role == Role::Lib {
+        quote!(input.strip_prefix("lib ").map(str::parse))
+      } else {
+        quote!(::std::option::Option::Some(input.parse()))
+      };
+      quote! {
+        if let ::std::option::Option::Some(::std::result::Result::Ok(x)) =
+          #parsed
+        {
+          return ::std::result::Result::Ok(Self::#ident(x));
+        }
+      }
+    });
+  let parse_fallback = match fallback {
+    Some(v) => {
+      let ident = &v.ident;
+      quote!(::std::result::Result::Ok(Self::#ident(input.into())))
+    }
+    None => quote! {
+      ::std::result::Result::Err(
+        ::babble::UnknownOpError(input.to_string())
+      )
+    },
+  };
+
+  let precedence = variants.iter().map(|v| {
+    let (pat, precedence) = (v.pattern(), v.precedence);
+    quote!(#pat => #precedence)
+  });
+
+  Ok(quote! {
+    impl ::babble::Arity for #ty {
+      fn min_arity(&self) -> usize {
+        match self {
+          #(#min_arity,)*
+        }
+      }
+
+      fn max_arity(&self) -> ::std::option::Option<usize> {
+        match self {
+          #(#max_arity,)*
+        }
+      }
+    }
+
+    impl ::std::fmt::Display for #ty {
+      fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        match self {
+          #(#display,)*
+        }
+      }
+    }
+
+    impl ::std::str::FromStr for #ty {
+      type Err = ::babble::UnknownOpError;
+
+      fn from_str(input: &str) -> ::std::result::Result<Self, Self::Err> {
+        match input {
+          #(#parse_names,)*
+          _ => {}
+        }
+        #(#parse_fields)*
+        #parse_fallback
+      }
+    }
+
+    impl ::babble::Teachable for #ty {
+      fn from_binding_expr<T>(
+        binding_expr: ::babble::BindingExpr<T>,
+      ) -> ::babble::AstNode<Self, T> {
+        match binding_expr {
+          ::babble::BindingExpr::Lambda(body) => {
+            ::babble::AstNode::new(Self::#lambda, [body])
+          }
+          ::babble::BindingExpr::Fix(body) => {
+            ::babble::AstNode::new(Self::#fix, [body])
+          }
+          ::babble::BindingExpr::Apply(fun, arg) => {
+            ::babble::AstNode::new(Self::#apply, [fun, arg])
+          }
+          ::babble::BindingExpr::Var(index) => {
+            ::babble::AstNode::leaf(Self::#var(index))
+          }
+          ::babble::BindingExpr::Lib(ix, bound_value, body) => {
+            ::babble::AstNode::new(Self::#lib(ix), [bound_value, body])
+          }
+          ::babble::BindingExpr::LibVar(ix) => {
+            ::babble::AstNode::leaf(Self::#lib_var(ix))
+          }
+        }
+      }
+
+      fn as_binding_expr<T>(
+        node: &::babble::AstNode<Self, T>,
+      ) -> ::std::option::Option<::babble::BindingExpr<&T>> {
+        let binding_expr = match node.as_parts() {
+          (Self::#lambda, [body]) => ::babble::BindingExpr::Lambda(body),
+          (Self::#fix, [body]) => ::babble::BindingExpr::Fix(body),
+          (Self::#apply, [fun, arg]) => ::babble::BindingExpr::Apply(fun, arg),
+          (Self::#var(index), []) => ::babble::BindingExpr::Var(*index),
+          (Self::#lib(ix), [bound_value, body]) => {
+            ::babble::BindingExpr::Lib(*ix, bound_value, body)
+          }
+          (Self::#lib_var(ix), []) => ::babble::BindingExpr::LibVar(*ix),
+          _ => return ::std::option::Option::None,
+        };
+        ::std::option::Option::Some(binding_expr)
+      }
+
+      fn list() -> Self {
+        Self::#list
+      }
+    }
+
+    impl ::babble::Printable for #ty {
+      fn precedence(&self) -> ::babble::Precedence {
+        match self {
+          #(#precedence,)*
+        }
+      }
+
+      fn print_naked<W: ::std::fmt::Write>(
+        expr: &::babble::Expr<Self>,
+        printer: &mut ::babble::Printer<W>,
+      ) -> ::std::fmt::Result {
+        match (expr.0.operation(), expr.0.args()) {
+          (Self::#list, ts) => {
+            let elem = |p: &mut ::babble::Printer<W>, i: usize| {
+              p.print_in_context(&ts[i], 0)
+            };
+            printer.in_brackets(|p| p.indented(|p| p.vsep(elem, ts.len(), ",")))
+          }
+          (op, args) => {
+            write!(printer.writer, "{}", op)?;
+            for arg in args {
+              printer.writer.write_char(' ')?;
+              printer.print(arg)?;
+            }
+            ::std::result::Result::Ok(())
+          }
+        }
+      }
+    }
+
+    impl ::babble::DiscriminantEq for #ty {
+      fn discriminant_eq(&self, other: &Self) -> bool {
+        self.eq(other)
+      }
+    }
+  })
+}
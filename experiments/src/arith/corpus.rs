@@ -0,0 +1,50 @@
+//! Sample corpora of arithmetic expressions, bundled with the binary so that
+//! it can be run without any other data.
+
+use crate::lang::Arith;
+use anyhow::{anyhow, Context};
+use babble::{sexp::Program, Expr};
+
+/// The names of the bundled corpora:
+///
+/// - `polynomials`: polynomials in a single variable, mostly quadratics and
+///   cubics.
+/// - `physics`: formulas from introductory kinematics and mechanics.
+pub(crate) const CORPORA: [&str; 2] = ["polynomials", "physics"];
+
+/// The source of the bundled corpus called `name`, one expression per line.
+fn source(name: &str) -> Option<&'static str> {
+  match name {
+    "polynomials" => {
+      Some(include_str!("../../../examples/arith/polynomials.bab"))
+    }
+    "physics" => Some(include_str!("../../../examples/arith/physics.bab")),
+    _ => None,
+  }
+}
+
+/// Parses the expressions of the bundled corpus called `name`, one of
+/// [`CORPORA`].
+///
+/// # Errors
+/// Errors if there is no such corpus.
+pub(crate) fn load(name: &str) -> anyhow::Result<Vec<Expr<Arith>>> {
+  let source = source(name).ok_or_else(|| anyhow!("No corpus named {name}"))?;
+  parse(source).with_context(|| format!("Failed to parse corpus {name}"))
+}
+
+/// Parses a sequence of arithmetic expressions in s-expression syntax, such
+/// as `(+ (* 2 x) 1)`.
+///
+/// # Errors
+/// Errors if `input` isn't a sequence of s-expressions or if one of them
+/// isn't a well-formed expression.
+pub(crate) fn parse(input: &str) -> anyhow::Result<Vec<Expr<Arith>>> {
+  Ok(
+    Program::parse(input)?
+      .0
+      .into_iter()
+      .map(Expr::try_from)
+      .collect::<Result<_, _>>()?,
+  )
+}
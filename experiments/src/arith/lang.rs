@@ -0,0 +1,246 @@
+//! The language of arithmetic expressions.
+
+use babble::{
+  Arity, AstNode, BindingExpr, DeBruijnIndex, DiscriminantEq, Expr, LibId,
+  ParseLibIdError, Precedence, Printable, Printer, Teachable,
+};
+use egg::{Rewrite, Symbol};
+use std::{
+  convert::Infallible,
+  fmt::{self, Display, Formatter, Write},
+  str::FromStr,
+};
+
+/// The operations/AST nodes of the arithmetic language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Arith {
+  /// An integer constant.
+  Int(i32),
+  /// A named input, such as `x`.
+  Symbol(Symbol),
+  /// Negate a number.
+  Neg,
+  /// Add two numbers.
+  Add,
+  /// Subtract the second number from the first.
+  Sub,
+  /// Multiply two numbers.
+  Mul,
+  /// Divide the first number by the second.
+  Div,
+  /// Raise the first number to the power of the second.
+  Pow,
+  /// A de Bruijn-indexed variable. These are represented with a dollar sign
+  /// followed by the index, i.e. `$0`, `$123`.
+  Var(DeBruijnIndex),
+  /// A reference to a lib fn.
+  LibVar(LibId),
+  /// Apply a function to an argument.
+  Apply,
+  /// Create an anonymous, de Bruijn-indexed function.
+  Lambda,
+  /// Create a recursive function as a fixpoint.
+  Fix,
+  /// Bind a lib fn within an expression.
+  Lib(LibId),
+  /// Bind a lib fn in a mutually recursive group within an expression.
+  LibRec(LibId),
+  /// A list of expressions.
+  List,
+}
+
+impl Arity for Arith {
+  fn min_arity(&self) -> usize {
+    match self {
+      Self::Int(_)
+      | Self::Symbol(_)
+      | Self::Var(_)
+      | Self::LibVar(_)
+      | Self::List => 0,
+      Self::Neg | Self::Lambda | Self::Fix => 1,
+      Self::Add
+      | Self::Sub
+      | Self::Mul
+      | Self::Div
+      | Self::Pow
+      | Self::Apply
+      | Self::Lib(_)
+      | Self::LibRec(_) => 2,
+    }
+  }
+
+  fn max_arity(&self) -> Option<usize> {
+    match self {
+      Self::List => None,
+      other => Some(other.min_arity()),
+    }
+  }
+}
+
+impl Display for Arith {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Int(n) => Display::fmt(n, f),
+      Self::Symbol(name) => Display::fmt(name, f),
+      Self::Neg => f.write_str("neg"),
+      Self::Add => f.write_str("+"),
+      Self::Sub => f.write_str("-"),
+      Self::Mul => f.write_str("*"),
+      Self::Div => f.write_str("/"),
+      Self::Pow => f.write_str("^"),
+      Self::Var(index) => write!(f, "{index}"),
+      Self::LibVar(ix) => write!(f, "{ix}"),
+      Self::Apply => f.write_str("@"),
+      Self::Lambda => f.write_str("λ"),
+      Self::Fix => f.write_str("fix"),
+      Self::Lib(ix) => write!(f, "lib {ix}"),
+      Self::LibRec(ix) => write!(f, "librec {ix}"),
+      Self::List => f.write_str("list"),
+    }
+  }
+}
+
+impl FromStr for Arith {
+  type Err = Infallible;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let op = match s {
+      "neg" => Self::Neg,
+      "+" => Self::Add,
+      "-" => Self::Sub,
+      "*" => Self::Mul,
+      "/" => Self::Div,
+      "^" => Self::Pow,
+      "apply" | "@" => Self::Apply,
+      "lambda" | "λ" => Self::Lambda,
+      "fix" => Self::Fix,
+      "list" => Self::List,
+      s => s
+        .parse()
+        .map(Self::Int)
+        .or_else(|_| s.parse().map(Self::Var))
+        .or_else(|_| s.parse().map(Self::LibVar))
+        .or_else(|_| {
+          s.strip_prefix("lib ")
+            .ok_or(ParseLibIdError::NoLeadingL)
+            .and_then(|x| x.parse().map(Self::Lib))
+        })
+        .or_else(|_| {
+          s.strip_prefix("librec ")
+            .ok_or(ParseLibIdError::NoLeadingL)
+            .and_then(|x| x.parse().map(Self::LibRec))
+        })
+        .unwrap_or_else(|_| Self::Symbol(s.into())),
+    };
+    Ok(op)
+  }
+}
+
+impl Teachable for Arith {
+  fn from_binding_expr<T>(binding_expr: BindingExpr<T>) -> AstNode<Self, T> {
+    match binding_expr {
+      BindingExpr::Lambda(body) => AstNode::new(Self::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(Self::Fix, [body]),
+      BindingExpr::Apply(fun, arg) => AstNode::new(Self::Apply, [fun, arg]),
+      BindingExpr::Var(index) => AstNode::leaf(Self::Var(index)),
+      BindingExpr::Lib(ix, bound_value, body) => {
+        AstNode::new(Self::Lib(ix), [bound_value, body])
+      }
+      BindingExpr::LibRec(ix, bound_value, body) => {
+        AstNode::new(Self::LibRec(ix), [bound_value, body])
+      }
+      BindingExpr::LibVar(ix) => AstNode::leaf(Self::LibVar(ix)),
+    }
+  }
+
+  fn as_binding_expr<T>(node: &AstNode<Self, T>) -> Option<BindingExpr<&T>> {
+    let binding_expr = match node.as_parts() {
+      (Self::Lambda, [body]) => BindingExpr::Lambda(body),
+      (Self::Fix, [body]) => BindingExpr::Fix(body),
+      (Self::Apply, [fun, arg]) => BindingExpr::Apply(fun, arg),
+      (&Self::Var(index), []) => BindingExpr::Var(index),
+      (Self::Lib(ix), [bound_value, body]) => {
+        BindingExpr::Lib(*ix, bound_value, body)
+      }
+      (Self::LibRec(ix), [bound_value, body]) => {
+        BindingExpr::LibRec(*ix, bound_value, body)
+      }
+      (Self::LibVar(ix), []) => BindingExpr::LibVar(*ix),
+      _ => return None,
+    };
+    Some(binding_expr)
+  }
+
+  fn list() -> Self {
+    Self::List
+  }
+}
+
+impl Printable for Arith {
+  fn precedence(&self) -> Precedence {
+    match self {
+      Self::Int(_) | Self::Symbol(_) | Self::Var(_) | Self::LibVar(_) => 60,
+      Self::List => 50,
+      Self::Apply => 45,
+      Self::Neg | Self::Pow => 40,
+      Self::Mul | Self::Div => 30,
+      Self::Add | Self::Sub => 20,
+      Self::Lambda | Self::Fix | Self::Lib(_) | Self::LibRec(_) => 10,
+    }
+  }
+
+  fn print_naked<W: Write>(
+    expr: &Expr<Self>,
+    printer: &mut Printer<W>,
+  ) -> fmt::Result {
+    match (expr.0.operation(), expr.0.args()) {
+      (&Self::Int(n), []) => write!(printer.writer, "{n}"),
+      (&Self::Symbol(name), []) => write!(printer.writer, "{name}"),
+      (&Self::Neg, [arg]) => {
+        printer.writer.write_str("-")?;
+        printer.print(arg)
+      }
+      // Addition and multiplication are left-associative; exponentiation is
+      // right-associative.
+      (&(Self::Add | Self::Sub | Self::Mul | Self::Div), [lhs, rhs]) => {
+        let op = expr.0.operation();
+        printer.print_in_context(lhs, op.precedence() - 1)?;
+        write!(printer.writer, " {op} ")?;
+        printer.print(rhs)
+      }
+      (&Self::Pow, [base, exponent]) => {
+        printer.print(base)?;
+        printer.writer.write_str("^")?;
+        printer.print_in_context(exponent, Self::Pow.precedence() - 1)
+      }
+      (&Self::List, ts) => {
+        let elem = |p: &mut Printer<W>, i: usize| {
+          p.print_in_context(&ts[i], 0) // children do not need parens
+        };
+        printer.in_brackets(|p| p.indented(|p| p.vsep(elem, ts.len(), ",")))
+      }
+      (op, _) => write!(printer.writer, "{op} ???"),
+    }
+  }
+}
+
+impl DiscriminantEq for Arith {
+  fn discriminant_eq(&self, other: &Self) -> bool {
+    self.eq(other)
+  }
+}
+
+/// Domain-specific rewrites for the arithmetic language: the algebraic laws
+/// of addition and multiplication, which let libraries match expressions
+/// written in a different order.
+pub(crate) fn dsrs<A: egg::Analysis<AstNode<Arith>>>(
+) -> Vec<Rewrite<AstNode<Arith>, A>> {
+  vec![
+    egg::rewrite!("add-comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+    egg::rewrite!("mul-comm"; "(* ?a ?b)" => "(* ?b ?a)"),
+    egg::rewrite!("add-assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+    egg::rewrite!("mul-assoc"; "(* ?a (* ?b ?c))" => "(* (* ?a ?b) ?c)"),
+    egg::rewrite!("square"; "(* ?a ?a)" => "(^ ?a 2)"),
+    egg::rewrite!("sub-neg"; "(- ?a ?b)" => "(+ ?a (neg ?b))"),
+  ]
+}
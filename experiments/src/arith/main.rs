@@ -0,0 +1,107 @@
+#![warn(
+  clippy::all,
+  clippy::pedantic,
+  anonymous_parameters,
+  elided_lifetimes_in_paths,
+  missing_copy_implementations,
+  missing_debug_implementations,
+  single_use_lifetimes,
+  trivial_casts,
+  unreachable_pub,
+  unused_lifetimes
+)]
+#![allow(clippy::non_ascii_literal)]
+
+use crate::lang::Arith;
+use babble::{combine_exprs, Expr, Pretty};
+use babble_experiments::Experiments;
+use clap::Parser;
+use egg::{AstSize, CostFunction, RecExpr};
+use std::{
+  fs,
+  io::{self, Read},
+  path::PathBuf,
+};
+
+mod corpus;
+mod lang;
+
+#[derive(Parser)]
+#[clap(version, author, about)]
+struct Opts {
+  /// The input file. If neither a file nor a corpus is specified, reads from
+  /// stdin.
+  #[clap(parse(from_os_str), conflicts_with = "corpus")]
+  file: Option<PathBuf>,
+
+  /// Use one of the bundled sample corpora instead of an input file.
+  #[clap(long, value_parser = corpus::CORPORA)]
+  corpus: Option<String>,
+
+  /// Do not use domain-specific rewrites
+  #[clap(long)]
+  no_dsr: bool,
+
+  /// Whether to learn "library functions" with no arguments.
+  #[clap(long)]
+  learn_constants: bool,
+
+  /// Maximum arity of functions to learn.
+  #[clap(long)]
+  max_arity: Option<usize>,
+
+  /// The beam sizes to use for the beam extractor
+  #[clap(long, default_value = "400")]
+  beams: Vec<usize>,
+
+  /// The number of libs to learn at a time
+  #[clap(long, default_value = "1")]
+  lps: Vec<usize>,
+
+  /// The number of rounds of lib learning to run
+  #[clap(long, default_value_t = 1)]
+  rounds: usize,
+}
+
+fn main() -> anyhow::Result<()> {
+  env_logger::init();
+  let opts: Opts = Opts::parse();
+
+  let prog: Vec<Expr<Arith>> = match (&opts.corpus, &opts.file) {
+    (Some(name), _) => corpus::load(name)?,
+    (None, Some(path)) => corpus::parse(&fs::read_to_string(path)?)?,
+    (None, None) => {
+      let mut buf = String::new();
+      io::stdin().read_to_string(&mut buf)?;
+      corpus::parse(&buf)?
+    }
+  };
+
+  // For the sake of pretty printing
+  {
+    let initial_expr: RecExpr<_> = combine_exprs(prog.clone());
+    let initial_cost = AstSize.cost_rec(&initial_expr);
+
+    println!("Initial expression (cost {initial_cost}):");
+    println!("{}", Pretty(&Expr::from(initial_expr)));
+    println!();
+  }
+
+  let dsrs = if opts.no_dsr { vec![] } else { lang::dsrs() };
+
+  let exps = Experiments::gen(
+    prog,
+    &[],
+    &dsrs,
+    opts.beams.clone(),
+    &opts.lps,
+    opts.rounds,
+    (),
+    opts.learn_constants,
+    opts.max_arity,
+  );
+
+  println!("running...");
+  exps.run("harness/data_gen/res_arith.csv")?;
+  Ok(())
+}
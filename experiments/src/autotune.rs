@@ -0,0 +1,250 @@
+//! Choosing the beam size of an experiment automatically: a short pilot is
+//! run on a few of the programs with each of a ladder of beam sizes, and the
+//! full experiment is run with the beam size at the knee of the resulting
+//! cost/time curve.
+
+use std::{
+  fmt::{self, Debug, Display, Formatter},
+  hash::{Hash, Hasher},
+  marker::PhantomData,
+  time::{Duration, Instant},
+};
+
+use babble::{
+  extract::beam::PartialLibCost, Arity, AstNode, Error, Expr, InternedCorpus,
+  Printable, Teachable,
+};
+use egg::Rewrite;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use super::{CsvWriter, Experiment, ExperimentResult, Metadata};
+
+/// The beam sizes tried by default.
+pub const BEAM_LADDER: [usize; 5] = [25, 50, 100, 200, 400];
+
+/// The number of program groups the pilot is run on by default.
+const PILOT_SIZE: usize = 20;
+
+/// The outcome of the pilot run with one beam size.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct PilotReport {
+  /// The beam size of the pilot run.
+  pub beam_size: usize,
+  /// The cost of the pilot programs after library learning.
+  pub final_cost: usize,
+  /// How long the pilot run took.
+  pub time_elapsed: Duration,
+}
+
+/// The beam size chosen by [`Autotune`], and the pilot runs it was chosen
+/// from.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct AutotuneReport {
+  /// The beam size the full experiment was run with.
+  pub beam_size: usize,
+  /// The pilot run with each beam size of the ladder, in order.
+  pub pilots: Vec<PilotReport>,
+}
+
+/// An experiment whose beam size is chosen automatically. The experiment
+/// with a given beam size is built by a function, so the pilot runs can't
+/// affect each other or the full run.
+pub struct Autotune<Op, T, F>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  T: Experiment<Op>,
+  F: Fn(usize) -> T,
+{
+  make: F,
+  /// The beam sizes to try, in increasing order.
+  ladder: Vec<usize>,
+  /// The number of program groups the pilot is run on.
+  pilot_size: usize,
+  /// The experiment with the largest beam size of the ladder, which
+  /// describes the experiment before a beam size is chosen.
+  base: T,
+  phantom: PhantomData<Op>,
+}
+
+impl<Op, T, F> Debug for Autotune<Op, T, F>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  T: Experiment<Op>,
+  F: Fn(usize) -> T,
+{
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Autotune")
+      .field("ladder", &self.ladder)
+      .field("pilot_size", &self.pilot_size)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<Op, T, F> Autotune<Op, T, F>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  T: Experiment<Op>,
+  F: Fn(usize) -> T,
+{
+  /// Tunes the beam size of the experiments built by `make` over
+  /// [`BEAM_LADDER`].
+  pub fn new(make: F) -> Self {
+    Self::with_ladder(make, BEAM_LADDER.to_vec())
+  }
+
+  /// Tunes the beam size of the experiments built by `make` over the beam
+  /// sizes of `ladder`.
+  ///
+  /// # Panics
+  /// Panics if `ladder` is empty.
+  pub fn with_ladder(make: F, mut ladder: Vec<usize>) -> Self {
+    ladder.sort_unstable();
+    ladder.dedup();
+    let base = make(*ladder.last().expect("the beam ladder is empty"));
+    Self { make, ladder, pilot_size: PILOT_SIZE, base, phantom: PhantomData }
+  }
+
+  /// Runs the pilot on the first `pilot_size` groups of programs. By
+  /// default, it is run on the first 20.
+  #[must_use]
+  pub fn with_pilot_size(mut self, pilot_size: usize) -> Self {
+    self.pilot_size = pilot_size.max(1);
+    self
+  }
+
+  /// Runs the pilot on the first groups of `corpus` with every beam size of
+  /// the ladder, and picks one.
+  fn tune(&self, corpus: &InternedCorpus<Op>) -> Result<AutotuneReport, Error> {
+    let pilot = InternedCorpus::from_groups(
+      corpus.groups().iter().take(self.pilot_size).map(|group| {
+        group.iter().map(|&expr| corpus.interner().expr(expr)).collect()
+      }),
+    );
+    let pilots: Vec<_> = self
+      .ladder
+      .iter()
+      .map(|&beam_size| {
+        let start = Instant::now();
+        let res = (self.make)(beam_size).run_interned(&pilot)?;
+        let report = PilotReport {
+          beam_size,
+          final_cost: self.expr_size(&res.final_expr),
+          time_elapsed: start.elapsed(),
+        };
+        log::info!(
+          "pilot with beam size {beam_size}: cost {} in {:.3}s",
+          report.final_cost,
+          report.time_elapsed.as_secs_f64()
+        );
+        Ok(report)
+      })
+      .collect::<Result<_, Error>>()?;
+    let beam_size = knee(&pilots);
+    log::info!("chose beam size {beam_size}");
+    Ok(AutotuneReport { beam_size, pilots })
+  }
+}
+
+/// The beam size at the knee of the curve of the pilots' costs against
+/// their running times: with both normalized to `[0, 1]`, the pilot whose
+/// reduction in cost most exceeds its increase in time. Ties go to the
+/// smaller beam size.
+#[allow(clippy::cast_precision_loss)]
+fn knee(pilots: &[PilotReport]) -> usize {
+  let normalize = |value: f64, min: f64, max: f64| {
+    if max > min {
+      (value - min) / (max - min)
+    } else {
+      0.0
+    }
+  };
+  let (min_cost, max_cost) = pilots
+    .iter()
+    .map(|pilot| pilot.final_cost as f64)
+    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), cost| {
+      (min.min(cost), max.max(cost))
+    });
+  let (min_time, max_time) = pilots
+    .iter()
+    .map(|pilot| pilot.time_elapsed.as_secs_f64())
+    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), time| {
+      (min.min(time), max.max(time))
+    });
+  let mut best = (f64::NEG_INFINITY, pilots[0].beam_size);
+  for pilot in pilots {
+    let saving = 1.0 - normalize(pilot.final_cost as f64, min_cost, max_cost);
+    let time = normalize(pilot.time_elapsed.as_secs_f64(), min_time, max_time);
+    if saving - time > best.0 {
+      best = (saving - time, pilot.beam_size);
+    }
+  }
+  best.1
+}
+
+impl<Op, T, F> Experiment<Op> for Autotune<Op, T, F>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Display,
+  T: Experiment<Op>,
+  F: Fn(usize) -> T,
+{
+  fn dsrs(&self) -> &[Rewrite<AstNode<Op>, PartialLibCost>] {
+    self.base.dsrs()
+  }
+
+  fn fingerprint(&self, mut hasher: &mut dyn Hasher) -> bool {
+    (&self.ladder, self.pilot_size).hash(&mut hasher);
+    self.base.fingerprint(hasher)
+  }
+
+  fn expr_size(&self, expr: &Expr<Op>) -> usize {
+    self.base.expr_size(expr)
+  }
+
+  fn run(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let corpus =
+      InternedCorpus::from_groups(exprs.iter().map(|expr| vec![expr.clone()]));
+    let report = self.tune(&corpus)?;
+    let mut res = (self.make)(report.beam_size).run(exprs, writer)?;
+    res.autotune = Some(report);
+    Ok(res)
+  }
+
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    self.run_interned(&InternedCorpus::from_groups(expr_groups))
+  }
+
+  fn run_interned(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let report = self.tune(corpus)?;
+    let mut res = (self.make)(report.beam_size).run_interned(corpus)?;
+    res.autotune = Some(report);
+    Ok(res)
+  }
+
+  fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "autotuned over beam sizes {:?} | ", self.ladder)?;
+    self.base.fmt_title(f)
+  }
+
+  fn total_rounds(&self) -> usize {
+    self.base.total_rounds()
+  }
+
+  fn metadata(&self) -> Metadata {
+    self.base.metadata().with("autotune", self.ladder.iter().join(","))
+  }
+}
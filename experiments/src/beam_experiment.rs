@@ -1,21 +1,63 @@
 use std::{
+  cmp::Reverse,
+  collections::{HashMap, HashSet},
   fmt::{self, Debug, Display, Formatter},
-  hash::Hash,
+  fs,
+  hash::{Hash, Hasher},
+  path::PathBuf,
+  sync::{
+    atomic::{self, AtomicUsize},
+    Arc, Mutex,
+  },
   time::{Duration, Instant},
 };
 
-use egg::{AstSize, CostFunction, EGraph, Id, RecExpr, Rewrite, Runner};
+use egg::{
+  EGraph, ENodeOrVar, Id, Pattern, RecExpr, Rewrite, Runner, Searcher,
+  StopReason,
+};
 use itertools::Itertools;
-use log::{debug, info};
-use serde::ser::Serialize;
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use babble::{
-  extract::{apply_libs, beam::PartialLibCost},
-  Arity, AstNode, COBuilder, DiscriminantEq, Expr, LearnedLibraryBuilder,
-  Pretty, Printable, Teachable,
+  apply_lib_rewrites_at,
+  extract::{
+    apply_libs_constrained, apply_libs_within,
+    beam::{
+      BeamStats, CostSet, ExtractionError, LibConstraints, LibSel,
+      PartialLibCost, PruningStrategy,
+    },
+    cost::{CostModel, DagSize, UnitCost},
+    inline_rare_libs,
+    mdl::{DescriptionLength, Objective},
+    serialize, split_libs,
+  },
+  lib_rewrite,
+  progress::{NoProgress, Phase, ProgressSink},
+  Arity, AstNode, BindingExpr, COBuilder, ControlFlow, DiscriminantEq, Error,
+  Expr, InternedCorpus, LearnedLibrary, LearnedLibraryBuilder,
+  LibCoOccurrences, LibId, Pretty, Printable, Provenance, Teachable,
+};
+
+use super::{
+  cache::{Cache, LibMemo, LibPatternAsts, LibSnapshot, Snapshot},
+  hash_dsrs, plumbing, CsvWriter, Experiment, ExperimentResult, Library,
+  Metadata, PendingResult, RestartReport, RoundReport, RuleScheduler,
+  SaturationReport,
 };
 
-use super::{CsvWriter, Experiment, ExperimentResult};
+/// The time limit for each run of the egraph, if the experiment has no
+/// timeout.
+const TIMEOUT: Duration = Duration::from_secs(60 * 100_000);
+
+/// The default maximum number of e-nodes while applying library rewrites.
+const NODE_LIMIT: usize = 1_000_000;
+
+/// The smallest portfolio under [`Objective::Mdl`], which can only tell lib
+/// selections apart once their programs are extracted.
+const MDL_PORTFOLIO: usize = 8;
 
 /// A `BeamExperiment` contains all of the information needed to run a
 /// library learning experiment with the beam extractor.
@@ -26,20 +68,116 @@ where
 {
   /// The domain-specific rewrites to apply
   dsrs: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// How the domain-specific rewrites are scheduled.
+  scheduler: RuleScheduler,
   /// The final beam size to use
   final_beams: usize,
   /// The inter beam size to use
   inter_beams: usize,
   /// The number of times to apply library rewrites
   lib_iter_limit: usize,
+  /// The maximum number of e-nodes while applying library rewrites.
+  node_limit: usize,
+  /// The number of library rewrites to retry with if the node limit is
+  /// reached, or `None` to keep the truncated egraph.
+  node_limit_retry: Option<usize>,
   /// The number of libs to learn at a time
   lps: usize,
+  /// The maximum number of libs in a lib selection, or `None` to use `lps`.
+  max_libs_per_sel: Option<usize>,
   /// Any extra data associated with this experiment
   extra_data: Extra,
   /// Whether to learn "library functions" with no arguments.
   learn_constants: bool,
   /// Maximum arity of a library function.
   max_arity: Option<usize>,
+  /// Whether to learn recursive library functions.
+  learn_recursive: bool,
+  /// Whether to bind repeated subterms of library functions to locals.
+  learn_lets: bool,
+  /// Whether to learn library functions which take functions as arguments.
+  learn_higher_order: bool,
+  /// If set, the differing arguments of the operations for which this
+  /// returns `true` are also abstracted over as a whole.
+  control_flow: Option<fn(&Op) -> bool>,
+  /// How dominated lib selections are removed during the beam search.
+  pruning: PruningStrategy,
+  /// The maximum beam size when the beam is widened adaptively, or `None` to
+  /// always use a fixed beam size.
+  adaptive_beam: Option<usize>,
+  /// Whether to keep lib selections whose libs may co-occur ahead of others
+  /// during the beam search.
+  co_occurrence_pruning: bool,
+  /// Whether the beam search must be reproducible bit-for-bit.
+  deterministic: bool,
+  /// The libs the chosen lib selection must or must not use.
+  lib_constraints: LibConstraints,
+  /// The amount of memory, in bytes, which the process may use while adding
+  /// libs to the egraph, or `None` if there is no limit.
+  memory_limit: Option<usize>,
+  /// The number of threads to use for beam search, or `None` to use rayon's
+  /// global thread pool.
+  threads: Option<usize>,
+  /// The number of the cheapest lib selections at the root which are each
+  /// extracted, keeping the one with the lowest final cost.
+  portfolio: usize,
+  /// The number of times the beam search is run, each time breaking ties
+  /// differently, keeping the best result.
+  restarts: usize,
+  /// The seed from which the tie-breaking of each restart is derived.
+  restart_seed: u64,
+  /// The cost model to minimize.
+  cost_model: Arc<dyn CostModel<Op>>,
+  /// Whether `cost_model` was set by [`BeamExperiment::with_cost_model`],
+  /// rather than being one of the built-in ones.
+  custom_cost_model: bool,
+  /// Whether expressions are measured by their DAG size, counting equal
+  /// subexpressions once.
+  dag_size: bool,
+  /// The structure penalty added to the cost for each lib.
+  lib_penalty: usize,
+  /// How the candidates of the portfolio are compared.
+  objective: Objective,
+  /// The number of uses below which a chosen lib is inlined after
+  /// extraction, if that doesn't increase the final cost, or `None` to keep
+  /// every chosen lib.
+  min_lib_uses: Option<usize>,
+  /// The time limit for each run, or `None` if there is no limit.
+  timeout: Option<Duration>,
+  /// Whether to reuse the anti-unifications of the previous run.
+  incremental: bool,
+  /// Whether to merge patterns which are equal modulo the DSRs.
+  semantic_dedup: bool,
+  /// The number of pairs of e-classes to anti-unify, or `None` to
+  /// anti-unify every pair.
+  candidate_budget: Option<usize>,
+  /// The minimum number of nodes in the body of a library function.
+  min_size: Option<usize>,
+  /// The maximum number of nodes in the body of a library function.
+  max_size: Option<usize>,
+  /// Operations at which no library function may be rooted.
+  excluded_root_ops: Vec<Op>,
+  /// If not empty, the only operations at which library functions may be
+  /// rooted.
+  anchor_ops: Vec<Op>,
+  /// The library learned by the previous run, if running incrementally.
+  previous_lib: Mutex<Option<LearnedLibrary<Op, (Id, Id)>>>,
+  /// Where the results of anti-unification are memoized, if anywhere.
+  au_cache: Option<Arc<dyn LibMemo<Op>>>,
+  /// Where to report the progress of each run.
+  progress: Arc<dyn ProgressSink>,
+  /// Where to write the egraph with the libs added, if anywhere.
+  dump_egraph: Option<PathBuf>,
+  /// Whether to gather telemetry about the beam search.
+  beam_stats: bool,
+  /// Previously learned library functions, which the programs are rewritten
+  /// to use before learning new ones.
+  initial_library: Library<Op>,
+  /// The rewrites introducing the functions of `initial_library`.
+  initial_rewrites: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// Identifies the custom pruning strategy and cost model, if any, in
+  /// cache keys.
+  cache_key: Option<String>,
 }
 
 impl<Op, Extra> BeamExperiment<Op, Extra>
@@ -74,54 +212,658 @@ where
   {
     Self {
       dsrs: dsrs.into_iter().collect(),
+      scheduler: RuleScheduler::default(),
       final_beams,
       inter_beams,
       lps,
+      max_libs_per_sel: None,
       extra_data,
       learn_constants,
       max_arity,
       lib_iter_limit,
+      node_limit: NODE_LIMIT,
+      node_limit_retry: None,
+      learn_recursive: false,
+      learn_lets: false,
+      learn_higher_order: false,
+      control_flow: None,
+      pruning: PruningStrategy::default(),
+      adaptive_beam: None,
+      co_occurrence_pruning: false,
+      deterministic: false,
+      lib_constraints: LibConstraints::default(),
+      memory_limit: None,
+      threads: None,
+      portfolio: 1,
+      restarts: 1,
+      restart_seed: 0,
+      cost_model: Arc::new(UnitCost),
+      custom_cost_model: false,
+      dag_size: false,
+      lib_penalty: 0,
+      objective: Objective::Size,
+      min_lib_uses: None,
+      timeout: None,
+      incremental: false,
+      semantic_dedup: false,
+      candidate_budget: None,
+      min_size: None,
+      max_size: None,
+      excluded_root_ops: Vec::new(),
+      anchor_ops: Vec::new(),
+      previous_lib: Mutex::new(None),
+      au_cache: None,
+      progress: Arc::new(NoProgress),
+      dump_egraph: None,
+      beam_stats: false,
+      initial_library: Library::default(),
+      initial_rewrites: Vec::new(),
+      cache_key: None,
     }
   }
 
-  fn run_egraph(
+  /// Sets whether to merge candidate library functions which are equal
+  /// modulo the DSRs, as well as ones which match in the same places. See
+  /// [`LearnedLibrary::semantic_dedup`]. By default, only the latter are
+  /// merged.
+  #[must_use]
+  pub fn with_semantic_dedup(mut self, semantic_dedup: bool) -> Self {
+    self.semantic_dedup = semantic_dedup;
+    self
+  }
+
+  /// Only anti-unifies the `budget` pairs of e-classes estimated to match
+  /// most often, trading completeness for speed on very large e-graphs. See
+  /// [`LearnedLibraryBuilder::candidate_budget`]. By default, every pair is
+  /// anti-unified.
+  #[must_use]
+  pub fn with_candidate_budget(mut self, budget: Option<usize>) -> Self {
+    self.candidate_budget = budget;
+    self
+  }
+
+  /// Only learns library functions whose bodies have between `min_size` and
+  /// `max_size` nodes, not counting their arguments. See
+  /// [`LearnedLibraryBuilder::min_size`] and
+  /// [`LearnedLibraryBuilder::max_size`]. By default, there are no bounds.
+  #[must_use]
+  pub fn with_body_size(
+    mut self,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+  ) -> Self {
+    self.min_size = min_size;
+    self.max_size = max_size;
+    self
+  }
+
+  /// Never learns library functions rooted at one of `excluded` and, unless
+  /// `anchors` is empty, only learns ones rooted at one of `anchors`. See
+  /// [`LearnedLibraryBuilder::exclude_root_op`] and
+  /// [`LearnedLibraryBuilder::anchor_op`]. By default, library functions may
+  /// be rooted at any operation.
+  #[must_use]
+  pub fn with_root_ops(mut self, excluded: Vec<Op>, anchors: Vec<Op>) -> Self {
+    self.excluded_root_ops = excluded;
+    self.anchor_ops = anchors;
+    self
+  }
+
+  /// Starts from the library functions in `library`, learned by a previous
+  /// run: they are run as rewrites along with the DSRs, and the ones the
+  /// final programs use are bound around them. New library functions are
+  /// named after them. Library functions which can't be expressed as a
+  /// rewrite are still named, but never used.
+  #[must_use]
+  pub fn with_initial_library(mut self, library: Library<Op>) -> Self {
+    self.initial_rewrites = library
+      .libs()
+      .iter()
+      .filter_map(|def| {
+        let rewrite = def.rewrite();
+        if rewrite.is_none() {
+          warn!("Can't match {} with a rewrite; ignoring it", def.name);
+        }
+        rewrite
+      })
+      .collect();
+    self.initial_library = library;
+    self
+  }
+
+  /// Reports the progress of each run to `progress`. By default, progress
+  /// is only logged.
+  #[must_use]
+  pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+    self.progress = progress;
+    self
+  }
+
+  /// Sets whether conditions, branches, and loop bodies of control-flow
+  /// operations are also abstracted over as a whole. See
+  /// [`LearnedLibraryBuilder::learn_control_flow`]. By default, only the
+  /// parts of them which differ are abstracted over.
+  #[must_use]
+  pub fn with_control_flow(mut self, control_flow: bool) -> Self
+  where
+    Op: ControlFlow,
+  {
+    self.control_flow =
+      if control_flow { Some(Op::is_control_flow) } else { None };
+    self
+  }
+
+  /// Sets the cost model which library learning tries to minimize. By
+  /// default, this is the size of the expression.
+  #[must_use]
+  pub fn with_cost_model(mut self, cost_model: Arc<dyn CostModel<Op>>) -> Self {
+    self.cost_model = cost_model;
+    self.custom_cost_model = true;
+    self
+  }
+
+  /// Sets whether expressions are measured by their DAG size, in which equal
+  /// subexpressions are counted once, rather than their tree size. This
+  /// replaces the cost model used to extract the programs, and the sizes
+  /// reported in summaries. By default, the tree size is used.
+  #[must_use]
+  pub fn with_dag_size(mut self, dag_size: bool) -> Self {
+    self.dag_size = dag_size;
+    if dag_size {
+      self.cost_model = Arc::new(DagSize);
+      self.custom_cost_model = false;
+    }
+    self
+  }
+
+  /// Sets the structure penalty: `penalty` is added to the cost of a lib
+  /// selection for each of its libs, both in the beam search and when
+  /// choosing between the candidates of a portfolio, so that fewer, bigger
+  /// libs are preferred. Mirrors DreamCoder's structure penalty. By default,
+  /// there is no penalty.
+  #[must_use]
+  pub fn with_lib_penalty(mut self, penalty: usize) -> Self {
+    self.lib_penalty = penalty;
+    self
+  }
+
+  /// Inlines the chosen libs which are used fewer than `min_uses` times in
+  /// the extracted programs, one at a time, as long as that doesn't increase
+  /// the final cost; see [`inline_rare_libs`]. The beam search only
+  /// estimates the cost of each lib selection, so it sometimes chooses libs
+  /// which don't pay for their definitions. By default, every chosen lib is
+  /// kept.
+  #[must_use]
+  pub fn with_inline_rare_libs(mut self, min_uses: usize) -> Self {
+    self.min_lib_uses = Some(min_uses);
+    self
+  }
+
+  /// Sets whether to gather telemetry about the beam search, reported as the
+  /// [`ExperimentResult::beam_stats`] of each run. By default, none is
+  /// gathered.
+  #[must_use]
+  pub fn with_beam_stats(mut self, beam_stats: bool) -> Self {
+    self.beam_stats = beam_stats;
+    self
+  }
+
+  /// Sets whether to learn recursive library functions, defined as fixpoints.
+  /// By default, they are not learned.
+  #[must_use]
+  pub fn with_recursive(mut self, learn_recursive: bool) -> Self {
+    self.learn_recursive = learn_recursive;
+    self
+  }
+
+  /// Sets whether to bind subterms which repeat within a library function to
+  /// locals with `let`. By default, they are duplicated.
+  #[must_use]
+  pub fn with_lets(mut self, learn_lets: bool) -> Self {
+    self.learn_lets = learn_lets;
+    self
+  }
+
+  /// Sets whether to learn library functions which take functions as
+  /// arguments. By default, differing lambdas are never abstracted as a whole.
+  #[must_use]
+  pub fn with_higher_order(mut self, learn_higher_order: bool) -> Self {
+    self.learn_higher_order = learn_higher_order;
+    self
+  }
+
+  /// Returns a fresh analysis for the beam search phase, which breaks ties
+  /// using `seed`, if given.
+  fn analysis(&self, seed: Option<u64>) -> PartialLibCost {
+    let mut analysis =
+      PartialLibCost::new(self.final_beams, self.inter_beams, self.lps)
+        .with_cost_model(self.cost_model.clone())
+        .with_deterministic(self.deterministic)
+        .with_pruning(self.pruning.clone())
+        .with_lib_penalty(self.lib_penalty)
+        .with_lib_constraints(self.lib_constraints.clone())
+        .with_stats(self.beam_stats);
+    if let Some(seed) = seed {
+      analysis = analysis.with_tie_break_seed(seed);
+    }
+    if let Some(max_libs_per_sel) = self.max_libs_per_sel {
+      analysis = analysis.with_max_libs_per_sel(max_libs_per_sel);
+    }
+    match self.adaptive_beam {
+      Some(max_beam) => analysis.with_adaptive_beam(max_beam),
+      None => analysis,
+    }
+  }
+
+  /// Sets the maximum number of libs in a lib selection during the beam
+  /// search. By default, this is the number of libs learned at a time.
+  #[must_use]
+  pub fn with_max_libs_per_sel(mut self, max_libs_per_sel: usize) -> Self {
+    self.max_libs_per_sel = Some(max_libs_per_sel);
+    self
+  }
+
+  /// Widens the beam, up to `max_beam` lib selections, wherever pruning to
+  /// the usual beam size would discard lib selections costing as much as
+  /// ones it keeps. By default, the beam size is fixed.
+  #[must_use]
+  pub fn with_adaptive_beam(mut self, max_beam: usize) -> Self {
+    self.adaptive_beam = Some(max_beam);
+    self
+  }
+
+  /// Sets whether to bias the beam search toward lib selections whose libs'
+  /// patterns may co-occur in the input programs. By default, lib selections
+  /// are pruned by cost alone.
+  #[must_use]
+  pub fn with_co_occurrence_pruning(mut self, enabled: bool) -> Self {
+    self.co_occurrence_pruning = enabled;
+    self
+  }
+
+  /// Sets whether the beam search must be reproducible bit-for-bit, in which
+  /// case cross products are computed on a single thread; see
+  /// [`PartialLibCost::with_deterministic`]. By default, large cross
+  /// products are computed in parallel.
+  #[must_use]
+  pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+    self.deterministic = deterministic;
+    self
+  }
+
+  /// Requires the chosen lib selection to use the libs `constraints`
+  /// requires, such as hand-curated ones defined in the input programs, and
+  /// none of the libs it forbids; see [`LibConstraints`]. If no lib
+  /// selection in the beam satisfies them, a warning is logged and only the
+  /// forbidden libs are avoided. By default, there are no constraints.
+  #[must_use]
+  pub fn with_lib_constraints(mut self, constraints: LibConstraints) -> Self {
+    self.lib_constraints = constraints;
+    self
+  }
+
+  /// Sets how lib selections which are dominated by others are removed
+  /// during the beam search; see [`PruningStrategy`]. By default, a lib
+  /// selection is removed if a cheaper one uses a subset of its libs.
+  #[must_use]
+  pub fn with_pruning(mut self, pruning: PruningStrategy) -> Self {
+    self.pruning = pruning;
+    self
+  }
+
+  /// Sets a key identifying the custom pruning strategy and cost model of
+  /// the experiment, which are otherwise indistinguishable from other custom
+  /// ones. The results of an experiment with either are only cached by
+  /// content if it has a key; see [`Experiment::fingerprint`]. By default,
+  /// there is no key.
+  #[must_use]
+  pub fn with_cache_key(mut self, key: impl Into<String>) -> Self {
+    self.cache_key = Some(key.into());
+    self
+  }
+
+  /// Limits the memory used while adding libs to the egraph to `bytes`.
+  /// Once three quarters of the limit are used, the beam is narrowed; once
+  /// the limit is reached, no more libs are added, and the results are
+  /// extracted from the egraph as it is. By default, only the number of
+  /// e-nodes is limited.
+  #[must_use]
+  pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+    self.memory_limit = Some(bytes);
+    self
+  }
+
+  /// Writes the egraph with the libs added, along with the beam of each
+  /// e-class, to `path` in the JSON format of `egraph-serialize`, for
+  /// visualization. Each run overwrites the egraph of the previous one. By
+  /// default, the egraph isn't written.
+  #[must_use]
+  pub fn with_dump_egraph(mut self, path: impl Into<PathBuf>) -> Self {
+    self.dump_egraph = Some(path.into());
+    self
+  }
+
+  /// Limits the egraph to `node_limit` e-nodes while applying library
+  /// rewrites. Once the limit is reached, no more libs are added, and the
+  /// beam search only considers the ones added until then. By default, the
+  /// limit is 1,000,000 e-nodes.
+  #[must_use]
+  pub fn with_node_limit(mut self, node_limit: usize) -> Self {
+    self.node_limit = node_limit;
+    self
+  }
+
+  /// If the node limit is reached while applying library rewrites, starts
+  /// over with only the `top_n` most promising ones, those estimated to save
+  /// the most nodes across their matches, rather than keeping the libs added
+  /// before the limit was reached, which are in no particular order. By
+  /// default, there is no retry.
+  #[must_use]
+  pub fn with_node_limit_retry(mut self, top_n: Option<usize>) -> Self {
+    self.node_limit_retry = top_n;
+    self
+  }
+
+  /// Limits each run to `timeout`. The DSRs, anti-unification, and adding
+  /// libs to the egraph each stop once the time is up, and the programs are
+  /// extracted using the libs found until then; the result is marked as
+  /// timed out. If extraction itself runs out of time, the programs are
+  /// extracted without any of the new libs instead. By default, there is no
+  /// time limit.
+  #[must_use]
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// The time left of a run which started at `start_time`.
+  fn time_left(&self, start_time: Instant) -> Duration {
+    self
+      .timeout
+      .map_or(TIMEOUT, |timeout| timeout.saturating_sub(start_time.elapsed()))
+  }
+
+  /// The time at which a run which started at `start_time` runs out of
+  /// time, if it can.
+  fn deadline(&self, start_time: Instant) -> Option<Instant> {
+    self.timeout.and_then(|timeout| start_time.checked_add(timeout))
+  }
+
+  /// Whether a run which started at `start_time` has run out of time.
+  fn timed_out(&self, start_time: Instant) -> bool {
+    self.timeout.map_or(false, |timeout| start_time.elapsed() >= timeout)
+  }
+
+  /// Sets how the domain-specific rewrites are scheduled. By default, egg's
+  /// backoff scheduler is used, which bans rewrites that match too often.
+  #[must_use]
+  pub fn with_scheduler(mut self, scheduler: RuleScheduler) -> Self {
+    self.scheduler = scheduler;
+    self
+  }
+
+  /// Sets whether each run reuses the anti-unifications found by the previous
+  /// run for the parts of the programs which have not changed since, as when
+  /// this experiment is run for several [`Rounds`](crate::Rounds). By
+  /// default, every run anti-unifies from scratch.
+  #[must_use]
+  pub fn with_incremental(mut self, incremental: bool) -> Self {
+    self.incremental = incremental;
+    self
+  }
+
+  /// Memoizes the library functions found by anti-unification in `cache`,
+  /// keyed by the egraph they were found in and the settings which affect
+  /// them, so that runs on the same programs which differ only in, say, their
+  /// beam size anti-unify once. Anti-unification which runs out of time or
+  /// reuses the previous run's is not memoized. By default, every run
+  /// anti-unifies.
+  // This lint gives false positives for higher-rank trait bounds.
+  #[allow(single_use_lifetimes)]
+  #[must_use]
+  pub fn with_au_cache(mut self, cache: Arc<Mutex<Cache<Op>>>) -> Self
+  where
+    Op: Debug + Serialize + for<'b> Deserialize<'b>,
+  {
+    self.au_cache = Some(cache);
+    self
+  }
+
+  /// Sets the number of threads used to compute the beam search. By default,
+  /// rayon's global thread pool is used.
+  #[must_use]
+  pub fn with_threads(mut self, threads: usize) -> Self {
+    self.threads = Some(threads);
+    self
+  }
+
+  /// Extracts the programs with each of the `k` cheapest lib selections found
+  /// by the beam search, in parallel, and keeps the result with the lowest
+  /// final cost. The beam's costs are only upper bounds, so the cheapest lib
+  /// selection doesn't always extract the smallest programs. By default, only
+  /// the cheapest lib selection is extracted.
+  #[must_use]
+  pub fn with_portfolio(mut self, k: usize) -> Self {
+    self.portfolio = k.max(1);
+    self
+  }
+
+  /// Sets how the candidates of the portfolio are compared once their
+  /// programs are extracted. Under [`Objective::Mdl`], the candidate with the
+  /// shortest [description length](DescriptionLength) is kept: the grammar
+  /// over operations and library functions is re-estimated from each
+  /// candidate's programs, and the programs' negative log-likelihood under it
+  /// is traded off against the size of the library, like Dream&shy;Coder's
+  /// compression. Since the beam search itself can't estimate description
+  /// lengths, the portfolio then has at least eight candidates; see
+  /// [`with_portfolio`](Self::with_portfolio). By default, the candidate
+  /// with the lowest final cost is kept.
+  #[must_use]
+  pub fn with_objective(mut self, objective: Objective) -> Self {
+    self.objective = objective;
+    self
+  }
+
+  /// Runs the beam search `k` times and keeps the result with the lowest
+  /// final cost. The first run is the usual search; each of the others
+  /// breaks ties between lib selections with the same cost in a different
+  /// pseudo-random order derived from `seed`, which can get the search out
+  /// of a local minimum. The DSRs and anti-unification are only run once.
+  /// The outcome of every restart is reported in
+  /// [`ExperimentResult::restarts`]. By default, the beam search runs once.
+  #[must_use]
+  pub fn with_restarts(mut self, k: usize, seed: u64) -> Self {
+    self.restarts = k.max(1);
+    self.restart_seed = seed;
+    self
+  }
+
+  /// Adds `exprs` to a fresh egraph, returning the egraph and the e-class of
+  /// each expression.
+  fn build_egraph(
+    &self,
+    exprs: Vec<Expr<Op>>,
+  ) -> (EGraph<AstNode<Op>, PartialLibCost>, Vec<Id>) {
+    // First, let's turn our list of exprs into a list of recexprs
+    let recexprs: Vec<RecExpr<AstNode<Op>>> =
+      exprs.into_iter().map_into().collect();
+
+    // Add one to account for root node, not added yet
+    let initial_cost = {
+      let s: usize = recexprs.iter().map(|x| self.cost_model.cost_rec(x)).sum();
+      s + self.cost_model.node_cost(&Op::list())
+    };
+
+    debug!("Starting cost: {}", initial_cost);
+
+    let mut egraph = EGraph::new(self.analysis(None));
+    let roots = recexprs.iter().map(|x| egraph.add_expr(x)).collect::<Vec<_>>();
+    egraph.rebuild();
+
+    (egraph, roots)
+  }
+
+  /// Builds an egraph containing the programs of `corpus`, returning it and
+  /// the e-class of each group of equivalent programs.
+  fn build_corpus_egraph(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> (EGraph<AstNode<Op>, PartialLibCost>, Vec<Id>) {
+    // Shared subexpressions of the corpus are only added once.
+    let mut egraph = EGraph::new(self.analysis(None));
+    let roots = corpus.add_to_egraph(&mut egraph);
+    egraph.rebuild();
+    (egraph, roots)
+  }
+
+  /// Runs the DSRs, anti-unification, and beam search on `egraph`, without
+  /// committing to a particular library selection.
+  /// If `weights` are given, each program counts as many times as its
+  /// weight.
+  fn beam_search(
     &self,
     roots: &[Id],
+    weights: Option<&[usize]>,
     egraph: EGraph<AstNode<Op>, PartialLibCost>,
-  ) -> ExperimentResult<Op> {
+  ) -> BeamSearch<Op> {
+    let start_time = Instant::now();
+    let (aeg, saturation) = self.run_dsrs(egraph, start_time);
+    let libs = self.anti_unify(roots, None, &aeg, start_time);
+    self.search_libs(
+      roots,
+      weights,
+      aeg,
+      libs,
+      Some(saturation),
+      None,
+      start_time,
+    )
+  }
+
+  /// Runs the DSRs on `egraph`, as part of a run which started at
+  /// `run_start_time`, returning the resulting egraph and statistics about
+  /// each DSR.
+  fn run_dsrs(
+    &self,
+    egraph: EGraph<AstNode<Op>, PartialLibCost>,
+    run_start_time: Instant,
+  ) -> (EGraph<AstNode<Op>, PartialLibCost>, SaturationReport) {
     let start_time = Instant::now();
-    let timeout = Duration::from_secs(60 * 100_000);
 
     info!("Initial egraph size: {}", egraph.total_size());
     info!("Running {} DSRs... ", self.dsrs.len());
+    self.progress.phase_started(Phase::Saturation);
 
     let runner = Runner::<_, _, ()>::new(PartialLibCost::empty())
       .with_egraph(egraph)
-      .with_time_limit(timeout)
-      .with_iter_limit(3)
-      .run(&self.dsrs);
+      .with_time_limit(self.time_left(run_start_time))
+      .with_iter_limit(3);
+    let rules: Vec<_> =
+      self.dsrs.iter().chain(&self.initial_rewrites).cloned().collect();
+    let (runner, rule_stats) = self.scheduler.run(runner, &rules);
+    let saturation = SaturationReport::new(&runner, &rules, rule_stats);
 
     let aeg = runner.egraph;
+    self.progress.egraph_size(
+      Phase::Saturation,
+      aeg.total_size(),
+      aeg.number_of_classes(),
+    );
+    self.progress.phase_finished(Phase::Saturation, start_time.elapsed());
 
     info!(
       "Finished in {}ms; final egraph size: {}",
       start_time.elapsed().as_millis(),
       aeg.total_size()
     );
+    for (name, stats) in &saturation.rule_stats {
+      debug!(
+        "{name}: {} matches, {} applications, {} nodes added",
+        stats.matches, stats.applications, stats.nodes_added
+      );
+    }
+
+    (aeg, saturation)
+  }
+
+  /// Finds candidate library functions in `aeg` by anti-unification, as
+  /// part of a run which started at `start_time`. If `split` is given, the
+  /// first `split` roots are one corpus and the rest another, and only the
+  /// candidates which match within both are kept.
+  fn anti_unify(
+    &self,
+    roots: &[Id],
+    split: Option<usize>,
+    aeg: &EGraph<AstNode<Op>, PartialLibCost>,
+    start_time: Instant,
+  ) -> LibSnapshot<Op> {
+    // Any libs learned in previous rounds are referenced by name in the
+    // egraph, so new libs have to be numbered after them, and after those
+    // of the initial library.
+    let lib_offset = self
+      .initial_library
+      .libs()
+      .iter()
+      .map(|def| def.name.0 + 1)
+      .fold(LibId::next_free(aeg).0, usize::max);
+
+    if self.timed_out(start_time) {
+      warn!("Out of time; skipping anti-unification");
+      return LibSnapshot { lib_offset, patterns: Vec::new(), sites: None };
+    }
+
+    let previous = self.previous_lib.lock().unwrap().take();
+    let memo = match (&self.au_cache, &previous) {
+      (Some(cache), None) => {
+        Some((cache, self.au_key(roots, split, lib_offset, aeg)))
+      }
+      _ => None,
+    };
+    if let Some((cache, key)) = &memo {
+      match cache.get_libs(key) {
+        Ok(Some(libs)) => {
+          info!("Reusing the results of anti-unification from {key}");
+          return libs;
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Couldn't read the results of anti-unification: {e}"),
+      }
+    }
 
     info!("Running co-occurrence analysis... ");
     let co_time = Instant::now();
-    let co_ext = COBuilder::new(&aeg, roots);
+    self.progress.phase_started(Phase::CoOccurrence);
+    let co_ext = COBuilder::new(aeg, roots);
     let co_occurs = co_ext.run();
+    self.progress.phase_finished(Phase::CoOccurrence, co_time.elapsed());
     info!("Finished in {}ms", co_time.elapsed().as_millis());
 
     info!("Running anti-unification... ");
     let au_time = Instant::now();
-    let mut learned_lib = LearnedLibraryBuilder::default()
+    let mut builder = LearnedLibraryBuilder::default()
+      .time_limit(self.time_left(start_time))
+      .lib_offset(lib_offset)
       .learn_constants(self.learn_constants)
       .max_arity(self.max_arity)
+      .min_size(self.min_size)
+      .max_size(self.max_size)
+      .exclude_root_ops(self.excluded_root_ops.iter().cloned())
+      .anchor_ops(self.anchor_ops.iter().cloned())
+      .learn_recursive(self.learn_recursive)
+      .learn_lets(self.learn_lets)
+      .learn_higher_order(self.learn_higher_order)
+      .candidate_budget(self.candidate_budget)
+      .split_control_flow(self.control_flow)
       .with_co_occurs(co_occurs)
-      .build(&aeg);
+      .with_progress(self.progress.clone());
+    if let Some(previous) = previous {
+      builder = builder.with_previous(previous);
+    }
+    let mut learned_lib = builder.build(aeg);
+    let timed_out = learned_lib.timed_out();
+    if timed_out {
+      warn!("Out of time; anti-unification stopped early");
+    }
     info!(
       "Found {} patterns in {}ms",
       learned_lib.size(),
@@ -130,61 +872,930 @@ where
 
     info!("Deduplicating patterns... ");
     let dedup_time = Instant::now();
-    learned_lib.deduplicate(&aeg);
-    let lib_rewrites: Vec<_> = learned_lib.rewrites().collect();
+    learned_lib.deduplicate(aeg);
+    if self.semantic_dedup {
+      learned_lib.semantic_dedup(&self.dsrs, PartialLibCost::empty(), 3);
+    }
+    if let Some(split) = split {
+      let (a, b) = roots.split_at(split);
+      learned_lib.retain_common(aeg, &[a, b]);
+    }
+    let patterns = learned_lib
+      .patterns()
+      .map(|(searcher, applier, recursive)| {
+        (searcher.ast, applier.ast, recursive)
+      })
+      .collect();
+    let sites = learned_lib.match_sites().collect();
     info!(
       "Reduced to {} patterns in {}ms",
       learned_lib.size(),
       dedup_time.elapsed().as_millis()
     );
 
+    if self.incremental {
+      *self.previous_lib.lock().unwrap() = Some(learned_lib);
+    }
+
+    let libs = LibSnapshot { lib_offset, patterns, sites };
+    if let Some((cache, key)) = memo.filter(|_| !timed_out) {
+      if let Err(e) = cache.insert_libs(&key, &libs) {
+        warn!("Couldn't save the results of anti-unification: {e}");
+      }
+    }
+    libs
+  }
+
+  /// The key under which the library functions found by anti-unification of
+  /// the programs at `roots` of `aeg`, numbered from `lib_offset`, are
+  /// memoized. It covers every setting which affects anti-unification.
+  fn au_key(
+    &self,
+    roots: &[Id],
+    split: Option<usize>,
+    lib_offset: usize,
+    aeg: &EGraph<AstNode<Op>, PartialLibCost>,
+  ) -> String {
+    Cache::egraph_key("au", aeg, roots, |mut hasher| {
+      (split, lib_offset, self.learn_constants, self.max_arity)
+        .hash(&mut hasher);
+      (self.min_size, self.max_size, self.candidate_budget).hash(&mut hasher);
+      (&self.excluded_root_ops, &self.anchor_ops).hash(&mut hasher);
+      (self.learn_recursive, self.learn_lets, self.learn_higher_order)
+        .hash(&mut hasher);
+      (self.control_flow.is_some(), self.semantic_dedup).hash(&mut hasher);
+      if self.semantic_dedup {
+        hash_dsrs(&self.dsrs, hasher);
+      }
+    })
+  }
+
+  /// Adds the library functions in `libs` to `aeg` and runs the beam search.
+  /// `saturation` describes the run of the DSRs which produced `aeg`, if it
+  /// is known. If `weights` are given, each program counts as many times as
+  /// its weight when selecting libs.
+  #[allow(clippy::too_many_arguments)]
+  fn search_libs(
+    &self,
+    roots: &[Id],
+    weights: Option<&[usize]>,
+    aeg: EGraph<AstNode<Op>, PartialLibCost>,
+    libs: LibSnapshot<Op>,
+    saturation: Option<SaturationReport>,
+    seed: Option<u64>,
+    start_time: Instant,
+  ) -> BeamSearch<Op> {
+    let LibSnapshot { lib_offset, patterns, sites } = libs;
+    let lib_rewrites: Vec<Rewrite<_, _>> = patterns
+      .iter()
+      .enumerate()
+      .map(|(i, (searcher, applier, recursive))| {
+        lib_rewrite(
+          format!("anti-unify {i}"),
+          Pattern::new(searcher.clone()),
+          Pattern::new(applier.clone()),
+          *recursive,
+        )
+      })
+      .collect();
+
     info!("Adding libs and running beam search... ");
     let lib_rewrite_time = Instant::now();
-    let runner = Runner::<_, _, ()>::new(PartialLibCost::new(
-      self.final_beams,
-      self.inter_beams,
-      self.lps,
-    ))
-    .with_egraph(aeg.clone())
-    .with_iter_limit(self.lib_iter_limit)
-    .with_time_limit(timeout)
-    .with_node_limit(1_000_000)
-    .run(lib_rewrites.iter());
-
-    let mut egraph = runner.egraph;
+    self.progress.phase_started(Phase::BeamSearch);
+    let mut analysis = self.analysis(seed);
+    if self.co_occurrence_pruning {
+      analysis = analysis.with_lib_co_occurrences(lib_co_occurrences(
+        roots, &aeg, lib_offset, &patterns,
+      ));
+    }
+    let (mut egraph, mut stop_reason) = self.add_libs(
+      &aeg,
+      &analysis,
+      &lib_rewrites,
+      sites.as_deref(),
+      start_time,
+    );
+    if let Some(StopReason::NodeLimit(nodes)) = stop_reason {
+      warn!(
+        "Reached the node limit with {nodes} nodes; not every lib was added"
+      );
+      if let Some(top_n) =
+        self.node_limit_retry.filter(|&top_n| top_n < lib_rewrites.len())
+      {
+        warn!(
+          "Retrying with the {top_n} most promising of {} libs",
+          lib_rewrites.len()
+        );
+        let chosen = most_promising(&aeg, &patterns, sites.as_deref(), top_n);
+        let rewrites: Vec<_> =
+          chosen.iter().map(|&i| lib_rewrites[i].clone()).collect();
+        let sites = sites.as_ref().map(|sites| {
+          chosen.iter().map(|&i| sites[i].clone()).collect::<Vec<_>>()
+        });
+        (egraph, stop_reason) = self.add_libs(
+          &aeg,
+          &analysis,
+          &rewrites,
+          sites.as_deref(),
+          start_time,
+        );
+        if let Some(StopReason::NodeLimit(nodes)) = stop_reason {
+          warn!("Reached the node limit again with {nodes} nodes");
+        }
+      }
+    }
+
     let root = egraph.add(AstNode::new(Op::list(), roots.iter().copied()));
-    let mut cs = egraph[egraph.find(root)].data.clone();
-    cs.set.sort_unstable_by_key(|elem| elem.full_cost);
+    let mut root_costs = match weights {
+      Some(weights) => {
+        let children = roots
+          .iter()
+          .zip(weights)
+          .map(|(&id, &weight)| (&egraph[id].data, weight));
+        let mut costs = egraph.analysis.cross_weighted(children);
+        costs.add_cost(self.cost_model.node_cost(&Op::list()));
+        costs
+      }
+      None => egraph[egraph.find(root)].data.clone(),
+    };
+    if !root_costs.retain_satisfying(&self.lib_constraints) {
+      warn!("No lib selection satisfies the lib constraints");
+    }
+    root_costs.sort_by_full_cost();
 
+    if let Some(path) = &self.dump_egraph {
+      let json = serialize::to_json(&egraph, &[root]);
+      if let Err(e) = fs::write(path, json.to_string()) {
+        warn!("Couldn't write the egraph to {}: {e}", path.display());
+      }
+    }
+
+    let beam_stats = egraph.analysis.stats();
+    let egraph_size = egraph.total_size();
+    self.progress.egraph_size(
+      Phase::BeamSearch,
+      egraph_size,
+      egraph.number_of_classes(),
+    );
+    self.progress.phase_finished(Phase::BeamSearch, lib_rewrite_time.elapsed());
     info!("Finished in {}ms", lib_rewrite_time.elapsed().as_millis());
-    info!("Stop reason: {:?}", runner.stop_reason.unwrap());
-    info!("Number of nodes: {}", egraph.total_size());
+    if let Some(stop_reason) = &stop_reason {
+      info!("Stop reason: {stop_reason:?}");
+    }
+    info!("Number of nodes: {}", egraph_size);
+
+    if let Some(best) = root_costs.set.first() {
+      debug!("learned libs");
+      for lib in &best.libs {
+        if let Some((_, applier, _)) =
+          lib.0 .0.checked_sub(lib_offset).and_then(|i| patterns.get(i))
+        {
+          debug!("{}: {}", lib.0, applier);
+        }
+      }
+
+      debug!("upper bound ('full') cost: {}", best.full_cost);
+    }
+
+    BeamSearch {
+      aeg,
+      lib_rewrites,
+      lib_offset,
+      root_costs,
+      egraph_size,
+      stop_reason,
+      saturation,
+      beam_stats,
+      weights: weights.map(<[usize]>::to_vec),
+      start_time,
+    }
+  }
+
+  /// Applies `lib_rewrites` to a copy of `aeg` with the analysis `analysis`,
+  /// as part of a run which started at `start_time`, returning the resulting
+  /// egraph and why the runner stopped. If `sites` are given, the first
+  /// iteration only searches the e-classes at which each rewrite is known to
+  /// match.
+  fn add_libs(
+    &self,
+    aeg: &EGraph<AstNode<Op>, PartialLibCost>,
+    analysis: &PartialLibCost,
+    lib_rewrites: &[Rewrite<AstNode<Op>, PartialLibCost>],
+    sites: Option<&[Vec<Id>]>,
+    start_time: Instant,
+  ) -> (EGraph<AstNode<Op>, PartialLibCost>, Option<StopReason>) {
+    let lib_iter_limit = self.lib_iter_limit;
+    let node_limit = self.node_limit;
+    let time_limit = self.time_left(start_time);
+    let memory_limit = self.memory_limit;
+    let mut lib_egraph = aeg.clone();
+    lib_egraph.analysis = analysis.clone();
+    let analysis = analysis.clone();
+    self.in_pool(|| {
+      // The libs match exactly where deduplication found them, so the first
+      // iteration only needs to search those e-classes.
+      let mut iter_limit = lib_iter_limit;
+      if let (Some(sites), 1..) = (sites, iter_limit) {
+        apply_lib_rewrites_at(&mut lib_egraph, lib_rewrites, sites);
+        iter_limit -= 1;
+        if iter_limit == 0 {
+          return (lib_egraph, Some(StopReason::IterationLimit(1)));
+        }
+      }
+      let mut runner = Runner::<_, _, ()>::new(analysis)
+        .with_egraph(lib_egraph)
+        .with_iter_limit(iter_limit)
+        .with_time_limit(time_limit)
+        .with_node_limit(node_limit);
+      if let Some(limit) = memory_limit {
+        runner = runner.with_hook(memory_hook(limit));
+      }
+      let runner = runner.run(lib_rewrites.iter());
+      (runner.egraph, runner.stop_reason)
+    })
+  }
+
+  /// Runs `f` on a thread pool with the configured number of threads, or on
+  /// the global thread pool if no such pool can be built.
+  fn in_pool<R, F>(&self, f: F) -> R
+  where
+    R: Send,
+    F: FnOnce() -> R + Send,
+  {
+    match self.threads.map(|threads| {
+      rayon::ThreadPoolBuilder::new().num_threads(threads).build()
+    }) {
+      Some(Ok(pool)) => pool.install(f),
+      Some(Err(e)) => {
+        warn!("Couldn't build a thread pool: {e}");
+        f()
+      }
+      None => f(),
+    }
+  }
 
-    debug!("learned libs");
-    let all_libs: Vec<_> = learned_lib.libs().collect();
-    let mut chosen_rewrites = Vec::new();
-    for lib in &cs.set[0].libs {
-      debug!("{}: {}", lib.0, &all_libs[lib.0 .0]);
-      chosen_rewrites.push(lib_rewrites[lib.0 .0].clone());
+  /// Rewrites the programs at `roots` using the libraries in `lib_sel`,
+  /// respecting `constraints` where possible, returning the rewrites for
+  /// those libraries and the rewritten programs. Libs in `lib_sel` which
+  /// weren't learned in this run, such as ones defined in the input
+  /// programs, need no rewrite. If `deadline` passes first, the programs
+  /// are extracted without the new libraries instead.
+  fn apply(
+    roots: &[Id],
+    search: &BeamSearch<Op>,
+    lib_sel: &LibSel,
+    cost_model: &Arc<dyn CostModel<Op>>,
+    constraints: &LibConstraints,
+    deadline: Option<Instant>,
+  ) -> Result<
+    (Vec<Rewrite<AstNode<Op>, PartialLibCost>>, RecExpr<AstNode<Op>>),
+    Error,
+  > {
+    let chosen_rewrites: Vec<_> = lib_sel
+      .libs
+      .iter()
+      .filter_map(|lib| lib.0 .0.checked_sub(search.lib_offset))
+      .map(|i| search.lib_rewrites[i].clone())
+      .collect();
+    let extract = |constraints: &LibConstraints| {
+      let egraph = search.aeg.clone();
+      let rewrites = &chosen_rewrites;
+      match deadline {
+        Some(deadline) => apply_libs_within(
+          egraph,
+          roots,
+          rewrites,
+          cost_model.clone(),
+          constraints,
+          deadline.saturating_duration_since(Instant::now()),
+        ),
+        None => apply_libs_constrained(
+          egraph,
+          roots,
+          rewrites,
+          cost_model.clone(),
+          constraints,
+        ),
+      }
+    };
+    let lifted = match extract(constraints) {
+      Err(ExtractionError::TimedOut) => Err(ExtractionError::TimedOut),
+      Err(e) => {
+        warn!("Couldn't satisfy the lib constraints: {e}");
+        extract(&LibConstraints::default())
+      }
+      lifted => lifted,
+    };
+    match lifted {
+      Ok(lifted) => Ok((chosen_rewrites, lifted)),
+      Err(ExtractionError::TimedOut) => {
+        warn!("Out of time; extracting the programs without the new libs");
+        let unrewritten = apply_libs_constrained(
+          search.aeg.clone(),
+          roots,
+          &[],
+          cost_model.clone(),
+          &LibConstraints::default(),
+        )?;
+        Ok((Vec::new(), unrewritten))
+      }
+      Err(e) => Err(e.into()),
     }
+  }
 
-    debug!("upper bound ('full') cost: {}", cs.set[0].full_cost);
+  /// The cost of `lifted`, a list of programs with the library functions
+  /// they use defined at the top, counting each program as many times as its
+  /// weight, if `weights` are given.
+  fn weighted_cost(
+    cost_model: &Arc<dyn CostModel<Op>>,
+    lifted: &RecExpr<AstNode<Op>>,
+    weights: Option<&[usize]>,
+  ) -> usize {
+    let cost = cost_model.cost_rec(lifted);
+    match weights {
+      Some(weights) => {
+        let repeats: usize = plumbing::exprs(lifted.as_ref())
+          .iter()
+          .zip(weights)
+          .map(|(expr, &weight)| (weight - 1) * cost_model.expr_cost(expr))
+          .sum();
+        cost + repeats
+      }
+      None => cost,
+    }
+  }
 
+  /// Rewrites the programs at `roots` using the libraries in `lib_sel`.
+  fn extract(
+    &self,
+    roots: &[Id],
+    search: &BeamSearch<Op>,
+    lib_sel: &LibSel,
+  ) -> Result<ExperimentResult<Op>, Error> {
     let ex_time = Instant::now();
     info!("Extracting... ");
-    let lifted = apply_libs(aeg.clone(), roots, &chosen_rewrites);
-    let final_cost = AstSize.cost_rec(&lifted);
+    self.progress.phase_started(Phase::Extraction);
+    let (chosen_rewrites, lifted) = Self::apply(
+      roots,
+      search,
+      lib_sel,
+      &self.cost_model,
+      &self.lib_constraints,
+      self.deadline(search.start_time),
+    )?;
+    self.progress.phase_finished(Phase::Extraction, ex_time.elapsed());
+    info!("Finished in {}ms", ex_time.elapsed().as_millis());
+    Ok(self.report(search, chosen_rewrites, lifted))
+  }
 
+  /// Rewrites the programs at `roots` using each of the lib selections in
+  /// the portfolio, and returns the result with the lowest final cost, plus
+  /// the structure penalty for each of its libs, or with the lowest
+  /// description length under the [`Objective::Mdl`] objective. Ties are
+  /// broken in favor of the cheaper lib selection in the beam.
+  fn extract_best(
+    &self,
+    roots: &[Id],
+    search: &BeamSearch<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let portfolio = match self.objective {
+      Objective::Size => self.portfolio,
+      Objective::Mdl => self.portfolio.max(MDL_PORTFOLIO),
+    };
+    let k = portfolio.min(search.root_costs.set.len());
+    if k <= 1 {
+      return self.extract(roots, search, &search.best_lib_sel());
+    }
+
+    let ex_time = Instant::now();
+    info!("Extracting {k} candidates... ");
+    self.progress.phase_started(Phase::Extraction);
+    let cost_model = &self.cost_model;
+    let constraints = &self.lib_constraints;
+    let progress = &self.progress;
+    let lib_penalty = self.lib_penalty;
+    let objective = self.objective;
+    let deadline = self.deadline(search.start_time);
+    let done = AtomicUsize::new(0);
+    let candidates: Vec<_> = self.in_pool(|| {
+      search.root_costs.set[..k]
+        .par_iter()
+        .map(|lib_sel| {
+          let (rewrites, lifted) = Self::apply(
+            roots,
+            search,
+            lib_sel,
+            cost_model,
+            constraints,
+            deadline,
+          )?;
+          let weights = search.weights.as_deref();
+          let final_cost = Self::weighted_cost(cost_model, &lifted, weights)
+            + lib_penalty * rewrites.len();
+          let length = (objective == Objective::Mdl)
+            .then(|| DescriptionLength::of(&lifted, weights).total());
+          let done = done.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+          progress.candidates(Phase::Extraction, done, Some(k));
+          Ok((final_cost, length, rewrites, lifted))
+        })
+        .collect::<Result<_, Error>>()
+    })?;
+    self.progress.phase_finished(Phase::Extraction, ex_time.elapsed());
     info!("Finished in {}ms", ex_time.elapsed().as_millis());
+
+    let candidates = candidates.into_iter().enumerate();
+    let (rank, (_, length, chosen_rewrites, lifted)) = match objective {
+      Objective::Size => {
+        candidates.min_by_key(|(rank, (final_cost, ..))| (*final_cost, *rank))
+      }
+      // `min_by` keeps the first of equal candidates, which has the lower
+      // rank.
+      Objective::Mdl => {
+        candidates.min_by(|(_, (_, a, ..)), (_, (_, b, ..))| {
+          a.unwrap_or_default().total_cmp(&b.unwrap_or_default())
+        })
+      }
+    }
+    .expect("more than one candidate is extracted");
+    debug!("chose candidate {rank} of {k}");
+    if let Some(length) = length {
+      info!("description length: {length:.1} nats");
+    }
+    Ok(self.report(search, chosen_rewrites, lifted))
+  }
+
+  /// Reports the result of rewriting the programs using `chosen_rewrites`.
+  /// The programs each library function matches in are left out; see
+  /// [`Self::with_lib_programs`].
+  fn report(
+    &self,
+    search: &BeamSearch<Op>,
+    mut chosen_rewrites: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+    mut lifted: RecExpr<AstNode<Op>>,
+  ) -> ExperimentResult<Op> {
+    if let Some(min_uses) = self.min_lib_uses {
+      let weights = search.weights.as_deref();
+      lifted = inline_rare_libs(&lifted, min_uses, |expr| {
+        Self::weighted_cost(&self.cost_model, expr, weights)
+      });
+      let kept: HashSet<_> =
+        split_libs(&lifted).libs.into_iter().map(|lib| lib.id).collect();
+      let num_libs = chosen_rewrites.len();
+      chosen_rewrites.retain(|rewrite| {
+        let index =
+          search.lib_rewrites.iter().position(|lib| lib.name == rewrite.name);
+        index.map_or(true, |i| kept.contains(&LibId(search.lib_offset + i)))
+      });
+      if chosen_rewrites.len() < num_libs {
+        debug!("Inlined {} rarely used libs", num_libs - chosen_rewrites.len());
+      }
+    }
+    let lifted: RecExpr<_> = self.initial_library.bind(lifted.into()).into();
+    let final_cost =
+      Self::weighted_cost(&self.cost_model, &lifted, search.weights.as_deref());
     info!("final cost: {}", final_cost);
     debug!("{}", Pretty(&Expr::from(lifted.clone())));
-    info!("round time: {}ms", start_time.elapsed().as_millis());
+    info!("round time: {}ms", search.start_time.elapsed().as_millis());
+
+    let round = RoundReport {
+      round: 0,
+      final_cost,
+      num_libs: chosen_rewrites.len(),
+      egraph_size: Some(search.egraph_size),
+      stop_reason: search.stop_reason.as_ref().map(|r| format!("{r:?}")),
+      time_elapsed: search.start_time.elapsed(),
+    };
 
     ExperimentResult {
       final_expr: lifted.into(),
       num_libs: chosen_rewrites.len(),
       rewrites: chosen_rewrites,
+      lib_programs: Vec::new(),
+      rounds: vec![round],
+      timed_out: self.timed_out(search.start_time),
+      saturation: search.saturation.clone(),
+      stopped_early: None,
+      beam_stats: search.beam_stats.clone(),
+      restarts: Vec::new(),
+      snapshots: Vec::new(),
+      autotune: None,
+      verification: None,
+    }
+  }
+
+  /// Fills in the programs at `roots` of `aeg` in which the pattern of each
+  /// library function of `res` matches.
+  fn with_lib_programs(
+    roots: &[Id],
+    aeg: &EGraph<AstNode<Op>, PartialLibCost>,
+    mut res: ExperimentResult<Op>,
+  ) -> ExperimentResult<Op> {
+    let provenance = Provenance::new(aeg, roots);
+    res.lib_programs = res
+      .rewrites
+      .iter()
+      .map(|rewrite| provenance.of_matches(aeg, &*rewrite.searcher))
+      .collect();
+    res
+  }
+
+  /// Runs a single beam search, without restarts, on the programs at `roots`
+  /// of `egraph`. Which programs each library function matches in is only
+  /// worked out once the result is finished.
+  fn run_pending(
+    &self,
+    roots: Vec<Id>,
+    weights: Option<&[usize]>,
+    egraph: EGraph<AstNode<Op>, PartialLibCost>,
+  ) -> Result<PendingResult<Op>, Error> {
+    if roots.is_empty() {
+      return Err(Error::NoPrograms);
+    }
+    let search = self.beam_search(&roots, weights, egraph);
+    let res = self.extract_best(&roots, &search)?;
+    let aeg = search.aeg;
+    Ok(PendingResult::new(res.final_expr.clone(), res.timed_out, move || {
+      Self::with_lib_programs(&roots, &aeg, res)
+    }))
+  }
+
+  fn run_egraph(
+    &self,
+    roots: &[Id],
+    weights: Option<&[usize]>,
+    egraph: EGraph<AstNode<Op>, PartialLibCost>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    if roots.is_empty() {
+      return Err(Error::NoPrograms);
+    }
+    if self.restarts <= 1 {
+      return Ok(self.run_pending(roots.to_vec(), weights, egraph)?.finish());
+    }
+
+    let start_time = Instant::now();
+    let (aeg, saturation) = self.run_dsrs(egraph, start_time);
+    let libs = self.anti_unify(roots, None, &aeg, start_time);
+
+    let mut best: Option<ExperimentResult<Op>> = None;
+    let mut restarts = Vec::with_capacity(self.restarts);
+    for restart in 0..self.restarts {
+      if restart > 0 && self.timed_out(start_time) {
+        warn!("Out of time; stopping after {restart} restarts");
+        break;
+      }
+      // The first restart is the unperturbed search.
+      let seed =
+        (restart > 0).then(|| self.restart_seed.wrapping_add(restart as u64));
+      let restart_time = Instant::now();
+      let search = self.search_libs(
+        roots,
+        weights,
+        aeg.clone(),
+        libs.clone(),
+        Some(saturation.clone()),
+        seed,
+        start_time,
+      );
+      let res = self.extract_best(roots, &search)?;
+      let final_cost = res.rounds[0].final_cost;
+      info!("restart {restart}: final cost {final_cost}");
+      restarts.push(RestartReport {
+        seed,
+        final_cost,
+        num_libs: res.num_libs,
+        time_elapsed: restart_time.elapsed(),
+      });
+      if best
+        .as_ref()
+        .map_or(true, |best| final_cost < best.rounds[0].final_cost)
+      {
+        best = Some(res);
+      }
     }
+
+    let mut best = best.expect("the first restart always runs");
+    best.restarts = restarts;
+    Ok(Self::with_lib_programs(roots, &aeg, best))
+  }
+
+  /// Runs library learning on `exprs`, saving a snapshot of the experiment
+  /// to `cache` after the DSRs have been run and after anti-unification. If
+  /// `cache` already has a snapshot of `experiment`, the experiment is
+  /// resumed from that snapshot instead, and `exprs` is ignored. The
+  /// snapshot is deleted once the experiment finishes.
+  ///
+  /// # Errors
+  ///
+  /// Errors with [`Error::Io`] if there is a problem accessing the cache,
+  /// or like [`Experiment::run`].
+  // This lint gives false positives for higher-rank trait bounds.
+  #[allow(single_use_lifetimes)]
+  pub fn run_cached(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    cache: &mut Cache<Op>,
+    experiment: &str,
+  ) -> Result<ExperimentResult<Op>, Error>
+  where
+    Op: Serialize + for<'b> Deserialize<'b>,
+  {
+    let start_time = Instant::now();
+
+    let (roots, aeg, libs, saturation) =
+      if let Some(snapshot) = cache.get_snapshot(experiment)? {
+        info!("Resuming from snapshot");
+        let Snapshot { roots, mut egraph, libs } = snapshot;
+        // The cost model isn't part of the snapshot.
+        egraph.analysis = self.analysis(None);
+        (roots, egraph, libs, None)
+      } else {
+        let (egraph, roots) = self.build_egraph(exprs);
+        let (aeg, saturation) = self.run_dsrs(egraph, start_time);
+        let snapshot = Snapshot { roots, egraph: aeg, libs: None };
+        cache.insert_snapshot(experiment, &snapshot)?;
+        (snapshot.roots, snapshot.egraph, None, Some(saturation))
+      };
+
+    let (roots, aeg, libs) = if let Some(libs) = libs {
+      (roots, aeg, libs)
+    } else {
+      let libs = self.anti_unify(&roots, None, &aeg, start_time);
+      let snapshot = Snapshot { roots, egraph: aeg, libs: Some(libs) };
+      cache.insert_snapshot(experiment, &snapshot)?;
+      let Snapshot { roots, egraph, libs } = snapshot;
+      (roots, egraph, libs.unwrap())
+    };
+
+    let search =
+      self.search_libs(&roots, None, aeg, libs, saturation, None, start_time);
+    let res = self.extract_best(&roots, &search)?;
+    cache.remove_snapshot(experiment)?;
+    Ok(Self::with_lib_programs(&roots, &search.aeg, res))
+  }
+
+  /// Learns library functions common to the programs `a` and `b`, rather
+  /// than library functions of their union: only the candidates which match
+  /// within both corpora are considered, and library functions which end up
+  /// used by the programs of only one corpus are dropped. The programs of
+  /// the result are those of `a` followed by those of `b`.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the rewritten programs can't be extracted.
+  pub fn run_common(
+    &self,
+    a: Vec<Expr<Op>>,
+    b: Vec<Expr<Op>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let split = a.len();
+    let (egraph, roots) = self.build_egraph(a.into_iter().chain(b).collect());
+    let start_time = Instant::now();
+    let (aeg, saturation) = self.run_dsrs(egraph, start_time);
+    let libs = self.anti_unify(&roots, Some(split), &aeg, start_time);
+    let search = self.search_libs(
+      &roots,
+      None,
+      aeg,
+      libs,
+      Some(saturation),
+      None,
+      start_time,
+    );
+
+    // Dropping a library function can change which of the others the
+    // programs use, so this is repeated until every one is used by both.
+    let mut lib_sel = search.best_lib_sel();
+    loop {
+      let (rewrites, lifted) = Self::apply(
+        &roots,
+        &search,
+        &lib_sel,
+        &self.cost_model,
+        &self.lib_constraints,
+        self.deadline(start_time),
+      )?;
+      let common = common_libs(&lifted, split);
+      if common.len() == lib_sel.libs.len() {
+        let res = self.report(&search, rewrites, lifted);
+        return Ok(Self::with_lib_programs(&roots, &search.aeg, res));
+      }
+      // The costs of `lib_sel` are now stale, but only its libs are used.
+      lib_sel.libs.retain(|(lib, _)| common.contains(lib));
+    }
+  }
+
+  /// Runs library learning on `exprs`, returning one result for every point
+  /// on the Pareto frontier of (number of libs, final cost) found by the beam
+  /// search. The results are sorted in ascending order of number of libs, and
+  /// each result is strictly cheaper than every result with fewer libs, as
+  /// measured when choosing among the portfolio: by the cost model, plus the
+  /// structure penalty for each lib.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the rewritten programs can't be extracted.
+  pub fn run_pareto(
+    &self,
+    exprs: Vec<Expr<Op>>,
+  ) -> Result<Vec<ExperimentResult<Op>>, Error> {
+    let (egraph, roots) = self.build_egraph(exprs);
+    let search = self.beam_search(&roots, None, egraph);
+
+    let cost = |res: &ExperimentResult<Op>| {
+      res.rounds[0].final_cost + self.lib_penalty * res.num_libs
+    };
+    let mut front: Vec<ExperimentResult<Op>> = Vec::new();
+    for lib_sel in search.root_costs.pareto_front() {
+      let res = self.extract(&roots, &search, &lib_sel)?;
+      // The costs in the beam are upper bounds, so the actual final costs
+      // might no longer form a frontier.
+      if front.last().map_or(true, |best| cost(&res) < cost(best)) {
+        front.push(Self::with_lib_programs(&roots, &search.aeg, res));
+      }
+    }
+    Ok(front)
+  }
+}
+
+/// Returns a runner hook which keeps the memory used by the process below
+/// `limit` bytes: past three quarters of the limit, the beam is narrowed, and
+/// past the limit, the runner is stopped.
+fn memory_hook<Op>(
+  limit: usize,
+) -> impl FnMut(&mut Runner<AstNode<Op>, PartialLibCost>) -> Result<(), String>
+where
+  Op: Teachable + Arity + Clone + Send + Sync + Debug + Hash + Ord + 'static,
+{
+  move |runner| {
+    let usage = match memory_stats::memory_stats() {
+      Some(stats) => stats.physical_mem,
+      None => return Ok(()),
+    };
+    if usage > limit {
+      return Err(format!("memory limit exceeded: {usage} > {limit} bytes"));
+    }
+    if usage > limit / 4 * 3 && runner.egraph.analysis.narrow_beam() {
+      warn!("Using {usage} of {limit} bytes of memory; narrowing the beam");
+    }
+    Ok(())
+  }
+}
+
+/// The indices of the `n` library functions in `patterns` which are
+/// estimated to save the most nodes in `aeg`: the number of nodes in the body
+/// of each, less one for its application, times the number of e-classes at
+/// which it matches. The
+/// match sites are taken from `sites` if given, and searched for otherwise.
+fn most_promising<Op>(
+  aeg: &EGraph<AstNode<Op>, PartialLibCost>,
+  patterns: &[LibPatternAsts<Op>],
+  sites: Option<&[Vec<Id>]>,
+  n: usize,
+) -> Vec<usize>
+where
+  Op: Teachable
+    + Arity
+    + Clone
+    + Send
+    + Sync
+    + Debug
+    + Display
+    + Hash
+    + Ord
+    + 'static,
+{
+  let savings = patterns.iter().enumerate().map(|(i, (searcher, _, _))| {
+    let matches = match sites {
+      Some(sites) => sites[i].len(),
+      None => Pattern::new(searcher.clone()).search(aeg).len(),
+    };
+    let body_size = searcher
+      .as_ref()
+      .iter()
+      .filter(|node| matches!(node, ENodeOrVar::ENode(_)))
+      .count();
+    (i, matches * body_size.saturating_sub(1))
+  });
+  savings
+    .sorted_by_key(|&(i, saving)| (Reverse(saving), i))
+    .take(n)
+    .map(|(i, _)| i)
+    .collect()
+}
+
+/// Computes which of the library functions in `patterns`, numbered from
+/// `lib_offset`, may co-occur in the programs at `roots` of `aeg`.
+fn lib_co_occurrences<Op>(
+  roots: &[Id],
+  aeg: &EGraph<AstNode<Op>, PartialLibCost>,
+  lib_offset: usize,
+  patterns: &[LibPatternAsts<Op>],
+) -> LibCoOccurrences
+where
+  Op: Teachable
+    + Arity
+    + Clone
+    + Send
+    + Sync
+    + Debug
+    + Display
+    + Hash
+    + Ord
+    + 'static,
+{
+  let co_occurs = COBuilder::new(aeg, roots).run();
+  let matches: Vec<_> = patterns
+    .iter()
+    .enumerate()
+    .map(|(i, (searcher, _, _))| {
+      let classes = Pattern::new(searcher.clone())
+        .search(aeg)
+        .into_iter()
+        .map(|m| m.eclass)
+        .collect();
+      (LibId(lib_offset + i), classes)
+    })
+    .collect();
+  LibCoOccurrences::new(&co_occurs, &matches)
+}
+
+/// The library functions used, directly or through the definitions of other
+/// library functions, by both the first `split` programs of `lifted` and the
+/// rest of them.
+fn common_libs<Op>(
+  lifted: &RecExpr<AstNode<Op>>,
+  split: usize,
+) -> HashSet<LibId>
+where
+  Op: Teachable + Clone + Debug + Hash + Ord,
+{
+  let applied = split_libs(lifted);
+  let defs: HashMap<LibId, &RecExpr<AstNode<Op>>> =
+    applied.libs.iter().map(|lib| (lib.id, &lib.definition)).collect();
+  let uses = |programs: &[RecExpr<AstNode<Op>>]| {
+    let mut used = HashSet::new();
+    let mut stack: Vec<_> = programs.iter().collect();
+    while let Some(expr) = stack.pop() {
+      for node in expr.as_ref() {
+        if let Some(BindingExpr::LibVar(lib)) = node.as_binding_expr() {
+          if used.insert(lib) {
+            stack.extend(defs.get(&lib).copied());
+          }
+        }
+      }
+    }
+    used
+  };
+  let (a, b) = applied.programs.split_at(split);
+  uses(a).intersection(&uses(b)).copied().collect()
+}
+
+/// The results of the beam search phase of a [`BeamExperiment`], from which
+/// the programs can be extracted for any library selection.
+struct BeamSearch<Op>
+where
+  Op: Teachable + Arity + Clone + Send + Sync + Debug + Hash + Ord + 'static,
+{
+  /// The egraph after running the DSRs, before any libs are added.
+  aeg: EGraph<AstNode<Op>, PartialLibCost>,
+  /// The rewrites for every learned library function, in order of
+  /// [`LibId`].
+  ///
+  /// [`LibId`]: babble::LibId
+  lib_rewrites: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// The [`LibId`] of the first learned library function.
+  ///
+  /// [`LibId`]: babble::LibId
+  lib_offset: usize,
+  /// The cost set of the root e-class, sorted by full cost.
+  root_costs: CostSet,
+  /// The number of e-nodes in the egraph once the libs were added.
+  egraph_size: usize,
+  /// Why adding the libs to the egraph stopped.
+  stop_reason: Option<StopReason>,
+  /// Statistics about running the DSRs, unless the run was resumed from a
+  /// snapshot taken after them.
+  saturation: Option<SaturationReport>,
+  /// Telemetry about the beam search, if it was gathered.
+  beam_stats: Option<BeamStats>,
+  /// How many times each program counts, if not once.
+  weights: Option<Vec<usize>>,
+  /// When the experiment started.
+  start_time: Instant,
+}
+
+impl<Op> BeamSearch<Op>
+where
+  Op: Teachable + Arity + Clone + Send + Sync + Debug + Hash + Ord + 'static,
+{
+  /// The cheapest lib selection at the root, or one with no libs if the
+  /// beam is empty.
+  fn best_lib_sel(&self) -> LibSel {
+    self.root_costs.set.first().cloned().unwrap_or(LibSel {
+      expr_cost: 0,
+      full_cost: 0,
+      libs: Vec::new(),
+    })
   }
 }
 
@@ -209,97 +1820,149 @@ where
     &self.dsrs
   }
 
+  fn fingerprint(&self, mut hasher: &mut dyn Hasher) -> bool {
+    // Destructure exhaustively, so that new parameters aren't forgotten.
+    // The cost model can't be hashed, so it is identified by the cache key
+    // if it is a custom one, and the other ignored fields don't affect the
+    // results.
+    let Self {
+      dsrs,
+      scheduler,
+      final_beams,
+      inter_beams,
+      lib_iter_limit,
+      node_limit,
+      node_limit_retry,
+      lps,
+      max_libs_per_sel,
+      extra_data,
+      learn_constants,
+      max_arity,
+      learn_recursive,
+      learn_lets,
+      learn_higher_order,
+      control_flow,
+      pruning,
+      adaptive_beam,
+      co_occurrence_pruning,
+      deterministic,
+      lib_constraints,
+      memory_limit,
+      threads: _,
+      portfolio,
+      restarts,
+      restart_seed,
+      cost_model: _,
+      custom_cost_model,
+      dag_size,
+      lib_penalty,
+      objective,
+      min_lib_uses,
+      timeout,
+      incremental,
+      semantic_dedup,
+      candidate_budget,
+      min_size,
+      max_size,
+      excluded_root_ops,
+      anchor_ops,
+      previous_lib: _,
+      au_cache: _,
+      progress: _,
+      dump_egraph: _,
+      beam_stats: _,
+      initial_library,
+      initial_rewrites: _,
+      cache_key,
+    } = self;
+    hash_dsrs(dsrs, hasher);
+    format!("{scheduler:?} {pruning:?} {extra_data:?}").hash(&mut hasher);
+    (final_beams, inter_beams, lib_iter_limit, lps, max_libs_per_sel)
+      .hash(&mut hasher);
+    (learn_constants, max_arity, learn_recursive, learn_lets).hash(&mut hasher);
+    (learn_higher_order, control_flow.is_some(), adaptive_beam)
+      .hash(&mut hasher);
+    (co_occurrence_pruning, deterministic, memory_limit, portfolio)
+      .hash(&mut hasher);
+    (restarts, restart_seed, dag_size, lib_penalty, timeout, incremental)
+      .hash(&mut hasher);
+    (semantic_dedup, candidate_budget, min_size, max_size, initial_library)
+      .hash(&mut hasher);
+    (excluded_root_ops, anchor_ops, lib_constraints, objective)
+      .hash(&mut hasher);
+    (node_limit, node_limit_retry, min_lib_uses).hash(&mut hasher);
+    cache_key.hash(&mut hasher);
+    let custom =
+      *custom_cost_model || matches!(pruning, PruningStrategy::Custom(_));
+    !custom || cache_key.is_some()
+  }
+
+  fn expr_size(&self, expr: &Expr<Op>) -> usize {
+    if self.dag_size {
+      expr.dag_len()
+    } else {
+      expr.len()
+    }
+  }
+
   fn run(
     &self,
     exprs: Vec<Expr<Op>>,
     _writer: &mut CsvWriter,
-  ) -> ExperimentResult<Op> {
-    // First, let's turn our list of exprs into a list of recexprs
-    let recexprs: Vec<RecExpr<AstNode<Op>>> =
-      exprs.into_iter().map_into().collect();
-
-    // Add one to account for root node, not added yet
-    let initial_cost = {
-      let s: usize = recexprs.iter().map(|x| AstSize.cost_rec(x)).sum();
-      s + 1
-    };
-
-    debug!("Starting cost: {}", initial_cost);
-
-    let mut egraph = EGraph::new(PartialLibCost::new(
-      self.final_beams,
-      self.inter_beams,
-      self.lps,
-    ));
-    let roots = recexprs.iter().map(|x| egraph.add_expr(x)).collect::<Vec<_>>();
-    egraph.rebuild();
-
-    self.run_egraph(&roots, egraph)
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let (egraph, roots) = self.build_egraph(exprs);
+    self.run_egraph(&roots, None, egraph)
   }
 
   fn total_rounds(&self) -> usize {
     1
   }
 
-  fn run_multi(&self, expr_groups: Vec<Vec<Expr<Op>>>) -> ExperimentResult<Op> {
-    // First, let's turn our list of exprs into a list of recexprs
-    let recexpr_groups: Vec<Vec<_>> = expr_groups
-      .into_iter()
-      .map(|group| group.into_iter().map(RecExpr::from).collect())
-      .collect();
-
-    let mut egraph = EGraph::new(PartialLibCost::new(
-      self.final_beams,
-      self.inter_beams,
-      self.lps,
-    ));
-
-    let roots: Vec<_> = recexpr_groups
-      .into_iter()
-      .map(|mut group| {
-        let first_expr = group.pop().unwrap();
-        let root = egraph.add_expr(&first_expr);
-        for expr in group {
-          let class = egraph.add_expr(&expr);
-          egraph.union(root, class);
-        }
-
-        root
-      })
-      .collect();
-
-    egraph.rebuild();
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    self.run_interned(&InternedCorpus::from_groups(expr_groups))
+  }
 
-    self.run_egraph(&roots, egraph)
+  fn run_interned(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let (egraph, roots) = self.build_corpus_egraph(corpus);
+    self.run_egraph(&roots, corpus.weights(), egraph)
   }
 
-  fn write_to_csv(
+  fn run_deferred(
     &self,
+    exprs: Vec<Expr<Op>>,
     writer: &mut CsvWriter,
-    round: usize,
-    initial_cost: usize,
-    final_cost: usize,
-    compression: f64,
-    num_libs: usize,
-    time_elapsed: Duration,
-  ) {
-    writer
-      .serialize((
-        "beam",
-        0,
-        self.final_beams,
-        self.inter_beams,
-        self.lps,
-        self.extra_data.clone(),
-        round,
-        initial_cost,
-        final_cost,
-        compression,
-        num_libs,
-        time_elapsed.as_secs_f64(),
-      ))
-      .unwrap();
-    writer.flush().unwrap();
+  ) -> Result<PendingResult<Op>, Error> {
+    if self.restarts > 1 {
+      return self.run(exprs, writer).map(PendingResult::ready);
+    }
+    let (egraph, roots) = self.build_egraph(exprs);
+    self.run_pending(roots, None, egraph)
+  }
+
+  fn run_interned_deferred(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<PendingResult<Op>, Error> {
+    if self.restarts > 1 {
+      return self.run_interned(corpus).map(PendingResult::ready);
+    }
+    let (egraph, roots) = self.build_corpus_egraph(corpus);
+    self.run_pending(roots, corpus.weights(), egraph)
+  }
+
+  fn metadata(&self) -> Metadata {
+    Metadata::new()
+      .with("kind", "beam")
+      .with("final_beams", self.final_beams)
+      .with("inter_beams", self.inter_beams)
+      .with("lps", self.lps)
+      .with_all(Metadata::from_serialize("extra", &self.extra_data))
   }
 
   fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
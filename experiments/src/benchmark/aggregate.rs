@@ -0,0 +1,340 @@
+//! Statistics aggregated over all of the benchmark runs: the geometric mean
+//! compression of each domain, and paired tests comparing runs with and
+//! without DSRs, and runs of babble against DreamCoder.
+//!
+//! Runs are compared by their compression factors on the same input files,
+//! using the Wilcoxon signed-rank test on the differences of the logarithms
+//! of the compression factors.
+
+use babble::util;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use super::{spec::Params, BenchResults};
+
+/// The maximum number of nonzero differences for which the p-value of the
+/// signed-rank test is computed exactly. Above this, the normal
+/// approximation is used.
+const EXACT_LIMIT: usize = 50;
+
+/// DreamCoder's results, keyed by benchmark name (as `{domain}_{benchmark}`)
+/// and input file.
+pub(crate) type DreamCoderResults = BTreeMap<(String, String), DreamCoderRun>;
+
+/// DreamCoder's result on a single input file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct DreamCoderRun {
+  /// The compression factor DreamCoder achieved.
+  pub(crate) compression: f64,
+  /// How long DreamCoder took in total, in seconds.
+  pub(crate) total_time: f64,
+}
+
+/// A row of the CSV of DreamCoder's results written by `parse_dc`.
+#[derive(Debug, Deserialize)]
+struct DreamCoderRow {
+  name: String,
+  iter: String,
+  #[serde(rename = "initial cost", alias = "initial_cost")]
+  initial_cost: usize,
+  #[serde(rename = "final cost", alias = "final_cost")]
+  final_cost: usize,
+  #[serde(rename = "total time")]
+  total_time: f64,
+}
+
+/// Reads DreamCoder's results from the CSV at `path`, as written by the
+/// `parse_dc` binary.
+///
+/// # Errors
+/// Returns an error if the file can't be read or isn't a valid CSV of
+/// results.
+pub(crate) fn read_dreamcoder(
+  path: &Path,
+) -> anyhow::Result<DreamCoderResults> {
+  let mut results = BTreeMap::new();
+  for row in csv::Reader::from_path(path)?.deserialize() {
+    let row: DreamCoderRow = row?;
+    let compression =
+      util::compression_factor(row.initial_cost, row.final_cost);
+    let run = DreamCoderRun { compression, total_time: row.total_time };
+    results.insert((row.name, row.iter), run);
+  }
+  Ok(results)
+}
+
+/// The summary of a set of benchmark runs.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Aggregate {
+  /// The geometric mean compression of each domain, for each set of
+  /// parameters.
+  domains: Vec<DomainStats>,
+  /// Tests of runs with DSRs (`babble` mode) against runs without them (`au`
+  /// mode) with otherwise equal parameters.
+  dsrs_vs_none: Vec<PairedTest>,
+  /// Tests of `babble` runs against DreamCoder, if its results were given.
+  babble_vs_dreamcoder: Vec<PairedTest>,
+}
+
+/// The compression achieved on a domain with a set of parameters.
+#[derive(Debug, Clone, Serialize)]
+struct DomainStats {
+  params: Params,
+  domain: String,
+  /// The number of input files.
+  runs: usize,
+  /// The geometric mean of the compression factors.
+  geomean_compression: f64,
+}
+
+/// A paired comparison of the compression factors of runs with `params`
+/// against another kind of run, on the same input files.
+#[derive(Debug, Clone, Serialize)]
+struct PairedTest {
+  params: Params,
+  /// The number of input files both kinds of run were on.
+  pairs: usize,
+  /// The number of input files on which runs with `params` compressed more.
+  wins: usize,
+  /// The number of input files on which runs with `params` compressed less.
+  losses: usize,
+  /// The geometric mean of the ratios of the compression factors.
+  geomean_ratio: f64,
+  /// The Wilcoxon signed-rank statistic: the sum of the ranks of the input
+  /// files on which runs with `params` compressed more.
+  statistic: f64,
+  /// The two-sided p-value of the signed-rank test, or `None` if the
+  /// compression factors were all equal.
+  p_value: Option<f64>,
+}
+
+impl Aggregate {
+  /// Aggregates `results`, comparing them against `dreamcoder` if given.
+  pub(crate) fn new(
+    results: &[BenchResults],
+    dreamcoder: Option<&DreamCoderResults>,
+  ) -> Self {
+    let compression = |result: &BenchResults| {
+      util::compression_factor(
+        result.summary.initial_cost,
+        result.summary.final_cost,
+      )
+    };
+
+    let mut by_domain: BTreeMap<(&Params, &str), Vec<f64>> = BTreeMap::new();
+    let mut by_input = BTreeMap::new();
+    for result in results {
+      let factor = compression(result);
+      by_domain
+        .entry((&result.params, result.domain.as_str()))
+        .or_default()
+        .push(factor);
+      by_input.insert(
+        (&result.params, &result.domain, &result.benchmark, &result.file),
+        factor,
+      );
+    }
+    let domains = by_domain
+      .into_iter()
+      .map(|((params, domain), factors)| DomainStats {
+        params: params.clone(),
+        domain: domain.to_string(),
+        runs: factors.len(),
+        geomean_compression: geomean(factors.iter().copied()),
+      })
+      .collect();
+
+    let mut dsr_pairs: BTreeMap<&Params, Vec<(f64, f64)>> = BTreeMap::new();
+    let mut dc_pairs: BTreeMap<&Params, Vec<(f64, f64)>> = BTreeMap::new();
+    for result in results.iter().filter(|r| r.params.mode == "babble") {
+      let factor = compression(result);
+      let without_dsrs =
+        Params { mode: "au".to_string(), ..result.params.clone() };
+      let key =
+        (&without_dsrs, &result.domain, &result.benchmark, &result.file);
+      if let Some(&other) = by_input.get(&key) {
+        dsr_pairs.entry(&result.params).or_default().push((factor, other));
+      }
+
+      let name = format!("{}_{}", result.domain, result.benchmark);
+      if let Some(other) =
+        dreamcoder.and_then(|dc| dc.get(&(name, result.file.clone())))
+      {
+        let pair = (factor, other.compression);
+        dc_pairs.entry(&result.params).or_default().push(pair);
+      }
+    }
+
+    Self {
+      domains,
+      dsrs_vs_none: dsr_pairs
+        .into_iter()
+        .map(|(params, pairs)| PairedTest::new(params, &pairs))
+        .collect(),
+      babble_vs_dreamcoder: dc_pairs
+        .into_iter()
+        .map(|(params, pairs)| PairedTest::new(params, &pairs))
+        .collect(),
+    }
+  }
+
+  /// Prints the summary in a human-readable form.
+  pub(crate) fn print(&self) {
+    println!("geometric mean compression:");
+    for stats in &self.domains {
+      println!(
+        "  {} {:20} {:>8.3} ({} files)",
+        stats.params, stats.domain, stats.geomean_compression, stats.runs
+      );
+    }
+    for (title, tests) in [
+      ("dsrs vs none", &self.dsrs_vs_none),
+      ("babble vs dreamcoder", &self.babble_vs_dreamcoder),
+    ] {
+      if tests.is_empty() {
+        continue;
+      }
+      println!("{title}:");
+      for test in tests {
+        let p_value =
+          test.p_value.map_or("-".to_string(), |p| format!("{p:.4}"));
+        println!(
+          "  {} ratio {:.3}, {} wins / {} losses of {}, p = {p_value}",
+          test.params, test.geomean_ratio, test.wins, test.losses, test.pairs
+        );
+      }
+    }
+  }
+}
+
+impl PairedTest {
+  /// Compares the pairs of compression factors `pairs`, the first of which
+  /// come from runs with `params`.
+  fn new(params: &Params, pairs: &[(f64, f64)]) -> Self {
+    let differences: Vec<f64> =
+      pairs.iter().map(|(a, b)| a.ln() - b.ln()).collect();
+    let (statistic, p_value) = signed_rank(&differences);
+    Self {
+      params: params.clone(),
+      pairs: pairs.len(),
+      wins: pairs.iter().filter(|(a, b)| a > b).count(),
+      losses: pairs.iter().filter(|(a, b)| a < b).count(),
+      geomean_ratio: geomean(pairs.iter().map(|(a, b)| a / b)),
+      statistic,
+      p_value,
+    }
+  }
+}
+
+/// The geometric mean of `values`, or 1 if there are none.
+fn geomean(values: impl Iterator<Item = f64>) -> f64 {
+  let (count, log_sum) = values
+    .fold((0_u32, 0.0), |(count, sum), value| (count + 1, sum + value.ln()));
+  if count == 0 {
+    1.0
+  } else {
+    (log_sum / f64::from(count)).exp()
+  }
+}
+
+/// The Wilcoxon signed-rank test of `differences`: returns the sum of the
+/// ranks of the positive differences and the two-sided p-value, or `None` if
+/// every difference is zero. Zero differences are dropped, and tied absolute
+/// differences get the mean of their ranks.
+#[allow(clippy::cast_precision_loss, clippy::float_cmp)]
+fn signed_rank(differences: &[f64]) -> (f64, Option<f64>) {
+  let mut nonzero: Vec<f64> =
+    differences.iter().copied().filter(|&d| d != 0.0).collect();
+  nonzero.sort_by(|a, b| a.abs().total_cmp(&b.abs()));
+  let n = nonzero.len();
+  if n == 0 {
+    return (0.0, None);
+  }
+
+  // The ranks are doubled, so that the mean rank of a group of ties is an
+  // integer.
+  let mut ranks = vec![0; n];
+  let mut tie_correction = 0.0;
+  let mut start = 0;
+  while start < n {
+    let mut end = start + 1;
+    while end < n && nonzero[end].abs() == nonzero[start].abs() {
+      end += 1;
+    }
+    // Positions `start..end` have ranks `start + 1..=end`.
+    for rank in &mut ranks[start..end] {
+      *rank = start + end + 1;
+    }
+    let ties = (end - start) as f64;
+    tie_correction += ties * ties * ties - ties;
+    start = end;
+  }
+  let doubled_statistic: usize = nonzero
+    .iter()
+    .zip(&ranks)
+    .filter(|(d, _)| **d > 0.0)
+    .map(|(_, &rank)| rank)
+    .sum();
+  let statistic = doubled_statistic as f64 / 2.0;
+
+  let p_value = if n <= EXACT_LIMIT {
+    exact_p_value(&ranks, doubled_statistic)
+  } else {
+    let n = n as f64;
+    let mean = n * (n + 1.0) / 4.0;
+    let variance =
+      n * (n + 1.0) * (2.0 * n + 1.0) / 24.0 - tie_correction / 48.0;
+    // With a continuity correction.
+    let deviation = ((statistic - mean).abs() - 0.5).max(0.0);
+    erfc(deviation / variance.sqrt() / std::f64::consts::SQRT_2)
+  };
+  (statistic, Some(p_value.min(1.0)))
+}
+
+/// The two-sided p-value of the doubled signed-rank statistic
+/// `doubled_statistic`, computed from the exact distribution of the
+/// statistic given the doubled `ranks`.
+fn exact_p_value(ranks: &[usize], doubled_statistic: usize) -> f64 {
+  // `counts[s]` is the number of subsets of the ranks which sum to `s`.
+  let total: usize = ranks.iter().sum();
+  let mut counts = vec![0.0; total + 1];
+  counts[0] = 1.0;
+  for &rank in ranks {
+    for sum in (rank..=total).rev() {
+      counts[sum] += counts[sum - rank];
+    }
+  }
+  let subsets: f64 = counts.iter().sum();
+  let lower: f64 = counts[..=doubled_statistic].iter().sum();
+  let upper: f64 = counts[doubled_statistic..].iter().sum();
+  2.0 * lower.min(upper) / subsets
+}
+
+/// The complementary error function, with a relative error below 1.2e-7
+/// (from Numerical Recipes).
+fn erfc(x: f64) -> f64 {
+  let z = x.abs();
+  let t = 1.0 / (1.0 + 0.5 * z);
+  let poly = -1.265_512_23
+    + t
+      * (1.000_023_68
+        + t
+          * (0.374_091_96
+            + t
+              * (0.096_784_18
+                + t
+                  * (-0.186_288_06
+                    + t
+                      * (0.278_868_07
+                        + t
+                          * (-1.135_203_98
+                            + t
+                              * (1.488_515_87
+                                + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+  let erfc = t * (-z * z + poly).exp();
+  if x >= 0.0 {
+    erfc
+  } else {
+    2.0 - erfc
+  }
+}
@@ -0,0 +1,115 @@
+//! Comparisons of babble against DreamCoder at an equal wall-clock budget.
+//!
+//! Each input DreamCoder was run on is run again with a timeout of
+//! DreamCoder's total running time on it, and the compression reached within
+//! that budget is written to a CSV next to DreamCoder's and to that of the
+//! run without a timeout.
+
+use babble::util;
+use babble_experiments::{dreamcoder::expr::DreamCoderOp, EarlyStop, Summary};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, time::Duration};
+
+use super::{aggregate::DreamCoderResults, BenchResults};
+
+/// The result of running an experiment with a time budget.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct EqualTime {
+  /// The wall-clock budget, DreamCoder's total running time on the input.
+  pub(crate) budget: Duration,
+  pub(crate) summary: Summary<DreamCoderOp>,
+}
+
+/// A row of the equal-time CSV.
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+  name: String,
+  iter: &'a str,
+  mode: &'a str,
+  #[serde(rename = "beam size")]
+  beam_size: usize,
+  lps: usize,
+  rounds: usize,
+  #[serde(rename = "max arity")]
+  max_arity: usize,
+  #[serde(rename = "lib iter limit")]
+  lib_iter_limit: usize,
+  /// The time budget, in seconds.
+  budget: f64,
+  /// How long the run within the budget took, in seconds.
+  time: f64,
+  #[serde(rename = "timed out")]
+  timed_out: bool,
+  #[serde(rename = "initial cost")]
+  initial_cost: usize,
+  #[serde(rename = "final cost")]
+  final_cost: usize,
+  compression: f64,
+  #[serde(rename = "dreamcoder compression")]
+  dreamcoder_compression: f64,
+  /// The compression reached by the run without a time budget.
+  #[serde(rename = "unbounded compression")]
+  unbounded_compression: f64,
+}
+
+/// Writes the compression reached within DreamCoder's running time by each
+/// of `results` which was run with a budget to a CSV file at `output`.
+///
+/// # Errors
+///
+/// Errors if the file can't be written.
+pub(crate) fn write_csv(
+  results: &[BenchResults],
+  dreamcoder: &DreamCoderResults,
+  output: &Path,
+) -> anyhow::Result<()> {
+  let mut csv_writer = csv::Writer::from_path(output)?;
+  for result in results {
+    let key =
+      (format!("{}_{}", result.domain, result.benchmark), result.file.clone());
+    if let (Some(equal_time), Some(dc)) =
+      (&result.equal_time, dreamcoder.get(&key))
+    {
+      csv_writer.serialize(row(result, equal_time, dc.compression, key.0))?;
+    }
+  }
+  csv_writer.flush()?;
+  Ok(())
+}
+
+/// The row of the equal-time CSV for `result`, which was run within
+/// `equal_time` and on whose input DreamCoder reached
+/// `dreamcoder_compression`.
+fn row<'a>(
+  result: &'a BenchResults,
+  equal_time: &EqualTime,
+  dreamcoder_compression: f64,
+  name: String,
+) -> Row<'a> {
+  let EqualTime { budget, summary } = equal_time;
+  let params = &result.params;
+  Row {
+    name,
+    iter: &result.file,
+    mode: &params.mode,
+    beam_size: params.beam_size,
+    lps: params.lps,
+    rounds: params.rounds,
+    max_arity: params.max_arity,
+    lib_iter_limit: params.lib_iter_limit,
+    budget: budget.as_secs_f64(),
+    time: summary.run_time.as_secs_f64(),
+    timed_out: summary.stopped_early == Some(EarlyStop::TimedOut),
+    initial_cost: summary.initial_cost,
+    final_cost: summary.final_cost,
+    compression: util::compression_factor(
+      summary.initial_cost,
+      summary.final_cost,
+    ),
+    dreamcoder_compression,
+    unbounded_compression: util::compression_factor(
+      result.summary.initial_cost,
+      result.summary.final_cost,
+    ),
+  }
+}
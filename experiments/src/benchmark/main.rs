@@ -12,23 +12,34 @@
 )]
 #![allow(clippy::non_ascii_literal)]
 
-use babble::{rewrites, util, Expr};
+use babble::{rewrites, sexp::Program, util, Error, Expr, InternedCorpus};
 use babble_experiments::{
   cache::Cache,
-  dreamcoder::{expr::DreamCoderOp, json::CompressionInput},
-  BeamExperiment, EqsatExperiment, Experiment, Rounds, Summary,
+  dreamcoder::{expr::DreamCoderOp, json::FrontierReader},
+  Autotune, BeamExperiment, EqsatExperiment, Experiment, Rounds, Summary,
 };
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::BTreeMap,
-  fs,
+  collections::{BTreeMap, BTreeSet},
+  fs::{self, File},
+  hash::Hash,
   path::{Path, PathBuf},
-  sync::Mutex,
+  sync::{Arc, Mutex},
+  time::Duration,
 };
 
 use rayon::prelude::*;
 
+mod aggregate;
+mod equal_time;
+mod report;
+mod spec;
+
+use aggregate::{Aggregate, DreamCoderResults};
+use equal_time::EqualTime;
+use spec::{Corpus, Params, Spec};
+
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Parser)]
 #[clap(version, author, about)]
@@ -43,24 +54,123 @@ struct Opts {
   #[clap(long)]
   cache: Option<PathBuf>,
 
-  /// File to dump the raw costs into
-  #[clap(long, short)]
-  output: PathBuf,
-
+  /// Evict the least recently used results once the cache grows beyond this
+  /// many megabytes.
   #[clap(long)]
-  beam_size: usize,
-  #[clap(long)]
-  lps: usize,
+  cache_max_size: Option<u64>,
+
+  /// Check the cache for corrupted or incompatible results, and remove them,
+  /// before running the benchmarks.
   #[clap(long)]
-  rounds: usize,
+  cache_verify: bool,
+
+  /// The directory to write reports into. Relative report paths are
+  /// resolved against it, and missing directories are created.
+  #[clap(long, parse(from_os_str))]
+  output_dir: Option<PathBuf>,
+
+  /// Continue an interrupted run: the results of each domain are saved to
+  /// `checkpoints/` in the output directory as they come in, and with this
+  /// flag, the files whose results were saved are not run again.
+  #[clap(long = "continue", requires = "output_dir")]
+  resume: bool,
+
+  /// File to dump the raw costs into. If none is specified, the raw costs
+  /// aren't written.
+  #[clap(long, short, parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Write a JSON summary of the results to this file: the geometric mean
+  /// compression of each domain, and paired tests of runs with and without
+  /// DSRs (and against DreamCoder, with `--dreamcoder`).
+  #[clap(long, parse(from_os_str))]
+  summary: Option<PathBuf>,
+
+  /// Write a self-contained HTML report of the results to this file, with
+  /// charts of the costs, the compression of each domain, and the impact of
+  /// DSRs.
+  #[clap(long, parse(from_os_str))]
+  report: Option<PathBuf>,
+
+  /// A CSV of DreamCoder's results on the same inputs, as written by
+  /// `parse_dc`, to compare against in the summary and with `--equal-time`.
+  #[clap(long, alias = "dc-baseline", parse(from_os_str))]
+  dreamcoder: Option<PathBuf>,
+
+  /// Also run each beam search experiment with a wall-clock budget of
+  /// DreamCoder's total running time on the same input, and write the
+  /// compression reached within it to this CSV, next to DreamCoder's.
+  #[clap(long, parse(from_os_str), requires = "dreamcoder")]
+  equal_time: Option<PathBuf>,
+
+  /// The number of experiments to run at once. Every file of every corpus
+  /// is run with every parameter combination in parallel, up to this many
+  /// at a time. Defaults to the number of CPUs.
   #[clap(long)]
-  max_arity: usize,
+  jobs: Option<usize>,
+
+  /// Choose the beam size of each beam search experiment automatically,
+  /// instead of using `--beam-size` or the beam sizes of the spec: a short
+  /// pilot is run on the first few programs of each input with a ladder of
+  /// beam sizes, and the input is run with the one at the knee of the
+  /// cost/time curve. The chosen beam size is recorded in the summary.
   #[clap(long)]
-  lib_iter_limit: usize,
-  #[clap(long)] // should be bool, but I don't want flags
-  use_all: usize,
-  #[clap(long, value_parser = ["babble", "au", "eqsat"])]
-  mode: String,
+  autotune: bool,
+
+  /// A DreamCoder `CompressionInput` of held-out tasks, which the libraries
+  /// learned from each input are evaluated on.
+  #[clap(long, parse(from_os_str))]
+  held_out: Option<PathBuf>,
+
+  /// A TOML file specifying a grid of parameters and the corpora to run them
+  /// on, instead of the input directory and parameters below. See the `spec`
+  /// module for the format.
+  #[clap(long, parse(from_os_str))]
+  spec: Option<PathBuf>,
+
+  #[clap(long, required_unless_present = "spec")]
+  beam_size: Option<usize>,
+  #[clap(long, required_unless_present = "spec")]
+  lps: Option<usize>,
+  #[clap(long, required_unless_present = "spec")]
+  rounds: Option<usize>,
+  #[clap(long, required_unless_present = "spec")]
+  max_arity: Option<usize>,
+  #[clap(long, required_unless_present = "spec")]
+  lib_iter_limit: Option<usize>,
+  #[clap(long, required_unless_present = "spec")]
+  // should be bool, but I don't want flags
+  use_all: Option<usize>,
+  #[clap(long, value_parser = spec::MODES, required_unless_present = "spec")]
+  mode: Option<String>,
+  /// The format of the input files: either DreamCoder `CompressionInput`
+  /// JSON, or plain S-expressions with one program per top-level expression.
+  #[clap(long, value_parser = spec::FORMATS, default_value = "dreamcoder")]
+  format: String,
+}
+
+impl Opts {
+  /// The corpus given on the command line.
+  fn corpus(&self) -> Corpus {
+    Corpus {
+      path: self.file.clone().unwrap_or(PathBuf::from(BENCHMARK_PATH)),
+      format: self.format.clone(),
+      domains: self.domain.iter().cloned().collect(),
+    }
+  }
+
+  /// The parameters given on the command line.
+  fn params(&self) -> Params {
+    Params {
+      mode: self.mode.clone().unwrap(),
+      beam_size: self.beam_size.unwrap(),
+      lps: self.lps.unwrap(),
+      rounds: self.rounds.unwrap(),
+      max_arity: self.max_arity.unwrap(),
+      lib_iter_limit: self.lib_iter_limit.unwrap(),
+      use_all: self.use_all.unwrap() > 0,
+    }
+  }
 }
 
 const BENCHMARK_PATH: &str = "harness/data/dreamcoder-benchmarks/benches";
@@ -114,24 +224,214 @@ struct BenchResults {
   domain: String,
   benchmark: String,
   file: String,
+  params: Params,
   summary: Summary<DreamCoderOp>,
+  /// The result of the run within DreamCoder's running time, with
+  /// `--equal-time`.
+  #[serde(default)]
+  equal_time: Option<EqualTime>,
 }
 
 fn main() -> anyhow::Result<()> {
   env_logger::init();
   let opts: Opts = Opts::parse();
 
-  let cache = opts.cache.clone().map_or_else(Cache::new, Cache::from_dir)?;
+  let mut cache =
+    opts.cache.clone().map_or_else(Cache::new, Cache::from_dir)?;
+  if let Some(max_size) = opts.cache_max_size {
+    cache = cache.with_max_size(max_size * 1_000_000)?;
+  }
 
   println!("using cache: {}", cache.path().to_str().unwrap());
 
-  let cache = Mutex::new(cache);
+  if opts.cache_verify {
+    let report = cache.verify()?;
+    println!(
+      "verified {} cache entries: removed {} corrupted and {} incompatible",
+      report.checked,
+      report.corrupted.len(),
+      report.incompatible.len()
+    );
+  }
+
+  // Experiments share the cache of summaries with the beam search, which
+  // memoizes anti-unification in it.
+  let cache = Arc::new(Mutex::new(cache));
+
+  let (corpora, grid) = match &opts.spec {
+    Some(spec) => {
+      let spec = Spec::from_file(spec)?;
+      let grid = spec.grid();
+      println!("running {} parameter combination(s)", grid.len());
+      (spec.corpora, grid)
+    }
+    None => (vec![opts.corpus()], vec![opts.params()]),
+  };
+
+  let dreamcoder =
+    opts.dreamcoder.as_deref().map(aggregate::read_dreamcoder).transpose()?;
+  // The budgets of the equal-time runs are DreamCoder's running times
+  let budgets = dreamcoder.as_ref().filter(|_| opts.equal_time.is_some());
+
+  let held_out = opts.held_out.as_deref().map(|path| {
+    InternedCorpus::from_groups(dreamcoder_program_groups(path, true))
+  });
 
-  let benchmark_path =
-    opts.file.clone().unwrap_or(PathBuf::from(BENCHMARK_PATH));
+  let output_dir = opts.output_dir.as_deref();
+  let checkpoints = output_dir.map(|dir| Checkpoints {
+    dir: dir.join("checkpoints"),
+    resume: opts.resume,
+  });
+
+  let runs: Vec<_> = corpora
+    .iter()
+    .flat_map(|corpus| grid.iter().map(move |params| (corpus, params)))
+    .collect();
+  // The cache is only locked while looking up or inserting a summary, and
+  // the reports are only written once every run has finished.
+  let run_all = || {
+    runs
+      .par_iter()
+      .map(|(corpus, params)| {
+        run_corpus(
+          corpus,
+          params,
+          opts.autotune,
+          held_out.as_ref(),
+          budgets,
+          &cache,
+          checkpoints.as_ref(),
+        )
+      })
+      .collect::<anyhow::Result<Vec<_>>>()
+  };
+  let results: Vec<_> = match opts.jobs {
+    Some(jobs) => rayon::ThreadPoolBuilder::new()
+      .num_threads(jobs)
+      .build()?
+      .install(run_all)?,
+    None => run_all()?,
+  }
+  .into_iter()
+  .flatten()
+  .collect();
+
+  if let Some(path) = &opts.output {
+    let path = report_path(output_dir, path)?;
+    replace_file(&path, |tmp| {
+      plot_raw_data(&results, tmp, opts.spec.is_some())
+    })?;
+  }
+
+  if let (Some(path), Some(dreamcoder)) = (&opts.equal_time, budgets) {
+    let path = report_path(output_dir, path)?;
+    replace_file(&path, |tmp| {
+      equal_time::write_csv(&results, dreamcoder, tmp)
+    })?;
+  }
+
+  if let Some(path) = &opts.summary {
+    let path = report_path(output_dir, path)?;
+    let aggregate = Aggregate::new(&results, dreamcoder.as_ref());
+    aggregate.print();
+    replace_file(&path, |tmp| {
+      Ok(fs::write(tmp, serde_json::to_string_pretty(&aggregate)?)?)
+    })?;
+  }
+
+  if let Some(path) = &opts.report {
+    let path = report_path(output_dir, path)?;
+    replace_file(&path, |tmp| report::write_html(&results, tmp))?;
+  }
+  Ok(())
+}
+
+/// Writes the file at `path` by calling `write` on a temporary path next to
+/// it, and then renaming the temporary file, so that `path` is never left
+/// partially written.
+fn replace_file(
+  path: &Path,
+  write: impl FnOnce(&Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+  let mut tmp = path.as_os_str().to_owned();
+  tmp.push(".tmp");
+  let tmp = PathBuf::from(tmp);
+  write(&tmp)?;
+  fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+/// Where the results of each domain are saved as they come in, so that an
+/// interrupted run can be continued.
+#[derive(Debug)]
+struct Checkpoints {
+  dir: PathBuf,
+  /// Whether to start from the results saved by a previous run, instead of
+  /// overwriting them.
+  resume: bool,
+}
+
+impl Checkpoints {
+  /// The file the results of running `domain` of `corpus` with `params` are
+  /// saved to.
+  fn path(&self, corpus: &Corpus, params: &Params, domain: &str) -> PathBuf {
+    let corpus_name = corpus
+      .path
+      .file_name()
+      .map_or_else(|| "corpus".into(), |name| name.to_string_lossy());
+    self
+      .dir
+      .join(params.to_string())
+      .join(&*corpus_name)
+      .join(domain)
+      .join("results.json")
+  }
+
+  /// The results saved to `path` by a previous run, if continuing one.
+  fn load(&self, path: &Path) -> anyhow::Result<Vec<BenchResults>> {
+    if self.resume && path.exists() {
+      Ok(serde_json::from_reader(File::open(path)?)?)
+    } else {
+      Ok(Vec::new())
+    }
+  }
+
+  /// Saves `results` to `path`, replacing the results saved before.
+  fn save(path: &Path, results: &[BenchResults]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    replace_file(path, |tmp| Ok(fs::write(tmp, serde_json::to_vec(results)?)?))
+  }
+}
+
+/// Resolves the report path `path` against `output_dir`, if it is given,
+/// and creates the directory the report will be written into.
+fn report_path(
+  output_dir: Option<&Path>,
+  path: &Path,
+) -> anyhow::Result<PathBuf> {
+  let path =
+    output_dir.map_or_else(|| path.to_path_buf(), |dir| dir.join(path));
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  Ok(path)
+}
+
+fn run_corpus(
+  corpus: &Corpus,
+  params: &Params,
+  autotune: bool,
+  held_out: Option<&InternedCorpus<DreamCoderOp>>,
+  budgets: Option<&DreamCoderResults>,
+  cache: &Arc<Mutex<Cache<DreamCoderOp>>>,
+  checkpoints: Option<&Checkpoints>,
+) -> anyhow::Result<Vec<BenchResults>> {
+  println!("corpus: {} ({params})", corpus.path.display());
 
   let mut benchmark_dirs = Vec::new();
-  for entry in fs::read_dir(benchmark_path)? {
+  for entry in fs::read_dir(&corpus.path)? {
     let path = entry?.path();
     if fs::metadata(&path)?.is_dir() {
       benchmark_dirs.push(path);
@@ -156,27 +456,73 @@ fn main() -> anyhow::Result<()> {
     println!("  {domain}: {} benchmark(s)", benchmarks.len());
   }
 
-  if let Some(domain) = &opts.domain {
-    run_domain(domain, &opts, &domains[domain.as_str()], &cache);
-  } else {
+  let mut results = Vec::new();
+  if corpus.domains.is_empty() {
     for (domain, benchmarks) in domains {
-      run_domain(domain, &opts, &benchmarks, &cache);
+      results.extend(run_domain(
+        domain,
+        corpus,
+        params,
+        autotune,
+        &benchmarks,
+        held_out,
+        budgets,
+        cache,
+        checkpoints,
+      )?);
+    }
+  } else {
+    for domain in &corpus.domains {
+      let benchmarks = domains
+        .get(domain.as_str())
+        .ok_or_else(|| anyhow::anyhow!("unknown domain: {domain}"))?;
+      results.extend(run_domain(
+        domain,
+        corpus,
+        params,
+        autotune,
+        benchmarks,
+        held_out,
+        budgets,
+        cache,
+        checkpoints,
+      )?);
     }
   }
-
-  Ok(())
+  Ok(results)
 }
 
+/// Runs every input file of `benchmarks` of `domain` with `params`, tuning
+/// the beam size of the beam search on each input if `autotune` is set. If
+/// `budgets` are given, the beam search is also run on each input with a
+/// timeout of DreamCoder's running time on it, if it has one.
+#[allow(clippy::too_many_arguments)]
 fn run_domain(
   domain: &str,
-  opts: &Opts,
+  corpus: &Corpus,
+  params: &Params,
+  autotune: bool,
   benchmarks: &[Benchmark<'_>],
-  _cache: &Mutex<Cache<DreamCoderOp>>,
-) {
-  let results = Mutex::new(Vec::new());
-
+  held_out: Option<&InternedCorpus<DreamCoderOp>>,
+  budgets: Option<&DreamCoderResults>,
+  cache: &Arc<Mutex<Cache<DreamCoderOp>>>,
+  checkpoints: Option<&Checkpoints>,
+) -> anyhow::Result<Vec<BenchResults>> {
   println!("domain: {domain}");
 
+  let checkpoint =
+    checkpoints.map(|checkpoints| checkpoints.path(corpus, params, domain));
+  let saved = match (checkpoints, &checkpoint) {
+    (Some(checkpoints), Some(path)) => checkpoints.load(path)?,
+    _ => Vec::new(),
+  };
+  let done: BTreeSet<(String, String)> =
+    saved.iter().map(|res| (res.benchmark.clone(), res.file.clone())).collect();
+  if !done.is_empty() {
+    println!("  continuing after {} completed file(s)", done.len());
+  }
+  let results = Mutex::new(saved);
+
   let dsr_file = PathBuf::from(DSR_PATH).join(format!("{domain}.rewrites"));
   let rewrites = rewrites::try_from_file(dsr_file).unwrap().unwrap_or_default();
 
@@ -198,51 +544,82 @@ fn run_domain(
     inputs.par_iter().for_each(|input| {
       let file = input.file_name().unwrap().to_str().unwrap();
 
+      if done.contains(&(benchmark.name.to_string(), file.to_string())) {
+        println!("    file: {file} (already done)");
+        return;
+      }
       println!("    file: {file}");
 
-      let input = fs::read_to_string(input).unwrap();
-      let input: CompressionInput = serde_json::from_str(&input).unwrap();
-
-      let program_groups: Vec<Vec<Expr<_>>> = input
-        .frontiers
-        .iter()
-        .cloned()
-        .map(|frontier| -> Vec<Expr<DreamCoderOp>> {
-          let programs =
-            frontier.programs.into_iter().map(|program| program.program.into());
-
-          if opts.use_all > 0 {
-            programs.collect()
-          } else {
-            programs.take(1).collect()
+      let contents = fs::read(input).unwrap();
+      let name = format!("{params}-{domain}_{}-{file}", benchmark.name);
+      let run = Run {
+        name: &name,
+        input: &(&contents, &corpus.format, params.use_all),
+        held_out,
+        cache,
+      };
+      let program_groups = || -> Box<dyn Iterator<Item = Vec<_>>> {
+        match corpus.format.as_str() {
+          "dreamcoder" => {
+            Box::new(dreamcoder_program_groups(input, params.use_all))
           }
-        })
-        .collect();
+          "sexp" => {
+            let input = std::str::from_utf8(&contents).unwrap();
+            Box::new(sexp_program_groups(input).into_iter())
+          }
+          f => panic!("bad format: {f}"),
+        }
+      };
 
-      let summary = if opts.mode == "eqsat" {
+      if program_groups().all(|group| group.is_empty()) {
+        println!("    file: {file} has no programs; skipping");
+        return;
+      }
+
+      let (summary, equal_time) = if params.mode == "eqsat" {
         let experiment =
           Rounds::new(1, EqsatExperiment::new(rewrites.clone(), ()));
-        experiment.run_multi_summary(program_groups)
+        (run.summary(&experiment, &program_groups), None)
       } else {
-        let use_dsrs = match opts.mode.as_str() {
+        let use_dsrs = match params.mode.as_str() {
           "babble" => true,
           "au" => false,
           m => panic!("bad mode: {m}"),
         };
-        let experiment = Rounds::new(
-          opts.rounds,
+        let beam_with_size = |beam_size: usize| {
           BeamExperiment::new(
             if use_dsrs { rewrites.clone() } else { vec![] },
-            opts.beam_size,
-            opts.beam_size,
-            opts.lps,
+            beam_size,
+            beam_size,
+            params.lps.min(beam_size),
             (),
             true,
-            Some(opts.max_arity),
-            opts.lib_iter_limit,
-          ),
-        );
-        experiment.run_multi_summary(program_groups)
+            Some(params.max_arity),
+            params.lib_iter_limit,
+          )
+          .with_au_cache(cache.clone())
+        };
+        let beam = || beam_with_size(params.beam_size);
+        let summary = if autotune {
+          let experiment = Autotune::new(|beam_size| {
+            Rounds::new(params.rounds, beam_with_size(beam_size))
+          });
+          run.summary(&experiment, &program_groups)
+        } else {
+          let experiment = Rounds::new(params.rounds, beam());
+          run.summary(&experiment, &program_groups)
+        };
+
+        let key = (format!("{domain}_{}", benchmark.name), file.to_string());
+        let equal_time = budgets.and_then(|dc| dc.get(&key)).map(|dc| {
+          let budget = Duration::from_secs_f64(dc.total_time);
+          let experiment =
+            Rounds::new(params.rounds, beam().with_timeout(budget))
+              .with_timeout(budget);
+          let summary = run.summary(&experiment, &program_groups);
+          EqualTime { budget, summary }
+        });
+        (summary, equal_time)
       };
 
       let name = format!("{domain}_{}/{file}", benchmark.name);
@@ -254,27 +631,169 @@ fn run_domain(
         summary.num_libs,
         summary.run_time.as_secs_f32(),
       );
+      if let Some(EqualTime { budget, summary }) = &equal_time {
+        println!(
+          "{name:20}        in {:>8.3}s: {} -> {} (r {:.3})",
+          budget.as_secs_f32(),
+          summary.initial_cost,
+          summary.final_cost,
+          util::compression_factor(summary.initial_cost, summary.final_cost),
+        );
+      }
+      if let Some(report) = &summary.autotune {
+        println!("{name:20}        autotuned beam size: {}", report.beam_size);
+      }
+      if let Some(report) = &summary.held_out {
+        println!(
+          "{name:20}        held out: {}/{} tasks covered, r {:.3} (transfer {:.3})",
+          report.covered,
+          report.tasks,
+          report.compression(),
+          summary.transfer().unwrap_or_default(),
+        );
+      }
 
       let bench_results = BenchResults {
         domain: domain.to_string(),
         benchmark: benchmark.name.to_string(),
         file: file.to_string(),
+        params: params.clone(),
         summary,
+        equal_time,
       };
 
       let mut locked = results.lock().unwrap();
       locked.push(bench_results);
+      if let Some(path) = &checkpoint {
+        if let Err(e) = Checkpoints::save(path, &locked) {
+          eprintln!("couldn't save results to {}: {e}", path.display());
+        }
+      }
     });
   });
 
-  let results = results.into_inner().unwrap();
-  plot_raw_data(&results, opts).unwrap();
+  Ok(results.into_inner().unwrap())
 }
 
+/// A run of an experiment on one input file, whose summary is cached.
+struct Run<'a, I: ?Sized> {
+  /// A readable name for the run, which prefixes its cache key.
+  name: &'a str,
+  /// Everything which determines the programs the experiment is run on.
+  input: &'a I,
+  /// The programs on which to evaluate the learned libraries, if any.
+  held_out: Option<&'a InternedCorpus<DreamCoderOp>>,
+  /// The cache of summaries.
+  cache: &'a Mutex<Cache<DreamCoderOp>>,
+}
+
+impl<I: Hash + ?Sized> Run<'_, I> {
+  /// Returns the summary of running `experiment` on the programs returned
+  /// by `program_groups`, from the cache if it has one. The cache key is
+  /// derived from the input, the held-out programs and the configuration of
+  /// `experiment`, so a cached summary is only reused if all of them are the
+  /// same. Experiments without a content key aren't cached at all.
+  fn summary<T, F>(
+    &self,
+    experiment: &T,
+    program_groups: F,
+  ) -> Summary<DreamCoderOp>
+  where
+    T: Experiment<DreamCoderOp>,
+    F: FnOnce() -> Box<dyn Iterator<Item = Vec<Expr<DreamCoderOp>>>>,
+  {
+    let run =
+      || summarize(experiment, &mut program_groups(), self.held_out).unwrap();
+    let key =
+      Cache::content_key(self.name, experiment, &(self.input, self.held_out));
+    let key = match key {
+      Some(key) => key,
+      None => return run(),
+    };
+    let cached = self.cache.lock().unwrap().get(&key).unwrap();
+    cached.unwrap_or_else(|| {
+      let summary = run();
+      self.cache.lock().unwrap().insert(key, &summary).unwrap();
+      summary
+    })
+  }
+}
+
+/// Runs `experiment` on `program_groups`, also evaluating the libraries it
+/// learns on `held_out` if given.
+fn summarize<T: Experiment<DreamCoderOp>>(
+  experiment: &T,
+  program_groups: &mut dyn Iterator<Item = Vec<Expr<DreamCoderOp>>>,
+  held_out: Option<&InternedCorpus<DreamCoderOp>>,
+) -> Result<Summary<DreamCoderOp>, Error> {
+  match held_out {
+    Some(held_out) => experiment.run_held_out_summary(
+      InternedCorpus::from_groups(program_groups),
+      held_out,
+    ),
+    None => experiment.run_streaming(program_groups),
+  }
+}
+
+/// Lazily reads the frontiers of the DreamCoder `CompressionInput` at `path`
+/// as groups of equivalent programs. With `use_all`, a group holds every
+/// program of its frontier rather than only the first; the programs of a
+/// group share an e-class, so a frontier only costs as much as its cheapest
+/// program.
+fn dreamcoder_program_groups(
+  path: &Path,
+  use_all: bool,
+) -> impl Iterator<Item = Vec<Expr<DreamCoderOp>>> {
+  let file = File::open(path).unwrap();
+
+  FrontierReader::new(file).map(move |frontier| {
+    let programs = frontier
+      .unwrap()
+      .programs
+      .into_iter()
+      .map(|program| program.program.into());
+
+    if use_all {
+      programs.collect()
+    } else {
+      programs.take(1).collect()
+    }
+  })
+}
+
+/// Reads a list of S-expressions, each of which is a group of its own.
+fn sexp_program_groups(input: &str) -> Vec<Vec<Expr<DreamCoderOp>>> {
+  Program::parse(input)
+    .expect("Failed to parse program")
+    .0
+    .into_iter()
+    .map(|sexp| {
+      vec![sexp.try_into().expect("Input is not a valid list of expressions")]
+    })
+    .collect()
+}
+
+/// Writes the results to a CSV file at `output`. If `with_params` is set,
+/// the parameters of each run are written too.
 #[allow(clippy::cast_precision_loss)]
-fn plot_raw_data(results: &[BenchResults], opts: &Opts) -> anyhow::Result<()> {
-  let mut csv_writer = csv::Writer::from_path(&opts.output)?;
-  csv_writer.serialize((
+fn plot_raw_data(
+  results: &[BenchResults],
+  output: &Path,
+  with_params: bool,
+) -> anyhow::Result<()> {
+  let mut csv_writer = csv::Writer::from_path(output)?;
+  let mut header = vec![];
+  if with_params {
+    header.extend([
+      "mode",
+      "beam size",
+      "lps",
+      "rounds",
+      "max arity",
+      "lib iter limit",
+    ]);
+  }
+  header.extend([
     "name",
     "iter",
     "initial cost",
@@ -282,24 +801,39 @@ fn plot_raw_data(results: &[BenchResults], opts: &Opts) -> anyhow::Result<()> {
     "compression",
     "total time",
     "num libs",
-  ))?;
+  ]);
+  csv_writer.write_record(header)?;
 
   for BenchResults {
     domain,
     benchmark,
     file,
+    params,
     summary: Summary { initial_cost, final_cost, num_libs, run_time, .. },
+    ..
   } in results
   {
-    csv_writer.serialize((
+    let mut record = vec![];
+    if with_params {
+      record.extend([
+        params.mode.clone(),
+        params.beam_size.to_string(),
+        params.lps.to_string(),
+        params.rounds.to_string(),
+        params.max_arity.to_string(),
+        params.lib_iter_limit.to_string(),
+      ]);
+    }
+    record.extend([
       format!("{domain}_{benchmark}"),
-      &file,
-      initial_cost,
-      final_cost,
-      util::compression_factor(*initial_cost, *final_cost),
-      run_time.as_secs_f32(),
-      num_libs,
-    ))?;
+      file.clone(),
+      initial_cost.to_string(),
+      final_cost.to_string(),
+      util::compression_factor(*initial_cost, *final_cost).to_string(),
+      run_time.as_secs_f32().to_string(),
+      num_libs.to_string(),
+    ]);
+    csv_writer.write_record(record)?;
   }
 
   csv_writer.flush()?;
@@ -0,0 +1,259 @@
+//! A self-contained HTML report of the benchmark results, with SVG charts of
+//! the initial and final costs of each run, the compression achieved on each
+//! domain, and the impact of DSRs.
+//!
+//! The charts are drawn with plotters and inlined into the HTML, so the
+//! report can be opened in a browser without any other files.
+
+use babble::util;
+use plotters::{coord::Shift, prelude::*};
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::Path};
+
+use super::{spec::Params, BenchResults};
+
+/// The size of each chart, in pixels.
+const CHART_SIZE: (u32, u32) = (900, 500);
+
+/// Writes the report of `results` to the HTML file at `path`.
+///
+/// # Errors
+/// Errors if a chart can't be drawn or the file can't be written.
+pub(crate) fn write_html(
+  results: &[BenchResults],
+  path: &Path,
+) -> anyhow::Result<()> {
+  let mut html = String::from(
+    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+     <title>babble benchmark report</title>\n<style>\n\
+     body { font-family: sans-serif; max-width: 960px; margin: 0 auto; }\n\
+     svg { display: block; margin: 1em 0; }\n\
+     </style>\n</head>\n<body>\n<h1>babble benchmark report</h1>\n",
+  );
+  writeln!(html, "<p>{} runs.</p>", results.len())?;
+
+  html.push_str("<h2>Costs</h2>\n");
+  html.push_str(&svg(|root| draw_costs(root, results))?);
+
+  html.push_str("<h2>Compression</h2>\n");
+  for (params, domains) in compression_by_domain(results) {
+    html.push_str(&svg(|root| draw_compression(root, &params, &domains))?);
+  }
+
+  html.push_str("<h2>Impact of DSRs</h2>\n");
+  let impact = dsr_impact(results);
+  if impact.is_empty() {
+    html.push_str(
+      "<p>No input was run both with DSRs (<code>babble</code> mode) and \
+       without them (<code>au</code> mode).</p>\n",
+    );
+  }
+  for (params, improvements) in impact {
+    html.push_str(&svg(|root| draw_dsr_impact(root, &params, &improvements))?);
+  }
+
+  html.push_str("</body>\n</html>\n");
+  fs::write(path, html)?;
+  Ok(())
+}
+
+/// Draws a chart with `draw` and returns it as an SVG document.
+fn svg<F>(draw: F) -> anyhow::Result<String>
+where
+  F: FnOnce(&DrawingArea<SVGBackend<'_>, Shift>) -> anyhow::Result<()>,
+{
+  let mut svg = String::new();
+  {
+    let root =
+      SVGBackend::with_string(&mut svg, CHART_SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    draw(&root)?;
+    root.present()?;
+  }
+  svg.push('\n');
+  Ok(svg)
+}
+
+/// The compression factor of `result`.
+fn compression(result: &BenchResults) -> f64 {
+  util::compression_factor(
+    result.summary.initial_cost,
+    result.summary.final_cost,
+  )
+}
+
+/// Plots the final cost of each run against its initial cost, with one
+/// series per set of parameters.
+#[allow(clippy::cast_precision_loss)]
+fn draw_costs(
+  root: &DrawingArea<SVGBackend<'_>, Shift>,
+  results: &[BenchResults],
+) -> anyhow::Result<()> {
+  let mut by_params: BTreeMap<&Params, Vec<(f64, f64)>> = BTreeMap::new();
+  for result in results {
+    let costs =
+      (result.summary.initial_cost as f64, result.summary.final_cost as f64);
+    by_params.entry(&result.params).or_default().push(costs);
+  }
+  let max = by_params
+    .values()
+    .flatten()
+    .map(|&(initial, _)| initial)
+    .fold(1.0, f64::max);
+
+  let mut chart = ChartBuilder::on(root)
+    .caption("Final cost against initial cost", ("sans-serif", 20))
+    .margin(10)
+    .x_label_area_size(40)
+    .y_label_area_size(60)
+    .build_cartesian_2d(0.0..max * 1.05, 0.0..max * 1.05)?;
+  chart.configure_mesh().x_desc("initial cost").y_desc("final cost").draw()?;
+
+  // Points below the diagonal were compressed.
+  chart.draw_series(LineSeries::new([(0.0, 0.0), (max, max)], &BLACK))?;
+  for (i, (params, points)) in by_params.into_iter().enumerate() {
+    let color = Palette99::pick(i).to_rgba();
+    chart
+      .draw_series(
+        points.into_iter().map(|point| Circle::new(point, 3, color.filled())),
+      )?
+      .label(params.to_string())
+      .legend(move |(x, y)| Circle::new((x, y), 3, color.filled()));
+  }
+  chart
+    .configure_series_labels()
+    .position(SeriesLabelPosition::UpperLeft)
+    .background_style(WHITE.mix(0.8))
+    .border_style(BLACK)
+    .draw()?;
+  Ok(())
+}
+
+/// The geometric mean compression of each domain, for each set of
+/// parameters.
+fn compression_by_domain(
+  results: &[BenchResults],
+) -> BTreeMap<Params, Vec<(String, f64)>> {
+  let mut factors: BTreeMap<(&Params, &str), Vec<f64>> = BTreeMap::new();
+  for result in results {
+    factors
+      .entry((&result.params, result.domain.as_str()))
+      .or_default()
+      .push(compression(result));
+  }
+  let mut by_params: BTreeMap<Params, Vec<(String, f64)>> = BTreeMap::new();
+  for ((params, domain), factors) in factors {
+    #[allow(clippy::cast_precision_loss)]
+    let geomean = (factors.iter().map(|factor| factor.ln()).sum::<f64>()
+      / factors.len() as f64)
+      .exp();
+    by_params
+      .entry(params.clone())
+      .or_default()
+      .push((domain.to_string(), geomean));
+  }
+  by_params
+}
+
+/// Draws a bar for the geometric mean compression of each domain.
+fn draw_compression(
+  root: &DrawingArea<SVGBackend<'_>, Shift>,
+  params: &Params,
+  domains: &[(String, f64)],
+) -> anyhow::Result<()> {
+  let max = domains.iter().map(|&(_, factor)| factor).fold(1.0, f64::max);
+  let mut chart = ChartBuilder::on(root)
+    .caption(format!("Compression by domain ({params})"), ("sans-serif", 20))
+    .margin(10)
+    .x_label_area_size(40)
+    .y_label_area_size(60)
+    .build_cartesian_2d((0..domains.len()).into_segmented(), 0.0..max * 1.1)?;
+  chart
+    .configure_mesh()
+    .disable_x_mesh()
+    .x_labels(domains.len())
+    .x_label_formatter(&|segment| match segment {
+      SegmentValue::Exact(i) | SegmentValue::CenterOf(i) => {
+        domains.get(*i).map_or_else(String::new, |(domain, _)| domain.clone())
+      }
+      SegmentValue::Last => String::new(),
+    })
+    .y_desc("geometric mean compression")
+    .draw()?;
+  chart.draw_series(
+    Histogram::vertical(&chart)
+      .style(BLUE.mix(0.6).filled())
+      .margin(10)
+      .data(domains.iter().enumerate().map(|(i, &(_, factor))| (i, factor))),
+  )?;
+  Ok(())
+}
+
+/// The percent improvement in compression of each input run with DSRs over
+/// the same input run without them, sorted, for each set of parameters of
+/// the runs with DSRs.
+fn dsr_impact(results: &[BenchResults]) -> BTreeMap<Params, Vec<f64>> {
+  let without_dsrs: BTreeMap<_, _> = results
+    .iter()
+    .filter(|result| result.params.mode == "au")
+    .map(|result| {
+      let key =
+        (&result.params, &result.domain, &result.benchmark, &result.file);
+      (key, compression(result))
+    })
+    .collect();
+
+  let mut impact: BTreeMap<Params, Vec<f64>> = BTreeMap::new();
+  for result in results.iter().filter(|result| result.params.mode == "babble") {
+    let params = Params { mode: "au".to_string(), ..result.params.clone() };
+    let key = (&params, &result.domain, &result.benchmark, &result.file);
+    if let Some(other) = without_dsrs.get(&key) {
+      let improvement = (compression(result) / other - 1.0) * 100.0;
+      impact.entry(result.params.clone()).or_default().push(improvement);
+    }
+  }
+  for improvements in impact.values_mut() {
+    improvements.sort_by(f64::total_cmp);
+  }
+  impact
+}
+
+/// Draws a bar for the improvement on each input, in increasing order.
+#[allow(clippy::cast_precision_loss)]
+fn draw_dsr_impact(
+  root: &DrawingArea<SVGBackend<'_>, Shift>,
+  params: &Params,
+  improvements: &[f64],
+) -> anyhow::Result<()> {
+  let min = improvements.iter().copied().fold(0.0, f64::min);
+  let max = improvements.iter().copied().fold(0.0, f64::max);
+  let margin = (max - min).max(1.0) * 0.05;
+  let mut chart = ChartBuilder::on(root)
+    .caption(format!("Impact of DSRs ({params})"), ("sans-serif", 20))
+    .margin(10)
+    .x_label_area_size(40)
+    .y_label_area_size(60)
+    .build_cartesian_2d(
+      0.0..improvements.len() as f64,
+      min - margin..max + margin,
+    )?;
+  chart
+    .configure_mesh()
+    .disable_x_mesh()
+    .x_desc("input")
+    .y_desc("percent improvement")
+    .draw()?;
+  chart.draw_series(improvements.iter().enumerate().map(
+    |(i, &improvement)| {
+      let x = i as f64;
+      Rectangle::new(
+        [(x + 0.1, 0.0), (x + 0.9, improvement)],
+        BLUE.mix(0.6).filled(),
+      )
+    },
+  ))?;
+  chart.draw_series(LineSeries::new(
+    [(0.0, 0.0), (improvements.len() as f64, 0.0)],
+    &BLACK,
+  ))?;
+  Ok(())
+}
@@ -0,0 +1,206 @@
+//! Experiment specs: grids of parameters to run the benchmarks with, read
+//! from a TOML file.
+//!
+//! A spec might look like this:
+//!
+//! ```toml
+//! modes = ["babble", "au"]
+//! beam_sizes = [100, 400]
+//! lps = [1, 5]
+//! rounds = [1, 10]
+//! max_arities = [3]
+//!
+//! [[corpora]]
+//! path = "harness/data/dreamcoder-benchmarks/benches"
+//! domains = ["list"]
+//!
+//! [[corpora]]
+//! path = "harness/data/cogsci"
+//! format = "sexp"
+//! ```
+//!
+//! Every combination of the listed parameters is run on every corpus.
+//! Combinations where `lps` is greater than the beam size are skipped, and
+//! `eqsat` mode, which does not learn libraries, is only run once.
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeSet,
+  fmt::{self, Display, Formatter},
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// The modes the benchmarks can be run in.
+pub(crate) const MODES: [&str; 3] = ["babble", "au", "eqsat"];
+
+/// The formats of the input files.
+pub(crate) const FORMATS: [&str; 2] = ["dreamcoder", "sexp"];
+
+/// A grid of parameters and the corpora to run them on.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct Spec {
+  pub(crate) corpora: Vec<Corpus>,
+  modes: Vec<String>,
+  beam_sizes: Vec<usize>,
+  lps: Vec<usize>,
+  rounds: Vec<usize>,
+  max_arities: Vec<usize>,
+  #[serde(default = "default_lib_iter_limits")]
+  lib_iter_limits: Vec<usize>,
+  #[serde(default)]
+  use_all: bool,
+}
+
+/// A directory of benchmarks, with one subdirectory per benchmark named
+/// `{domain}_{benchmark}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct Corpus {
+  pub(crate) path: PathBuf,
+  /// The format of the input files: either Dream&shy;Coder
+  /// `CompressionInput` JSON, or plain S-expressions.
+  #[serde(default = "default_format")]
+  pub(crate) format: String,
+  /// The domains to run, or all of them if empty.
+  #[serde(default)]
+  pub(crate) domains: Vec<String>,
+}
+
+/// The parameters of a single benchmark run.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub(crate) struct Params {
+  pub(crate) mode: String,
+  pub(crate) beam_size: usize,
+  pub(crate) lps: usize,
+  pub(crate) rounds: usize,
+  pub(crate) max_arity: usize,
+  pub(crate) lib_iter_limit: usize,
+  pub(crate) use_all: bool,
+}
+
+fn default_lib_iter_limits() -> Vec<usize> {
+  vec![3]
+}
+
+fn default_format() -> String {
+  "dreamcoder".to_string()
+}
+
+impl Spec {
+  /// Reads a spec from the TOML file at `path`.
+  ///
+  /// # Errors
+  /// Returns an error if the file can't be read or isn't a valid spec.
+  pub(crate) fn from_file(path: &Path) -> anyhow::Result<Self> {
+    Self::parse(&fs::read_to_string(path)?)
+  }
+
+  /// Parses a spec from the TOML `input`.
+  ///
+  /// # Errors
+  /// Returns an error if `input` isn't a valid spec.
+  fn parse(input: &str) -> anyhow::Result<Self> {
+    let spec: Self = toml::from_str(input)?;
+    for mode in &spec.modes {
+      if !MODES.contains(&mode.as_str()) {
+        bail!("bad mode: {mode}");
+      }
+    }
+    for corpus in &spec.corpora {
+      if !FORMATS.contains(&corpus.format.as_str()) {
+        bail!("bad format: {}", corpus.format);
+      }
+    }
+    Ok(spec)
+  }
+
+  /// Returns every combination of parameters in this spec, in order.
+  pub(crate) fn grid(&self) -> Vec<Params> {
+    let mut grid = BTreeSet::new();
+    for mode in &self.modes {
+      if mode == "eqsat" {
+        grid.insert(Params {
+          mode: mode.clone(),
+          beam_size: 0,
+          lps: 0,
+          rounds: 1,
+          max_arity: 0,
+          lib_iter_limit: 0,
+          use_all: self.use_all,
+        });
+        continue;
+      }
+      for &beam_size in &self.beam_sizes {
+        for &lps in self.lps.iter().filter(|&&lps| lps <= beam_size) {
+          for &rounds in &self.rounds {
+            for &max_arity in &self.max_arities {
+              for &lib_iter_limit in &self.lib_iter_limits {
+                grid.insert(Params {
+                  mode: mode.clone(),
+                  beam_size,
+                  lps,
+                  rounds,
+                  max_arity,
+                  lib_iter_limit,
+                  use_all: self.use_all,
+                });
+              }
+            }
+          }
+        }
+      }
+    }
+    grid.into_iter().collect()
+  }
+}
+
+/// Displays the parameters as a string which can be used as (part of) a
+/// file name, e.g. to identify the run in a cache.
+impl Display for Params {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}-b{}-l{}-r{}-a{}-i{}",
+      self.mode,
+      self.beam_size,
+      self.lps,
+      self.rounds,
+      self.max_arity,
+      self.lib_iter_limit
+    )?;
+    if self.use_all {
+      write!(f, "-all")?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Spec;
+
+  #[test]
+  fn parses_toml_specs() {
+    let input = r#"
+      modes = ["babble", "eqsat"]
+      beam_sizes = [1, 10]
+      lps = [1, 5]
+      rounds = [1]
+      max_arities = [3]
+
+      [[corpora]]
+      path = "benches"
+      domains = ["list"]
+    "#;
+    let spec = Spec::parse(input).unwrap();
+    assert_eq!(spec.corpora[0].format, "dreamcoder");
+    assert_eq!(spec.corpora[0].domains, ["list"]);
+    // lps 5 is skipped with beam size 1, and eqsat runs once.
+    assert_eq!(spec.grid().len(), 4);
+
+    assert!(Spec::parse(&input.replace("eqsat", "fast")).is_err());
+  }
+}
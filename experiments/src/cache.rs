@@ -1,12 +1,21 @@
 //! Provide a mechanism to cache the results of experiments.
 
-use super::Summary;
+use super::{Experiment, Summary};
+use anyhow::anyhow;
+use babble::{
+  extract::beam::PartialLibCost, Arity, AstNode, Printable, Teachable,
+};
+use egg::{Analysis, EGraph, Id, PatternAst, Var};
 use serde::{Deserialize, Serialize};
 use std::{
   collections::BTreeMap,
+  fmt::Debug,
   fs,
+  hash::{Hash, Hasher},
+  io,
   marker::PhantomData,
   path::{Path, PathBuf},
+  sync::Mutex,
 };
 use time::{
   format_description::well_known::{iso8601, Iso8601},
@@ -18,14 +27,100 @@ const ISO8601_CONFIG: iso8601::EncodedConfig =
   iso8601::Config::DEFAULT.set_use_separators(false).encode();
 const DATE_FORMAT: Iso8601<ISO8601_CONFIG> = Iso8601;
 
+/// The version of the semantics of cached results. Bump this whenever the
+/// experiments change in a way which makes existing results stale: entries
+/// written by other versions are ignored.
+///
+/// Changes to the layout of [`Summary`] don't make results stale, and are
+/// handled by bumping [`SUMMARY_VERSION`](crate::SUMMARY_VERSION) instead, so
+/// that existing entries are migrated when they are read.
+pub const CACHE_VERSION: u32 = 2;
+
 /// A cache of experiment results.
 #[derive(Clone, Debug)]
 pub struct Cache<Op> {
   path: PathBuf,
-  index: BTreeMap<String, PathBuf>,
+  index: BTreeMap<String, Entry>,
+  /// The total size of the cached results above which the least recently
+  /// used ones are evicted, if any.
+  max_size: Option<u64>,
+  /// A logical clock, advanced every time an entry is used.
+  clock: u64,
   phantom: PhantomData<Op>,
 }
 
+/// An entry in the index of a [`Cache`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+  /// The file containing the result.
+  file: PathBuf,
+  /// The size of the file in bytes.
+  size: u64,
+  /// The checksum of the file's contents.
+  checksum: u64,
+  /// The logical time at which the entry was last used.
+  last_used: u64,
+  /// The [`CACHE_VERSION`] of the cache which wrote the entry.
+  version: u32,
+}
+
+/// The index format of earlier versions of the cache, which only mapped
+/// experiments to files.
+type LegacyIndex = BTreeMap<String, PathBuf>;
+
+/// The result of [`Cache::verify`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+  /// The number of entries which were checked.
+  pub checked: usize,
+  /// The experiments whose results were written by a different
+  /// [`CACHE_VERSION`], or which could not be read as a [`Summary`].
+  pub incompatible: Vec<String>,
+  /// The experiments whose results are missing or do not match their
+  /// checksum.
+  pub corrupted: Vec<String>,
+}
+
+/// A hasher which gives the same results on every platform and with every
+/// version of Rust (64-bit FNV-1a), so that it can be used in cache keys.
+#[derive(Clone, Copy, Debug)]
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+  fn default() -> Self {
+    Self(0xcbf2_9ce4_8422_2325)
+  }
+}
+
+impl Hasher for StableHasher {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      self.0 ^= u64::from(byte);
+      self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+  }
+}
+
+/// Converts an error reading or writing a snapshot's format into an I/O
+/// error, which is what experiments report when a snapshot can't be used.
+fn invalid_data<E>(error: E) -> io::Error
+where
+  E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+  io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Computes the checksum of a cached file's contents.
+fn checksum(contents: &str) -> u64 {
+  let mut hasher = StableHasher::default();
+  hasher.write(contents.as_bytes());
+  hasher.finish()
+}
+
 // This lint gives false positives for higher-rank trait bounds.
 #[allow(single_use_lifetimes)]
 impl<Op> Cache<Op>
@@ -55,12 +150,34 @@ where
   pub fn from_dir(path: PathBuf) -> anyhow::Result<Self> {
     fs::create_dir_all(&path)?;
 
-    let mut cache = Self { path, index: BTreeMap::new(), phantom: PhantomData };
+    let mut cache = Self {
+      path,
+      index: BTreeMap::new(),
+      max_size: None,
+      clock: 0,
+      phantom: PhantomData,
+    };
 
     let index_file = cache.index_file();
     if index_file.exists() {
       let index_str = fs::read_to_string(&index_file)?;
-      cache.index = ron::from_str(&index_str)?;
+      cache.index = match ron::from_str(&index_str) {
+        Ok(index) => index,
+        Err(e) => {
+          // Entries of a legacy index are kept, but never returned.
+          let legacy: LegacyIndex = ron::from_str(&index_str).map_err(|_| e)?;
+          legacy
+            .into_iter()
+            .map(|(experiment, file)| {
+              let entry =
+                Entry { file, size: 0, checksum: 0, last_used: 0, version: 0 };
+              (experiment, entry)
+            })
+            .collect()
+        }
+      };
+      cache.clock =
+        cache.index.values().map(|entry| entry.last_used).max().unwrap_or(0);
     } else {
       cache.flush()?;
     };
@@ -68,6 +185,68 @@ where
     Ok(cache)
   }
 
+  /// Limit the total size of the cached results to `max_size` bytes. When it
+  /// is exceeded, the least recently used results are evicted. Snapshots
+  /// don't count toward the limit.
+  ///
+  /// # Errors
+  ///
+  /// Errors if there is a problem evicting results from the cache.
+  pub fn with_max_size(mut self, max_size: u64) -> anyhow::Result<Self> {
+    self.max_size = Some(max_size);
+    self.evict()?;
+    self.flush()?;
+    Ok(self)
+  }
+
+  /// Return a key identifying the experiment `name` run with `params`, for
+  /// use with the other methods of the cache. The key includes a hash of
+  /// `params` and of the [`CACHE_VERSION`], so results are not reused when
+  /// parameters which are not part of `name` change.
+  #[must_use]
+  pub fn key<P: Hash + ?Sized>(name: &str, params: &P) -> String {
+    let mut hasher = StableHasher::default();
+    CACHE_VERSION.hash(&mut hasher);
+    params.hash(&mut hasher);
+    format!("{name}-{:016x}", hasher.finish())
+  }
+
+  /// Return a key identifying a run of `experiment` on `input`, derived from
+  /// `input`, the configuration and DSRs of `experiment` (see
+  /// [`Experiment::fingerprint`]), and the [`CACHE_VERSION`]. Unlike keys
+  /// from [`Cache::key`], these change whenever anything which affects the
+  /// result does, so a hit is always valid; `name` only makes the key
+  /// readable. `input` should be the contents of the corpus rather than,
+  /// say, its path.
+  ///
+  /// Returns `None` if `experiment` can't be fingerprinted fully, in which
+  /// case its results shouldn't be cached.
+  #[must_use]
+  pub fn content_key<T, I>(
+    name: &str,
+    experiment: &T,
+    input: &I,
+  ) -> Option<String>
+  where
+    Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+    T: Experiment<Op> + ?Sized,
+    I: Hash + ?Sized,
+  {
+    let mut hasher = StableHasher::default();
+    CACHE_VERSION.hash(&mut hasher);
+    if !experiment.fingerprint(&mut hasher) {
+      return None;
+    }
+    input.hash(&mut hasher);
+    Some(format!("{name}-{:016x}", hasher.finish()))
+  }
+
+  /// The total size of the cached results, in bytes.
+  #[must_use]
+  pub fn size(&self) -> u64 {
+    self.index.values().map(|entry| entry.size).sum()
+  }
+
   /// Return the directory where the cache is stored.
   #[must_use]
   pub fn path(&self) -> &Path {
@@ -84,11 +263,50 @@ where
     Ok(())
   }
 
+  /// Evict the least recently used results until the cache is within its
+  /// size limit, if it has one.
+  fn evict(&mut self) -> anyhow::Result<()> {
+    let max_size = match self.max_size {
+      Some(max_size) => max_size,
+      None => return Ok(()),
+    };
+    let mut size = self.size();
+    while size > max_size {
+      let oldest = self
+        .index
+        .iter()
+        .min_by_key(|(_, entry)| entry.last_used)
+        .map(|(experiment, _)| experiment.clone());
+      match oldest {
+        Some(experiment) => size -= self.remove(&experiment)?,
+        None => break,
+      }
+    }
+    Ok(())
+  }
+
+  /// Remove the results of `experiment` from the index and delete their
+  /// file, returning their size.
+  fn remove(&mut self, experiment: &str) -> anyhow::Result<u64> {
+    match self.index.remove(experiment) {
+      Some(entry) => {
+        if entry.file.exists() {
+          fs::remove_file(&entry.file)?;
+        }
+        Ok(entry.size)
+      }
+      None => Ok(0),
+    }
+  }
+
   /// Return `true` if the cache contains results for `experiment`. If it does
   /// not, return `false`.
   #[must_use]
   pub fn contains(&self, experiment: &str) -> bool {
-    self.index.contains_key(experiment)
+    self
+      .index
+      .get(experiment)
+      .is_some_and(|entry| entry.version == CACHE_VERSION)
   }
 
   /// Add an experiment to the cache, identified by the string `name`.
@@ -105,24 +323,76 @@ where
     let experiment_file =
       self.path.join(format!("experiment-{}.ron", &experiment));
     let serialized_result = ron::to_string(&result)?;
-    fs::write(&experiment_file, serialized_result)?;
-    self.index.insert(experiment, experiment_file);
+    fs::write(&experiment_file, &serialized_result)?;
+    self.clock += 1;
+    let entry = Entry {
+      file: experiment_file,
+      size: serialized_result.len() as u64,
+      checksum: checksum(&serialized_result),
+      last_used: self.clock,
+      version: CACHE_VERSION,
+    };
+    self.index.insert(experiment, entry);
+    self.evict()?;
     self.flush()
   }
 
   /// Return the results of the given `experiment`. If the results have not
-  /// been cached, return `None`.
+  /// been cached, or were cached by a different [`CACHE_VERSION`], return
+  /// `None`.
   ///
   /// # Errors
   ///
-  /// Errors if the cache is malformed.
-  pub fn get(&self, experiment: &str) -> anyhow::Result<Option<Summary<Op>>> {
-    if let Some(file) = self.index.get(experiment) {
-      let experiment_str = fs::read_to_string(file)?;
-      Ok(Some(ron::from_str(&experiment_str)?))
-    } else {
-      Ok(None)
+  /// Errors if the cache is malformed, or if the results are corrupted.
+  pub fn get(
+    &mut self,
+    experiment: &str,
+  ) -> anyhow::Result<Option<Summary<Op>>> {
+    let entry = match self.index.get_mut(experiment) {
+      Some(entry) if entry.version == CACHE_VERSION => entry,
+      _ => return Ok(None),
+    };
+    let experiment_str = fs::read_to_string(&entry.file)?;
+    if checksum(&experiment_str) != entry.checksum {
+      anyhow::bail!("corrupted cache entry: {experiment}");
+    }
+    let summary = ron::from_str(&experiment_str)?;
+    self.clock += 1;
+    entry.last_used = self.clock;
+    self.flush()?;
+    Ok(Some(summary))
+  }
+
+  /// Check every entry of the cache, removing the ones which are corrupted
+  /// or were written by a different [`CACHE_VERSION`].
+  ///
+  /// # Errors
+  ///
+  /// Errors if there is a problem accessing the cache.
+  pub fn verify(&mut self) -> anyhow::Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    for (experiment, entry) in &self.index {
+      report.checked += 1;
+      let contents = match fs::read_to_string(&entry.file) {
+        Ok(contents) => contents,
+        Err(_) => {
+          report.corrupted.push(experiment.clone());
+          continue;
+        }
+      };
+      if entry.version != CACHE_VERSION {
+        report.incompatible.push(experiment.clone());
+      } else if checksum(&contents) != entry.checksum {
+        report.corrupted.push(experiment.clone());
+      } else if ron::from_str::<Summary<Op>>(&contents).is_err() {
+        report.incompatible.push(experiment.clone());
+      }
+    }
+    for experiment in report.corrupted.iter().chain(&report.incompatible) {
+      self.remove(experiment)?;
     }
+    self.flush()?;
+    Ok(report)
   }
 
   /// Return the results of the given `experiment`. If the results have not
@@ -146,3 +416,233 @@ where
     }
   }
 }
+
+/// The intermediate state of a [`BeamExperiment`], from which an interrupted
+/// experiment can be resumed.
+///
+/// [`BeamExperiment`]: crate::BeamExperiment
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+  serialize = "Op: Serialize",
+  deserialize = "Op: for<'b> Deserialize<'b>"
+))]
+pub struct Snapshot<Op>
+where
+  Op: Teachable + Arity + Clone + Send + Sync + Debug + Hash + Ord + 'static,
+{
+  /// The e-classes of the programs being compressed.
+  pub roots: Vec<Id>,
+  /// The egraph after running the domain-specific rewrites.
+  pub egraph: EGraph<AstNode<Op>, PartialLibCost>,
+  /// The library functions found by anti-unification, or `None` if the
+  /// experiment was interrupted before anti-unification finished.
+  pub libs: Option<LibSnapshot<Op>>,
+}
+
+/// The library functions found by anti-unification.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+  serialize = "Op: Serialize",
+  deserialize = "Op: for<'b> Deserialize<'b>"
+))]
+pub struct LibSnapshot<Op> {
+  /// The [`LibId`](babble::LibId) of the first library function.
+  pub lib_offset: usize,
+  /// The left- and right-hand sides of the library rewrites, and the variable
+  /// at which each recursive library function calls itself.
+  pub patterns: Vec<LibPatternAsts<Op>>,
+  /// The e-classes at which each left-hand side matches in the e-graph, if
+  /// they are known for all of them; see
+  /// [`LearnedLibrary::match_sites`](babble::LearnedLibrary::match_sites).
+  #[serde(default)]
+  pub sites: Option<Vec<Vec<Id>>>,
+}
+
+/// The left- and right-hand sides of a library rewrite, and the variable at
+/// which a recursive library function calls itself.
+pub type LibPatternAsts<Op> =
+  (PatternAst<AstNode<Op>>, PatternAst<AstNode<Op>>, Option<Var>);
+
+impl<Op: Hash> Cache<Op> {
+  /// Return a key identifying the results of running `name` on the programs
+  /// at `roots` of `egraph`, derived from the contents of `egraph`, the
+  /// parameters which `params` feeds to its hasher, and the
+  /// [`CACHE_VERSION`]. Anti-unification only depends on the e-graph and a
+  /// few of the settings of an experiment, so its results can be shared by
+  /// experiments which differ in everything else, such as their beam size.
+  #[must_use]
+  pub fn egraph_key<A, F>(
+    name: &str,
+    egraph: &EGraph<AstNode<Op>, A>,
+    roots: &[Id],
+    params: F,
+  ) -> String
+  where
+    A: Analysis<AstNode<Op>>,
+    F: FnOnce(&mut dyn Hasher),
+  {
+    let mut hasher = StableHasher::default();
+    CACHE_VERSION.hash(&mut hasher);
+    // The e-classes are stored in no particular order.
+    let mut classes: Vec<_> = egraph.classes().collect();
+    classes.sort_unstable_by_key(|class| class.id);
+    classes.len().hash(&mut hasher);
+    for class in classes {
+      (class.id, &class.nodes).hash(&mut hasher);
+    }
+    let roots: Vec<_> = roots.iter().map(|&root| egraph.find(root)).collect();
+    roots.hash(&mut hasher);
+    params(&mut hasher);
+    format!("{name}-{:016x}", hasher.finish())
+  }
+}
+
+// This lint gives false positives for higher-rank trait bounds.
+#[allow(single_use_lifetimes)]
+impl<Op> Cache<Op>
+where
+  Op: Teachable
+    + Arity
+    + Clone
+    + Send
+    + Sync
+    + Debug
+    + Hash
+    + Ord
+    + Serialize
+    + for<'b> Deserialize<'b>
+    + 'static,
+{
+  fn snapshot_file(&self, experiment: &str) -> PathBuf {
+    self.path.join(format!("snapshot-{experiment}.ron"))
+  }
+
+  /// Save a snapshot of the intermediate state of `experiment`, replacing
+  /// any previous snapshot.
+  ///
+  /// # Errors
+  ///
+  /// Errors if there is a problem accessing the cache.
+  pub fn insert_snapshot(
+    &mut self,
+    experiment: &str,
+    snapshot: &Snapshot<Op>,
+  ) -> io::Result<()> {
+    let serialized_snapshot = ron::to_string(snapshot).map_err(invalid_data)?;
+    fs::write(self.snapshot_file(experiment), serialized_snapshot)?;
+    Ok(())
+  }
+
+  /// Return the last snapshot of `experiment`. If no snapshot has been
+  /// saved, return `None`.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the snapshot is malformed.
+  pub fn get_snapshot(
+    &self,
+    experiment: &str,
+  ) -> io::Result<Option<Snapshot<Op>>> {
+    let file = self.snapshot_file(experiment);
+    if file.exists() {
+      let snapshot_str = fs::read_to_string(file)?;
+      Ok(Some(ron::from_str(&snapshot_str).map_err(invalid_data)?))
+    } else {
+      Ok(None)
+    }
+  }
+
+  fn libs_file(&self, key: &str) -> PathBuf {
+    self.path.join(format!("libs-{key}.ron"))
+  }
+
+  /// Save the library functions found by anti-unification under `key`, from
+  /// [`Cache::egraph_key`], replacing any saved before. Like snapshots, they
+  /// don't count toward the size limit.
+  ///
+  /// # Errors
+  ///
+  /// Errors if there is a problem accessing the cache.
+  pub fn insert_libs(
+    &mut self,
+    key: &str,
+    libs: &LibSnapshot<Op>,
+  ) -> anyhow::Result<()> {
+    let serialized_libs = ron::to_string(libs)?;
+    fs::write(self.libs_file(key), serialized_libs)?;
+    Ok(())
+  }
+
+  /// Return the library functions saved under `key`. If none have been
+  /// saved, return `None`.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the saved library functions are malformed.
+  pub fn get_libs(&self, key: &str) -> anyhow::Result<Option<LibSnapshot<Op>>> {
+    let file = self.libs_file(key);
+    if file.exists() {
+      let libs_str = fs::read_to_string(file)?;
+      Ok(Some(ron::from_str(&libs_str)?))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Delete the snapshot of `experiment`, if there is one.
+  ///
+  /// # Errors
+  ///
+  /// Errors if there is a problem accessing the cache.
+  pub fn remove_snapshot(&mut self, experiment: &str) -> io::Result<()> {
+    let file = self.snapshot_file(experiment);
+    if file.exists() {
+      fs::remove_file(file)?;
+    }
+    Ok(())
+  }
+}
+
+/// The memoized results of anti-unification, keyed by [`Cache::egraph_key`].
+/// This lets a [`BeamExperiment`](crate::BeamExperiment), whose language
+/// needn't be serializable, use a cache which can store its results.
+pub(crate) trait LibMemo<Op>: Debug + Send + Sync {
+  /// The library functions saved under `key`, if any.
+  fn get_libs(&self, key: &str) -> anyhow::Result<Option<LibSnapshot<Op>>>;
+
+  /// Saves `libs` under `key`.
+  fn insert_libs(
+    &self,
+    key: &str,
+    libs: &LibSnapshot<Op>,
+  ) -> anyhow::Result<()>;
+}
+
+// This lint gives false positives for higher-rank trait bounds.
+#[allow(single_use_lifetimes)]
+impl<Op> LibMemo<Op> for Mutex<Cache<Op>>
+where
+  Op: Teachable
+    + Arity
+    + Clone
+    + Send
+    + Sync
+    + Debug
+    + Hash
+    + Ord
+    + Serialize
+    + for<'b> Deserialize<'b>
+    + 'static,
+{
+  fn get_libs(&self, key: &str) -> anyhow::Result<Option<LibSnapshot<Op>>> {
+    self.lock().map_err(|e| anyhow!("{e}"))?.get_libs(key)
+  }
+
+  fn insert_libs(
+    &self,
+    key: &str,
+    libs: &LibSnapshot<Op>,
+  ) -> anyhow::Result<()> {
+    self.lock().map_err(|e| anyhow!("{e}"))?.insert_libs(key, libs)
+  }
+}
@@ -0,0 +1,356 @@
+#![warn(
+  clippy::all,
+  clippy::pedantic,
+  anonymous_parameters,
+  elided_lifetimes_in_paths,
+  missing_copy_implementations,
+  missing_debug_implementations,
+  single_use_lifetimes,
+  trivial_casts,
+  unreachable_pub,
+  unused_lifetimes
+)]
+#![allow(clippy::non_ascii_literal)]
+
+//! Learns a library from a file of programs and prints the learned libraries
+//! and the programs rewritten to use them.
+
+use babble::{
+  extract::mdl::Objective, progress::StderrProgress, rewrites, sexp::Program,
+  util, Arity, DiscriminantEq, Expr, LibNames, PrettyOptions, Printable,
+  Teachable,
+};
+use babble_experiments::{
+  dreamcoder::expr::DreamCoderOp, plumbing, BeamExperiment, CsvWriter,
+  Experiment, LibReport, Library,
+};
+use clap::Parser;
+use egg::RecExpr;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  fmt::{Debug, Display},
+  fs,
+  hash::Hash,
+  io::{self, Read},
+  path::PathBuf,
+  str::FromStr,
+  sync::Arc,
+};
+
+#[allow(dead_code)]
+#[path = "../list/lang.rs"]
+mod list;
+
+#[derive(Parser)]
+#[clap(version, author, about)]
+struct Opts {
+  /// The file of programs, as S-expressions. If no file is specified, reads
+  /// from stdin.
+  #[clap(parse(from_os_str))]
+  file: Option<PathBuf>,
+
+  /// The language of the programs.
+  #[clap(long, value_parser = ["dreamcoder", "list"], default_value = "dreamcoder")]
+  lang: String,
+
+  /// A second file of programs. If given, only the libraries common to both
+  /// files are learned, and the rewritten programs of this file are printed
+  /// after those of the first.
+  #[clap(long, parse(from_os_str))]
+  common_with: Option<PathBuf>,
+
+  /// A file of domain-specific rewrites.
+  #[clap(long, parse(from_os_str))]
+  dsrs: Option<PathBuf>,
+
+  /// The beam size to use for the beam extractor.
+  #[clap(long, default_value_t = 400)]
+  beam_size: usize,
+
+  /// The intermediate beam size. Defaults to the beam size.
+  #[clap(long)]
+  inter_beam_size: Option<usize>,
+
+  /// The number of libs to learn at a time.
+  #[clap(long, default_value_t = 1)]
+  lps: usize,
+
+  /// Maximum arity of functions to learn.
+  #[clap(long)]
+  max_arity: Option<usize>,
+
+  /// Minimum number of nodes in the body of functions to learn, not
+  /// counting their arguments.
+  #[clap(long)]
+  min_body_size: Option<usize>,
+
+  /// Maximum number of nodes in the body of functions to learn, not
+  /// counting their arguments.
+  #[clap(long)]
+  max_body_size: Option<usize>,
+
+  /// Whether to learn "library functions" with no arguments.
+  #[clap(long)]
+  learn_constants: bool,
+
+  /// The number of times to apply library rewrites.
+  #[clap(long, default_value_t = 1)]
+  lib_iter_limit: usize,
+
+  /// The maximum number of e-nodes while applying library rewrites.
+  #[clap(long, default_value_t = 1_000_000)]
+  node_limit: usize,
+
+  /// If the node limit is reached, start over with only this many of the
+  /// library rewrites, picking the ones estimated to save the most nodes.
+  #[clap(long)]
+  node_limit_retry: Option<usize>,
+
+  /// After extraction, inline the learned functions which are used fewer
+  /// than this many times, as long as that doesn't make the programs larger.
+  #[clap(long)]
+  inline_below: Option<usize>,
+
+  /// Only anti-unify this many pairs of e-classes, picking the ones
+  /// estimated to match most often. Speeds up learning on very large inputs
+  /// at the cost of missing some libraries.
+  #[clap(long)]
+  candidate_budget: Option<usize>,
+
+  /// Run the beam search this many times, breaking ties between equally
+  /// cheap lib selections differently each time, and keep the best result.
+  #[clap(long, default_value_t = 1)]
+  restarts: usize,
+
+  /// The seed from which the tie-breaking of each restart is derived.
+  #[clap(long, default_value_t = 0)]
+  seed: u64,
+
+  /// Extract the programs with this many of the cheapest lib selections
+  /// found by the beam search, and keep the best result.
+  #[clap(long, default_value_t = 1)]
+  portfolio: usize,
+
+  /// Keep the result of the portfolio with the shortest description length
+  /// under a grammar re-estimated from its programs, like DreamCoder, rather
+  /// than the smallest one. The portfolio then has at least 8 candidates.
+  #[clap(long)]
+  mdl: bool,
+
+  /// Write the final egraph to this file in the JSON format of
+  /// `egraph-serialize`, for visualization.
+  #[clap(long, parse(from_os_str))]
+  dump_egraph: Option<PathBuf>,
+
+  /// Print telemetry about the beam search: how often it crossed, combined,
+  /// unified and pruned cost sets, the time spent doing so, and how large
+  /// they got.
+  #[clap(long)]
+  beam_stats: bool,
+
+  /// Refer to the learned libraries by descriptive names derived from their
+  /// definitions, such as `map_filter_2`, instead of by their ids.
+  #[clap(long)]
+  name_libs: bool,
+
+  /// Show a progress bar for each phase on stderr.
+  #[clap(long)]
+  progress: bool,
+
+  /// A file of libraries saved by a previous run, to start from.
+  #[clap(long, parse(from_os_str))]
+  library: Option<PathBuf>,
+
+  /// Save the learned libraries to this file.
+  #[clap(long, parse(from_os_str))]
+  save_library: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+  env_logger::init();
+  let opts: Opts = Opts::parse();
+
+  let input = opts.file.as_ref().map_or_else(
+    || {
+      let mut buf = String::new();
+      io::stdin().read_to_string(&mut buf).map(|_| buf)
+    },
+    fs::read_to_string,
+  )?;
+
+  match opts.lang.as_str() {
+    "dreamcoder" => compress::<DreamCoderOp>(&input, &opts),
+    "list" => compress::<list::ListOp>(&input, &opts),
+    _ => unreachable!(),
+  }
+}
+
+/// Runs a single beam experiment on the programs in `input` and prints the
+/// results.
+fn compress<Op>(input: &str, opts: &Opts) -> anyhow::Result<()>
+where
+  Op: FromStr
+    + Serialize
+    + DeserializeOwned
+    + Arity
+    + Teachable
+    + Printable
+    + Debug
+    + Display
+    + Hash
+    + Clone
+    + Ord
+    + Send
+    + Sync
+    + DiscriminantEq
+    + 'static,
+  <Op as FromStr>::Err: Send + Sync + std::error::Error + 'static,
+{
+  let exprs: Vec<Expr<Op>> = parse_programs(input)?;
+  let common_with: Option<Vec<Expr<Op>>> = opts
+    .common_with
+    .as_ref()
+    .map(|path| parse_programs(&fs::read_to_string(path)?))
+    .transpose()?;
+
+  let dsrs = match &opts.dsrs {
+    Some(path) => rewrites::from_file(path)?,
+    None => Vec::new(),
+  };
+
+  let mut experiment = BeamExperiment::new(
+    dsrs,
+    opts.beam_size,
+    opts.inter_beam_size.unwrap_or(opts.beam_size),
+    opts.lps,
+    (),
+    opts.learn_constants,
+    opts.max_arity,
+    opts.lib_iter_limit,
+  )
+  .with_node_limit(opts.node_limit)
+  .with_node_limit_retry(opts.node_limit_retry)
+  .with_candidate_budget(opts.candidate_budget)
+  .with_body_size(opts.min_body_size, opts.max_body_size)
+  .with_restarts(opts.restarts, opts.seed)
+  .with_portfolio(opts.portfolio)
+  .with_beam_stats(opts.beam_stats);
+  if let Some(min_uses) = opts.inline_below {
+    experiment = experiment.with_inline_rare_libs(min_uses);
+  }
+  if opts.mdl {
+    experiment = experiment.with_objective(Objective::Mdl);
+  }
+  if let Some(path) = &opts.dump_egraph {
+    experiment = experiment.with_dump_egraph(path);
+  }
+  if opts.progress {
+    experiment = experiment.with_progress(Arc::new(StderrProgress::new()));
+  }
+  if let Some(path) = &opts.library {
+    experiment = experiment.with_initial_library(Library::load(path)?);
+  }
+
+  // Add one to account for the root node.
+  let initial_cost = exprs
+    .iter()
+    .chain(common_with.iter().flatten())
+    .map(Expr::len)
+    .sum::<usize>()
+    + 1;
+  let mut writer = CsvWriter::from_writer(Box::new(io::sink()));
+  let res = match common_with {
+    Some(other) => experiment.run_common(exprs, other)?,
+    None => experiment.run(exprs, &mut writer)?,
+  };
+  let final_cost = res.final_expr.len();
+  if let Some(path) = &opts.save_library {
+    res.library().save(path)?;
+  }
+
+  let names = if opts.name_libs {
+    LibNames::from_expr(&res.final_expr)
+  } else {
+    LibNames::default()
+  };
+  println!("Learned libraries:");
+  for lib in LibReport::from_expr_with_names(&res.final_expr, &names) {
+    println!("{} = {}", lib.name, lib.definition);
+  }
+  println!();
+
+  println!("Rewritten programs:");
+  let rc: RecExpr<_> = res.final_expr.into();
+  for expr in plumbing::exprs(rc.as_ref()) {
+    println!(
+      "{}",
+      expr.pretty_with(PrettyOptions::default()).with_lib_names(&names)
+    );
+  }
+  println!();
+
+  println!(
+    "cost: {initial_cost} -> {final_cost} (compression factor {})",
+    util::compression_factor(initial_cost, final_cost)
+  );
+
+  if !res.restarts.is_empty() {
+    println!();
+    println!("Restarts:");
+    for (i, restart) in res.restarts.iter().enumerate() {
+      println!(
+        "{i}: cost {} with {} libs in {}ms",
+        restart.final_cost,
+        restart.num_libs,
+        restart.time_elapsed.as_millis()
+      );
+    }
+  }
+
+  if let Some(stats) = &res.beam_stats {
+    println!();
+    println!("Beam search:");
+    println!(
+      "cross: {} calls, {}ms",
+      stats.crosses,
+      stats.cross_time.as_millis()
+    );
+    println!(
+      "combine: {} calls, {}ms",
+      stats.combines,
+      stats.combine_time.as_millis()
+    );
+    println!(
+      "unify: {} calls, {}ms",
+      stats.unifies,
+      stats.unify_time.as_millis()
+    );
+    println!(
+      "prune: {} calls, {}ms",
+      stats.prunes,
+      stats.prune_time.as_millis()
+    );
+    println!(
+      "largest cost set: {} lib selections (over {} e-classes)",
+      stats.max_set_size(),
+      stats.max_set_sizes.len()
+    );
+  }
+
+  Ok(())
+}
+
+/// Parses the programs in `input`, one per top-level S-expression.
+fn parse_programs<Op>(input: &str) -> anyhow::Result<Vec<Expr<Op>>>
+where
+  Op: FromStr + Arity + Debug + Send + Sync + 'static,
+  <Op as FromStr>::Err: Send + Sync + std::error::Error + 'static,
+{
+  Ok(
+    Program::parse(input)?
+      .0
+      .into_iter()
+      .map(Expr::try_from)
+      .collect::<Result<_, _>>()?,
+  )
+}
@@ -14,7 +14,10 @@
 
 use babble::{combine_exprs, Expr, Pretty};
 use babble_experiments::{
-  dreamcoder::{expr::DreamCoderOp, json::CompressionInput},
+  dreamcoder::{
+    expr::DreamCoderOp,
+    json::{self, CompressionInput},
+  },
   Experiments,
 };
 use clap::Parser;
@@ -50,6 +53,11 @@ struct Opts {
   #[clap(long)]
   no_dsr: bool,
 
+  /// Expand the invented primitives (`#(...)`) of the input into library
+  /// functions, instead of treating them as opaque primitives.
+  #[clap(long)]
+  expand_inventions: bool,
+
   /// The number of programs to anti-unify
   #[clap(long)]
   limit: Vec<usize>,
@@ -97,6 +105,11 @@ fn main() {
       .map(|program| program.program.into())
       .take(limit)
       .collect();
+    let exprs = if opts.expand_inventions {
+      json::expand_inventions(exprs)
+    } else {
+      exprs
+    };
 
     // For the sake of pretty printing
     {
@@ -134,5 +147,5 @@ fn main() {
   }
 
   println!("running...");
-  all.run("harness/data_gen/res_compression.csv");
+  all.run("harness/data_gen/res_compression.csv").unwrap();
 }
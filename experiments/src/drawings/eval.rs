@@ -134,7 +134,9 @@ impl<'a> Context<'a> {
         let theta = self.eval(th)?.to_float()?;
         Ok(Value::Num(theta.tan()))
       }
-      (&Drawing::Lib(name), [bound_value, body]) => {
+      // Lambdas are evaluated in the context they're applied in, so the
+      // libs of a recursive group are bound by the time they're called.
+      (&(Drawing::Lib(name) | Drawing::LibRec(name)), [bound_value, body]) => {
         let bound_value = self.eval(bound_value)?;
         let context = self.clone().with_lib(name, bound_value);
 
@@ -19,10 +19,14 @@ pub(crate) enum Drawing {
   Var(DeBruijnIndex),
   /// Create an anonymous, de Bruijn-indexed function.
   Lambda,
+  /// Create a recursive function as a fixpoint.
+  Fix,
   /// A reference to a lib fn
   LibVar(LibId),
   /// Bind a lib fn within an expression.
   Lib(LibId),
+  /// Bind a lib fn in a mutually recursive group within an expression.
+  LibRec(LibId),
   /// Apply a function to an argument.
   Apply,
   /// A top-level list of programs.
@@ -79,9 +83,15 @@ impl Arity for Drawing {
       | Self::Circle
       | Self::Line
       | Self::Square => 0,
-      Self::Lambda | Self::List | Self::Sin | Self::Cos | Self::Tan => 1,
+      Self::Lambda
+      | Self::Fix
+      | Self::List
+      | Self::Sin
+      | Self::Cos
+      | Self::Tan => 1,
       Self::Apply
       | Self::Lib(_)
+      | Self::LibRec(_)
       | Self::Add
       | Self::Sub
       | Self::Mul
@@ -109,8 +119,10 @@ impl Display for Drawing {
     match self {
       Self::Var(i) => write!(f, "{i}"),
       Self::Lambda => f.write_str("λ"),
+      Self::Fix => f.write_str("fix"),
       Self::LibVar(ix) => write!(f, "{ix}"),
       Self::Lib(ix) => write!(f, "lib-{ix}"),
+      Self::LibRec(ix) => write!(f, "librec-{ix}"),
       Self::Apply => f.write_str("@"),
       Self::List => f.write_str(":"),
       Self::Pi => f.write_str("π"),
@@ -143,6 +155,7 @@ impl FromStr for Drawing {
   fn from_str(s: &str) -> Result<Self, Self::Err> {
     let kind = match s {
       "lambda" | "λ" => Self::Lambda,
+      "fix" => Self::Fix,
       "apply" | "@" => Self::Apply,
       "pi" | "π" => Self::Pi,
       "+" => Self::Add,
@@ -175,6 +188,12 @@ impl FromStr for Drawing {
           .and_then(str::parse)
         {
           Self::Lib(lv)
+        } else if let Ok(lv) = s
+          .strip_prefix("librec-")
+          .ok_or(ParseLibIdError::NoLeadingL)
+          .and_then(str::parse)
+        {
+          Self::LibRec(lv)
         } else if let Ok(f) = s.parse::<NotNan<f64>>() {
           Self::Float(f)
         } else {
@@ -190,23 +209,31 @@ impl Teachable for Drawing {
   fn from_binding_expr<T>(binding_expr: BindingExpr<T>) -> AstNode<Self, T> {
     match binding_expr {
       BindingExpr::Lambda(body) => AstNode::new(Self::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(Self::Fix, [body]),
       BindingExpr::Apply(fun, arg) => AstNode::new(Self::Apply, [fun, arg]),
       BindingExpr::Var(index) => AstNode::leaf(Self::Var(index)),
       BindingExpr::LibVar(ix) => AstNode::leaf(Self::LibVar(ix)),
       BindingExpr::Lib(ix, bound_value, body) => {
         AstNode::new(Self::Lib(ix), [bound_value, body])
       }
+      BindingExpr::LibRec(ix, bound_value, body) => {
+        AstNode::new(Self::LibRec(ix), [bound_value, body])
+      }
     }
   }
 
   fn as_binding_expr<T>(node: &AstNode<Self, T>) -> Option<BindingExpr<&T>> {
     let binding_expr = match node.as_parts() {
       (Self::Lambda, [body]) => BindingExpr::Lambda(body),
+      (Self::Fix, [body]) => BindingExpr::Fix(body),
       (Self::Apply, [fun, arg]) => BindingExpr::Apply(fun, arg),
       (&Self::Var(index), []) => BindingExpr::Var(index),
       (Self::Lib(ix), [bound_value, body]) => {
         BindingExpr::Lib(*ix, bound_value, body)
       }
+      (Self::LibRec(ix), [bound_value, body]) => {
+        BindingExpr::LibRec(*ix, bound_value, body)
+      }
       (Self::LibVar(ix), []) => BindingExpr::LibVar(*ix),
       _ => return None,
     };
@@ -243,7 +270,7 @@ impl Printable for Drawing {
       Self::Pow => 35,
       Self::Mul | Self::Div => 30,
       Self::Add | Self::Sub => 20,
-      Self::Lambda | Self::Lib(_) => 10,
+      Self::Lambda | Self::Fix | Self::Lib(_) | Self::LibRec(_) => 10,
     }
   }
 
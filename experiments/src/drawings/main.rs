@@ -261,7 +261,7 @@ fn main() {
     );
 
     println!("running...");
-    exps.run(&opts.output);
+    exps.run(&opts.output).unwrap();
   }
 }
 
@@ -108,6 +108,11 @@ pub enum DreamCoderOp {
   /// An anonymous function.
   Lambda,
 
+  /// A fixpoint, used for recursive library functions. This is not part of
+  /// DreamCoder's language, so it is written `μ` rather than `fix`, which
+  /// is a DreamCoder primitive.
+  Fix,
+
   /// An application of a function to a variable. Dream&shy;Coder allows
   /// applying a function to multiple arguments, we translate these to nested
   /// applications. That is, `(foo bar baz quux)`, is interpreted as
@@ -115,6 +120,8 @@ pub enum DreamCoderOp {
   App,
 
   Lib(LibId),
+  /// A binding of a library function in a mutually recursive group.
+  LibRec(LibId),
   LibVar(LibId),
 
   /// A utility operation that allows us to do extraction taking into account
@@ -163,8 +170,8 @@ impl Arity for DreamCoderOp {
       | DreamCoderOp::Symbol(_)
       | DreamCoderOp::Inlined(_)
       | DreamCoderOp::LibVar(_) => 0,
-      DreamCoderOp::Lambda | DreamCoderOp::Combine => 1,
-      DreamCoderOp::App | DreamCoderOp::Lib(_) => 2,
+      DreamCoderOp::Lambda | DreamCoderOp::Fix | DreamCoderOp::Combine => 1,
+      DreamCoderOp::App | DreamCoderOp::Lib(_) | DreamCoderOp::LibRec(_) => 2,
     }
   }
 
@@ -183,6 +190,7 @@ impl FromStr for DreamCoderOp {
     let op = match input {
       "apply" | "@" => Self::App,
       "lambda" | "λ" => Self::Lambda,
+      "μ" => Self::Fix,
       input => input
         .parse()
         .map(Self::Var)
@@ -194,6 +202,12 @@ impl FromStr for DreamCoderOp {
             .ok_or(ParseLibIdError::NoLeadingL)
             .and_then(|x| x.parse().map(Self::Lib))
         })
+        .or_else(|_| {
+          input
+            .strip_prefix("librec ")
+            .ok_or(ParseLibIdError::NoLeadingL)
+            .and_then(|x| x.parse().map(Self::LibRec))
+        })
         .unwrap_or_else(|_| Self::Symbol(input.into())),
     };
 
@@ -208,12 +222,16 @@ impl Teachable for DreamCoderOp {
         AstNode::leaf(DreamCoderOp::Var(index))
       }
       BindingExpr::Lambda(body) => AstNode::new(DreamCoderOp::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(DreamCoderOp::Fix, [body]),
       BindingExpr::Apply(fun, arg) => {
         AstNode::new(DreamCoderOp::App, [fun, arg])
       }
       BindingExpr::Lib(ix, def, body) => {
         AstNode::new(DreamCoderOp::Lib(ix), [def, body])
       }
+      BindingExpr::LibRec(ix, def, body) => {
+        AstNode::new(DreamCoderOp::LibRec(ix), [def, body])
+      }
       BindingExpr::LibVar(ix) => AstNode::leaf(DreamCoderOp::LibVar(ix)),
     }
   }
@@ -222,8 +240,12 @@ impl Teachable for DreamCoderOp {
     let binding_expr = match node.as_parts() {
       (DreamCoderOp::Var(index), []) => BindingExpr::Var(DeBruijnIndex(*index)),
       (DreamCoderOp::Lambda, [body]) => BindingExpr::Lambda(body),
+      (DreamCoderOp::Fix, [body]) => BindingExpr::Fix(body),
       (DreamCoderOp::App, [fun, arg]) => BindingExpr::Apply(fun, arg),
       (DreamCoderOp::Lib(ix), [def, body]) => BindingExpr::Lib(*ix, def, body),
+      (DreamCoderOp::LibRec(ix), [def, body]) => {
+        BindingExpr::LibRec(*ix, def, body)
+      }
       (DreamCoderOp::LibVar(ix), []) => BindingExpr::LibVar(*ix),
       _ => return None,
     };
@@ -239,8 +261,10 @@ impl Display for DreamCoderOp {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
     let s = match self {
       DreamCoderOp::Lambda => "lambda",
+      DreamCoderOp::Fix => "μ",
       DreamCoderOp::App => "@",
       DreamCoderOp::Lib(ix) => return write!(f, "lib {ix}"),
+      DreamCoderOp::LibRec(ix) => return write!(f, "librec {ix}"),
       DreamCoderOp::LibVar(ix) => return write!(f, "{ix}"),
       DreamCoderOp::Var(index) => return write!(f, "${index}"),
       DreamCoderOp::Inlined(expr) => {
@@ -259,7 +283,7 @@ impl Printable for DreamCoderOp {
       Self::Symbol(_) | Self::Var(_) | Self::LibVar(_) | Self::Inlined(_) => 60,
       Self::Combine => 50,
       Self::App => 40,
-      Self::Lambda | Self::Lib(_) => 10,
+      Self::Lambda | Self::Fix | Self::Lib(_) | Self::LibRec(_) => 10,
     }
   }
 
@@ -288,8 +312,14 @@ impl Printable for DreamCoderOp {
   }
 }
 
+/// Prints the expression in Dream&shy;Coder's syntax, which
+/// [`parse_dc_expr`] parses back into the same expression.
 impl Display for DcExpr {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    // At the top level, applications are parenthesized.
+    if f.precision().is_none() {
+      return write!(f, "{self:.1}");
+    }
     let node: &AstNode<_, _> = self.as_ref();
     match node.as_parts() {
       (DreamCoderOp::Symbol(name), []) => write!(f, "{name}"),
@@ -321,21 +351,63 @@ impl Display for DcExpr {
 #[derive(Debug, Clone)]
 pub struct ParseExprError {
   message: String,
+  offset: usize,
+  line: usize,
+  column: usize,
+}
+
+impl ParseExprError {
+  /// The byte offset in the input at which parsing failed.
+  #[must_use]
+  pub fn offset(&self) -> usize {
+    self.offset
+  }
+
+  /// The line, starting at 1, at which parsing failed.
+  #[must_use]
+  pub fn line(&self) -> usize {
+    self.line
+  }
+
+  /// The column, in characters and starting at 1, at which parsing failed.
+  #[must_use]
+  pub fn column(&self) -> usize {
+    self.column
+  }
 }
 
 impl Display for ParseExprError {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-    f.write_str(&self.message)
+    write!(f, "at line {}, column {}: {}", self.line, self.column, self.message)
   }
 }
 
+impl std::error::Error for ParseExprError {}
+
+/// Parses a program in Dream&shy;Coder's syntax, such as
+/// `(lambda (map #(lambda (+ $0 1)) $0))`.
+///
+/// # Errors
+///
+/// Returns an error, with the position at which parsing failed, if `s` is not
+/// a valid program.
+pub fn parse_dc_expr(s: &str) -> Result<DcExpr, ParseExprError> {
+  parse::parse(s).map(DcExpr).map_err(|e| {
+    // The first error is the innermost one, which is the most precise.
+    let offset = e.errors.first().map_or(0, |(rest, _)| s.len() - rest.len());
+    let before = &s[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    ParseExprError { message: convert_error(s, e), offset, line, column }
+  })
+}
+
 impl FromStr for DcExpr {
   type Err = ParseExprError;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    parse::parse(s)
-      .map(DcExpr)
-      .map_err(|e| ParseExprError { message: convert_error(s, e) })
+    parse_dc_expr(s)
   }
 }
 
@@ -349,7 +421,7 @@ impl DiscriminantEq for DreamCoderOp {
 mod tests {
   use crate::AstNode;
 
-  use super::{DcExpr, DreamCoderOp};
+  use super::{parse_dc_expr, DcExpr, DreamCoderOp};
 
   impl DcExpr {
     fn lambda(body: Self) -> Self {
@@ -392,4 +464,56 @@ mod tests {
     assert_eq!(parsed, expr);
     assert_eq!(expr.to_string(), input);
   }
+
+  #[test]
+  fn strings() {
+    let input = r#"(lambda (concat "a (b) #(c) $0" "\"q\" \\" $0))"#;
+    let expr = DcExpr::lambda(DcExpr::app(
+      DcExpr::app(
+        DcExpr::app(
+          DcExpr::symbol("concat"),
+          DcExpr::symbol(r#""a (b) #(c) $0""#),
+        ),
+        DcExpr::symbol(r#""\"q\" \\""#),
+      ),
+      DcExpr::var(0),
+    ));
+
+    assert_eq!(parse_dc_expr(input).unwrap(), expr);
+    assert_eq!(expr.to_string(), input);
+  }
+
+  #[test]
+  fn error_position() {
+    let err = parse_dc_expr("(lambda\n  (map $x))").unwrap_err();
+    assert_eq!((err.line(), err.column()), (2, 9));
+  }
+
+  /// Generates a random expression of depth at most `depth` from `state`.
+  fn random_expr(state: &mut u64, depth: usize) -> DcExpr {
+    *state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+    let choice = if depth == 0 { *state >> 62 } else { *state >> 61 };
+    match choice {
+      0 => DcExpr::var((*state >> 40) as usize % 3),
+      1 => {
+        DcExpr::symbol(["map", "+", "1", "empty?"][(*state >> 40) as usize % 4])
+      }
+      2 => DcExpr::symbol(r#""s \" (t)""#),
+      3 => DcExpr::inlined(DcExpr::lambda(DcExpr::var(0))),
+      4 | 5 => DcExpr::lambda(random_expr(state, depth - 1)),
+      _ => {
+        let fun = random_expr(state, depth - 1);
+        DcExpr::app(fun, random_expr(state, depth - 1))
+      }
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut state = 0;
+    for _ in 0..1000 {
+      let expr = random_expr(&mut state, 5);
+      assert_eq!(parse_dc_expr(&expr.to_string()).unwrap(), expr);
+    }
+  }
 }
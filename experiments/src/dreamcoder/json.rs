@@ -1,7 +1,23 @@
 //! The JSON interface to Dream&shy;Coder.
 
-use super::{expr::DcExpr, types::Type};
-use serde::{Deserialize, Serialize};
+use super::{
+  expr::{DcExpr, DreamCoderOp},
+  types::Type,
+};
+use crate::{plumbing, Library};
+use babble::{extract::cost::CostModel, AstNode, Expr, LibId};
+use egg::RecExpr;
+use serde::{
+  de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+  Deserialize, Deserializer, Serialize,
+};
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt::{self, Formatter},
+  io::{BufReader, Read},
+  sync::mpsc::{self, Receiver, SyncSender},
+  thread,
+};
 
 /// The input format of the `compression` tool.
 #[allow(missing_docs)]
@@ -25,6 +41,117 @@ pub struct CompressionInput {
   pub frontiers: Vec<Frontier>,
 }
 
+/// The number of parsed frontiers a [`FrontierReader`] buffers ahead of its
+/// consumer.
+const FRONTIER_BUFFER: usize = 64;
+
+/// An iterator over the frontiers of a [`CompressionInput`], which parses
+/// them lazily from a reader instead of deserializing the whole input at
+/// once.
+///
+/// Parsing happens on a background thread, which stays at most a few
+/// frontiers ahead of the consumer, so only those frontiers are held in
+/// memory. The grammar and any other fields of the input are skipped.
+#[derive(Debug)]
+pub struct FrontierReader {
+  frontiers: Receiver<serde_json::Result<Frontier>>,
+}
+
+impl FrontierReader {
+  /// Starts reading the frontiers of the [`CompressionInput`] JSON in
+  /// `reader`.
+  #[must_use]
+  pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+    let (sender, frontiers) = mpsc::sync_channel(FRONTIER_BUFFER);
+    thread::spawn(move || {
+      let mut deserializer =
+        serde_json::Deserializer::from_reader(BufReader::new(reader));
+      let result = InputSeed(&sender)
+        .deserialize(&mut deserializer)
+        .and_then(|()| deserializer.end());
+      if let Err(e) = result {
+        // The receiver may have been dropped, in which case nobody cares.
+        let _ = sender.send(Err(e));
+      }
+    });
+    Self { frontiers }
+  }
+}
+
+/// Yields each frontier as it is parsed, or an error if the input is
+/// malformed, after which the iterator ends.
+impl Iterator for FrontierReader {
+  type Item = serde_json::Result<Frontier>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.frontiers.recv().ok()
+  }
+}
+
+/// Deserializes a [`CompressionInput`], sending its frontiers to a channel.
+struct InputSeed<'a>(&'a SyncSender<serde_json::Result<Frontier>>);
+
+impl<'de> DeserializeSeed<'de> for InputSeed<'_> {
+  type Value = ();
+
+  fn deserialize<D: Deserializer<'de>>(
+    self,
+    deserializer: D,
+  ) -> Result<(), D::Error> {
+    deserializer.deserialize_map(self)
+  }
+}
+
+impl<'de> Visitor<'de> for InputSeed<'_> {
+  type Value = ();
+
+  fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "a compression input")
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+    while let Some(key) = map.next_key::<String>()? {
+      if key == "frontiers" {
+        map.next_value_seed(FrontiersSeed(self.0))?;
+      } else {
+        map.next_value::<IgnoredAny>()?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Deserializes a list of frontiers, sending each to a channel.
+struct FrontiersSeed<'a>(&'a SyncSender<serde_json::Result<Frontier>>);
+
+impl<'de> DeserializeSeed<'de> for FrontiersSeed<'_> {
+  type Value = ();
+
+  fn deserialize<D: Deserializer<'de>>(
+    self,
+    deserializer: D,
+  ) -> Result<(), D::Error> {
+    deserializer.deserialize_seq(self)
+  }
+}
+
+impl<'de> Visitor<'de> for FrontiersSeed<'_> {
+  type Value = ();
+
+  fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "a list of frontiers")
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+    while let Some(frontier) = seq.next_element()? {
+      if self.0.send(Ok(frontier)).is_err() {
+        return Err(de::Error::custom("frontier reader dropped"));
+      }
+    }
+    Ok(())
+  }
+}
+
 /// The output format of the `compression` tool.
 #[allow(missing_docs)]
 #[derive(
@@ -36,6 +163,186 @@ pub struct CompressionOutput {
   pub frontiers: Vec<Frontier>,
 }
 
+impl CompressionOutput {
+  /// Creates the output of a compression run from its `input` and the
+  /// result `expr` of running library learning on the input's frontiers.
+  ///
+  /// Every learned library function is converted into a Dream&shy;Coder
+  /// invented primitive (`#(...)`) and added to the input's grammar, and
+  /// every use of a library function in the rewritten programs is replaced
+  /// by the corresponding invented primitive. Library functions which were
+  /// expanded from the input's invented primitives by [`expand_inventions`]
+  /// and left unchanged are folded back into the same invented primitives,
+  /// which the grammar already has.
+  ///
+  /// If `expr` contains one program per frontier (as produced by
+  /// [`Experiment::run_multi`](crate::Experiment::run_multi)), each frontier
+  /// is replaced by its rewritten program. Otherwise, the programs are
+  /// matched up in order with the programs of the input's frontiers.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `expr` contains neither one program per frontier nor one
+  /// program per program of the input.
+  #[must_use]
+  pub fn from_expr(input: &CompressionInput, expr: Expr<DreamCoderOp>) -> Self {
+    let rec_expr: RecExpr<AstNode<DreamCoderOp>> = expr.into();
+    let libs = plumbing::libs(rec_expr.as_ref());
+    let programs = plumbing::exprs(rec_expr.as_ref());
+
+    let mut inventions = BTreeMap::new();
+    for &lib in libs.keys() {
+      invent(lib, &libs, &mut inventions);
+    }
+
+    let known: HashSet<&Expr<DreamCoderOp>> = input
+      .dsl
+      .productions
+      .iter()
+      .map(|production| &*production.expression)
+      .collect();
+    let new_inventions: Vec<_> = inventions
+      .values()
+      .filter(|invention| !known.contains(invention))
+      .map(|invention| Production {
+        log_probability: 0.0,
+        expression: invention.clone().into(),
+      })
+      .collect();
+    let mut dsl = input.dsl.clone();
+    dsl.productions.extend(new_inventions);
+
+    let num_programs: usize =
+      input.frontiers.iter().map(|frontier| frontier.programs.len()).sum();
+    let mut programs = programs.into_iter().map(|program| {
+      DcExpr::from(inline_libs(program, &mut |lib| inventions[&lib].clone()))
+    });
+
+    let frontiers = if programs.len() == input.frontiers.len() {
+      input
+        .frontiers
+        .iter()
+        .zip(programs)
+        .map(|(frontier, program)| {
+          let log_likelihood = frontier
+            .programs
+            .iter()
+            .map(|program| program.log_likelihood)
+            .fold(f64::NEG_INFINITY, f64::max);
+          Frontier {
+            task: frontier.task.clone(),
+            request: frontier.request.clone(),
+            programs: vec![Program { log_likelihood, program }],
+          }
+        })
+        .collect()
+    } else {
+      assert_eq!(
+        programs.len(),
+        num_programs,
+        "expected one program per frontier or one program per input program"
+      );
+      input
+        .frontiers
+        .iter()
+        .map(|frontier| Frontier {
+          task: frontier.task.clone(),
+          request: frontier.request.clone(),
+          programs: frontier
+            .programs
+            .iter()
+            .map(|program| Program {
+              log_likelihood: program.log_likelihood,
+              program: programs.next().unwrap(),
+            })
+            .collect(),
+        })
+        .collect()
+    };
+
+    Self { dsl, frontiers }
+  }
+}
+
+/// Expands the invented primitives (`#(...)`) of `programs` into library
+/// functions, so that library learning can refine, merge, and reuse them
+/// instead of treating them as opaque primitives. Each distinct invented
+/// primitive is replaced by a reference to a library function, and every
+/// program is wrapped in `lib` bindings of the library functions it uses.
+/// Invented primitives used in the definitions of other invented primitives
+/// are expanded too.
+///
+/// [`CompressionOutput::from_expr`] folds the library functions back into
+/// invented primitives.
+#[must_use]
+pub fn expand_inventions(
+  programs: Vec<Expr<DreamCoderOp>>,
+) -> Vec<Expr<DreamCoderOp>> {
+  let mut names = HashMap::new();
+  let mut defs = BTreeMap::new();
+  let programs: Vec<_> = programs
+    .into_iter()
+    .map(|program| expand(program, &mut names, &mut defs))
+    .collect();
+  let library = Library::from_defs(defs);
+  programs.into_iter().map(|program| library.bind(program)).collect()
+}
+
+/// Replaces every invented primitive in `expr` by a reference to a library
+/// function, naming it in `names` and adding its expanded definition to
+/// `defs` if it hasn't been seen before.
+fn expand(
+  expr: Expr<DreamCoderOp>,
+  names: &mut HashMap<Expr<DreamCoderOp>, LibId>,
+  defs: &mut BTreeMap<LibId, Expr<DreamCoderOp>>,
+) -> Expr<DreamCoderOp> {
+  if let DreamCoderOp::Inlined(body) = expr.0.operation() {
+    let lib = if let Some(&lib) = names.get(&**body) {
+      lib
+    } else {
+      let def = expand((**body).clone(), names, defs);
+      let lib = LibId(names.len());
+      names.insert((**body).clone(), lib);
+      defs.insert(lib, def);
+      lib
+    };
+    return AstNode::leaf(DreamCoderOp::LibVar(lib)).into();
+  }
+  Expr(expr.0.map(|arg| expand(arg, names, defs)))
+}
+
+/// Returns the invented primitive corresponding to the library function
+/// `lib`, converting it and any library functions it uses if they haven't
+/// been converted already.
+fn invent(
+  lib: LibId,
+  libs: &HashMap<LibId, Vec<AstNode<DreamCoderOp>>>,
+  inventions: &mut BTreeMap<LibId, Expr<DreamCoderOp>>,
+) -> Expr<DreamCoderOp> {
+  if let Some(invention) = inventions.get(&lib) {
+    return invention.clone();
+  }
+
+  let body: Expr<_> = RecExpr::from(libs[&lib].clone()).into();
+  let body = inline_libs(body, &mut |lib| invent(lib, libs, inventions));
+  let invention: Expr<_> =
+    AstNode::leaf(DreamCoderOp::Inlined(Box::new(body))).into();
+  inventions.insert(lib, invention.clone());
+  invention
+}
+
+/// Replaces every reference to a library function in `expr` by the result of
+/// applying `f` to it.
+fn inline_libs<F>(expr: Expr<DreamCoderOp>, f: &mut F) -> Expr<DreamCoderOp>
+where
+  F: FnMut(LibId) -> Expr<DreamCoderOp>,
+{
+  if let DreamCoderOp::LibVar(lib) = expr.0.operation() {
+    return f(*lib);
+  }
+  Expr(expr.0.map(|arg| inline_libs(arg, f)))
+}
+
 /// The primitives and learned functions for the language.
 #[allow(missing_docs)]
 #[derive(
@@ -47,6 +354,122 @@ pub struct Grammar {
   pub productions: Vec<Production>,
 }
 
+impl Grammar {
+  /// Parses a Dream&shy;Coder grammar from JSON, in the format of the `DSL`
+  /// field of a [`CompressionInput`].
+  ///
+  /// # Errors
+  /// Returns an error if `json` is not a valid grammar.
+  pub fn from_json(json: &str) -> serde_json::Result<Self> {
+    serde_json::from_str(json)
+  }
+
+  /// Returns a cost model under which the cost of each primitive and
+  /// variable is its negative log-likelihood under this grammar. See
+  /// [`GrammarCost`].
+  #[must_use]
+  pub fn cost_model(&self) -> GrammarCost {
+    GrammarCost::new(self)
+  }
+}
+
+/// A cost model which matches Dream&shy;Coder's description length: the cost
+/// of a primitive, invented primitive, or variable is its negative
+/// log-likelihood under a [`Grammar`], and applications and abstractions
+/// cost nothing. Minimizing the cost of a program therefore maximizes its
+/// likelihood.
+///
+/// Production log-probabilities are normalized over all the productions of
+/// the grammar and variables, ignoring types. Library functions and symbols
+/// which are not in the grammar are treated as productions with a
+/// log-probability of 0, like new inventions in
+/// [`CompressionOutput::from_expr`].
+///
+/// Costs are integers, so negative log-likelihoods are measured in units of
+/// `1 / precision` nats and rounded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarCost {
+  /// The negative log-likelihood of each production, indexed by its
+  /// operation.
+  productions: HashMap<DreamCoderOp, f64>,
+  /// The negative log-likelihood of a variable.
+  variable: f64,
+  /// The negative log-likelihood of a production which is not in the grammar.
+  unknown: f64,
+  /// The number of cost units per nat.
+  precision: f64,
+}
+
+impl GrammarCost {
+  /// Creates a cost model from `grammar`, measuring costs in tenths of a nat.
+  #[must_use]
+  pub fn new(grammar: &Grammar) -> Self {
+    let log_weights = grammar
+      .productions
+      .iter()
+      .map(|production| production.log_probability)
+      .chain([grammar.log_variable]);
+    let max = log_weights.clone().fold(f64::NEG_INFINITY, f64::max);
+    let log_total =
+      max + log_weights.map(|weight| (weight - max).exp()).sum::<f64>().ln();
+
+    let productions = grammar
+      .productions
+      .iter()
+      .filter_map(|production| {
+        let expr: &Expr<DreamCoderOp> = &production.expression;
+        expr.0.is_empty().then(|| {
+          let op = expr.0.operation().clone();
+          (op, log_total - production.log_probability)
+        })
+      })
+      .collect();
+
+    Self {
+      productions,
+      variable: log_total - grammar.log_variable,
+      unknown: log_total,
+      precision: 10.0,
+    }
+  }
+
+  /// Measures costs in units of `1 / precision` nats.
+  ///
+  /// # Panics
+  /// Panics if `precision` is not positive.
+  #[must_use]
+  pub fn with_precision(mut self, precision: f64) -> Self {
+    assert!(precision > 0.0, "expected a positive precision");
+    self.precision = precision;
+    self
+  }
+
+  /// The negative log-likelihood of `op`, in nats.
+  #[must_use]
+  pub fn log_likelihood_cost(&self, op: &DreamCoderOp) -> f64 {
+    match op {
+      DreamCoderOp::Var(_) => self.variable,
+      DreamCoderOp::Symbol(_) | DreamCoderOp::Inlined(_) => {
+        self.productions.get(op).copied().unwrap_or(self.unknown)
+      }
+      DreamCoderOp::LibVar(_) => self.unknown,
+      DreamCoderOp::Lambda
+      | DreamCoderOp::Fix
+      | DreamCoderOp::App
+      | DreamCoderOp::Lib(_)
+      | DreamCoderOp::LibRec(_)
+      | DreamCoderOp::Combine => 0.0,
+    }
+  }
+}
+
+impl CostModel<DreamCoderOp> for GrammarCost {
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn node_cost(&self, op: &DreamCoderOp) -> usize {
+    (self.log_likelihood_cost(op) * self.precision).round().max(0.0) as usize
+  }
+}
+
 /// A primitive or learned function.
 #[allow(missing_docs)]
 #[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
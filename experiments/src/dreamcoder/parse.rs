@@ -5,12 +5,12 @@ use std::str::FromStr;
 
 use nom::{
   branch::alt,
-  bytes::complete::{tag, take_till1},
-  character::complete::{char, digit1, multispace0, multispace1},
-  combinator::{all_consuming, cut, flat_map, map, map_res},
+  bytes::complete::{escaped, is_not, tag, take_till1},
+  character::complete::{anychar, char, digit1, multispace0, multispace1},
+  combinator::{all_consuming, cut, flat_map, map, map_res, opt, recognize},
   error::{context, VerboseError},
   multi::fold_many1,
-  sequence::{delimited, preceded},
+  sequence::{delimited, preceded, terminated},
   Finish, IResult, Parser,
 };
 
@@ -56,6 +56,22 @@ fn symbol(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
   )(s)
 }
 
+/// A string literal, which may contain whitespace, parentheses, and
+/// backslash escapes. It is kept as a symbol, quotes and escapes included, so
+/// that it is printed exactly as it was written.
+fn string(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
+  context(
+    "string",
+    map(
+      recognize(preceded(
+        char('"'),
+        cut(terminated(opt(escaped(is_not("\\\""), '\\', anychar)), char('"'))),
+      )),
+      |string| AstNode::leaf(DreamCoderOp::Symbol(Symbol::from(string))).into(),
+    ),
+  )(s)
+}
+
 fn inlined(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
   context(
     "inlined",
@@ -75,6 +91,16 @@ fn lambda(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
   )(s)
 }
 
+fn fix(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
+  context(
+    "fix",
+    map(
+      parenthesized(preceded(tag("μ"), preceded(multispace1, cut(expr)))),
+      |body| AstNode::new(DreamCoderOp::Fix, [body]).into(),
+    ),
+  )(s)
+}
+
 fn app(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
   context(
     "app",
@@ -89,9 +115,11 @@ fn app(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
 }
 
 fn expr(s: &str) -> ParseResult<'_, Expr<DreamCoderOp>> {
-  alt((var, inlined, symbol, lambda, app))(s)
+  alt((var, inlined, string, symbol, lambda, fix, app))(s)
 }
 
 pub(crate) fn parse(s: &str) -> Result<Expr<DreamCoderOp>, VerboseError<&str>> {
-  all_consuming(expr)(s).finish().map(|(_, e)| e)
+  all_consuming(delimited(multispace0, expr, multispace0))(s)
+    .finish()
+    .map(|(_, e)| e)
 }
@@ -1,10 +1,16 @@
 use std::{
+  cell::RefCell,
+  collections::BTreeMap,
   fmt::{self, Debug, Display, Formatter},
   hash::Hash,
+  rc::Rc,
   time::{Duration, Instant},
 };
 
-use egg::{AstSize, CostFunction, EGraph, Id, RecExpr, Rewrite, Runner};
+use egg::{
+  Analysis, AstSize, BackoffScheduler, CostFunction, EGraph, Id, Language,
+  RecExpr, Rewrite, RewriteScheduler, Runner, SearchMatches, SimpleScheduler,
+};
 use itertools::Itertools;
 use log::debug;
 use serde::ser::Serialize;
@@ -14,10 +20,118 @@ use babble::{
     beam::{LibExtractor, PartialLibCost},
     lift_libs,
   },
-  Arity, AstNode, Expr, Pretty, Printable, Teachable,
+  Arity, AstNode, Error, Expr, Pretty, Printable, Teachable,
+};
+
+use super::{
+  CsvWriter, Experiment, ExperimentResult, Metadata, RoundReport, RuleStats,
+  SaturationReport,
 };
 
-use super::{CsvWriter, Experiment, ExperimentResult};
+/// How equality saturation schedules the rewrites in each iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleScheduler {
+  /// Apply every rewrite to all of its matches in every iteration.
+  Simple,
+  /// Temporarily ban rewrites which match too often, as egg's
+  /// [`BackoffScheduler`] does: a rewrite with more than `match_limit`
+  /// matches is banned for `ban_length` iterations, and both double each
+  /// time it is banned.
+  Backoff { match_limit: usize, ban_length: usize },
+}
+
+impl RuleScheduler {
+  /// Runs `rewrites` with `runner`, scheduled by this scheduler, returning
+  /// the finished runner along with statistics about each rewrite.
+  pub(crate) fn run<L, N>(
+    self,
+    runner: Runner<L, N, ()>,
+    rewrites: &[Rewrite<L, N>],
+  ) -> (Runner<L, N, ()>, BTreeMap<String, RuleStats>)
+  where
+    L: Language,
+    N: Analysis<L>,
+  {
+    let stats = Rc::new(RefCell::new(BTreeMap::new()));
+    let runner = match self {
+      Self::Simple => {
+        runner.with_scheduler(RecordStats::new(SimpleScheduler, stats.clone()))
+      }
+      Self::Backoff { match_limit, ban_length } => {
+        runner.with_scheduler(RecordStats::new(
+          BackoffScheduler::default()
+            .with_initial_match_limit(match_limit)
+            .with_ban_length(ban_length),
+          stats.clone(),
+        ))
+      }
+    }
+    .run(rewrites);
+    let stats = stats.take();
+    (runner, stats)
+  }
+}
+
+impl Default for RuleScheduler {
+  /// egg's default scheduler.
+  fn default() -> Self {
+    Self::Backoff { match_limit: 1_000, ban_length: 5 }
+  }
+}
+
+/// A scheduler which records the [`RuleStats`] of the rewrites scheduled by
+/// another scheduler. Matches are only counted when the inner scheduler
+/// searches for them, so banned rewrites don't count.
+struct RecordStats<S> {
+  inner: S,
+  stats: Rc<RefCell<BTreeMap<String, RuleStats>>>,
+}
+
+impl<S> RecordStats<S> {
+  fn new(inner: S, stats: Rc<RefCell<BTreeMap<String, RuleStats>>>) -> Self {
+    Self { inner, stats }
+  }
+}
+
+impl<L, N, S> RewriteScheduler<L, N> for RecordStats<S>
+where
+  L: Language,
+  N: Analysis<L>,
+  S: RewriteScheduler<L, N>,
+{
+  fn can_stop(&mut self, iteration: usize) -> bool {
+    self.inner.can_stop(iteration)
+  }
+
+  fn search_rewrite<'a>(
+    &mut self,
+    iteration: usize,
+    egraph: &EGraph<L, N>,
+    rewrite: &'a Rewrite<L, N>,
+  ) -> Vec<SearchMatches<'a, L>> {
+    let matches = self.inner.search_rewrite(iteration, egraph, rewrite);
+    let found: usize = matches.iter().map(|m| m.substs.len()).sum();
+    let mut stats = self.stats.borrow_mut();
+    stats.entry(rewrite.name.to_string()).or_default().matches += found;
+    matches
+  }
+
+  fn apply_rewrite(
+    &mut self,
+    iteration: usize,
+    egraph: &mut EGraph<L, N>,
+    rewrite: &Rewrite<L, N>,
+    matches: Vec<SearchMatches<'_, L>>,
+  ) -> usize {
+    let size = egraph.total_size();
+    let applied = self.inner.apply_rewrite(iteration, egraph, rewrite, matches);
+    let mut stats = self.stats.borrow_mut();
+    let stats = stats.entry(rewrite.name.to_string()).or_default();
+    stats.applications += applied;
+    stats.nodes_added += egraph.total_size().saturating_sub(size);
+    applied
+  }
+}
 
 /// A `BeamExperiment` contains all of the information needed to run a
 /// library learning experiment with the beam extractor.
@@ -30,6 +144,14 @@ where
   dsrs: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
   /// Any extra data associated with this experiment
   extra_data: Extra,
+  /// The maximum number of iterations of equality saturation.
+  iter_limit: usize,
+  /// The maximum number of e-nodes in the e-graph.
+  node_limit: usize,
+  /// The maximum time equality saturation may take.
+  time_limit: Duration,
+  /// How the rewrites are scheduled.
+  scheduler: RuleScheduler,
 }
 
 impl<Op, Extra> EqsatExperiment<Op, Extra>
@@ -50,24 +172,69 @@ where
   where
     I: IntoIterator<Item = Rewrite<AstNode<Op>, PartialLibCost>>,
   {
-    Self { dsrs: dsrs.into_iter().collect(), extra_data }
+    Self {
+      dsrs: dsrs.into_iter().collect(),
+      extra_data,
+      iter_limit: 30,
+      node_limit: 10_000,
+      time_limit: Duration::from_secs(60 * 100_000),
+      scheduler: RuleScheduler::default(),
+    }
+  }
+
+  /// Sets the maximum number of iterations of equality saturation. By
+  /// default, this is 30.
+  #[must_use]
+  pub fn with_iter_limit(mut self, iter_limit: usize) -> Self {
+    self.iter_limit = iter_limit;
+    self
+  }
+
+  /// Sets the maximum number of e-nodes in the e-graph. By default, this is
+  /// 10,000.
+  #[must_use]
+  pub fn with_node_limit(mut self, node_limit: usize) -> Self {
+    self.node_limit = node_limit;
+    self
+  }
+
+  /// Sets the maximum time equality saturation may take. By default, there
+  /// is practically no limit.
+  #[must_use]
+  pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+    self.time_limit = time_limit;
+    self
+  }
+
+  /// Sets how the rewrites are scheduled. By default, egg's backoff
+  /// scheduler is used.
+  #[must_use]
+  pub fn with_scheduler(mut self, scheduler: RuleScheduler) -> Self {
+    self.scheduler = scheduler;
+    self
   }
 
   fn run_egraph(
     &self,
     roots: &[Id],
     egraph: EGraph<AstNode<Op>, PartialLibCost>,
-  ) -> ExperimentResult<Op> {
+  ) -> Result<ExperimentResult<Op>, Error> {
+    if roots.is_empty() {
+      return Err(Error::NoPrograms);
+    }
     let start_time = Instant::now();
-    let timeout = Duration::from_secs(60 * 100_000);
 
     debug!("Running {} DSRs... ", self.dsrs.len());
 
     let runner = Runner::<_, _, ()>::new(PartialLibCost::empty())
       .with_egraph(egraph)
-      .with_time_limit(timeout)
-      .run(&self.dsrs);
+      .with_iter_limit(self.iter_limit)
+      .with_node_limit(self.node_limit)
+      .with_time_limit(self.time_limit);
+    let (runner, rule_stats) = self.scheduler.run(runner, &self.dsrs);
 
+    let saturation = SaturationReport::new(&runner, &self.dsrs, rule_stats);
+    let stop_reason = saturation.stop_reason.clone();
     let mut fin = runner.egraph;
 
     debug!("Finished in {}ms", start_time.elapsed().as_millis());
@@ -77,7 +244,7 @@ where
     let root = fin.add(AstNode::new(Op::list(), roots.iter().copied()));
 
     let mut extractor = LibExtractor::new(&fin);
-    let best = extractor.best(root);
+    let best = extractor.best(root)?;
 
     let lifted = lift_libs(&best);
     let final_cost = AstSize.cost_rec(&lifted);
@@ -87,11 +254,30 @@ where
     debug!("{}", Pretty(&Expr::from(lifted.clone())));
     debug!("round time: {}ms", start_time.elapsed().as_millis());
 
-    ExperimentResult {
+    let round = RoundReport {
+      round: 0,
+      final_cost,
+      num_libs: self.dsrs.len(),
+      egraph_size: Some(fin.total_size()),
+      stop_reason,
+      time_elapsed: start_time.elapsed(),
+    };
+
+    Ok(ExperimentResult {
       final_expr: lifted.into(),
       num_libs: self.dsrs.len(),
       rewrites: self.dsrs.clone(),
-    }
+      lib_programs: Vec::new(),
+      rounds: vec![round],
+      timed_out: false,
+      saturation: Some(saturation),
+      stopped_early: None,
+      beam_stats: None,
+      restarts: Vec::new(),
+      snapshots: Vec::new(),
+      autotune: None,
+      verification: None,
+    })
   }
 }
 
@@ -118,7 +304,7 @@ where
     &self,
     exprs: Vec<Expr<Op>>,
     _writer: &mut CsvWriter,
-  ) -> ExperimentResult<Op> {
+  ) -> Result<ExperimentResult<Op>, Error> {
     // First, let's turn our list of exprs into a list of recexprs
     let recexprs: Vec<RecExpr<AstNode<Op>>> =
       exprs.into_iter().map_into().collect();
@@ -134,7 +320,10 @@ where
     1
   }
 
-  fn run_multi(&self, expr_groups: Vec<Vec<Expr<Op>>>) -> ExperimentResult<Op> {
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
     // First, let's turn our list of exprs into a list of recexprs
     let recexpr_groups: Vec<Vec<_>> = expr_groups
       .into_iter()
@@ -143,17 +332,18 @@ where
 
     let mut egraph = EGraph::new(PartialLibCost::new(0, 0, 1));
 
+    // Empty groups have no programs, so they are ignored.
     let roots: Vec<_> = recexpr_groups
       .into_iter()
-      .map(|mut group| {
-        let first_expr = group.pop().unwrap();
+      .filter_map(|mut group| {
+        let first_expr = group.pop()?;
         let root = egraph.add_expr(&first_expr);
         for expr in group {
           let class = egraph.add_expr(&expr);
           egraph.union(root, class);
         }
 
-        root
+        Some(root)
       })
       .collect();
 
@@ -162,34 +352,10 @@ where
     self.run_egraph(&roots, egraph)
   }
 
-  fn write_to_csv(
-    &self,
-    writer: &mut CsvWriter,
-    round: usize,
-    initial_cost: usize,
-    final_cost: usize,
-    compression: f64,
-    num_libs: usize,
-    time_elapsed: Duration,
-  ) {
-    writer
-      .serialize((
-        "eqsat",
-        0,
-        0,
-        0,
-        0,
-        0,
-        self.extra_data.clone(),
-        round,
-        initial_cost,
-        final_cost,
-        compression,
-        num_libs,
-        time_elapsed.as_secs_f64(),
-      ))
-      .unwrap();
-    writer.flush().unwrap();
+  fn metadata(&self) -> Metadata {
+    Metadata::new()
+      .with("kind", "eqsat")
+      .with_all(Metadata::from_serialize("extra", &self.extra_data))
   }
 
   fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
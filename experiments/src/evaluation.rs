@@ -0,0 +1,123 @@
+//! Evaluation of learned libraries on held-out programs, which weren't used
+//! to learn them.
+
+use std::{
+  fmt::{Debug, Display},
+  hash::Hash,
+};
+
+use babble::{
+  extract::{apply_libs_per_root, beam::PartialLibCost},
+  Arity, AstNode, BindingExpr, Error, Expr, InternedCorpus, Printable,
+  Teachable,
+};
+use egg::{EGraph, Rewrite, Runner};
+use serde::{Deserialize, Serialize};
+
+use crate::Experiment;
+
+/// How well the libraries learned from one set of programs apply to a
+/// held-out set of programs.
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct HeldOutReport {
+  /// The number of held-out tasks, each a group of equivalent programs.
+  pub tasks: usize,
+  /// The number of held-out tasks whose rewritten program uses at least one
+  /// of the libraries.
+  pub covered: usize,
+  /// The size of the held-out programs, taking the smallest program of each
+  /// task.
+  pub initial_cost: usize,
+  /// The size of the held-out programs rewritten with the libraries, not
+  /// counting the definitions of the libraries.
+  pub final_cost: usize,
+}
+
+impl HeldOutReport {
+  /// Rewrites each task of `held_out` using the domain-specific rewrites of
+  /// `experiment` and the library rewrites `libs` it learned, and measures
+  /// the result.
+  ///
+  /// # Errors
+  /// Errors if the rewritten held-out programs can't be extracted.
+  pub fn evaluate<Op, T>(
+    experiment: &T,
+    libs: &[Rewrite<AstNode<Op>, PartialLibCost>],
+    held_out: &InternedCorpus<Op>,
+  ) -> Result<Self, Error>
+  where
+    T: Experiment<Op> + ?Sized,
+    Op: Printable
+      + Teachable
+      + Hash
+      + Clone
+      + Debug
+      + Arity
+      + Ord
+      + Display
+      + Send
+      + Sync
+      + 'static,
+  {
+    if held_out.is_empty() {
+      return Ok(Self::default());
+    }
+    let tasks = held_out.len();
+    let initial_cost = experiment.corpus_size(held_out)?;
+
+    let mut egraph = EGraph::new(PartialLibCost::empty());
+    let roots = held_out.add_to_egraph(&mut egraph);
+    egraph.rebuild();
+
+    let egraph = Runner::<_, _, ()>::new(PartialLibCost::empty())
+      .with_egraph(egraph)
+      .run(experiment.dsrs())
+      .egraph;
+    let programs: Vec<Expr<Op>> = apply_libs_per_root(egraph, &roots, libs)?
+      .programs
+      .into_iter()
+      .map(Expr::from)
+      .collect();
+
+    Ok(Self {
+      tasks,
+      covered: programs.iter().filter(|expr| uses_lib(expr)).count(),
+      initial_cost,
+      final_cost: experiment.exprs_size(&programs),
+    })
+  }
+
+  /// The compression factor of the held-out programs.
+  #[must_use]
+  pub fn compression(&self) -> f64 {
+    babble::util::compression_factor(self.initial_cost, self.final_cost)
+  }
+
+  /// The fraction of held-out tasks which use at least one library.
+  #[must_use]
+  #[allow(clippy::cast_precision_loss)]
+  pub fn coverage(&self) -> f64 {
+    if self.tasks == 0 {
+      0.0
+    } else {
+      self.covered as f64 / self.tasks as f64
+    }
+  }
+}
+
+/// Does `expr` use a library function?
+fn uses_lib<Op: Teachable>(expr: &Expr<Op>) -> bool {
+  matches!(expr.0.as_binding_expr(), Some(BindingExpr::LibVar(_)))
+    || expr.0.iter().any(uses_lib)
+}
@@ -1,37 +1,129 @@
+pub use self::autotune::{Autotune, AutotuneReport, PilotReport, BEAM_LADDER};
 pub use self::beam_experiment::BeamExperiment;
-pub use self::eqsat_experiment::EqsatExperiment;
+pub use self::eqsat_experiment::{EqsatExperiment, RuleScheduler};
+pub use self::evaluation::HeldOutReport;
+pub use self::library::{LibDef, Library};
+pub use self::maxsat_experiment::MaxSatExperiment;
+pub use self::metadata::{CsvWriter, MetaValue, Metadata, RESULT_COLUMNS};
+pub use self::metrics::LibMetrics;
+pub use self::registry::{LibraryRegistry, RegisteredLib};
+pub use self::report::{
+  ExperimentReport, LibReport, ReportFormat, ReportWriter, RestartReport,
+  RoundReport, RoundSnapshot, RuleStats, SaturationReport,
+};
+pub use self::schema::SUMMARY_VERSION;
+pub use self::verify::{Mismatch, VerificationReport, Verify};
 
 use babble::{
-  extract::{apply_libs, beam::PartialLibCost},
-  util, Arity, AstNode, DiscriminantEq, Expr, Pretty, Printable, Teachable,
+  combine_exprs,
+  extract::{
+    apply_libs,
+    beam::{BeamStats, PartialLibCost},
+  },
+  progress::{NoProgress, Phase, ProgressSink},
+  util, Arity, AstNode, DiscriminantEq, Error, Expr, InternedCorpus, Pretty,
+  Printable, Teachable,
 };
 use egg::{EGraph, Id, RecExpr, Rewrite, Runner};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::HashMap,
+  collections::{BTreeSet, HashMap},
   fmt::{self, Debug, Display, Formatter},
-  hash::Hash,
+  hash::{Hash, Hasher},
   io,
   marker::PhantomData,
+  sync::Arc,
+  thread,
   time::{Duration, Instant},
 };
 
+mod autotune;
 mod beam_experiment;
 pub mod cache;
 pub mod dreamcoder;
 mod eqsat_experiment;
-
+mod evaluation;
+mod library;
+mod maxsat_experiment;
+mod metadata;
+mod metrics;
+mod registry;
+mod report;
+pub mod schema;
+mod verify;
+
+/// The outcome of an experiment, as it is cached and saved.
+///
+/// Summaries written by earlier versions of the experiments are migrated when
+/// they are read; see the [`schema`] module.
 #[derive(
   Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
 )]
+#[serde(
+  try_from = "schema::SummaryRepr<Op>",
+  bound(deserialize = "Op: Deserialize<'de> + Clone + Eq + std::hash::Hash")
+)]
 pub struct Summary<Op> {
-  pub initial_expr_groups: Vec<Vec<Expr<Op>>>,
+  /// The [`SUMMARY_VERSION`] of the format the summary is written in.
+  pub version: u32,
+  /// The groups of equivalent expressions the experiment was run on.
+  pub initial_corpus: InternedCorpus<Op>,
+  /// The size of the initial corpus, counting each group as many times as
+  /// its weight if the corpus is weighted.
   pub initial_cost: usize,
   pub final_expr: Expr<Op>,
+  /// The size of `final_expr`, counting each program as many times as its
+  /// weight if the corpus is weighted.
   pub final_cost: usize,
   pub num_libs: usize,
   pub run_time: Duration,
+  /// Metrics about each library function used by `final_expr`.
+  #[serde(default)]
+  pub libs: Vec<LibMetrics>,
+  /// Statistics about equality saturation, if the experiment ran it.
+  #[serde(default)]
+  pub saturation: Option<SaturationReport>,
+  /// How the learned libraries apply to held-out programs, if there were
+  /// any.
+  #[serde(default)]
+  pub held_out: Option<HeldOutReport>,
+  /// Why the experiment stopped before running all of its rounds, if it
+  /// did.
+  #[serde(default)]
+  pub stopped_early: Option<EarlyStop>,
+  /// The outcome of each restart of the beam search, if it was restarted.
+  #[serde(default)]
+  pub restarts: Vec<RestartReport>,
+  /// What each round produced, if the experiment ran in rounds.
+  #[serde(default)]
+  pub rounds: Vec<RoundSnapshot<Op>>,
+  /// How the beam size was chosen, if it was tuned automatically.
+  #[serde(default)]
+  pub autotune: Option<AutotuneReport>,
+  /// Whether the rewritten programs compute the same values as the
+  /// original ones, if they were checked.
+  #[serde(default)]
+  pub verification: Option<VerificationReport>,
+}
+
+impl<Op> Summary<Op> {
+  /// The compression factor on the held-out programs relative to the one on
+  /// the programs the libraries were learned from, if there were held-out
+  /// programs.
+  #[must_use]
+  pub fn transfer(&self) -> Option<f64> {
+    let compression =
+      util::compression_factor(self.initial_cost, self.final_cost);
+    self.held_out.as_ref().map(|held_out| held_out.compression() / compression)
+  }
+
+  /// The initial cost followed by the cost after each round, if the
+  /// experiment ran in rounds.
+  pub fn cost_trajectory(&self) -> impl Iterator<Item = usize> + '_ {
+    std::iter::once(self.initial_cost)
+      .chain(self.rounds.iter().map(|snapshot| snapshot.cost))
+  }
 }
 
 struct ExperimentTitle<
@@ -58,9 +150,160 @@ pub struct ExperimentResult<
   pub final_expr: Expr<Op>,
   pub num_libs: usize,
   pub rewrites: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// The indices of the input programs in which the pattern of each of
+  /// `rewrites` matches, if they introduce learned library functions; see
+  /// [`Provenance`](babble::Provenance). Empty if the experiment doesn't
+  /// learn libraries.
+  pub lib_programs: Vec<BTreeSet<usize>>,
+  /// Statistics about each round of library learning.
+  pub rounds: Vec<RoundReport>,
+  /// Whether the experiment ran out of time, in which case the result is the
+  /// best one found before then.
+  pub timed_out: bool,
+  /// Statistics about equality saturation, if the experiment ran it. For
+  /// experiments with several rounds, these are the statistics of the last
+  /// round which ran it.
+  pub saturation: Option<SaturationReport>,
+  /// Why the experiment stopped before running all of its rounds, if it
+  /// did.
+  pub stopped_early: Option<EarlyStop>,
+  /// Telemetry about the beam search, if the experiment ran it with
+  /// telemetry enabled. For experiments with several rounds, this is the
+  /// telemetry of the last round which gathered it.
+  pub beam_stats: Option<BeamStats>,
+  /// The outcome of each restart of the beam search, if the experiment
+  /// restarted it. For experiments with several rounds, these are the
+  /// restarts of the last round which restarted it.
+  pub restarts: Vec<RestartReport>,
+  /// What each round produced, if the experiment ran in [`Rounds`].
+  pub snapshots: Vec<RoundSnapshot<Op>>,
+  /// How the beam size was chosen, if it was tuned by [`Autotune`].
+  pub autotune: Option<AutotuneReport>,
+  /// Whether the rewritten programs compute the same values as the
+  /// original ones, if they were checked by [`Verify`].
+  pub verification: Option<VerificationReport>,
 }
 
-pub type CsvWriter = csv::Writer<Box<dyn io::Write>>;
+/// The result of an experiment whose rewritten programs are already known,
+/// but the rest of whose result may still have to be worked out; see
+/// [`Experiment::run_deferred`].
+pub struct PendingResult<
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+> {
+  /// The rewritten programs, which will be the
+  /// [`final_expr`](ExperimentResult::final_expr) of the finished result.
+  pub final_expr: Expr<Op>,
+  /// Whether the experiment ran out of time.
+  pub timed_out: bool,
+  finish: Finish<Op>,
+}
+
+/// The rest of a [`PendingResult`].
+enum Finish<Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord> {
+  /// The finished result.
+  Ready(Box<ExperimentResult<Op>>),
+  /// Works out the finished result.
+  Later(Box<dyn FnOnce() -> ExperimentResult<Op> + Send>),
+}
+
+impl<Op> PendingResult<Op>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  /// A result whose programs are `final_expr`, and the rest of which is
+  /// worked out by `finish`.
+  pub fn new<F>(final_expr: Expr<Op>, timed_out: bool, finish: F) -> Self
+  where
+    F: FnOnce() -> ExperimentResult<Op> + Send + 'static,
+  {
+    Self { final_expr, timed_out, finish: Finish::Later(Box::new(finish)) }
+  }
+
+  /// A result which is already finished.
+  #[must_use]
+  pub fn ready(res: ExperimentResult<Op>) -> Self {
+    Self {
+      final_expr: res.final_expr.clone(),
+      timed_out: res.timed_out,
+      finish: Finish::Ready(Box::new(res)),
+    }
+  }
+
+  /// Starts working out the rest of the result on another thread.
+  fn in_background(self) -> Self
+  where
+    Op: Send + 'static,
+  {
+    let finish = match self.finish {
+      Finish::Later(finish) => {
+        let handle = thread::spawn(finish);
+        Finish::Later(Box::new(move || {
+          handle
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+        }))
+      }
+      ready @ Finish::Ready(_) => ready,
+    };
+    Self { finish, ..self }
+  }
+
+  /// Works out the rest of the result, waiting for it if it is being worked
+  /// out on another thread.
+  #[must_use]
+  pub fn finish(self) -> ExperimentResult<Op> {
+    match self.finish {
+      Finish::Ready(res) => *res,
+      Finish::Later(finish) => finish(),
+    }
+  }
+}
+
+impl<Op> Debug for PendingResult<Op>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PendingResult")
+      .field("final_expr", &self.final_expr)
+      .field("timed_out", &self.timed_out)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Why [`Rounds`] stopped before running all of its rounds.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub enum EarlyStop {
+  /// A round reduced the cost by less than the minimum improvement.
+  Plateau,
+  /// A round learned no new library functions.
+  NoNewLibs,
+  /// The time budget ran out, or a round ran out of time.
+  TimedOut,
+}
+
+impl<Op> ExperimentResult<Op>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  /// The library functions defined in the final expression, each as a
+  /// closed definition after the library functions it depends on.
+  #[must_use]
+  pub fn library(&self) -> Library<Op> {
+    Library::from_expr(&self.final_expr)
+  }
+}
 
 /// Library learning experiment.
 pub trait Experiment<Op>
@@ -70,41 +313,222 @@ where
   /// The list of domain-specific rewrites used in this experiment.
   fn dsrs(&self) -> &[Rewrite<AstNode<Op>, PartialLibCost>];
 
+  /// Feeds everything other than the input which determines the results of
+  /// the experiment to `hasher`, for [`Cache::content_key`]. By default, this
+  /// is the title and the DSRs of the experiment; experiments whose title
+  /// leaves out some of their parameters should override it.
+  ///
+  /// Returns `false` if some of what determines the results can't be fed to
+  /// `hasher`, such as a custom cost model, in which case experiments which
+  /// differ only in it would have the same fingerprint.
+  ///
+  /// [`Cache::content_key`]: cache::Cache::content_key
+  fn fingerprint(&self, mut hasher: &mut dyn Hasher) -> bool {
+    let title = ExperimentTitle { experiment: self, phantom: PhantomData };
+    title.to_string().hash(&mut hasher);
+    hash_dsrs(self.dsrs(), hasher);
+    true
+  }
+
   // Ideally exprs would have type `I: IntoIterator<Item = Expr<Op>>` but that's not object-safe.
   // This function also gets a writer method to write out intermediate results to the csv.
+  /// Runs the experiment on `exprs`, writing intermediate results to
+  /// `writer`.
+  ///
+  /// # Errors
+  ///
+  /// Errors if there are no programs, if the rewritten programs can't be
+  /// extracted, or if intermediate results can't be written.
   fn run(
     &self,
     exprs: Vec<Expr<Op>>,
     writer: &mut CsvWriter,
-  ) -> ExperimentResult<Op>;
+  ) -> Result<ExperimentResult<Op>, Error>;
 
-  fn run_multi(&self, expr_groups: Vec<Vec<Expr<Op>>>) -> ExperimentResult<Op>;
+  /// Runs the experiment on `expr_groups`, groups of equivalent expressions
+  /// of which any one will do.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run`], or if the experiment doesn't support
+  /// groups of expressions.
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error>;
 
-  fn run_multi_summary(&self, expr_groups: Vec<Vec<Expr<Op>>>) -> Summary<Op> {
-    let start_time = Instant::now();
+  /// The size of `expr`, as reported in summaries and CSVs. By default, this
+  /// is its number of AST nodes.
+  fn expr_size(&self, expr: &Expr<Op>) -> usize {
+    expr.len()
+  }
 
-    let initial_expr_groups = expr_groups.clone();
-    let initial_cost: usize = initial_expr_groups
+  /// The size of `exprs` combined into a single list, as measured by
+  /// [`Experiment::expr_size`], or 0 if there are none.
+  fn exprs_size(&self, exprs: &[Expr<Op>]) -> usize {
+    if exprs.is_empty() {
+      return 0;
+    }
+    self.expr_size(&Expr::from(combine_exprs(exprs.to_vec())))
+  }
+
+  /// The size of the smallest expression of each of `expr_groups`, combined
+  /// into a single list. Empty groups are ignored.
+  fn min_exprs_size(&self, expr_groups: &[Vec<Expr<Op>>]) -> usize {
+    let exprs: Vec<Expr<Op>> = expr_groups
       .iter()
-      .map(|group| group.iter().map(Expr::len).min().unwrap())
+      .filter_map(|group| {
+        group.iter().min_by_key(|expr| self.expr_size(expr)).cloned()
+      })
+      .collect();
+    self.exprs_size(&exprs)
+  }
+
+  /// Like [`Experiment::min_exprs_size`], but for the groups of an interned
+  /// corpus. Only the smallest expression of each group is built as a tree.
+  /// If the corpus is weighted, each group counts as many times as its
+  /// weight.
+  ///
+  /// # Errors
+  ///
+  /// Errors if a group of the corpus is empty.
+  fn corpus_size(&self, corpus: &InternedCorpus<Op>) -> Result<usize, Error> {
+    let exprs = corpus
+      .groups()
+      .iter()
+      .map(|group| {
+        group
+          .iter()
+          .map(|&expr| corpus.interner().expr(expr))
+          .min_by_key(|expr| self.expr_size(expr))
+          .ok_or(Error::NoPrograms)
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let repeats: usize = exprs
+      .iter()
+      .enumerate()
+      .map(|(i, expr)| (corpus.weight(i) - 1) * self.expr_size(expr))
       .sum();
-    let initial_cost = initial_cost + 1;
+    Ok(self.exprs_size(&exprs) + repeats)
+  }
 
-    let res = self.run_multi(expr_groups);
-    let final_expr = res.final_expr;
-    let final_cost = final_expr.len();
+  /// The size of `expr`, a list of programs with the library functions they
+  /// use defined at the top, counting each program as many times as its
+  /// weight in `weights`.
+  fn weighted_size(&self, expr: &Expr<Op>, weights: &[usize]) -> usize {
+    let rc: RecExpr<AstNode<Op>> = expr.clone().into();
+    let repeats: usize = plumbing::exprs(rc.as_ref())
+      .iter()
+      .zip(weights)
+      .map(|(program, &weight)| (weight - 1) * self.expr_size(program))
+      .sum();
+    self.expr_size(expr) + repeats
+  }
 
-    Summary {
-      initial_expr_groups,
-      initial_cost,
-      final_expr,
-      final_cost,
-      num_libs: res.num_libs,
-      run_time: start_time.elapsed(),
-    }
+  /// Like [`Experiment::run_multi`], but takes the groups of equivalent
+  /// expressions as an interned corpus. By default, this builds the trees
+  /// of every expression and runs [`Experiment::run_multi`] on them.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run_multi`].
+  fn run_interned(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    self.run_multi(corpus.expr_groups())
+  }
+
+  /// Like [`Experiment::run`], but returns as soon as the rewritten programs
+  /// are known, deferring the bookkeeping which only the finished result
+  /// needs, such as which programs use each library function. By default,
+  /// nothing is deferred.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run`].
+  fn run_deferred(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<PendingResult<Op>, Error> {
+    self.run(exprs, writer).map(PendingResult::ready)
   }
 
-  /// Write experiments result to CSV.
+  /// Like [`Experiment::run_interned`], but returns as soon as the rewritten
+  /// programs are known; see [`Experiment::run_deferred`].
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run_interned`].
+  fn run_interned_deferred(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<PendingResult<Op>, Error> {
+    self.run_interned(corpus).map(PendingResult::ready)
+  }
+
+  /// Runs the experiment on `corpus` and summarizes the results.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run_multi`].
+  fn run_multi_summary(
+    &self,
+    corpus: InternedCorpus<Op>,
+  ) -> Result<Summary<Op>, Error> {
+    Ok(summarize(self, corpus)?.0)
+  }
+
+  /// Like [`Experiment::run_multi_summary`], but also rewrites the groups of
+  /// equivalent expressions `held_out`, which aren't used for learning, with
+  /// the learned libraries, and reports the result in the summary.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run_multi`], or if the rewritten held-out
+  /// programs can't be extracted.
+  fn run_held_out_summary(
+    &self,
+    corpus: InternedCorpus<Op>,
+    held_out: &InternedCorpus<Op>,
+  ) -> Result<Summary<Op>, Error>
+  where
+    Op: Display + Send + Sync + 'static,
+  {
+    let (mut summary, libs) = summarize(self, corpus)?;
+    summary.held_out = Some(HeldOutReport::evaluate(self, &libs, held_out)?);
+    Ok(summary)
+  }
+
+  /// Like [`Experiment::run_multi_summary`], but takes the groups of
+  /// equivalent expressions from an iterator, such as one which parses them
+  /// lazily from a large input with
+  /// [`FrontierReader`](dreamcoder::json::FrontierReader). The expressions
+  /// are interned as they are read, so neither the raw input nor the trees
+  /// of every expression ever have to be held in memory.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run_multi`].
+  fn run_streaming(
+    &self,
+    expr_groups: &mut dyn Iterator<Item = Vec<Expr<Op>>>,
+  ) -> Result<Summary<Op>, Error> {
+    self.run_multi_summary(InternedCorpus::from_groups(expr_groups))
+  }
+
+  /// Named metadata describing the experiment, such as its kind and
+  /// parameters, written as the first columns of its CSV rows and included
+  /// in its report.
+  fn metadata(&self) -> Metadata;
+
+  /// Write experiments result to CSV, as a row of the experiment's
+  /// [`Experiment::metadata`] followed by the results.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the row can't be written.
   #[allow(clippy::too_many_arguments)]
   fn write_to_csv(
     &self,
@@ -115,7 +539,18 @@ where
     compression: f64,
     num_libs: usize,
     time_elapsed: Duration,
-  );
+  ) -> Result<(), Error> {
+    let results = [
+      round.into(),
+      initial_cost.into(),
+      final_cost.into(),
+      compression.into(),
+      num_libs.into(),
+      time_elapsed.as_secs_f64().into(),
+    ];
+    writer.write_row(&self.metadata(), results).map_err(io::Error::from)?;
+    Ok(())
+  }
 
   /// Format the experiment's title
   ///
@@ -126,17 +561,27 @@ where
 
   fn total_rounds(&self) -> usize;
 
-  /// Run experiment and write results to CSV.
-  fn run_csv(&self, exprs: Vec<Expr<Op>>, writer: &mut CsvWriter) {
-    println!("{}", ExperimentTitle { experiment: self, phantom: PhantomData });
+  /// Run experiment and write results to CSV, returning a structured report
+  /// of the results.
+  ///
+  /// # Errors
+  ///
+  /// Errors like [`Experiment::run`], or if the results can't be written.
+  fn run_csv(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<ExperimentReport, Error> {
+    let title = ExperimentTitle { experiment: self, phantom: PhantomData };
+    println!("{title}");
 
     let start_time = Instant::now();
 
-    // Add one to account for root node, not added yet
-    let initial_cost = exprs.iter().map(Expr::len).sum::<usize>() + 1;
-    let res = self.run(exprs, writer);
+    // Measure the exprs as one list, since the root node is not added yet
+    let initial_cost = self.exprs_size(&exprs);
+    let res = self.run(exprs, writer)?;
 
-    let final_cost = res.final_expr.len();
+    let final_cost = self.expr_size(&res.final_expr);
     let compression = util::compression_factor(initial_cost, final_cost);
     let time_elapsed = start_time.elapsed();
 
@@ -156,10 +601,66 @@ where
       compression,
       res.num_libs,
       time_elapsed,
-    );
+    )?;
+
+    Ok(ExperimentReport {
+      title: title.to_string(),
+      metadata: self.metadata(),
+      initial_cost,
+      final_cost,
+      compression,
+      num_libs: res.num_libs,
+      time_elapsed,
+      libs: LibReport::from_expr(&res.final_expr),
+      rounds: res.rounds,
+      timed_out: res.timed_out,
+    })
   }
 }
 
+/// Runs `experiment` on `corpus`, returning a summary of the results and the
+/// rewrites introducing the learned libraries.
+fn summarize<Op, T>(
+  experiment: &T,
+  corpus: InternedCorpus<Op>,
+) -> Result<(Summary<Op>, Vec<Rewrite<AstNode<Op>, PartialLibCost>>), Error>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  T: Experiment<Op> + ?Sized,
+{
+  let start_time = Instant::now();
+
+  let initial_cost = experiment.corpus_size(&corpus)?;
+
+  let res = experiment.run_interned(&corpus)?;
+  let final_expr = res.final_expr;
+  let final_cost = match corpus.weights() {
+    Some(weights) => experiment.weighted_size(&final_expr, weights),
+    None => experiment.expr_size(&final_expr),
+  };
+
+  let libs = LibMetrics::from_expr(&final_expr);
+
+  let summary = Summary {
+    version: SUMMARY_VERSION,
+    initial_corpus: corpus,
+    initial_cost,
+    final_expr,
+    final_cost,
+    num_libs: res.num_libs,
+    run_time: start_time.elapsed(),
+    libs,
+    saturation: res.saturation,
+    held_out: None,
+    stopped_early: res.stopped_early,
+    restarts: res.restarts,
+    rounds: res.snapshots,
+    autotune: res.autotune,
+    verification: res.verification,
+  };
+  Ok((summary, res.rewrites))
+}
+
 /// A set of `Experiments` is just a list of individual `Experiment` structs
 pub struct Experiments<Op> {
   experiments: Vec<Box<dyn Experiment<Op>>>,
@@ -271,19 +772,60 @@ where
     Self { exprs, experiments: res }
   }
 
+  /// The union of the metadata keys of the experiments in this set, in
+  /// order of first appearance.
+  fn columns(&self) -> Vec<String> {
+    self
+      .experiments
+      .iter()
+      .flat_map(|experiment| {
+        let metadata = experiment.metadata();
+        metadata.keys().map(str::to_owned).collect::<Vec<_>>()
+      })
+      .unique()
+      .collect()
+  }
+
   /// Runs all experiments in this set
   ///
-  /// # Panics
+  /// # Errors
   ///
-  /// Panics if a csv cannot be created at the given path, or if any of the
-  /// experiments' `run_csv` methods panic.
-  pub fn run(self, csv_path: &str) {
-    let file = std::fs::File::create(csv_path).unwrap();
-    let mut writer: CsvWriter = csv::Writer::from_writer(Box::new(file));
+  /// Errors if a csv cannot be created at the given path, or if any of the
+  /// experiments fails.
+  pub fn run(self, csv_path: &str) -> Result<(), Error> {
+    let file = std::fs::File::create(csv_path)?;
+    let mut writer =
+      CsvWriter::from_writer(Box::new(file)).with_columns(self.columns());
 
     for experiment in self.experiments {
-      experiment.run_csv(self.exprs.clone(), &mut writer);
+      experiment.run_csv(self.exprs.clone(), &mut writer)?;
     }
+    Ok(())
+  }
+
+  /// Runs all experiments in this set, writing the results both to a CSV
+  /// and as structured reports in `format`.
+  ///
+  /// # Errors
+  ///
+  /// Errors if a csv or report cannot be created at the given path or
+  /// written, or if any of the experiments fails.
+  pub fn run_with_report(
+    self,
+    csv_path: &str,
+    report_path: &str,
+    format: ReportFormat,
+  ) -> Result<(), Error> {
+    let file = std::fs::File::create(csv_path)?;
+    let mut writer =
+      CsvWriter::from_writer(Box::new(file)).with_columns(self.columns());
+    let report_file = std::fs::File::create(report_path)?;
+    let mut reports = ReportWriter::new(Box::new(report_file), format);
+
+    for experiment in self.experiments {
+      reports.write(experiment.run_csv(self.exprs.clone(), &mut writer)?)?;
+    }
+    reports.finish().map_err(Error::from)
   }
 }
 
@@ -363,8 +905,10 @@ pub mod plumbing {
     Op: Teachable + Clone + std::hash::Hash + Ord + std::fmt::Debug,
   {
     // Check what kind of node we're at.
-    if let Some(babble::BindingExpr::Lib(lid, defn, b)) =
-      &from[usize::from(ix)].as_binding_expr()
+    if let Some(
+      babble::BindingExpr::Lib(lid, defn, b)
+      | babble::BindingExpr::LibRec(lid, defn, b),
+    ) = &from[usize::from(ix)].as_binding_expr()
     {
       // Extract recursive expression
       let rc = build_recexpr(**defn, |x| {
@@ -382,7 +926,10 @@ pub mod plumbing {
         //   (body)
         // ```
         match &from[usize::from(x)].as_binding_expr() {
-          Some(babble::BindingExpr::Lib(_n_lid, _n_defn, n_b)) => {
+          Some(
+            babble::BindingExpr::Lib(_n_lid, _n_defn, n_b)
+            | babble::BindingExpr::LibRec(_n_lid, _n_defn, n_b),
+          ) => {
             // We have a nested lib!
             // Process the lib itself by walking thru this node
             walk_libs(from, res, x);
@@ -423,7 +970,10 @@ pub mod plumbing {
   {
     // Check what kind of node we're at.
     match &from[usize::from(ix)].as_binding_expr() {
-      Some(babble::BindingExpr::Lib(_, _, b)) => {
+      Some(
+        babble::BindingExpr::Lib(_, _, b)
+        | babble::BindingExpr::LibRec(_, _, b),
+      ) => {
         // Recursively walk in body
         walk_exprs(from, res, **b);
       }
@@ -485,6 +1035,30 @@ pub mod plumbing {
   }
 }
 
+/// Feeds the names and patterns of `dsrs` to `hasher`.
+fn hash_dsrs<Op>(
+  dsrs: &[Rewrite<AstNode<Op>, PartialLibCost>],
+  mut hasher: &mut dyn Hasher,
+) where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  dsrs.len().hash(&mut hasher);
+  for dsr in dsrs {
+    dsr.name.as_str().hash(&mut hasher);
+    dsr.searcher.get_pattern_ast().hash(&mut hasher);
+    dsr.applier.get_pattern_ast().hash(&mut hasher);
+  }
+}
+
+/// Assigns the round number `round` to the statistics of a single round of a
+/// wrapped experiment.
+fn renumber(
+  reports: Vec<RoundReport>,
+  round: usize,
+) -> impl Iterator<Item = RoundReport> {
+  reports.into_iter().map(move |report| RoundReport { round, ..report })
+}
+
 #[derive(Debug)]
 pub struct Rounds<Op, T: Experiment<Op>>
 where
@@ -492,6 +1066,18 @@ where
 {
   rounds: usize,
   experiment: T,
+  /// The time after which no more rounds are started, if any.
+  timeout: Option<Duration>,
+  /// The minimum percentage by which each round must reduce the cost for
+  /// another round to be started, if any.
+  min_improvement: Option<f64>,
+  /// Whether to stop after a round which learns no new library functions.
+  stop_without_new_libs: bool,
+  /// How to run the wrapped experiment if each round is finished on another
+  /// thread while the next one runs.
+  background: Option<Background<Op, T>>,
+  /// Where to report the start and end of each round.
+  progress: Arc<dyn ProgressSink>,
   phantom: PhantomData<Op>,
 }
 
@@ -500,48 +1086,255 @@ where
   Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
 {
   pub fn new(rounds: usize, experiment: T) -> Self {
-    Self { rounds, experiment, phantom: PhantomData }
+    Self {
+      rounds,
+      experiment,
+      timeout: None,
+      min_improvement: None,
+      stop_without_new_libs: false,
+      background: None,
+      progress: Arc::new(NoProgress),
+      phantom: PhantomData,
+    }
+  }
+
+  /// Reports the start and end of each round to `progress`. The wrapped
+  /// experiment reports its own progress separately.
+  #[must_use]
+  pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+    self.progress = progress;
+    self
+  }
+
+  /// Runs `f` as round `round`, reporting its start and end.
+  fn in_round<R>(&self, round: usize, f: impl FnOnce() -> R) -> R {
+    let phase = Phase::Round { round, rounds: self.rounds };
+    let start = Instant::now();
+    self.progress.phase_started(phase);
+    let res = f();
+    self.progress.phase_finished(phase, start.elapsed());
+    res
+  }
+
+  /// Stops starting new rounds once `timeout` has passed since the
+  /// experiment started, or once a round runs out of time. A round which is
+  /// already running is only stopped by the wrapped experiment's own
+  /// timeout. By default, every round is run.
+  #[must_use]
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Stops starting new rounds once a round reduces the cost by less than
+  /// `percent` percent of the cost before it. By default, every round is
+  /// run.
+  #[must_use]
+  pub fn with_min_improvement(mut self, percent: f64) -> Self {
+    self.min_improvement = Some(percent);
+    self
+  }
+
+  /// Sets whether to stop starting new rounds once a round learns no new
+  /// library functions. By default, every round is run.
+  #[must_use]
+  pub fn with_stop_without_new_libs(mut self, stop: bool) -> Self {
+    self.stop_without_new_libs = stop;
+    self
+  }
+
+  /// Why no more rounds should be started after a round which reduced the
+  /// cost from `before` to `after` and learned `new_libs` new library
+  /// functions, if they shouldn't be.
+  #[allow(clippy::cast_precision_loss)]
+  fn plateaued(
+    &self,
+    before: usize,
+    after: usize,
+    new_libs: usize,
+  ) -> Option<EarlyStop> {
+    if self.stop_without_new_libs && new_libs == 0 {
+      return Some(EarlyStop::NoNewLibs);
+    }
+    let min_improvement = self.min_improvement?;
+    let improvement = if before == 0 {
+      0.0
+    } else {
+      before.saturating_sub(after) as f64 / before as f64 * 100.0
+    };
+    if improvement < min_improvement {
+      Some(EarlyStop::Plateau)
+    } else {
+      None
+    }
+  }
+
+  /// A snapshot of round `round`, after which the programs are `exprs` and
+  /// the library functions learned so far are those of `registry`.
+  fn snapshot(
+    &self,
+    round: usize,
+    registry: &LibraryRegistry<Op>,
+    exprs: &[Expr<Op>],
+  ) -> RoundSnapshot<Op> {
+    RoundSnapshot {
+      round,
+      corpus: exprs.to_vec(),
+      libs: registry.learned_in(round),
+      cost: self.expr_size(&registry.combine(exprs.to_vec())),
+    }
+  }
+
+  /// Whether no more rounds should be started, given that the experiment
+  /// started at `start` and whether the last round ran out of time.
+  fn out_of_time(&self, start: Instant, round_timed_out: bool) -> bool {
+    round_timed_out
+      || self.timeout.map_or(false, |timeout| start.elapsed() >= timeout)
   }
 }
 
-impl<Op, T: Experiment<Op>> Experiment<Op> for Rounds<Op, T>
+impl<Op, T: Experiment<Op>> Rounds<Op, T>
 where
-  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Display,
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  Op: Send + 'static,
 {
-  /// The list of domain-specific rewrites used in this experiment.
-  fn dsrs(&self) -> &[Rewrite<AstNode<Op>, PartialLibCost>] {
-    self.experiment.dsrs()
+  /// Sets whether to finish each round's result on another thread while the
+  /// next round runs. Only the bookkeeping which the wrapped experiment
+  /// defers is moved off the critical path, such as working out which
+  /// programs use each library function; see [`Experiment::run_deferred`].
+  /// The next round still waits for this round's extraction, since it learns
+  /// from the programs extracted. The results are the same either way. By
+  /// default, each round is finished before the next one starts.
+  #[must_use]
+  pub fn with_background_finish(mut self, background: bool) -> Self {
+    self.background = background.then_some(Background {
+      run: Self::run_in_background,
+      run_interned: Self::run_interned_in_background,
+    });
+    self
   }
 
-  fn run(
-    &self,
+  /// Runs `experiment` on `exprs`, finishing its result in the background.
+  fn run_in_background(
+    experiment: &T,
     exprs: Vec<Expr<Op>>,
     writer: &mut CsvWriter,
-  ) -> ExperimentResult<Op> {
-    let initial_cost = exprs.iter().map(Expr::len).sum::<usize>() + 1;
-    let start = std::time::Instant::now();
+  ) -> Result<PendingResult<Op>, Error> {
+    experiment.run_deferred(exprs, writer).map(PendingResult::in_background)
+  }
 
-    let mut current_exprs = exprs;
-    let mut rc: RecExpr<AstNode<Op>>;
-    let mut libs = HashMap::new();
-    let mut current_rewrites = Vec::new();
+  /// Runs `experiment` on `corpus`, finishing its result in the background.
+  fn run_interned_in_background(
+    experiment: &T,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<PendingResult<Op>, Error> {
+    experiment.run_interned_deferred(corpus).map(PendingResult::in_background)
+  }
+}
 
-    for round in 0..self.rounds {
-      let round_res = self.experiment.run(current_exprs, writer);
+/// How [`Rounds`] runs the wrapped experiment `T` when each round is finished
+/// on another thread. These are only set where `Op` can be sent to another
+/// thread, so that [`Rounds`] itself doesn't require it.
+struct Background<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  run:
+    fn(&T, Vec<Expr<Op>>, &mut CsvWriter) -> Result<PendingResult<Op>, Error>,
+  run_interned: fn(&T, &InternedCorpus<Op>) -> Result<PendingResult<Op>, Error>,
+}
 
-      rc = round_res.final_expr.into();
+impl<Op, T> Debug for Background<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Background").finish_non_exhaustive()
+  }
+}
 
-      let ls = plumbing::libs(rc.as_ref());
-      libs.extend(ls);
-      current_exprs = plumbing::exprs(rc.as_ref());
-      current_rewrites.extend(round_res.rewrites);
+impl<Op, T: Experiment<Op>> Rounds<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Display,
+{
+  /// Runs the wrapped experiment on `exprs`, finishing its result in the
+  /// background if [`with_background_finish`](Self::with_background_finish)
+  /// is set.
+  fn learn(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<PendingResult<Op>, Error> {
+    match &self.background {
+      Some(background) => (background.run)(&self.experiment, exprs, writer),
+      None => self.experiment.run(exprs, writer).map(PendingResult::ready),
+    }
+  }
+
+  /// Runs every round, starting from programs of size `initial_cost` whose
+  /// library functions are reserved in `registry`. The first round is run by
+  /// `first`, and each later one on the programs of the round before it.
+  ///
+  /// Each round has three phases: running the wrapped experiment until the
+  /// rewritten programs are known; registering the library functions they
+  /// define, which gives the programs of the next round and decides whether
+  /// to start it; and finishing the wrapped experiment's result. Each round
+  /// is merged into the results once the next one has run, so that with
+  /// [`with_background_finish`](Self::with_background_finish), the last
+  /// phase runs alongside the next round.
+  fn run_rounds<F>(
+    &self,
+    initial_cost: usize,
+    mut registry: LibraryRegistry<Op>,
+    first: F,
+    writer: &mut CsvWriter,
+  ) -> Result<ExperimentResult<Op>, Error>
+  where
+    F: FnOnce(&mut CsvWriter) -> Result<PendingResult<Op>, Error>,
+  {
+    let start = Instant::now();
+    let mut first = Some(first);
+    let mut current_exprs = Vec::new();
+    let mut finished = FinishedRounds::new();
+    let mut stopped_early = None;
+    let mut prev_cost = initial_cost;
+
+    // The round before the current one, which is yet to be merged.
+    let mut finishing: Option<(usize, PendingResult<Op>)> = None;
+    for round in 0..self.rounds {
+      if round > 0 && self.out_of_time(start, finished.timed_out) {
+        log::warn!("Out of time; stopping after {round} rounds");
+        finished.timed_out = true;
+        stopped_early = Some(EarlyStop::TimedOut);
+        break;
+      }
+
+      let exprs = std::mem::take(&mut current_exprs);
+      let pending = self.in_round(round, || match first.take() {
+        Some(first) => first(writer),
+        None => self.learn(exprs, writer),
+      })?;
+      finished.timed_out |= pending.timed_out;
+
+      let rc: RecExpr<AstNode<Op>> = pending.final_expr.clone().into();
+      let num_libs = registry.len();
+      current_exprs = registry.register_round(round, rc.as_ref());
+      let new_libs = registry.len() - num_libs;
+      let snapshot = self.snapshot(round, &registry, &current_exprs);
+      let inter_cost = snapshot.cost;
+      finished.snapshots.push(snapshot);
+
+      // The rounds are merged in order, so the previous round, which may
+      // have been finishing alongside this one, is merged now.
+      if let Some((prev_round, prev)) = finishing.replace((round, pending)) {
+        finished.merge(prev_round, prev.finish());
+      }
 
       // We record intermediate results if we're not at the last round yet
       if round == self.rounds - 1 {
-        log::info!(" finished!");
+        log::info!("finished!");
       } else {
-        let inter_expr = plumbing::combine(libs.clone(), current_exprs.clone());
-        let inter_cost = inter_expr.len();
+        let inter_expr = registry.combine(current_exprs.clone());
         let compression = util::compression_factor(initial_cost, inter_cost);
 
         self.write_to_csv(
@@ -550,9 +1343,9 @@ where
           initial_cost,
           inter_cost,
           compression,
-          libs.len(),
+          registry.len(),
           start.elapsed(),
-        );
+        )?;
 
         log::info!(
           "round {}/{} results: {inter_cost}/{initial_cost} (r {compression})",
@@ -561,119 +1354,169 @@ where
         );
 
         log::debug!("{}", Pretty(&inter_expr));
+
+        stopped_early = self.plateaued(prev_cost, inter_cost, new_libs);
+        if let Some(reason) = stopped_early {
+          log::info!("Stopping after {} rounds: {reason:?}", round + 1);
+          break;
+        }
+        prev_cost = inter_cost;
       }
     }
+    if let Some((round, pending)) = finishing {
+      finished.merge(round, pending.finish());
+    }
 
-    let ll = libs.len();
-
-    let final_expr = plumbing::combine(libs, current_exprs);
+    let FinishedRounds {
+      rewrites,
+      lib_programs,
+      rounds,
+      snapshots,
+      timed_out,
+      saturation,
+      beam_stats,
+      restarts,
+    } = finished;
+    // Combine back into one big recexpr at the end
+    Ok(ExperimentResult {
+      num_libs: registry.len(),
+      final_expr: registry.combine(current_exprs),
+      rewrites,
+      lib_programs,
+      rounds,
+      timed_out,
+      saturation,
+      stopped_early,
+      beam_stats,
+      restarts,
+      snapshots,
+      autotune: None,
+      verification: None,
+    })
+  }
+}
 
-    // FIXME: make this more robust or smth idk lmao
-    // Print out the raw recexpr of the results to a file
-    std::fs::write(
-      "target/rec_expr",
-      RecExpr::from(final_expr.clone()).pretty(100),
-    )
-    .unwrap();
+/// The results of the rounds of [`Rounds`] which have finished so far.
+struct FinishedRounds<Op> {
+  rewrites: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  lib_programs: Vec<BTreeSet<usize>>,
+  rounds: Vec<RoundReport>,
+  snapshots: Vec<RoundSnapshot<Op>>,
+  timed_out: bool,
+  saturation: Option<SaturationReport>,
+  beam_stats: Option<BeamStats>,
+  restarts: Vec<RestartReport>,
+}
 
-    // Combine back into one big recexpr at the end
-    ExperimentResult { final_expr, num_libs: ll, rewrites: current_rewrites }
+impl<Op> FinishedRounds<Op>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+{
+  fn new() -> Self {
+    Self {
+      rewrites: Vec::new(),
+      lib_programs: Vec::new(),
+      rounds: Vec::new(),
+      snapshots: Vec::new(),
+      timed_out: false,
+      saturation: None,
+      beam_stats: None,
+      restarts: Vec::new(),
+    }
   }
 
-  fn run_multi(&self, expr_groups: Vec<Vec<Expr<Op>>>) -> ExperimentResult<Op> {
-    // Hack: just ignore any written info.
-    let mut writer = CsvWriter::from_writer(Box::new(io::sink()));
+  /// Merges in `res`, the result of round `round`. Only the statistics of
+  /// the last round which gathered them are kept.
+  fn merge(&mut self, round: usize, res: ExperimentResult<Op>) {
+    self.rewrites.extend(res.rewrites);
+    self.lib_programs.extend(res.lib_programs);
+    self.rounds.extend(renumber(res.rounds, round));
+    self.timed_out |= res.timed_out;
+    self.saturation = res.saturation.or(self.saturation.take());
+    self.beam_stats = res.beam_stats.or(self.beam_stats.take());
+    if !res.restarts.is_empty() {
+      self.restarts = res.restarts;
+    }
+  }
+}
 
-    let initial_cost = expr_groups
-      .iter()
-      .map(|expr_group| expr_group.iter().map(Expr::len).min().unwrap())
-      .sum::<usize>()
-      + 1;
-    let start = std::time::Instant::now();
+impl<Op, T: Experiment<Op>> Experiment<Op> for Rounds<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Display,
+{
+  /// The list of domain-specific rewrites used in this experiment.
+  fn dsrs(&self) -> &[Rewrite<AstNode<Op>, PartialLibCost>] {
+    self.experiment.dsrs()
+  }
 
-    let first_res = self.experiment.run_multi(expr_groups);
-    let mut rc: RecExpr<AstNode<Op>> = first_res.final_expr.into();
+  fn fingerprint(&self, mut hasher: &mut dyn Hasher) -> bool {
+    let min_improvement = self.min_improvement.map(f64::to_bits);
+    (self.rounds, self.timeout, min_improvement, self.stop_without_new_libs)
+      .hash(&mut hasher);
+    self.experiment.fingerprint(hasher)
+  }
 
-    let mut current_exprs = plumbing::exprs(rc.as_ref());
-    let mut libs = plumbing::libs(rc.as_ref());
-    let mut current_rewrites = first_res.rewrites;
+  fn expr_size(&self, expr: &Expr<Op>) -> usize {
+    self.experiment.expr_size(expr)
+  }
 
-    {
-      let inter_expr = plumbing::combine(libs.clone(), current_exprs.clone());
-      let inter_cost = inter_expr.len();
-      let compression = util::compression_factor(initial_cost, inter_cost);
-
-      self.write_to_csv(
-        &mut writer,
-        1,
-        initial_cost,
-        inter_cost,
-        compression,
-        libs.len(),
-        start.elapsed(),
-      );
-
-      log::info!(
-        "round {}/{} results: {}/{} (r {})",
-        1,
-        self.rounds,
-        inter_cost,
-        initial_cost,
-        compression
-      );
-
-      log::debug!("{}", Pretty(&inter_expr));
+  fn run(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    if exprs.is_empty() {
+      return Err(Error::NoPrograms);
     }
+    let initial_cost = self.exprs_size(&exprs);
 
-    for round in 1..self.rounds {
-      let round_res = self.experiment.run(current_exprs, &mut writer);
-
-      rc = round_res.final_expr.into();
+    let mut registry = LibraryRegistry::new();
+    registry.reserve(&exprs);
 
-      let ls = plumbing::libs(rc.as_ref());
-      libs.extend(ls);
-      current_exprs = plumbing::exprs(rc.as_ref());
-      current_rewrites.extend(round_res.rewrites);
+    let res = self.run_rounds(
+      initial_cost,
+      registry,
+      |writer| self.learn(exprs, writer),
+      writer,
+    )?;
 
-      // We record intermediate results if we're not at the last round yet
-      if round == self.rounds - 1 {
-        log::info!("finished!");
-      } else {
-        let inter_expr = plumbing::combine(libs.clone(), current_exprs.clone());
-        let inter_cost = inter_expr.len();
-        let compression = util::compression_factor(initial_cost, inter_cost);
+    // FIXME: make this more robust or smth idk lmao
+    // Print out the raw recexpr of the results to a file
+    std::fs::write(
+      "target/rec_expr",
+      RecExpr::from(res.final_expr.clone()).pretty(100),
+    )?;
 
-        self.write_to_csv(
-          &mut writer,
-          round,
-          initial_cost,
-          inter_cost,
-          compression,
-          libs.len(),
-          start.elapsed(),
-        );
+    Ok(res)
+  }
 
-        log::info!(
-          "round {}/{} results: {}/{} (r {})",
-          round + 1,
-          self.rounds,
-          inter_cost,
-          initial_cost,
-          compression
-        );
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    self.run_interned(&InternedCorpus::from_groups(expr_groups))
+  }
 
-        log::debug!("{}", Pretty(&inter_expr));
-      }
+  fn run_interned(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    if corpus.is_empty() {
+      return Err(Error::NoPrograms);
     }
+    // Hack: just ignore any written info.
+    let mut writer = CsvWriter::from_writer(Box::new(io::sink()));
 
-    let ll = libs.len();
+    let initial_cost = self.corpus_size(corpus)?;
 
-    // Combine back into one big recexpr at the end
-    ExperimentResult {
-      final_expr: plumbing::combine(libs, current_exprs),
-      num_libs: ll,
-      rewrites: current_rewrites,
-    }
+    let mut registry = LibraryRegistry::new();
+    registry.reserve_corpus(corpus);
+
+    let first = |_: &mut CsvWriter| match &self.background {
+      Some(background) => (background.run_interned)(&self.experiment, corpus),
+      None => self.experiment.run_interned(corpus).map(PendingResult::ready),
+    };
+    self.run_rounds(initial_cost, registry, first, &mut writer)
   }
 
   fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -684,25 +1527,8 @@ where
     self.rounds
   }
 
-  fn write_to_csv(
-    &self,
-    writer: &mut CsvWriter,
-    round: usize,
-    initial_cost: usize,
-    final_cost: usize,
-    compression: f64,
-    num_libs: usize,
-    time_elapsed: Duration,
-  ) {
-    self.experiment.write_to_csv(
-      writer,
-      round,
-      initial_cost,
-      final_cost,
-      compression,
-      num_libs,
-      time_elapsed,
-    );
+  fn metadata(&self) -> Metadata {
+    self.experiment.metadata().with("rounds", self.rounds)
   }
 }
 
@@ -775,42 +1601,78 @@ where
     self.experiment.dsrs()
   }
 
+  fn fingerprint(&self, mut hasher: &mut dyn Hasher) -> bool {
+    (self.rounds, &self.test_set).hash(&mut hasher);
+    self.experiment.fingerprint(hasher)
+  }
+
+  fn expr_size(&self, expr: &Expr<Op>) -> usize {
+    self.experiment.expr_size(expr)
+  }
+
   fn run(
     &self,
     exprs: Vec<Expr<Op>>,
     writer: &mut CsvWriter,
-  ) -> ExperimentResult<Op> {
+  ) -> Result<ExperimentResult<Op>, Error> {
+    if self.test_set.is_empty() {
+      return Err(Error::NoPrograms);
+    }
     let mut current_train_exprs = exprs;
     let mut current_test_exprs = self.test_set.clone();
     let mut rc: RecExpr<AstNode<Op>>;
     let mut libs = HashMap::new();
     let mut test_libs = HashMap::new(); // can be subset of the libs
     let mut current_rewrites = Vec::new();
+    let mut lib_programs = Vec::new();
+    let mut rounds = Vec::new();
+    let mut timed_out = false;
+    let mut saturation = None;
+    let mut beam_stats = None;
+    let mut restarts = Vec::new();
 
     for round in 0..self.rounds {
       println!("round {}/{}", round + 1, self.rounds);
 
-      let round_res = self.experiment.run(current_train_exprs, writer);
+      let round_res = self.experiment.run(current_train_exprs, writer)?;
 
       rc = round_res.final_expr.into();
       libs.extend(plumbing::libs(rc.as_ref()));
       current_train_exprs = plumbing::exprs(rc.as_ref());
 
       let (aeg, roots) = self.to_egraph(current_test_exprs.clone());
-      rc = apply_libs(aeg, &roots, &round_res.rewrites);
+      rc = apply_libs(aeg, &roots, &round_res.rewrites)?;
       test_libs.extend(plumbing::libs(rc.as_ref()));
       current_test_exprs = plumbing::exprs(rc.as_ref());
 
       current_rewrites.extend(round_res.rewrites);
+      lib_programs.extend(round_res.lib_programs);
+      rounds.extend(renumber(round_res.rounds, round));
+      timed_out |= round_res.timed_out;
+      saturation = round_res.saturation.or(saturation);
+      beam_stats = round_res.beam_stats.or(beam_stats);
+      if !round_res.restarts.is_empty() {
+        restarts = round_res.restarts;
+      }
     }
 
     let ll = test_libs.len();
 
-    ExperimentResult {
+    Ok(ExperimentResult {
       final_expr: plumbing::combine(test_libs, current_test_exprs),
       num_libs: ll,
       rewrites: current_rewrites,
-    }
+      lib_programs,
+      rounds,
+      timed_out,
+      saturation,
+      stopped_early: None,
+      beam_stats,
+      restarts,
+      snapshots: Vec::new(),
+      autotune: None,
+      verification: None,
+    })
   }
 
   fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -821,38 +1683,30 @@ where
     1
   }
 
-  fn write_to_csv(
-    &self,
-    writer: &mut CsvWriter,
-    rounds: usize,
-    initial_cost: usize,
-    final_cost: usize,
-    compression: f64,
-    num_libs: usize,
-    time_elapsed: Duration,
-  ) {
-    self.experiment.write_to_csv(
-      writer,
-      rounds,
-      initial_cost,
-      final_cost,
-      compression,
-      num_libs,
-      time_elapsed,
-    );
+  fn metadata(&self) -> Metadata {
+    self
+      .experiment
+      .metadata()
+      .with("rounds", self.rounds)
+      .with("test_programs", self.test_set.len())
   }
 
   /// Run experiment and write results to CSV.
-  fn run_csv(&self, exprs: Vec<Expr<Op>>, writer: &mut CsvWriter) {
-    println!("{}", ExperimentTitle { experiment: self, phantom: PhantomData });
+  fn run_csv(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<ExperimentReport, Error> {
+    let title = ExperimentTitle { experiment: self, phantom: PhantomData };
+    println!("{title}");
 
     let start_time = Instant::now();
 
-    // Add one to account for root node, not added yet
-    let initial_cost = self.test_set.iter().map(Expr::len).sum::<usize>() + 1;
-    let res = self.run(exprs, writer);
+    // Measure the exprs as one list, since the root node is not added yet
+    let initial_cost = self.exprs_size(&self.test_set);
+    let res = self.run(exprs, writer)?;
 
-    let final_cost = res.final_expr.len();
+    let final_cost = self.expr_size(&res.final_expr);
     let compression = util::compression_factor(initial_cost, final_cost);
     let time_elapsed = start_time.elapsed();
 
@@ -872,13 +1726,96 @@ where
       compression,
       res.num_libs,
       time_elapsed,
-    );
+    )?;
+
+    Ok(ExperimentReport {
+      title: title.to_string(),
+      metadata: self.metadata(),
+      initial_cost,
+      final_cost,
+      compression,
+      num_libs: res.num_libs,
+      time_elapsed,
+      libs: LibReport::from_expr(&res.final_expr),
+      rounds: res.rounds,
+      timed_out: res.timed_out,
+    })
   }
 
   fn run_multi(
     &self,
     _expr_groups: Vec<Vec<Expr<Op>>>,
-  ) -> ExperimentResult<Op> {
-    unimplemented!()
+  ) -> Result<ExperimentResult<Op>, Error> {
+    Err(Error::Unsupported(
+      "running a generalization experiment on groups of programs",
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{BeamExperiment, Experiment, ExperimentResult, Rounds};
+  use babble::{
+    extract::{
+      beam::{LibSel, PruningStrategy},
+      cost::UnitCost,
+    },
+    simple_lang::SimpleOp,
+    Expr, InternedCorpus,
+  };
+  use std::{collections::hash_map::DefaultHasher, hash::Hasher, sync::Arc};
+
+  fn experiment() -> BeamExperiment<SimpleOp, ()> {
+    BeamExperiment::new(Vec::new(), 10, 10, 1, (), false, None, 1)
+  }
+
+  fn rounds(
+    background: bool,
+  ) -> Rounds<SimpleOp, BeamExperiment<SimpleOp, ()>> {
+    let experiment = experiment().with_deterministic(true);
+    Rounds::new(2, experiment).with_background_finish(background)
+  }
+
+  #[test]
+  fn background_finish_gives_same_result() {
+    let corpus = InternedCorpus::from_groups(
+      [
+        "(list (list a b) (list a c))",
+        "(list (list a d) (list a e))",
+        "(list (list f b) (list f c))",
+      ]
+      .map(|program| vec![Expr::parse_with(program).unwrap()]),
+    );
+    let foreground = rounds(false).run_interned(&corpus).unwrap();
+    let background = rounds(true).run_interned(&corpus).unwrap();
+    assert_eq!(background.final_expr, foreground.final_expr);
+    assert_eq!(background.num_libs, foreground.num_libs);
+    assert_eq!(background.lib_programs, foreground.lib_programs);
+    let costs = |res: &ExperimentResult<SimpleOp>| {
+      res.rounds.iter().map(|round| round.final_cost).collect::<Vec<_>>()
+    };
+    assert_eq!(costs(&background), costs(&foreground));
+    assert_eq!(background.snapshots, foreground.snapshots);
+  }
+
+  #[test]
+  fn custom_experiments_need_a_cache_key() {
+    let custom = || {
+      let dominates = |_: &LibSel, _: &LibSel| false;
+      experiment().with_pruning(PruningStrategy::Custom(Arc::new(dominates)))
+    };
+    let fingerprint = |experiment: &BeamExperiment<SimpleOp, ()>| {
+      let mut hasher = DefaultHasher::new();
+      experiment.fingerprint(&mut hasher).then(|| hasher.finish())
+    };
+
+    assert!(fingerprint(&experiment()).is_some());
+    assert_eq!(fingerprint(&custom()), None);
+    let cost_model = experiment().with_cost_model(Arc::new(UnitCost));
+    assert_eq!(fingerprint(&cost_model), None);
+
+    let a = fingerprint(&custom().with_cache_key("a"));
+    assert!(a.is_some());
+    assert_ne!(a, fingerprint(&custom().with_cache_key("b")));
   }
 }
@@ -0,0 +1,381 @@
+//! Learned libraries as standalone definitions, which can be saved to a file
+//! and preloaded by a later run.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt::Debug,
+  fs,
+  hash::Hash,
+  path::Path,
+};
+
+use anyhow::bail;
+use babble::{Arity, AstNode, BindingExpr, Expr, LibId, Teachable};
+use egg::{Analysis, ENodeOrVar, Id, Pattern, RecExpr, Rewrite, Var};
+use serde::{Deserialize, Serialize};
+
+use super::plumbing;
+
+/// A learned library function.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct LibDef<Op> {
+  /// The name of the library function.
+  pub name: LibId,
+  /// The number of arguments the library function takes.
+  pub arity: usize,
+  /// The definition of the library function, with each argument bound by a
+  /// lambda. The only free names in it are the library functions in
+  /// `dependencies`.
+  pub definition: Expr<Op>,
+  /// The library functions referenced by the definition, in order of name.
+  pub dependencies: Vec<LibId>,
+  /// Whether the library function is one of a group of mutually recursive
+  /// library functions, which come next to each other in the library and
+  /// may depend on each other.
+  #[serde(default)]
+  pub recursive: bool,
+}
+
+impl<Op> LibDef<Op>
+where
+  Op: Teachable + Arity + Clone + Debug + Hash + Ord + Send + Sync + 'static,
+{
+  /// A rewrite which replaces instances of the definition's body with
+  /// applications of the library function to its arguments.
+  ///
+  /// Returns `None` if the definition can't be matched by a pattern: if it
+  /// is recursive, if it ignores an argument or refers to one under a
+  /// lambda, or if its body is just an argument.
+  #[must_use]
+  pub fn rewrite<A>(&self) -> Option<Rewrite<AstNode<Op>, A>>
+  where
+    A: Analysis<AstNode<Op>>,
+  {
+    let mut body = &self.definition;
+    for _ in 0..self.arity {
+      body = match body.0.as_binding_expr() {
+        Some(BindingExpr::Lambda(inner)) => inner,
+        _ => return None,
+      };
+    }
+    let mut searcher = RecExpr::default();
+    let root = to_pattern(body, 0, self.arity, &mut searcher)?;
+    if matches!(searcher[root], ENodeOrVar::Var(_)) {
+      return None;
+    }
+
+    let mut applier = RecExpr::default();
+    let mut fun = applier.add(ENodeOrVar::ENode(Op::lib_var(self.name)));
+    for arg in 0..self.arity {
+      let arg = applier.add(ENodeOrVar::Var(arg_var(arg)));
+      fun = applier.add(ENodeOrVar::ENode(Op::apply(fun, arg)));
+    }
+
+    Rewrite::new(
+      format!("library {}", self.name),
+      Pattern::new(searcher),
+      Pattern::new(applier),
+    )
+    .ok()
+  }
+}
+
+/// A set of learned library functions, each defined after the library
+/// functions it depends on, apart from those in its own group of mutually
+/// recursive library functions.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Library<Op> {
+  libs: Vec<LibDef<Op>>,
+}
+
+impl<Op> Default for Library<Op> {
+  fn default() -> Self {
+    Self { libs: Vec::new() }
+  }
+}
+
+impl<Op> Library<Op>
+where
+  Op: Teachable + Arity + Clone + Debug + Hash + Ord,
+{
+  /// Returns the library functions defined in `expr`, the final expression
+  /// of a run of library learning.
+  #[must_use]
+  pub fn from_expr(expr: &Expr<Op>) -> Self {
+    let rc: RecExpr<_> = expr.clone().into();
+    let defs = plumbing::libs(rc.as_ref())
+      .into_iter()
+      .map(|(lib, nodes)| (lib, RecExpr::from(nodes).into()))
+      .collect();
+    Self::from_defs(defs)
+  }
+
+  /// Orders the library functions in `defs` so that each one comes after
+  /// the library functions it depends on. Library functions which depend on
+  /// each other, directly or indirectly, are grouped together as mutually
+  /// recursive library functions. Library functions and groups which don't
+  /// depend on each other are ordered by name.
+  #[must_use]
+  pub fn from_defs(defs: BTreeMap<LibId, Expr<Op>>) -> Self {
+    let mut components = Components {
+      defs: &defs,
+      index: BTreeMap::new(),
+      low: BTreeMap::new(),
+      stack: Vec::new(),
+      libs: Vec::with_capacity(defs.len()),
+    };
+    for &lib in defs.keys() {
+      if !components.index.contains_key(&lib) {
+        components.visit(lib);
+      }
+    }
+    Self { libs: components.libs }
+  }
+}
+
+impl<Op> Library<Op> {
+  /// The library functions, each after the library functions it depends on
+  /// outside of its group.
+  #[must_use]
+  pub fn libs(&self) -> &[LibDef<Op>] {
+    &self.libs
+  }
+
+  /// Wraps `expr` in `lib` bindings of the library functions it refers to,
+  /// and of the library functions they depend on. Mutually recursive
+  /// library functions are bound as a group.
+  #[must_use]
+  pub fn bind(&self, expr: Expr<Op>) -> Expr<Op>
+  where
+    Op: Teachable + Clone,
+  {
+    let mut needed = BTreeSet::new();
+    lib_refs(&expr, &mut needed);
+    let mut pending: Vec<_> = needed.iter().copied().collect();
+    while let Some(lib) = pending.pop() {
+      for &dep in
+        self.get(lib).map_or(&[][..], |def| def.dependencies.as_slice())
+      {
+        if needed.insert(dep) {
+          pending.push(dep);
+        }
+      }
+    }
+    // Binding the library functions in reverse binds each one outside of
+    // the library functions which depend on it.
+    self.libs.iter().rev().filter(|def| needed.contains(&def.name)).fold(
+      expr,
+      |expr, def| {
+        let definition = def.definition.clone();
+        Expr(if def.recursive {
+          Op::lib_rec(def.name, definition, expr)
+        } else {
+          Op::lib(def.name, definition, expr)
+        })
+      },
+    )
+  }
+
+  /// Returns the library function named `lib`, if there is one.
+  #[must_use]
+  pub fn get(&self, lib: LibId) -> Option<&LibDef<Op>> {
+    self.libs.iter().find(|def| def.name == lib)
+  }
+
+  /// The number of library functions.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.libs.len()
+  }
+
+  /// Returns `true` if there are no library functions.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.libs.is_empty()
+  }
+
+  /// Saves the library to `path`, so that it can be loaded with
+  /// [`Library::load`].
+  ///
+  /// # Errors
+  ///
+  /// Errors if the library cannot be serialized or written.
+  pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()>
+  where
+    Op: Serialize,
+  {
+    fs::write(path, ron::to_string(self)?)?;
+    Ok(())
+  }
+
+  /// Loads a library saved with [`Library::save`].
+  ///
+  /// # Errors
+  ///
+  /// Errors if the file cannot be read or parsed, or if a library function
+  /// is defined twice or before one of its dependencies outside of its
+  /// group.
+  // This lint gives false positives for higher-rank trait bounds.
+  #[allow(single_use_lifetimes)]
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self>
+  where
+    Op: for<'de> Deserialize<'de>,
+  {
+    let library: Self = ron::from_str(&fs::read_to_string(path)?)?;
+    let mut defined = BTreeSet::new();
+    let mut group = BTreeSet::new();
+    for (i, def) in library.libs.iter().enumerate() {
+      // A group is a run of recursive library functions, whose members may
+      // depend on the ones after them.
+      group.clear();
+      if def.recursive {
+        group.extend(
+          library.libs[i..]
+            .iter()
+            .take_while(|other| other.recursive)
+            .map(|other| other.name),
+        );
+      }
+      if let Some(dep) = def
+        .dependencies
+        .iter()
+        .find(|dep| !defined.contains(*dep) && !group.contains(*dep))
+      {
+        bail!("{} is defined before its dependency {dep}", def.name);
+      }
+      if !defined.insert(def.name) {
+        bail!("{} is defined twice", def.name);
+      }
+    }
+    Ok(library)
+  }
+}
+
+/// The state of Tarjan's algorithm, which orders library functions after
+/// their dependencies by finding the strongly connected components of the
+/// dependency graph: the groups of mutually recursive library functions.
+struct Components<'a, Op> {
+  defs: &'a BTreeMap<LibId, Expr<Op>>,
+  /// The order in which each library function was visited.
+  index: BTreeMap<LibId, usize>,
+  /// The earliest visited library function reachable from each library
+  /// function through the ones on the stack.
+  low: BTreeMap<LibId, usize>,
+  /// The visited library functions whose component isn't complete yet.
+  stack: Vec<LibId>,
+  /// The library functions of the completed components.
+  libs: Vec<LibDef<Op>>,
+}
+
+impl<Op: Teachable + Clone> Components<'_, Op> {
+  /// Visits `lib` and the library functions it depends on, adding each
+  /// component to `libs` once its dependencies have been added.
+  fn visit(&mut self, lib: LibId) {
+    let index = self.index.len();
+    self.index.insert(lib, index);
+    self.low.insert(lib, index);
+    self.stack.push(lib);
+
+    let defs = self.defs;
+    let mut dependencies = BTreeSet::new();
+    lib_refs(&defs[&lib], &mut dependencies);
+    for &dep in dependencies.iter().filter(|dep| defs.contains_key(dep)) {
+      let reachable = match self.index.get(&dep) {
+        None => {
+          self.visit(dep);
+          self.low[&dep]
+        }
+        Some(&dep_index) if self.stack.contains(&dep) => dep_index,
+        Some(_) => continue,
+      };
+      let low = self.low.get_mut(&lib).unwrap_or_else(|| unreachable!());
+      *low = (*low).min(reachable);
+    }
+    if self.low[&lib] != index {
+      return;
+    }
+
+    let start = self.stack.iter().position(|&other| other == lib);
+    let mut component =
+      self.stack.split_off(start.unwrap_or_else(|| unreachable!()));
+    component.sort_unstable();
+    let recursive = component.len() > 1 || dependencies.contains(&lib);
+    for member in component {
+      let definition = &self.defs[&member];
+      let mut dependencies = BTreeSet::new();
+      lib_refs(definition, &mut dependencies);
+      dependencies.remove(&member);
+      self.libs.push(LibDef {
+        name: member,
+        arity: arity(definition),
+        definition: definition.clone(),
+        dependencies: dependencies.into_iter().collect(),
+        recursive,
+      });
+    }
+  }
+}
+
+/// Adds the library functions referenced in `expr` to `refs`.
+fn lib_refs<Op: Teachable>(expr: &Expr<Op>, refs: &mut BTreeSet<LibId>) {
+  if let Some(BindingExpr::LibVar(lib)) = expr.0.as_binding_expr() {
+    refs.insert(lib);
+  }
+  for arg in expr.0.iter() {
+    lib_refs(arg, refs);
+  }
+}
+
+/// The pattern variable for the argument `index` of a library function.
+fn arg_var(index: usize) -> Var {
+  format!("?x{index}").parse().unwrap_or_else(|_| unreachable!())
+}
+
+/// Adds `expr`, under `binders` lambdas of the body of a library function
+/// with `arity` arguments, to `pattern`, with the arguments replaced by
+/// pattern variables. Returns `None` if an argument is referred to under a
+/// lambda, or if `expr` is recursive.
+fn to_pattern<Op>(
+  expr: &Expr<Op>,
+  binders: usize,
+  arity: usize,
+  pattern: &mut RecExpr<ENodeOrVar<AstNode<Op>>>,
+) -> Option<Id>
+where
+  Op: Teachable + Arity + Clone + Debug + Hash + Ord,
+{
+  let inner = match expr.0.as_binding_expr() {
+    Some(BindingExpr::Var(index)) if index.0 >= binders => {
+      let arg = index.0 - binders;
+      if binders > 0 || arg >= arity {
+        return None;
+      }
+      // The innermost lambda binds the last argument.
+      return Some(pattern.add(ENodeOrVar::Var(arg_var(arity - 1 - arg))));
+    }
+    Some(BindingExpr::Fix(_)) => return None,
+    Some(BindingExpr::Lambda(_)) => binders + 1,
+    _ => binders,
+  };
+  let args = expr
+    .0
+    .iter()
+    .map(|arg| to_pattern(arg, inner, arity, pattern))
+    .collect::<Option<Vec<_>>>()?;
+  let node = AstNode::new(expr.0.operation().clone(), args);
+  Some(pattern.add(ENodeOrVar::ENode(node)))
+}
+
+/// The number of leading lambdas of `expr`, looking through the fixpoint of
+/// a recursive definition.
+fn arity<Op: Teachable>(expr: &Expr<Op>) -> usize {
+  match expr.0.as_binding_expr() {
+    Some(BindingExpr::Lambda(body)) => 1 + arity(body),
+    Some(BindingExpr::Fix(body)) => arity(body),
+    _ => 0,
+  }
+}
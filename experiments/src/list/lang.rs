@@ -1,12 +1,13 @@
 //! The language of list transformations.
 
 use babble::{
-  Arity, AstNode, BindingExpr, DeBruijnIndex, DiscriminantEq, Expr, LibId,
-  ParseLibIdError, Precedence, Printable, Printer, Teachable,
+  Arity, AstNode, BindingExpr, ControlFlow, DeBruijnIndex, DiscriminantEq,
+  Expr, LibId, ParseLibIdError, Precedence, Printable, Printer, Teachable,
 };
 use babble_macros::rewrite_rules;
 use egg::{Rewrite, Symbol};
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use std::{
   convert::Infallible,
   fmt::{self, Display, Formatter, Write},
@@ -14,7 +15,18 @@ use std::{
 };
 
 /// List operations
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
 pub enum ListOp {
   /// Add an element to the front of a list
   Cons,
@@ -32,8 +44,12 @@ pub enum ListOp {
   Ident(Symbol),
   /// An anonymous function
   Lambda,
+  /// A fixpoint, for recursive functions
+  Fix,
   /// A library function binding
   Lib(LibId),
+  /// A binding of a library function in a mutually recursive group
+  LibRec(LibId),
   /// A reference to a lib var
   LibVar(LibId),
   /// A list
@@ -49,8 +65,8 @@ impl Arity for ListOp {
       | Self::Ident(_)
       | Self::LibVar(_)
       | Self::List => 0,
-      Self::Lambda => 1,
-      Self::Cons | Self::Apply | Self::Lib(_) => 2,
+      Self::Lambda | Self::Fix => 1,
+      Self::Cons | Self::Apply | Self::Lib(_) | Self::LibRec(_) => 2,
       Self::If => 3,
     }
   }
@@ -70,10 +86,14 @@ impl Display for ListOp {
       Self::If => "if",
       Self::Apply => "@",
       Self::Lambda => "λ",
+      Self::Fix => "fix",
       Self::List => "list",
       Self::Lib(ix) => {
         return write!(f, "lib {ix}");
       }
+      Self::LibRec(ix) => {
+        return write!(f, "librec {ix}");
+      }
       Self::LibVar(ix) => {
         return write!(f, "{ix}");
       }
@@ -103,6 +123,7 @@ impl FromStr for ListOp {
       "if" => Self::If,
       "apply" | "@" => Self::Apply,
       "lambda" | "λ" => Self::Lambda,
+      "fix" => Self::Fix,
       "list" => Self::List,
       input => input
         .parse()
@@ -116,6 +137,12 @@ impl FromStr for ListOp {
             .ok_or(ParseLibIdError::NoLeadingL)
             .and_then(|x| x.parse().map(Self::Lib))
         })
+        .or_else(|_| {
+          input
+            .strip_prefix("librec ")
+            .ok_or(ParseLibIdError::NoLeadingL)
+            .and_then(|x| x.parse().map(Self::LibRec))
+        })
         .unwrap_or_else(|_| Self::Ident(input.into())),
     };
     Ok(op)
@@ -126,11 +153,15 @@ impl Teachable for ListOp {
   fn from_binding_expr<T>(binding_expr: BindingExpr<T>) -> AstNode<Self, T> {
     match binding_expr {
       BindingExpr::Lambda(body) => AstNode::new(Self::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(Self::Fix, [body]),
       BindingExpr::Apply(fun, arg) => AstNode::new(Self::Apply, [fun, arg]),
       BindingExpr::Var(index) => AstNode::leaf(Self::Var(index)),
       BindingExpr::Lib(ix, bound_value, body) => {
         AstNode::new(Self::Lib(ix), [bound_value, body])
       }
+      BindingExpr::LibRec(ix, bound_value, body) => {
+        AstNode::new(Self::LibRec(ix), [bound_value, body])
+      }
       BindingExpr::LibVar(ix) => AstNode::leaf(Self::LibVar(ix)),
     }
   }
@@ -138,11 +169,15 @@ impl Teachable for ListOp {
   fn as_binding_expr<T>(node: &AstNode<Self, T>) -> Option<BindingExpr<&T>> {
     let binding_expr = match node.as_parts() {
       (Self::Lambda, [body]) => BindingExpr::Lambda(body),
+      (Self::Fix, [body]) => BindingExpr::Fix(body),
       (Self::Apply, [fun, arg]) => BindingExpr::Apply(fun, arg),
       (&Self::Var(index), []) => BindingExpr::Var(index),
       (Self::Lib(ix), [bound_value, body]) => {
         BindingExpr::Lib(*ix, bound_value, body)
       }
+      (Self::LibRec(ix), [bound_value, body]) => {
+        BindingExpr::LibRec(*ix, bound_value, body)
+      }
       (Self::LibVar(ix), []) => BindingExpr::LibVar(*ix),
       _ => return None,
     };
@@ -166,7 +201,7 @@ impl Printable for ListOp {
       Self::Apply => 40,
       Self::Cons => 30,
       Self::If => 20,
-      Self::Lambda | ListOp::Lib(_) => 10,
+      Self::Lambda | Self::Fix | ListOp::Lib(_) | ListOp::LibRec(_) => 10,
     }
   }
 
@@ -219,11 +254,17 @@ impl DiscriminantEq for ListOp {
   }
 }
 
+impl ControlFlow for ListOp {
+  fn is_control_flow(&self) -> bool {
+    matches!(self, Self::If)
+  }
+}
+
 lazy_static! {
     pub(crate) static ref LIFT_LIB_REWRITES: &'static [Rewrite<AstNode<ListOp>, ()>] = {
         let rules = rewrite_rules! {
-            // TODO: Check for captures of de Bruijn variables and re-index if necessary.
-            // lift_lambda: "(lambda (lib ?x ?v ?e))" => "(lib ?x ?v (lambda ?e))";
+            // Libs are lifted out of lambdas by `babble::extract::lift_libs`, which
+            // re-indexes their definitions.
 
             // Binding expressions
         //     lift_let_both: "(let ?x1 (lib ?x2 ?v2 ?v1) (lib ?x2 ?v2 ?e))" => "(lib ?x2 ?v2 (let ?x1 ?v1 ?e))" if not_free_in("?v2", "?x1");
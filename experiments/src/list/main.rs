@@ -121,5 +121,5 @@ fn main() {
   );
 
   println!("running...");
-  exps.run("harness/data_gen/res_list.csv");
+  exps.run("harness/data_gen/res_list.csv").unwrap();
 }
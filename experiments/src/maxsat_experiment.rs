@@ -0,0 +1,275 @@
+use std::{
+  fmt::{self, Debug, Display, Formatter},
+  hash::Hash,
+  time::{Duration, Instant},
+};
+
+use egg::{AstSize, CostFunction, EGraph, Id, RecExpr, Rewrite, Runner};
+use itertools::Itertools;
+use log::{debug, info};
+use serde::ser::Serialize;
+
+use babble::{
+  extract::{
+    apply_libs,
+    beam::PartialLibCost,
+    maxsat::{MaxSatExtractor, MaxSatSolver},
+  },
+  Arity, AstNode, COBuilder, DiscriminantEq, Error, Expr,
+  LearnedLibraryBuilder, LibId, Pretty, Printable, Provenance, Teachable,
+};
+
+use super::{CsvWriter, Experiment, ExperimentResult, Metadata, RoundReport};
+
+/// A `MaxSatExperiment` contains all of the information needed to run a
+/// library learning experiment which selects libraries with a Max-SAT solver.
+#[derive(Debug)]
+pub struct MaxSatExperiment<Op, Extra>
+where
+  Op: Display + Hash + Clone + Ord + Teachable + Arity + Send + Sync + 'static,
+{
+  /// The domain-specific rewrites to apply
+  dsrs: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// The solver used to select libraries
+  solver: MaxSatSolver,
+  /// The number of times to apply library rewrites
+  lib_iter_limit: usize,
+  /// Any extra data associated with this experiment
+  extra_data: Extra,
+  /// Whether to learn "library functions" with no arguments.
+  learn_constants: bool,
+  /// Maximum arity of a library function.
+  max_arity: Option<usize>,
+}
+
+impl<Op, Extra> MaxSatExperiment<Op, Extra>
+where
+  Op: Arity
+    + Teachable
+    + Printable
+    + Debug
+    + Display
+    + Hash
+    + Clone
+    + Ord
+    + Sync
+    + Send
+    + DiscriminantEq
+    + 'static,
+{
+  pub fn new<I>(
+    dsrs: I,
+    solver: MaxSatSolver,
+    extra_data: Extra,
+    learn_constants: bool,
+    max_arity: Option<usize>,
+    lib_iter_limit: usize,
+  ) -> Self
+  where
+    I: IntoIterator<Item = Rewrite<AstNode<Op>, PartialLibCost>>,
+  {
+    Self {
+      dsrs: dsrs.into_iter().collect(),
+      solver,
+      lib_iter_limit,
+      extra_data,
+      learn_constants,
+      max_arity,
+    }
+  }
+
+  fn run_egraph(
+    &self,
+    roots: &[Id],
+    egraph: EGraph<AstNode<Op>, PartialLibCost>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    if roots.is_empty() {
+      return Err(Error::NoPrograms);
+    }
+    let start_time = Instant::now();
+    let timeout = Duration::from_secs(60 * 100_000);
+
+    info!("Running {} DSRs... ", self.dsrs.len());
+
+    let runner = Runner::<_, _, ()>::new(PartialLibCost::empty())
+      .with_egraph(egraph)
+      .with_time_limit(timeout)
+      .with_iter_limit(3)
+      .run(&self.dsrs);
+
+    let aeg = runner.egraph;
+
+    info!("Finished in {}ms", start_time.elapsed().as_millis());
+
+    info!("Running anti-unification... ");
+    let au_time = Instant::now();
+    let co_occurs = COBuilder::new(&aeg, roots).run();
+    // Any libs learned in previous rounds are referenced by name in the
+    // egraph, so new libs have to be numbered after them.
+    let lib_offset = LibId::next_free(&aeg).0;
+
+    let mut learned_lib = LearnedLibraryBuilder::default()
+      .lib_offset(lib_offset)
+      .learn_constants(self.learn_constants)
+      .max_arity(self.max_arity)
+      .with_co_occurs(co_occurs)
+      .build(&aeg);
+    learned_lib.deduplicate(&aeg);
+    let lib_rewrites: Vec<_> = learned_lib.rewrites().collect();
+    info!(
+      "Found {} patterns in {}ms",
+      learned_lib.size(),
+      au_time.elapsed().as_millis()
+    );
+
+    info!("Adding libs and running Max-SAT solver... ");
+    let sat_time = Instant::now();
+    let runner = Runner::<_, _, ()>::new(PartialLibCost::empty())
+      .with_egraph(aeg.clone())
+      .with_iter_limit(self.lib_iter_limit)
+      .with_time_limit(timeout)
+      .with_node_limit(1_000_000)
+      .run(lib_rewrites.iter());
+    let stop_reason = runner.stop_reason.map(|r| format!("{r:?}"));
+    let egraph = runner.egraph;
+
+    let libs =
+      MaxSatExtractor::new(&egraph, self.solver.clone()).select_libs(roots)?;
+    info!(
+      "Selected {} libs in {}ms",
+      libs.len(),
+      sat_time.elapsed().as_millis()
+    );
+
+    let chosen_rewrites: Vec<_> = libs
+      .iter()
+      .filter_map(|&lib| learned_lib.lib_index(lib))
+      .map(|i| lib_rewrites[i].clone())
+      .collect();
+
+    let provenance = Provenance::new(&aeg, roots);
+    let lib_programs = chosen_rewrites
+      .iter()
+      .map(|rewrite| provenance.of_matches(&aeg, &*rewrite.searcher))
+      .collect();
+
+    let ex_time = Instant::now();
+    info!("Extracting... ");
+    let lifted = apply_libs(aeg, roots, &chosen_rewrites)?;
+    let final_cost = AstSize.cost_rec(&lifted);
+
+    info!("Finished in {}ms", ex_time.elapsed().as_millis());
+    info!("final cost: {}", final_cost);
+    debug!("{}", Pretty(&Expr::from(lifted.clone())));
+    info!("round time: {}ms", start_time.elapsed().as_millis());
+
+    let round = RoundReport {
+      round: 0,
+      final_cost,
+      num_libs: chosen_rewrites.len(),
+      egraph_size: Some(egraph.total_size()),
+      stop_reason,
+      time_elapsed: start_time.elapsed(),
+    };
+
+    Ok(ExperimentResult {
+      final_expr: lifted.into(),
+      num_libs: chosen_rewrites.len(),
+      rewrites: chosen_rewrites,
+      lib_programs,
+      rounds: vec![round],
+      timed_out: false,
+      saturation: None,
+      stopped_early: None,
+      beam_stats: None,
+      restarts: Vec::new(),
+      snapshots: Vec::new(),
+      autotune: None,
+      verification: None,
+    })
+  }
+}
+
+impl<Op, Extra> Experiment<Op> for MaxSatExperiment<Op, Extra>
+where
+  Op: Teachable
+    + Printable
+    + Arity
+    + Clone
+    + Send
+    + Sync
+    + Debug
+    + Display
+    + Hash
+    + Ord
+    + DiscriminantEq
+    + 'static,
+  Extra: Serialize + Debug + Clone,
+{
+  /// The list of domain-specific rewrites used in this experiment.
+  fn dsrs(&self) -> &[Rewrite<AstNode<Op>, PartialLibCost>] {
+    &self.dsrs
+  }
+
+  fn run(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    _writer: &mut CsvWriter,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    // First, let's turn our list of exprs into a list of recexprs
+    let recexprs: Vec<RecExpr<AstNode<Op>>> =
+      exprs.into_iter().map_into().collect();
+
+    let mut egraph = EGraph::new(PartialLibCost::empty());
+    let roots: Vec<_> = recexprs.iter().map(|x| egraph.add_expr(x)).collect();
+    egraph.rebuild();
+
+    self.run_egraph(&roots, egraph)
+  }
+
+  fn total_rounds(&self) -> usize {
+    1
+  }
+
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    // First, let's turn our list of exprs into a list of recexprs
+    let recexpr_groups: Vec<Vec<_>> = expr_groups
+      .into_iter()
+      .map(|group| group.into_iter().map(RecExpr::from).collect())
+      .collect();
+
+    let mut egraph = EGraph::new(PartialLibCost::empty());
+
+    // Empty groups have no programs, so they are ignored.
+    let roots: Vec<_> = recexpr_groups
+      .into_iter()
+      .filter_map(|mut group| {
+        let first_expr = group.pop()?;
+        let root = egraph.add_expr(&first_expr);
+        for expr in group {
+          let class = egraph.add_expr(&expr);
+          egraph.union(root, class);
+        }
+
+        Some(root)
+      })
+      .collect();
+
+    egraph.rebuild();
+
+    self.run_egraph(&roots, egraph)
+  }
+
+  fn metadata(&self) -> Metadata {
+    Metadata::new()
+      .with("kind", "maxsat")
+      .with_all(Metadata::from_serialize("extra", &self.extra_data))
+  }
+
+  fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "maxsat | extra_data: {:?}", self.extra_data)
+  }
+}
@@ -0,0 +1,260 @@
+//! Named metadata describing an experiment, written as columns of the CSV
+//! output and as keys of the structured reports, so that the results of
+//! different kinds of experiments can be analyzed side by side.
+
+use std::{
+  fmt::{self, Display, Formatter},
+  io,
+};
+
+use indexmap::IndexMap;
+use serde::{Serialize, Serializer};
+
+/// The value of a single metadata key.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MetaValue {
+  Bool(bool),
+  UInt(u64),
+  Int(i64),
+  Float(f64),
+  Str(String),
+}
+
+impl Display for MetaValue {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Bool(b) => b.fmt(f),
+      Self::UInt(n) => n.fmt(f),
+      Self::Int(n) => n.fmt(f),
+      Self::Float(x) => x.fmt(f),
+      Self::Str(s) => s.fmt(f),
+    }
+  }
+}
+
+impl From<bool> for MetaValue {
+  fn from(b: bool) -> Self {
+    Self::Bool(b)
+  }
+}
+
+impl From<usize> for MetaValue {
+  fn from(n: usize) -> Self {
+    Self::UInt(n as u64)
+  }
+}
+
+impl From<u64> for MetaValue {
+  fn from(n: u64) -> Self {
+    Self::UInt(n)
+  }
+}
+
+impl From<i64> for MetaValue {
+  fn from(n: i64) -> Self {
+    Self::Int(n)
+  }
+}
+
+impl From<f64> for MetaValue {
+  fn from(x: f64) -> Self {
+    Self::Float(x)
+  }
+}
+
+impl From<&str> for MetaValue {
+  fn from(s: &str) -> Self {
+    Self::Str(s.to_owned())
+  }
+}
+
+impl From<String> for MetaValue {
+  fn from(s: String) -> Self {
+    Self::Str(s)
+  }
+}
+
+impl From<serde_json::Value> for MetaValue {
+  /// Converts a scalar JSON value. Arrays and objects are kept as their JSON
+  /// text, and `null` becomes the empty string.
+  fn from(value: serde_json::Value) -> Self {
+    match value {
+      serde_json::Value::Null => Self::Str(String::new()),
+      serde_json::Value::Bool(b) => Self::Bool(b),
+      serde_json::Value::Number(n) => n
+        .as_u64()
+        .map(Self::UInt)
+        .or_else(|| n.as_i64().map(Self::Int))
+        .unwrap_or_else(|| Self::Float(n.as_f64().unwrap_or(f64::NAN))),
+      serde_json::Value::String(s) => Self::Str(s),
+      value => Self::Str(value.to_string()),
+    }
+  }
+}
+
+/// Metadata describing an experiment, as keys with values, in the order in
+/// which they were inserted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata(IndexMap<String, MetaValue>);
+
+impl Metadata {
+  /// Creates empty metadata.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets `key` to `value`, keeping the original position of `key` if it
+  /// was already set.
+  #[must_use]
+  pub fn with(
+    mut self,
+    key: impl Into<String>,
+    value: impl Into<MetaValue>,
+  ) -> Self {
+    self.insert(key, value);
+    self
+  }
+
+  /// Sets every key of `other`, like [`Metadata::with`].
+  #[must_use]
+  pub fn with_all(mut self, other: Self) -> Self {
+    self.0.extend(other.0);
+    self
+  }
+
+  /// Sets `key` to `value`, keeping the original position of `key` if it
+  /// was already set.
+  pub fn insert(
+    &mut self,
+    key: impl Into<String>,
+    value: impl Into<MetaValue>,
+  ) {
+    self.0.insert(key.into(), value.into());
+  }
+
+  /// The value of `key`, if it is set.
+  #[must_use]
+  pub fn get(&self, key: &str) -> Option<&MetaValue> {
+    self.0.get(key)
+  }
+
+  /// The keys which are set, in order.
+  pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+    self.0.keys().map(String::as_str)
+  }
+
+  /// Converts `value` to metadata through its JSON representation: each
+  /// field of a struct or map becomes a key of its own, unit values such as
+  /// `()` are left out, and any other value is stored under `key`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `value` can't be represented as JSON.
+  #[must_use]
+  pub fn from_serialize<T: Serialize>(key: &str, value: &T) -> Self {
+    let mut metadata = Self::new();
+    match serde_json::to_value(value).unwrap() {
+      serde_json::Value::Null => {}
+      serde_json::Value::Object(fields) => {
+        for (name, value) in fields {
+          metadata.insert(name, value);
+        }
+      }
+      value => metadata.insert(key, value),
+    }
+    metadata
+  }
+}
+
+impl Serialize for Metadata {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(&self.0)
+  }
+}
+
+/// The names of the result columns written after the metadata columns of
+/// every CSV row.
+pub const RESULT_COLUMNS: [&str; 6] = [
+  "round",
+  "initial_cost",
+  "final_cost",
+  "compression",
+  "num_libs",
+  "time_elapsed",
+];
+
+/// Writes results of experiments as rows of a CSV with a header naming each
+/// column: the metadata columns, followed by the [`RESULT_COLUMNS`]. Cells
+/// for metadata keys which an experiment doesn't set are left empty.
+pub struct CsvWriter {
+  writer: csv::Writer<Box<dyn io::Write>>,
+  /// The metadata columns, or `None` to use the keys of the first row.
+  columns: Option<Vec<String>>,
+  /// Whether the header has been written.
+  wrote_header: bool,
+}
+
+impl CsvWriter {
+  /// Creates a writer writing to `writer`.
+  #[must_use]
+  pub fn from_writer(writer: Box<dyn io::Write>) -> Self {
+    Self {
+      writer: csv::Writer::from_writer(writer),
+      columns: None,
+      wrote_header: false,
+    }
+  }
+
+  /// Uses `columns` as the metadata columns, rather than the metadata keys
+  /// of the first row written. This should be the union of the metadata keys
+  /// of every experiment which writes to this file.
+  #[must_use]
+  pub fn with_columns<I>(mut self, columns: I) -> Self
+  where
+    I: IntoIterator,
+    I::Item: Into<String>,
+  {
+    self.columns = Some(columns.into_iter().map(Into::into).collect());
+    self
+  }
+
+  /// Writes a row of `metadata` and `results`, in the order of
+  /// [`RESULT_COLUMNS`], preceded by the header if this is the first row.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the row can't be written.
+  pub fn write_row(
+    &mut self,
+    metadata: &Metadata,
+    results: [MetaValue; RESULT_COLUMNS.len()],
+  ) -> csv::Result<()> {
+    let columns = self
+      .columns
+      .get_or_insert_with(|| metadata.keys().map(str::to_owned).collect());
+    if !self.wrote_header {
+      let header = columns.iter().map(String::as_str).chain(RESULT_COLUMNS);
+      self.writer.write_record(header)?;
+      self.wrote_header = true;
+    }
+    let cells = columns
+      .iter()
+      .map(|column| metadata.get(column).map(ToString::to_string))
+      .map(Option::unwrap_or_default)
+      .chain(results.iter().map(ToString::to_string));
+    self.writer.write_record(cells)?;
+    self.writer.flush()?;
+    Ok(())
+  }
+}
+
+impl fmt::Debug for CsvWriter {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("CsvWriter")
+      .field("columns", &self.columns)
+      .field("wrote_header", &self.wrote_header)
+      .finish_non_exhaustive()
+  }
+}
@@ -0,0 +1,143 @@
+//! Metrics measuring how much each learned library function contributes to
+//! the compression of a set of programs.
+
+use std::collections::BTreeMap;
+
+use babble::{BindingExpr, Expr, LibId, Teachable};
+use serde::{Deserialize, Serialize};
+
+/// Metrics about a single learned library function.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct LibMetrics {
+  /// The library function's name.
+  pub lib: LibId,
+  /// The number of places the library function is used, including within
+  /// the definitions of other library functions.
+  pub uses: usize,
+  /// The size of the library function's definition.
+  pub size: usize,
+  /// The number of arguments the library function takes.
+  pub arity: usize,
+  /// How much larger the programs would be if every use of the library
+  /// function were inlined. Negative if inlining would make them smaller.
+  pub contribution: isize,
+}
+
+impl LibMetrics {
+  /// Computes the metrics of every library function defined in `expr`, in
+  /// order of name.
+  #[must_use]
+  pub fn from_expr<Op: Teachable>(expr: &Expr<Op>) -> Vec<Self> {
+    let mut defs = BTreeMap::new();
+    collect_defs(expr, &mut defs);
+    let mut calls = BTreeMap::new();
+    collect_calls(expr, &mut calls);
+
+    defs
+      .into_iter()
+      .map(|(lib, def)| {
+        let (arity, body) = strip_lambdas(def);
+        let calls = calls.remove(&lib).unwrap_or_default();
+        let contribution =
+          calls.iter().map(|args| inline_savings(def, arity, body, args)).sum();
+        Self { lib, uses: calls.len(), size: def.len(), arity, contribution }
+      })
+      .collect()
+  }
+}
+
+/// Collects the definition of every library function defined in `expr`.
+fn collect_defs<'a, Op: Teachable>(
+  expr: &'a Expr<Op>,
+  defs: &mut BTreeMap<LibId, &'a Expr<Op>>,
+) {
+  if let Some(
+    BindingExpr::Lib(lib, def, _) | BindingExpr::LibRec(lib, def, _),
+  ) = expr.0.as_binding_expr()
+  {
+    defs.insert(lib, def);
+  }
+  for child in expr.0.iter() {
+    collect_defs(child, defs);
+  }
+}
+
+/// Collects the arguments each library function is applied to at each of
+/// its uses in `expr`.
+fn collect_calls<'a, Op: Teachable>(
+  expr: &'a Expr<Op>,
+  calls: &mut BTreeMap<LibId, Vec<Vec<&'a Expr<Op>>>>,
+) {
+  let mut head = expr;
+  let mut args = Vec::new();
+  while let Some(BindingExpr::Apply(fun, arg)) = head.0.as_binding_expr() {
+    args.push(arg);
+    head = fun;
+  }
+
+  if let Some(BindingExpr::LibVar(lib)) = head.0.as_binding_expr() {
+    args.reverse();
+    for arg in &args {
+      collect_calls(arg, calls);
+    }
+    calls.entry(lib).or_default().push(args);
+  } else {
+    for child in expr.0.iter() {
+      collect_calls(child, calls);
+    }
+  }
+}
+
+/// Splits the definition of a library function into the number of lambdas
+/// it starts with and the body of the innermost lambda.
+fn strip_lambdas<Op: Teachable>(mut def: &Expr<Op>) -> (usize, &Expr<Op>) {
+  let mut arity = 0;
+  while let Some(BindingExpr::Lambda(body)) = def.0.as_binding_expr() {
+    arity += 1;
+    def = body;
+  }
+  (arity, def)
+}
+
+/// How much larger a call to a library function with definition `def` and
+/// arguments `args` becomes when it is inlined. A call with all of its
+/// arguments is beta-reduced; any other call is inlined by substituting the
+/// definition for the library function's name.
+#[allow(clippy::cast_possible_wrap)]
+fn inline_savings<Op: Teachable>(
+  def: &Expr<Op>,
+  arity: usize,
+  body: &Expr<Op>,
+  args: &[&Expr<Op>],
+) -> isize {
+  if args.len() != arity {
+    return def.len() as isize - 1;
+  }
+
+  let call_size = 1 + arity + args.iter().map(|arg| arg.len()).sum::<usize>();
+  let inlined_size =
+    args.iter().enumerate().fold(body.len() as isize, |size, (i, arg)| {
+      // The first argument is bound by the outermost lambda.
+      let occurrences = count_var(body, arity - 1 - i, 0);
+      size + (occurrences * (arg.len() - 1)) as isize
+    });
+  inlined_size - call_size as isize
+}
+
+/// Counts the occurrences of the variable with de Bruijn index `index` in
+/// `expr`, which is nested within `depth` binders.
+fn count_var<Op: Teachable>(
+  expr: &Expr<Op>,
+  index: usize,
+  depth: usize,
+) -> usize {
+  match expr.0.as_binding_expr() {
+    Some(BindingExpr::Var(var)) => usize::from(var.0 == index + depth),
+    Some(BindingExpr::Lambda(body) | BindingExpr::Fix(body)) => {
+      count_var(body, index, depth + 1)
+    }
+    _ => expr.0.iter().map(|child| count_var(child, index, depth)).sum(),
+  }
+}
@@ -12,7 +12,7 @@
 )]
 #![allow(clippy::non_ascii_literal, clippy::cast_precision_loss)]
 
-use babble::{AstNode, Expr};
+use babble::{util, AstNode, Expr};
 use babble_experiments::dreamcoder::{
   expr::DreamCoderOp,
   json::{CompressionInput, CompressionOutput, CompressionSummary},
@@ -41,8 +41,14 @@ struct Opts {
 
   #[clap(long)]
   cache: Option<PathBuf>,
+
+  /// The CSV file to write DreamCoder's results into. Missing directories
+  /// are created.
+  #[clap(long, parse(from_os_str), default_value = DC_RESULTS_PATH)]
+  output: PathBuf,
 }
 const BENCHMARK_PATH: &str = "harness/data/dreamcoder-benchmarks/benches";
+const DC_RESULTS_PATH: &str = "harness/data_gen/dc_res.csv";
 
 /// A cost calculation for Dream&shy;Coder exprs, with a few modifications.
 /// In particular, when we have `Inlined` expressions (i.e. learned libs),
@@ -100,7 +106,10 @@ fn main() -> anyhow::Result<()> {
 
   benchmark_dirs.sort_unstable();
 
-  let mut wtr = csv::Writer::from_path("harness/data_gen/dc_res.csv")?;
+  if let Some(parent) = opts.output.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let mut wtr = csv::Writer::from_path(&opts.output)?;
   wtr.serialize((
     "name",
     "iter",
@@ -232,7 +241,7 @@ fn process_bench_file(
 
   // Write to our csv.
   let bench_name = benchmark_dir.file_name().unwrap();
-  let cost_ratio = (initial_cost as f64) / (final_cost as f64);
+  let cost_ratio = util::compression_factor(initial_cost, final_cost);
   let total_time_seconds = processed.metrics.s_total;
   let bench_time_seconds =
     processed.metrics.ms_per_inv * (processed.num_inventions as f64) / 1000.0;
@@ -0,0 +1,198 @@
+//! A registry of the library functions learned over multiple rounds of
+//! library learning.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt::Debug,
+  hash::Hash,
+};
+
+use babble::{
+  Arity, AstNode, BindingExpr, Expr, InternedCorpus, LibId, Teachable,
+};
+use egg::RecExpr;
+
+use super::plumbing;
+
+/// A library function registered in a [`LibraryRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RegisteredLib<Op> {
+  /// The round in which the library function was learned.
+  pub round: usize,
+  /// The definition of the library function.
+  pub body: Vec<AstNode<Op>>,
+}
+
+/// Every round of library learning names the library functions it learns
+/// independently of previous rounds. A `LibraryRegistry` keeps track of the
+/// library functions learned so far, giving each one a stable [`LibId`] that
+/// is unique across rounds, so that later rounds can refer to libraries
+/// learned in earlier rounds.
+#[derive(Debug, Clone)]
+pub struct LibraryRegistry<Op> {
+  libs: BTreeMap<LibId, RegisteredLib<Op>>,
+  /// The next unused [`LibId`].
+  next_id: usize,
+}
+
+impl<Op> Default for LibraryRegistry<Op> {
+  fn default() -> Self {
+    Self { libs: BTreeMap::new(), next_id: 0 }
+  }
+}
+
+impl<Op> LibraryRegistry<Op>
+where
+  Op: Teachable + Arity + Clone + Debug + Hash + Ord,
+{
+  /// Creates an empty registry.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Makes sure that no library function registered in the future is named
+  /// the same as a library function referenced in `exprs`.
+  pub fn reserve(&mut self, exprs: &[Expr<Op>]) {
+    fn max_lib<Op: Teachable>(expr: &Expr<Op>) -> Option<usize> {
+      let here = match expr.0.as_binding_expr() {
+        Some(
+          BindingExpr::LibVar(lib)
+          | BindingExpr::Lib(lib, _, _)
+          | BindingExpr::LibRec(lib, _, _),
+        ) => Some(lib.0),
+        _ => None,
+      };
+      expr.0.iter().filter_map(max_lib).chain(here).max()
+    }
+
+    if let Some(max) = exprs.iter().filter_map(max_lib).max() {
+      self.next_id = self.next_id.max(max + 1);
+    }
+  }
+
+  /// Like [`LibraryRegistry::reserve`], for every expression in `corpus`.
+  pub fn reserve_corpus(&mut self, corpus: &InternedCorpus<Op>) {
+    let max = corpus
+      .interner()
+      .nodes()
+      .filter_map(|(_, node)| match node.as_binding_expr() {
+        Some(
+          BindingExpr::LibVar(lib)
+          | BindingExpr::Lib(lib, _, _)
+          | BindingExpr::LibRec(lib, _, _),
+        ) => Some(lib.0),
+        _ => None,
+      })
+      .max();
+    if let Some(max) = max {
+      self.next_id = self.next_id.max(max + 1);
+    }
+  }
+
+  /// Registers the library functions defined in `llr`, the result of a round
+  /// of library learning, under fresh names. Returns the rewritten
+  /// expressions of `llr`, with references to the new library functions
+  /// renamed accordingly.
+  pub fn register_round(
+    &mut self,
+    round: usize,
+    llr: &[AstNode<Op>],
+  ) -> Vec<Expr<Op>> {
+    let new_libs = plumbing::libs(llr);
+
+    let mut names: HashMap<LibId, LibId> = HashMap::new();
+    for &lib in new_libs.keys() {
+      names.insert(lib, LibId(self.next_id));
+      self.next_id += 1;
+    }
+
+    for (lib, body) in new_libs {
+      let body = rename(&body, &names);
+      self.libs.insert(names[&lib], RegisteredLib { round, body });
+    }
+
+    plumbing::exprs(llr)
+      .into_iter()
+      .map(|expr| {
+        let expr: RecExpr<_> = expr.into();
+        RecExpr::from(rename(expr.as_ref(), &names)).into()
+      })
+      .collect()
+  }
+
+  /// Combines the registered library functions and `exprs` into a single
+  /// expression.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `exprs` is empty.
+  #[must_use]
+  pub fn combine(&self, exprs: Vec<Expr<Op>>) -> Expr<Op> {
+    let libs: HashMap<_, _> =
+      self.libs.iter().map(|(&lib, reg)| (lib, reg.body.clone())).collect();
+    plumbing::combine(libs, exprs)
+  }
+
+  /// Returns the library function named `lib`, if it has been registered.
+  #[must_use]
+  pub fn get(&self, lib: LibId) -> Option<&RegisteredLib<Op>> {
+    self.libs.get(&lib)
+  }
+
+  /// The definitions of the library functions learned in round `round`, by
+  /// name.
+  #[must_use]
+  pub fn learned_in(&self, round: usize) -> BTreeMap<LibId, Expr<Op>> {
+    self
+      .libs
+      .iter()
+      .filter(|(_, reg)| reg.round == round)
+      .map(|(&lib, reg)| (lib, RecExpr::from(reg.body.clone()).into()))
+      .collect()
+  }
+
+  /// Returns an iterator over the registered library functions, in order of
+  /// their names.
+  pub fn iter(&self) -> impl Iterator<Item = (LibId, &RegisteredLib<Op>)> + '_ {
+    self.libs.iter().map(|(&lib, reg)| (lib, reg))
+  }
+
+  /// The number of registered library functions.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.libs.len()
+  }
+
+  /// Returns `true` if no library functions have been registered.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.libs.is_empty()
+  }
+}
+
+/// Renames the library functions defined or referenced in `nodes` according
+/// to `names`. Library functions not in `names` keep their names.
+fn rename<Op>(
+  nodes: &[AstNode<Op>],
+  names: &HashMap<LibId, LibId>,
+) -> Vec<AstNode<Op>>
+where
+  Op: Teachable + Clone,
+{
+  nodes
+    .iter()
+    .map(|node| match node.as_binding_expr() {
+      Some(BindingExpr::LibVar(lib)) => {
+        names.get(&lib).map_or_else(|| node.clone(), |&new| Op::lib_var(new))
+      }
+      Some(BindingExpr::Lib(lib, def, body)) => names
+        .get(&lib)
+        .map_or_else(|| node.clone(), |&new| Op::lib(new, *def, *body)),
+      Some(BindingExpr::LibRec(lib, def, body)) => names
+        .get(&lib)
+        .map_or_else(|| node.clone(), |&new| Op::lib_rec(new, *def, *body)),
+      _ => node.clone(),
+    })
+    .collect()
+}
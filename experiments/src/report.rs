@@ -0,0 +1,291 @@
+//! Structured reports of experiment runs, written as JSON alongside the CSV
+//! output of [`Experiment::write_to_csv`](super::Experiment::write_to_csv).
+
+use std::{
+  collections::BTreeMap,
+  fmt::{self, Debug, Display, Formatter},
+  hash::Hash,
+  io,
+  time::Duration,
+};
+
+use babble::{
+  Arity, Expr, LibId, LibNames, Pretty, PrettyOptions, Printable, Teachable,
+};
+use egg::{Analysis, Language, RecExpr, Rewrite, Runner, StopReason};
+use serde::{Deserialize, Serialize};
+
+use super::{plumbing, Metadata};
+
+/// Statistics about a single round of library learning.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RoundReport {
+  /// The index of the round, starting from 0.
+  pub round: usize,
+  /// The cost of the programs produced by this round.
+  pub final_cost: usize,
+  /// The number of libraries used by the programs produced by this round.
+  pub num_libs: usize,
+  /// The number of e-nodes in the e-graph from which the programs were
+  /// extracted, if there was one.
+  pub egraph_size: Option<usize>,
+  /// Why equality saturation stopped, if it was run.
+  pub stop_reason: Option<String>,
+  /// How long the round took.
+  pub time_elapsed: Duration,
+}
+
+/// What a single round of [`Rounds`](super::Rounds) produced: the programs
+/// it rewrote, the library functions it learned, and the resulting cost.
+/// Together, the snapshots of every round show how the library functions
+/// of later rounds build on those of earlier ones.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct RoundSnapshot<Op> {
+  /// The index of the round, starting from 0.
+  pub round: usize,
+  /// The programs as rewritten by this round. Library functions are
+  /// referred to by the names they have in the final expression.
+  pub corpus: Vec<Expr<Op>>,
+  /// The definitions of the library functions learned in this round.
+  pub libs: BTreeMap<LibId, Expr<Op>>,
+  /// The cost of `corpus` together with the definitions of every library
+  /// function learned so far.
+  pub cost: usize,
+}
+
+/// The outcome of one restart of a beam search with restarts; see
+/// [`BeamExperiment::with_restarts`](super::BeamExperiment::with_restarts).
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct RestartReport {
+  /// The seed with which ties in the beam were broken, or `None` for the
+  /// unperturbed search.
+  pub seed: Option<u64>,
+  /// The cost of the programs found by this restart.
+  pub final_cost: usize,
+  /// The number of libraries used by those programs.
+  pub num_libs: usize,
+  /// How long the restart took, not counting the DSRs and anti-unification,
+  /// which are shared between restarts.
+  pub time_elapsed: Duration,
+}
+
+/// Statistics about a run of equality saturation.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct SaturationReport {
+  /// The number of times each rewrite was applied, by name.
+  pub rule_applications: BTreeMap<String, usize>,
+  /// The number of iterations which were run.
+  pub iterations: usize,
+  /// Whether the e-graph was saturated, i.e. no rewrite applied anymore.
+  pub saturated: bool,
+  /// Why equality saturation stopped.
+  pub stop_reason: Option<String>,
+  /// Statistics about each rewrite, by name.
+  #[serde(default)]
+  pub rule_stats: BTreeMap<String, RuleStats>,
+}
+
+/// Statistics about a single rewrite during a run of equality saturation.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  Default,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct RuleStats {
+  /// The number of matches the rewrite's searcher found.
+  pub matches: usize,
+  /// The number of times the rewrite was applied.
+  pub applications: usize,
+  /// The number of e-nodes added to the e-graph by applying the rewrite,
+  /// counted before each rebuild.
+  pub nodes_added: usize,
+}
+
+impl SaturationReport {
+  /// Summarizes the run of `rewrites` by `runner`, whose scheduler recorded
+  /// `rule_stats`.
+  pub(crate) fn new<L, N>(
+    runner: &Runner<L, N, ()>,
+    rewrites: &[Rewrite<L, N>],
+    rule_stats: BTreeMap<String, RuleStats>,
+  ) -> Self
+  where
+    L: Language,
+    N: Analysis<L>,
+  {
+    let mut rule_applications: BTreeMap<String, usize> =
+      rewrites.iter().map(|rewrite| (rewrite.name.to_string(), 0)).collect();
+    for iteration in &runner.iterations {
+      for (name, &applied) in &iteration.applied {
+        *rule_applications.entry(name.to_string()).or_default() += applied;
+      }
+    }
+    Self {
+      rule_applications,
+      iterations: runner.iterations.len(),
+      saturated: matches!(runner.stop_reason, Some(StopReason::Saturated)),
+      stop_reason: runner.stop_reason.as_ref().map(|r| format!("{r:?}")),
+      rule_stats,
+    }
+  }
+}
+
+/// The definition of a learned library function.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LibReport {
+  /// The name of the library function.
+  pub name: String,
+  /// The pretty-printed definition of the library function.
+  pub definition: String,
+}
+
+/// The structured result of running an experiment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExperimentReport {
+  /// The experiment's title.
+  pub title: String,
+  /// The experiment's metadata, as in the columns of its CSV rows.
+  pub metadata: Metadata,
+  /// The cost of the input programs.
+  pub initial_cost: usize,
+  /// The cost of the final programs.
+  pub final_cost: usize,
+  /// The compression factor from the input to the final programs.
+  pub compression: f64,
+  /// The number of libraries used by the final programs.
+  pub num_libs: usize,
+  /// How long the whole experiment took.
+  pub time_elapsed: Duration,
+  /// The definitions of the libraries used by the final programs, in order
+  /// of name.
+  pub libs: Vec<LibReport>,
+  /// Statistics about each round of the experiment.
+  pub rounds: Vec<RoundReport>,
+  /// Whether the experiment ran out of time.
+  pub timed_out: bool,
+}
+
+impl LibReport {
+  /// Returns the definitions of the libraries defined in `expr`, in order of
+  /// name.
+  pub fn from_expr<Op>(expr: &Expr<Op>) -> Vec<Self>
+  where
+    Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  {
+    let rc: RecExpr<_> = expr.clone().into();
+    let mut libs: Vec<_> = plumbing::libs(rc.as_ref()).into_iter().collect();
+    libs.sort_unstable_by_key(|(lib, _)| *lib);
+    libs
+      .into_iter()
+      .map(|(lib, nodes)| Self {
+        name: lib.to_string(),
+        definition: Pretty(&Expr::from(RecExpr::from(nodes))).to_string(),
+      })
+      .collect()
+  }
+
+  /// Like [`LibReport::from_expr`], but refers to the libraries, including
+  /// within their definitions, by their names in `names`.
+  pub fn from_expr_with_names<Op>(
+    expr: &Expr<Op>,
+    names: &LibNames,
+  ) -> Vec<Self>
+  where
+    Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Display,
+  {
+    let rc: RecExpr<_> = expr.clone().into();
+    let mut libs: Vec<_> = plumbing::libs(rc.as_ref()).into_iter().collect();
+    libs.sort_unstable_by_key(|(lib, _)| *lib);
+    libs
+      .into_iter()
+      .map(|(lib, nodes)| Self {
+        name: names.label(lib),
+        definition: Expr::from(RecExpr::from(nodes))
+          .pretty_with(PrettyOptions::default())
+          .with_lib_names(names)
+          .to_string(),
+      })
+      .collect()
+  }
+}
+
+/// The format in which a [`ReportWriter`] writes reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+  /// A single JSON array of all the reports, written once all of the
+  /// experiments have finished.
+  Json,
+  /// One JSON object per line, written as soon as each experiment finishes.
+  Ndjson,
+}
+
+/// Writes [`ExperimentReport`]s in a [`ReportFormat`].
+pub struct ReportWriter {
+  writer: Box<dyn io::Write>,
+  format: ReportFormat,
+  /// Reports which have not been written yet.
+  pending: Vec<ExperimentReport>,
+}
+
+impl Debug for ReportWriter {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ReportWriter")
+      .field("format", &self.format)
+      .field("pending", &self.pending)
+      .finish_non_exhaustive()
+  }
+}
+
+impl ReportWriter {
+  /// Creates a writer which writes reports to `writer` in `format`.
+  #[must_use]
+  pub fn new(writer: Box<dyn io::Write>, format: ReportFormat) -> Self {
+    Self { writer, format, pending: Vec::new() }
+  }
+
+  /// Writes `report`. Depending on the format, the report might not be
+  /// written until [`ReportWriter::finish`] is called.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the report cannot be written.
+  pub fn write(&mut self, report: ExperimentReport) -> io::Result<()> {
+    match self.format {
+      ReportFormat::Json => self.pending.push(report),
+      ReportFormat::Ndjson => {
+        serde_json::to_writer(&mut self.writer, &report)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Writes any reports which have not been written yet.
+  ///
+  /// # Errors
+  ///
+  /// Errors if the reports cannot be written.
+  pub fn finish(mut self) -> io::Result<()> {
+    if self.format == ReportFormat::Json {
+      serde_json::to_writer_pretty(&mut self.writer, &self.pending)?;
+      writeln!(self.writer)?;
+    }
+    self.writer.flush()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,199 @@
+//! Reading [`Summary`]s written by earlier versions of the experiments.
+//!
+//! Every serialized summary records the [`SUMMARY_VERSION`] it was written
+//! with; summaries without one were written before versioning and are
+//! version 0. A summary is first read into `SummaryRepr`, which accepts the
+//! fields of every version, and then migrated to the current layout.
+//!
+//! Fields which are only added to [`Summary`] should have a
+//! `#[serde(default)]` both there and here, which doesn't need a new version.
+//! Changing or removing a field does: bump [`SUMMARY_VERSION`], keep the old
+//! field here, and convert it in `SummaryRepr::migrate`.
+
+use crate::{
+  AutotuneReport, EarlyStop, HeldOutReport, LibMetrics, RestartReport,
+  RoundSnapshot, SaturationReport, Summary, VerificationReport,
+};
+use babble::{Expr, InternedCorpus};
+use serde::{Deserialize, Deserializer};
+use std::{hash::Hash, time::Duration};
+
+/// The version of the serialization format of [`Summary`].
+///
+/// - Version 0 stored the initial expressions as `initial_expr_groups`, a
+///   list of groups of expressions.
+/// - Version 1 stores them as `initial_corpus`, an [`InternedCorpus`], and
+///   records the version itself.
+pub const SUMMARY_VERSION: u32 = 1;
+
+/// The fields of a [`Summary`] of any version up to [`SUMMARY_VERSION`].
+#[derive(Debug, Deserialize)]
+#[serde(bound(deserialize = "Op: Deserialize<'de> + Clone + Eq + Hash"))]
+pub(crate) struct SummaryRepr<Op> {
+  #[serde(default)]
+  version: u32,
+  /// Written since version 1.
+  #[serde(default, deserialize_with = "some")]
+  initial_corpus: Option<InternedCorpus<Op>>,
+  /// Written by version 0.
+  #[serde(default, deserialize_with = "some")]
+  initial_expr_groups: Option<Vec<Vec<Expr<Op>>>>,
+  initial_cost: usize,
+  final_expr: Expr<Op>,
+  final_cost: usize,
+  num_libs: usize,
+  run_time: Duration,
+  #[serde(default)]
+  libs: Vec<LibMetrics>,
+  #[serde(default)]
+  saturation: Option<SaturationReport>,
+  #[serde(default)]
+  held_out: Option<HeldOutReport>,
+  #[serde(default)]
+  stopped_early: Option<EarlyStop>,
+  #[serde(default)]
+  restarts: Vec<RestartReport>,
+  #[serde(default)]
+  rounds: Vec<RoundSnapshot<Op>>,
+  #[serde(default)]
+  autotune: Option<AutotuneReport>,
+  #[serde(default)]
+  verification: Option<VerificationReport>,
+}
+
+impl<Op: Clone + Eq + Hash> SummaryRepr<Op> {
+  /// Converts the fields of any version into a current [`Summary`].
+  fn migrate(self) -> Result<Summary<Op>, String> {
+    if self.version > SUMMARY_VERSION {
+      return Err(format!(
+        "summary version {} is newer than the supported version \
+         {SUMMARY_VERSION}",
+        self.version
+      ));
+    }
+    let initial_corpus = match (self.initial_corpus, self.initial_expr_groups) {
+      (Some(corpus), _) => corpus,
+      (None, Some(groups)) => InternedCorpus::from_groups(groups),
+      (None, None) => return Err("missing field `initial_corpus`".into()),
+    };
+    Ok(Summary {
+      version: SUMMARY_VERSION,
+      initial_corpus,
+      initial_cost: self.initial_cost,
+      final_expr: self.final_expr,
+      final_cost: self.final_cost,
+      num_libs: self.num_libs,
+      run_time: self.run_time,
+      libs: self.libs,
+      saturation: self.saturation,
+      held_out: self.held_out,
+      stopped_early: self.stopped_early,
+      restarts: self.restarts,
+      rounds: self.rounds,
+      autotune: self.autotune,
+      verification: self.verification,
+    })
+  }
+}
+
+impl<Op: Clone + Eq + Hash> TryFrom<SummaryRepr<Op>> for Summary<Op> {
+  type Error = String;
+
+  fn try_from(repr: SummaryRepr<Op>) -> Result<Self, Self::Error> {
+    repr.migrate()
+  }
+}
+
+/// Deserializes a field which is optional only because it is missing from
+/// some versions, so that present values don't need to be written as
+/// `Some(..)` in RON.
+fn some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Deserialize<'de>,
+{
+  T::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SUMMARY_VERSION;
+  use crate::{dreamcoder::expr::DreamCoderOp, Summary};
+  use babble::{AstNode, Expr, InternedCorpus};
+  use serde::Serialize;
+  use std::time::Duration;
+
+  /// The layout of a [`Summary`] before versioning.
+  #[derive(Serialize)]
+  struct SummaryV0 {
+    initial_expr_groups: Vec<Vec<Expr<DreamCoderOp>>>,
+    initial_cost: usize,
+    final_expr: Expr<DreamCoderOp>,
+    final_cost: usize,
+    num_libs: usize,
+    run_time: Duration,
+  }
+
+  fn symbol(name: &str) -> Expr<DreamCoderOp> {
+    Expr(AstNode::leaf(DreamCoderOp::Symbol(name.into())))
+  }
+
+  fn summary() -> Summary<DreamCoderOp> {
+    Summary {
+      version: SUMMARY_VERSION,
+      initial_corpus: InternedCorpus::from_groups(vec![vec![symbol("a")]]),
+      initial_cost: 1,
+      final_expr: symbol("a"),
+      final_cost: 1,
+      num_libs: 0,
+      run_time: Duration::from_millis(5),
+      libs: Vec::new(),
+      saturation: None,
+      held_out: None,
+      stopped_early: None,
+      restarts: Vec::new(),
+      rounds: Vec::new(),
+      autotune: None,
+      verification: None,
+    }
+  }
+
+  #[test]
+  fn reads_unversioned_summary() {
+    let groups = vec![vec![symbol("a")], vec![symbol("b")]];
+    let legacy = SummaryV0 {
+      initial_expr_groups: groups.clone(),
+      initial_cost: 2,
+      final_expr: symbol("c"),
+      final_cost: 1,
+      num_libs: 0,
+      run_time: Duration::from_secs(1),
+    };
+    let serialized = ron::to_string(&legacy).unwrap();
+    let summary: Summary<DreamCoderOp> = ron::from_str(&serialized).unwrap();
+    assert_eq!(summary.version, SUMMARY_VERSION);
+    assert_eq!(summary.initial_corpus, InternedCorpus::from_groups(groups));
+    assert_eq!(summary.final_expr, symbol("c"));
+    assert!(summary.libs.is_empty());
+  }
+
+  #[test]
+  fn round_trips_current_summary() {
+    let summary = summary();
+    let serialized = ron::to_string(&summary).unwrap();
+    let read: Summary<DreamCoderOp> = ron::from_str(&serialized).unwrap();
+    assert_eq!(read, summary);
+  }
+
+  #[test]
+  fn rejects_newer_summary() {
+    let serialized = ron::to_string(&summary()).unwrap();
+    let newer = serialized.replacen(
+      &format!("version:{SUMMARY_VERSION}"),
+      &format!("version:{}", SUMMARY_VERSION + 1),
+      1,
+    );
+    assert_ne!(newer, serialized);
+    assert!(ron::from_str::<Summary<DreamCoderOp>>(&newer).is_err());
+  }
+}
@@ -75,7 +75,9 @@ impl<'a> Context<'a> {
         let val = self.eval(expr)?;
         val.map_shapes(|shape| shape.rotate(angle))
       }
-      (&Smiley::Lib(name), [bound_value, body]) => {
+      // Lambdas are evaluated in the context they're applied in, so the
+      // libs of a recursive group are bound by the time they're called.
+      (&(Smiley::Lib(name) | Smiley::LibRec(name)), [bound_value, body]) => {
         let bound_value = self.eval(bound_value)?;
         let context = self.clone().with_lib(name, bound_value);
         context.eval(body)?
@@ -45,8 +45,12 @@ pub(crate) enum Smiley {
   Apply,
   /// Create an anonymous, de Bruijn-indexed function.
   Lambda,
+  /// Create a recursive function as a fixpoint.
+  Fix,
   /// Bind a lib fn within an expression.
   Lib(LibId),
+  /// Bind a lib fn in a mutually recursive group within an expression.
+  LibRec(LibId),
 }
 
 impl Debug for Smiley {
@@ -64,13 +68,14 @@ impl Arity for Smiley {
       | Self::Circle
       | Self::Line
       | Self::LibVar(_) => 0,
-      Self::Lambda | Self::Compose => 1,
+      Self::Lambda | Self::Fix | Self::Compose => 1,
       Self::Scale
       | Self::ScaleX
       | Self::ScaleY
       | Self::Rotate
       | Self::Apply
-      | Self::Lib(_) => 2,
+      | Self::Lib(_)
+      | Self::LibRec(_) => 2,
       Self::Move => 3,
     }
   }
@@ -99,7 +104,9 @@ impl Display for Smiley {
       Self::Compose => f.write_str("+"),
       Self::Apply => f.write_str("@"),
       Self::Lambda => f.write_str("λ"),
+      Self::Fix => f.write_str("fix"),
       Self::Lib(ix) => write!(f, "lib {ix}"),
+      Self::LibRec(ix) => write!(f, "librec {ix}"),
       Self::LibVar(ix) => write!(f, "{ix}"),
     }
   }
@@ -113,6 +120,7 @@ impl FromStr for Smiley {
       "circle" => Self::Circle,
       "line" => Self::Line,
       "lambda" | "λ" => Self::Lambda,
+      "fix" => Self::Fix,
       "scale" => Self::Scale,
       "scale-x" => Self::ScaleX,
       "scale-y" => Self::ScaleY,
@@ -131,6 +139,12 @@ impl FromStr for Smiley {
           .and_then(str::parse)
         {
           Self::Lib(lv)
+        } else if let Ok(lv) = s
+          .strip_prefix("librec ")
+          .ok_or(ParseLibIdError::NoLeadingL)
+          .and_then(str::parse)
+        {
+          Self::LibRec(lv)
         } else if let Ok(n) = s.parse::<i32>() {
           Self::Int(n)
         } else if let Ok(f) = s.parse::<NotNan<f64>>() {
@@ -148,23 +162,31 @@ impl Teachable for Smiley {
   fn from_binding_expr<T>(binding_expr: BindingExpr<T>) -> AstNode<Self, T> {
     match binding_expr {
       BindingExpr::Lambda(body) => AstNode::new(Self::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(Self::Fix, [body]),
       BindingExpr::Apply(fun, arg) => AstNode::new(Self::Apply, [fun, arg]),
       BindingExpr::Var(index) => AstNode::leaf(Self::Var(index)),
       BindingExpr::LibVar(ix) => AstNode::leaf(Self::LibVar(ix)),
       BindingExpr::Lib(ix, bound_value, body) => {
         AstNode::new(Self::Lib(ix), [bound_value, body])
       }
+      BindingExpr::LibRec(ix, bound_value, body) => {
+        AstNode::new(Self::LibRec(ix), [bound_value, body])
+      }
     }
   }
 
   fn as_binding_expr<T>(node: &AstNode<Self, T>) -> Option<BindingExpr<&T>> {
     let binding_expr = match node.as_parts() {
       (Self::Lambda, [body]) => BindingExpr::Lambda(body),
+      (Self::Fix, [body]) => BindingExpr::Fix(body),
       (Self::Apply, [fun, arg]) => BindingExpr::Apply(fun, arg),
       (&Self::Var(index), []) => BindingExpr::Var(index),
       (Self::Lib(ix), [bound_value, body]) => {
         BindingExpr::Lib(*ix, bound_value, body)
       }
+      (Self::LibRec(ix), [bound_value, body]) => {
+        BindingExpr::LibRec(*ix, bound_value, body)
+      }
       (Self::LibVar(ix), []) => BindingExpr::LibVar(*ix),
       _ => return None,
     };
@@ -192,7 +214,7 @@ impl Printable for Smiley {
       | Self::ScaleY
       | Self::Rotate
       | Self::Apply => 40,
-      Self::Lambda | Self::Lib(_) => 10,
+      Self::Lambda | Self::Fix | Self::Lib(_) | Self::LibRec(_) => 10,
     }
   }
 
@@ -271,8 +293,8 @@ lazy_static! {
     ]
     .leak();
 
-        // TODO: Check for captures of de Bruijn variables and re-index if necessary.
-        // lift_lambda: "(lambda (lib ?x ?v ?e))" => "(lib ?x ?v (lambda ?e))";
+        // Libs are lifted out of lambdas by `babble::extract::lift_libs`, which
+        // re-indexes their definitions.
 
         // (Effectively) unary operators
         // lift_scale: "(scale ?a (lib ?x ?v ?e))" => "(lib ?x ?v (scale ?a ?e))";
@@ -125,6 +125,6 @@ fn main() {
     );
 
     println!("running...");
-    exps.run("harness/data_gen/res_smiley.csv");
+    exps.run("harness/data_gen/res_smiley.csv").unwrap();
   }
 }
@@ -0,0 +1,408 @@
+//! Checking that library learning preserves the meaning of programs: each
+//! original program and its rewritten counterpart are run on the same
+//! inputs, and their outputs are compared. This catches unsound
+//! domain-specific rewrites and bugs in extraction.
+
+use std::{
+  fmt::{self, Debug, Formatter},
+  hash::{Hash, Hasher},
+  marker::PhantomData,
+};
+
+use babble::{
+  extract::beam::PartialLibCost, Arity, AstNode, BindingExpr, Error, Eval,
+  EvalError, Expr, InternedCorpus, Interpreter, Printable, Teachable,
+};
+use egg::Rewrite;
+use serde::{Deserialize, Serialize};
+
+use super::{CsvWriter, Experiment, ExperimentResult, Metadata};
+
+/// The number of evaluation steps each run of a program may take by
+/// default.
+const FUEL: usize = 1_000_000;
+
+/// A program whose rewritten counterpart computed something different.
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Mismatch {
+  /// The index of the program.
+  pub program: usize,
+  /// The index of the input it was run on.
+  pub input: usize,
+  /// What the original program computed.
+  pub expected: String,
+  /// What the rewritten program computed, or why it failed.
+  pub found: String,
+}
+
+/// The outcome of running the original and rewritten programs on the
+/// inputs given to [`Verify`].
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct VerificationReport {
+  /// The number of pairs of a program and an input which were checked.
+  pub checked: usize,
+  /// The number of pairs which couldn't be checked, because the original
+  /// program failed on the input or computed a function.
+  pub skipped: usize,
+  /// The pairs on which the rewritten program didn't compute what the
+  /// original one did.
+  pub mismatches: Vec<Mismatch>,
+  /// The number of programs which couldn't be checked because they have no
+  /// counterpart: original programs which weren't rewritten, or rewritten
+  /// programs which have no original.
+  #[serde(default)]
+  pub unmatched: usize,
+}
+
+impl VerificationReport {
+  /// Whether every original program was rewritten into one which computed
+  /// what the original one did.
+  #[must_use]
+  pub fn preserved(&self) -> bool {
+    self.mismatches.is_empty() && self.unmatched == 0
+  }
+}
+
+/// An experiment whose rewritten programs are checked against the original
+/// ones by running both on the same inputs. Each input is a list of
+/// arguments, which every program is applied to in turn; with no inputs,
+/// the programs are evaluated as they are.
+pub struct Verify<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  T: Experiment<Op>,
+{
+  experiment: T,
+  inputs: Vec<Vec<Expr<Op>>>,
+  /// The number of evaluation steps each run of a program may take.
+  fuel: usize,
+  /// Whether a mismatch fails the experiment.
+  strict: bool,
+  phantom: PhantomData<Op>,
+}
+
+impl<Op, T> Debug for Verify<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord,
+  T: Experiment<Op>,
+{
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Verify")
+      .field("inputs", &self.inputs)
+      .field("fuel", &self.fuel)
+      .field("strict", &self.strict)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<Op, T> Verify<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Eval,
+  T: Experiment<Op>,
+{
+  /// Checks the programs rewritten by `experiment` on `inputs`.
+  pub fn new(experiment: T, inputs: Vec<Vec<Expr<Op>>>) -> Self {
+    Self { experiment, inputs, fuel: FUEL, strict: false, phantom: PhantomData }
+  }
+
+  /// Lets each run of a program take `fuel` evaluation steps, after which
+  /// it counts as failed. By default, it may take a million.
+  #[must_use]
+  pub fn with_fuel(mut self, fuel: usize) -> Self {
+    self.fuel = fuel;
+    self
+  }
+
+  /// Fails the experiment with [`Error::NotPreserved`] if a rewritten
+  /// program computes something different from the original one, or if
+  /// some program has no counterpart, if `strict` is set. By default, these
+  /// are only recorded in the result's
+  /// [`verification`](ExperimentResult::verification).
+  #[must_use]
+  pub fn with_strict(mut self, strict: bool) -> Self {
+    self.strict = strict;
+    self
+  }
+
+  /// Runs each of `originals` and the corresponding rewritten program of
+  /// `final_expr` on every input, and records the outcome in `res`.
+  fn check(
+    &self,
+    originals: &[Expr<Op>],
+    mut res: ExperimentResult<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let no_inputs = [Vec::new()];
+    let inputs: &[Vec<Expr<Op>>] =
+      if self.inputs.is_empty() { &no_inputs } else { &*self.inputs };
+    let rewritten = with_libs(&res.final_expr);
+    let mut report = VerificationReport::default();
+    if rewritten.len() != originals.len() {
+      let matched = originals.len().min(rewritten.len());
+      log::warn!(
+        "{} programs were rewritten into {}; checking the first {matched}",
+        originals.len(),
+        rewritten.len(),
+      );
+      if self.strict {
+        return Err(Error::NotPreserved(matched));
+      }
+      report.unmatched = originals.len().abs_diff(rewritten.len());
+    }
+
+    for (program, (original, rewritten)) in
+      originals.iter().zip(&rewritten).enumerate()
+    {
+      for (input, args) in inputs.iter().enumerate() {
+        let expected = match self.run_program(original, args) {
+          Ok(expected) => expected,
+          Err(_) => {
+            report.skipped += 1;
+            continue;
+          }
+        };
+        report.checked += 1;
+        let found = self.run_program(rewritten, args);
+        if found.as_ref() != Ok(&expected) {
+          let found = match found {
+            Ok(value) => format!("{value:?}"),
+            Err(e) => e.to_string(),
+          };
+          log::warn!(
+            "rewritten program {program} computed {found} on input {input}, \
+             expected {expected:?}"
+          );
+          if self.strict {
+            return Err(Error::NotPreserved(program));
+          }
+          report.mismatches.push(Mismatch {
+            program,
+            input,
+            expected: format!("{expected:?}"),
+            found,
+          });
+        }
+      }
+    }
+    log::info!(
+      "checked {} runs of the rewritten programs: {} mismatches, {} skipped, \
+       {} unmatched programs",
+      report.checked,
+      report.mismatches.len(),
+      report.skipped,
+      report.unmatched
+    );
+    res.verification = Some(report);
+    Ok(res)
+  }
+
+  /// Applies `program` to `args` and evaluates the result, which must not be
+  /// a function.
+  fn run_program(
+    &self,
+    program: &Expr<Op>,
+    args: &[Expr<Op>],
+  ) -> Result<Op::Value, EvalError> {
+    let applied = args
+      .iter()
+      .fold(program.clone(), |fun, arg| Expr(Op::apply(fun, arg.clone())));
+    Interpreter::new().with_fuel(self.fuel).eval(&applied)?.into_prim()
+  }
+}
+
+/// Splits `expr`, a list of programs with the library functions they use
+/// defined at the top, into the programs, each wrapped in the definitions
+/// of the library functions so that it can be evaluated on its own.
+fn with_libs<Op>(expr: &Expr<Op>) -> Vec<Expr<Op>>
+where
+  Op: Teachable + Clone + PartialEq,
+{
+  let mut libs = Vec::new();
+  let mut body = expr;
+  loop {
+    match body.0.as_binding_expr() {
+      Some(BindingExpr::Lib(id, definition, next)) => {
+        libs.push((false, id, definition));
+        body = next;
+      }
+      Some(BindingExpr::LibRec(id, definition, next)) => {
+        libs.push((true, id, definition));
+        body = next;
+      }
+      _ => break,
+    }
+  }
+  let programs = if *body.0.operation() == Op::list() {
+    body.0.args()
+  } else {
+    std::slice::from_ref(body)
+  };
+  programs
+    .iter()
+    .map(|program| {
+      libs.iter().rev().fold(program.clone(), |body, &(rec, id, definition)| {
+        let definition = definition.clone();
+        Expr(if rec {
+          Op::lib_rec(id, definition, body)
+        } else {
+          Op::lib(id, definition, body)
+        })
+      })
+    })
+    .collect()
+}
+
+impl<Op, T> Experiment<Op> for Verify<Op, T>
+where
+  Op: Printable + Teachable + Hash + Clone + Debug + Arity + Ord + Eval,
+  T: Experiment<Op>,
+{
+  fn dsrs(&self) -> &[Rewrite<AstNode<Op>, PartialLibCost>] {
+    self.experiment.dsrs()
+  }
+
+  fn fingerprint(&self, mut hasher: &mut dyn Hasher) -> bool {
+    (&self.inputs, self.fuel, self.strict).hash(&mut hasher);
+    self.experiment.fingerprint(hasher)
+  }
+
+  fn expr_size(&self, expr: &Expr<Op>) -> usize {
+    self.experiment.expr_size(expr)
+  }
+
+  fn run(
+    &self,
+    exprs: Vec<Expr<Op>>,
+    writer: &mut CsvWriter,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let res = self.experiment.run(exprs.clone(), writer)?;
+    self.check(&exprs, res)
+  }
+
+  fn run_multi(
+    &self,
+    expr_groups: Vec<Vec<Expr<Op>>>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let originals: Vec<_> =
+      expr_groups.iter().filter_map(|group| group.first().cloned()).collect();
+    let res = self.experiment.run_multi(expr_groups)?;
+    self.check(&originals, res)
+  }
+
+  fn run_interned(
+    &self,
+    corpus: &InternedCorpus<Op>,
+  ) -> Result<ExperimentResult<Op>, Error> {
+    let originals: Vec<_> = corpus
+      .groups()
+      .iter()
+      .filter_map(|group| {
+        group.first().map(|&expr| corpus.interner().expr(expr))
+      })
+      .collect();
+    let res = self.experiment.run_interned(corpus)?;
+    self.check(&originals, res)
+  }
+
+  fn fmt_title(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "verified on {} inputs | ", self.inputs.len())?;
+    self.experiment.fmt_title(f)
+  }
+
+  fn total_rounds(&self) -> usize {
+    self.experiment.total_rounds()
+  }
+
+  fn metadata(&self) -> Metadata {
+    self.experiment.metadata().with("verify", self.inputs.len())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Verify;
+  use crate::{BeamExperiment, CsvWriter, Experiment};
+  use babble::{rewrites, simple_lang::SimpleOp, Error, Expr};
+  use std::io;
+
+  fn parse(input: &str) -> Expr<SimpleOp> {
+    Expr::parse_with(input).unwrap()
+  }
+
+  /// A beam search which uses the rewrites in `dsrs`.
+  fn experiment(dsrs: &str) -> BeamExperiment<SimpleOp, ()> {
+    let dsrs = rewrites::parse(dsrs).unwrap();
+    BeamExperiment::new(dsrs, 10, 10, 1, (), false, None, 1)
+      .with_deterministic(true)
+  }
+
+  /// A rewrite which drops the second element of a list.
+  const UNSOUND: &str = "first: (list ?x ?y) => ?x";
+
+  fn programs() -> Vec<Expr<SimpleOp>> {
+    vec![parse("(list a b)"), parse("(list (list c) (list c))")]
+  }
+
+  #[test]
+  fn sound_rewrites_are_preserved() {
+    let verify = Verify::new(experiment(""), Vec::new());
+    let mut writer = CsvWriter::from_writer(Box::new(io::sink()));
+    let res = verify.run(programs(), &mut writer).unwrap();
+    let report = res.verification.unwrap();
+    assert!(report.preserved(), "{report:?}");
+    assert_eq!(report.checked, 2);
+  }
+
+  #[test]
+  fn reports_unsound_rewrites() {
+    let verify = Verify::new(experiment(UNSOUND), Vec::new());
+    let groups = programs().into_iter().map(|program| vec![program]).collect();
+    let res = verify.run_multi(groups).unwrap();
+    let report = res.verification.unwrap();
+    assert!(!report.preserved());
+    let programs: Vec<_> =
+      report.mismatches.iter().map(|mismatch| mismatch.program).collect();
+    assert_eq!(programs, [0, 1]);
+  }
+
+  #[test]
+  fn strict_fails_on_mismatch() {
+    let verify = Verify::new(experiment(UNSOUND), Vec::new()).with_strict(true);
+    let mut writer = CsvWriter::from_writer(Box::new(io::sink()));
+    assert!(matches!(
+      verify.run(programs(), &mut writer),
+      Err(Error::NotPreserved(0))
+    ));
+  }
+
+  #[test]
+  fn reports_missing_programs() {
+    let verify = Verify::new(experiment(""), Vec::new());
+    let programs = programs();
+    let res =
+      verify.experiment.run_multi(vec![programs[..1].to_vec()]).unwrap();
+    let report = verify.check(&programs, res).unwrap().verification.unwrap();
+    assert_eq!(report.unmatched, 1);
+    assert!(!report.preserved());
+
+    let strict = verify.with_strict(true);
+    let res =
+      strict.experiment.run_multi(vec![programs[..1].to_vec()]).unwrap();
+    assert!(matches!(
+      strict.check(&programs, res),
+      Err(Error::NotPreserved(1))
+    ));
+  }
+}
@@ -34,15 +34,20 @@ def param_sweep(path_to_drawing_bab, dts):
                     if not os.path.exists("harness/data_gen/res_drawing.csv"):
                         continue
                     with open("harness/data_gen/res_drawing.csv", 'r') as one:     
-                        for l in one.readlines():
+                        lines = one.readlines()
+                        # Only keep the header of the first run
+                        if all.tell() > 0:
+                            lines = lines[1:]
+                        for l in lines:
                             all.write(l)
 
 def parse_results_csv(path):
-    FIELDS = \
-        ['exp_type', 'timeout', 'beam_size', 'beam_size_2', 'lps',
-            'extra_data', 'round', 'init_size', 'final_size', 'compression', 'num_libs', 'time', 'memory']
+    # The CSV header names the columns; rename the ones plotted below
+    RENAMES = {
+        'kind': 'exp_type', 'final_beams': 'beam_size', 'inter_beams': 'beam_size_2',
+        'initial_cost': 'init_size', 'final_cost': 'final_size', 'time_elapsed': 'time'}
     with open(path) as f:
-        rows = list(csv.DictReader(f, fieldnames=FIELDS))
+        rows = [{RENAMES.get(k, k): v for k, v in row.items()} for row in csv.DictReader(f)]
 
     return rows
 
@@ -23,11 +23,21 @@ pub struct AstNode<Op, T = Id> {
   args: Vec<T>,
 }
 
-pub use expr::{combine_exprs, Expr};
+pub use eval::{Args, Closure, Eval, EvalError, Interpreter, Value};
+pub use expr::{combine_exprs, Expr, ParseExprError, TextPosition};
+pub use interned::{ExprRef, InternedCorpus, Interner};
+pub use lib_names::LibNames;
+pub use named::{NamedExpr, ParseNamedError, UnboundVarError, VarNames};
 pub use partial_expr::PartialExpr;
-pub use pretty::{Precedence, Pretty, Printable, Printer};
+pub use pretty::{
+  Precedence, Pretty, PrettyOptions, PrettyWith, Printable, Printer, Syntax,
+};
 
+mod eval;
 mod expr;
+mod interned;
+mod lib_names;
+mod named;
 mod partial_expr;
 mod pretty;
 
@@ -332,6 +342,12 @@ pub enum ParseNodeError<Op, T, E> {
   ArityError(ArityError<Op, T>),
 }
 
+/// An error which can be returned when parsing an operation whose name is not
+/// recognized, as returned by operations using `#[derive(BabbleOp)]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+#[error("unknown operation: {0}")]
+pub struct UnknownOpError(pub String);
+
 impl<Op> FromOp for AstNode<Op>
 where
   Op: Debug + Arity + FromStr + Clone + Ord + Hash + 'static,
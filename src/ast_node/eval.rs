@@ -0,0 +1,490 @@
+//! An interpreter for expressions, for running programs before and after
+//! library learning.
+//!
+//! The interpreter implements the binding constructs of [`Teachable`]
+//! languages itself: lambdas, applications, fixpoints, de Bruijn-indexed
+//! variables, and library functions, including mutually recursive groups.
+//! A local binding (a "let") is an application of a lambda, so it needs no
+//! special treatment. Every other operation is given its meaning by the
+//! language's [`Eval`] implementation, which computes the operation's value
+//! from its arguments.
+//!
+//! Evaluation is call-by-value, except that the arguments of an operation
+//! are only evaluated when its [`Eval`] implementation asks for them, so
+//! conditionals can be defined without evaluating both branches.
+
+use super::Expr;
+use crate::{
+  learn::LibId,
+  teachable::{BindingExpr, DeBruijnIndex, Teachable},
+};
+use std::{
+  fmt::{self, Debug, Formatter},
+  rc::Rc,
+};
+use thiserror::Error;
+
+/// A trait for languages whose operations have a meaning over values of type
+/// [`Eval::Value`].
+pub trait Eval: Teachable + Sized {
+  /// The values computed by operations, apart from functions.
+  type Value: Clone + Debug + PartialEq;
+
+  /// Computes the value of the operation `self` applied to `args`, which
+  /// can be evaluated as needed.
+  ///
+  /// # Errors
+  /// Returns an error if the operation is applied to arguments it has no
+  /// meaning for, or if evaluating an argument fails.
+  fn eval<'a>(
+    &self,
+    args: Args<'_, 'a, Self>,
+  ) -> Result<Value<'a, Self>, EvalError>;
+}
+
+/// An error from evaluating an expression.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EvalError {
+  /// A de Bruijn-indexed variable isn't bound.
+  #[error("unbound variable {0}")]
+  UnboundVar(DeBruijnIndex),
+  /// A library function isn't bound.
+  #[error("unbound library function {0}")]
+  UnboundLib(LibId),
+  /// A value which isn't a function was applied to an argument.
+  #[error("applied a value which is not a function")]
+  NotAFunction,
+  /// A function was found where another value was expected.
+  #[error("expected a value, found a function")]
+  NotAValue,
+  /// An operation asked for an argument it wasn't given.
+  #[error("no argument {0}")]
+  MissingArg(usize),
+  /// An operation has no meaning for its arguments.
+  #[error("{0}")]
+  Op(String),
+  /// Evaluation took more steps than the interpreter allows.
+  #[error("ran out of fuel")]
+  OutOfFuel,
+}
+
+/// The value of an expression: either a value computed by an operation, or
+/// a function.
+pub enum Value<'a, Op>
+where
+  Op: Eval,
+{
+  /// A value computed by an operation.
+  Prim(Op::Value),
+  /// A function.
+  Fun(Closure<'a, Op>),
+}
+
+impl<Op: Eval> Value<'_, Op> {
+  /// Returns the value computed by an operation, or [`None`] if `self` is a
+  /// function.
+  #[must_use]
+  pub fn as_prim(&self) -> Option<&Op::Value> {
+    match self {
+      Self::Prim(value) => Some(value),
+      Self::Fun(_) => None,
+    }
+  }
+
+  /// Converts `self` into the value computed by an operation.
+  ///
+  /// # Errors
+  /// Returns [`EvalError::NotAValue`] if `self` is a function.
+  pub fn into_prim(self) -> Result<Op::Value, EvalError> {
+    match self {
+      Self::Prim(value) => Ok(value),
+      Self::Fun(_) => Err(EvalError::NotAValue),
+    }
+  }
+
+  /// Returns `true` if `self` is a function.
+  #[must_use]
+  pub fn is_fun(&self) -> bool {
+    matches!(self, Self::Fun(_))
+  }
+}
+
+impl<Op: Eval> Clone for Value<'_, Op> {
+  fn clone(&self) -> Self {
+    match self {
+      Self::Prim(value) => Self::Prim(value.clone()),
+      Self::Fun(closure) => Self::Fun(closure.clone()),
+    }
+  }
+}
+
+impl<Op: Eval> Debug for Value<'_, Op> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Prim(value) => f.debug_tuple("Prim").field(value).finish(),
+      Self::Fun(closure) => f.debug_tuple("Fun").field(closure).finish(),
+    }
+  }
+}
+
+/// A function: the body of a lambda, and the bindings it was evaluated
+/// with.
+pub struct Closure<'a, Op>
+where
+  Op: Eval,
+{
+  body: &'a Expr<Op>,
+  env: Rc<Env<'a, Op>>,
+}
+
+impl<Op: Eval> Clone for Closure<'_, Op> {
+  fn clone(&self) -> Self {
+    Self { body: self.body, env: Rc::clone(&self.env) }
+  }
+}
+
+impl<Op: Eval> Debug for Closure<'_, Op> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Closure").finish_non_exhaustive()
+  }
+}
+
+/// The bindings in scope, innermost first.
+enum Env<'a, Op>
+where
+  Op: Eval,
+{
+  Empty,
+  /// A de Bruijn-indexed variable bound to a value.
+  Var(Value<'a, Op>, Rc<Self>),
+  /// A de Bruijn-indexed variable bound to the fixpoint with this body,
+  /// which is evaluated in this environment whenever it is referenced.
+  Fix(&'a Expr<Op>, Rc<Self>),
+  /// A library function bound to a value.
+  Lib(LibId, Value<'a, Op>, Rc<Self>),
+  /// A group of mutually recursive library functions, each of whose
+  /// definitions is evaluated in this environment whenever it is
+  /// referenced.
+  LibRec(Vec<(LibId, &'a Expr<Op>)>, Rc<Self>),
+}
+
+/// The arguments of an operation, which are evaluated on demand by
+/// [`Args::value`].
+pub struct Args<'i, 'a, Op>
+where
+  Op: Eval,
+{
+  interpreter: &'i mut Interpreter,
+  args: ArgList<'i, 'a, Op>,
+}
+
+/// Where the arguments of an operation come from.
+enum ArgList<'i, 'a, Op>
+where
+  Op: Eval,
+{
+  /// Expressions, to be evaluated with these bindings.
+  Exprs(&'a [Expr<Op>], Rc<Env<'a, Op>>),
+  /// Values which have already been computed.
+  Values(&'i [Value<'a, Op>]),
+}
+
+impl<'a, Op: Eval> Args<'_, 'a, Op> {
+  /// The number of arguments.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    match &self.args {
+      ArgList::Exprs(args, _) => args.len(),
+      ArgList::Values(args) => args.len(),
+    }
+  }
+
+  /// Returns `true` if there are no arguments.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Evaluates the argument at `index`. An argument given as an expression
+  /// is evaluated again every time this is called.
+  ///
+  /// # Errors
+  /// Returns an error if there is no such argument or evaluating it fails.
+  pub fn value(&mut self, index: usize) -> Result<Value<'a, Op>, EvalError> {
+    match self.args {
+      ArgList::Exprs(args, ref env) => {
+        let arg = args.get(index).ok_or(EvalError::MissingArg(index))?;
+        self.interpreter.eval_in(arg, Rc::clone(env))
+      }
+      ArgList::Values(args) => {
+        args.get(index).cloned().ok_or(EvalError::MissingArg(index))
+      }
+    }
+  }
+
+  /// Evaluates the argument at `index`, which must not be a function.
+  ///
+  /// # Errors
+  /// Returns an error if there is no such argument, evaluating it fails, or
+  /// it is a function.
+  pub fn prim(&mut self, index: usize) -> Result<Op::Value, EvalError> {
+    self.value(index)?.into_prim()
+  }
+
+  /// Evaluates every argument, none of which may be a function.
+  ///
+  /// # Errors
+  /// Returns an error if evaluating an argument fails or one is a function.
+  pub fn prims(&mut self) -> Result<Vec<Op::Value>, EvalError> {
+    (0..self.len()).map(|index| self.prim(index)).collect()
+  }
+
+  /// Applies the function `fun` to `arg`, as [`Interpreter::apply`] does.
+  ///
+  /// # Errors
+  /// Returns an error if `fun` isn't a function or applying it fails.
+  pub fn apply(
+    &mut self,
+    fun: Value<'a, Op>,
+    arg: Value<'a, Op>,
+  ) -> Result<Value<'a, Op>, EvalError> {
+    self.interpreter.apply(fun, arg)
+  }
+}
+
+impl<Op: Eval> Debug for Args<'_, '_, Op> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Args").field("len", &self.len()).finish_non_exhaustive()
+  }
+}
+
+/// An interpreter for expressions in [`Eval`] languages; see the [module
+/// documentation](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Interpreter {
+  /// The number of steps evaluation may still take, if it is limited.
+  fuel: Option<usize>,
+}
+
+impl Interpreter {
+  /// Creates an interpreter.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Stops evaluation with [`EvalError::OutOfFuel`] once it has taken
+  /// `fuel` steps in total, each step being the evaluation of one
+  /// subexpression, so that programs which don't terminate can be run
+  /// safely. By default, evaluation takes as many steps as it needs.
+  #[must_use]
+  pub fn with_fuel(mut self, fuel: usize) -> Self {
+    self.fuel = Some(fuel);
+    self
+  }
+
+  /// The number of steps evaluation may still take, or [`None`] if it is
+  /// unlimited.
+  #[must_use]
+  pub fn fuel(&self) -> Option<usize> {
+    self.fuel
+  }
+
+  /// Evaluates the closed expression `expr`.
+  ///
+  /// # Errors
+  /// Returns an error if `expr` has free variables, applies a value which
+  /// isn't a function, runs out of fuel, or uses an operation in a way it
+  /// has no meaning for.
+  pub fn eval<'a, Op: Eval>(
+    &mut self,
+    expr: &'a Expr<Op>,
+  ) -> Result<Value<'a, Op>, EvalError> {
+    self.eval_in(expr, Rc::new(Env::Empty))
+  }
+
+  /// Applies the function `fun` to `arg`.
+  ///
+  /// # Errors
+  /// Returns an error if `fun` isn't a function or evaluating its body
+  /// fails.
+  pub fn apply<'a, Op: Eval>(
+    &mut self,
+    fun: Value<'a, Op>,
+    arg: Value<'a, Op>,
+  ) -> Result<Value<'a, Op>, EvalError> {
+    match fun {
+      Value::Fun(Closure { body, env }) => {
+        self.eval_in(body, Rc::new(Env::Var(arg, env)))
+      }
+      Value::Prim(_) => Err(EvalError::NotAFunction),
+    }
+  }
+
+  /// Applies the operation `op`, which must not be a binding construct, to
+  /// the values `args`. This gives operations a meaning outside of
+  /// expressions, e.g. to evaluate the patterns of rewrites.
+  ///
+  /// # Errors
+  /// Returns an error if `op` has no meaning for `args`.
+  pub fn eval_op<'a, Op: Eval>(
+    &mut self,
+    op: &Op,
+    args: &[Value<'a, Op>],
+  ) -> Result<Value<'a, Op>, EvalError> {
+    self.step()?;
+    op.eval(Args { interpreter: self, args: ArgList::Values(args) })
+  }
+
+  /// Takes one step of evaluation.
+  fn step(&mut self) -> Result<(), EvalError> {
+    match &mut self.fuel {
+      Some(0) => Err(EvalError::OutOfFuel),
+      Some(fuel) => {
+        *fuel -= 1;
+        Ok(())
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// Evaluates `expr` with the bindings `env`.
+  fn eval_in<'a, Op: Eval>(
+    &mut self,
+    expr: &'a Expr<Op>,
+    env: Rc<Env<'a, Op>>,
+  ) -> Result<Value<'a, Op>, EvalError> {
+    self.step()?;
+    match expr.0.as_binding_expr() {
+      Some(BindingExpr::Var(index)) => self.lookup_var(index, &env),
+      Some(BindingExpr::LibVar(id)) => self.lookup_lib(id, &env),
+      Some(BindingExpr::Lambda(body)) => Ok(Value::Fun(Closure { body, env })),
+      Some(BindingExpr::Apply(fun, arg)) => {
+        let fun = self.eval_in(fun, Rc::clone(&env))?;
+        let arg = self.eval_in(arg, env)?;
+        self.apply(fun, arg)
+      }
+      Some(BindingExpr::Fix(body)) => {
+        let env = Rc::new(Env::Fix(body, env));
+        self.eval_in(body, env)
+      }
+      Some(BindingExpr::Lib(id, value, body)) => {
+        let value = self.eval_in(value, Rc::clone(&env))?;
+        self.eval_in(body, Rc::new(Env::Lib(id, value, env)))
+      }
+      Some(BindingExpr::LibRec(id, value, mut body)) => {
+        let mut group = vec![(id, value)];
+        while let Some(BindingExpr::LibRec(id, value, next)) =
+          body.0.as_binding_expr()
+        {
+          group.push((id, value));
+          body = next;
+        }
+        self.eval_in(body, Rc::new(Env::LibRec(group, env)))
+      }
+      None => expr.0.operation().eval(Args {
+        interpreter: self,
+        args: ArgList::Exprs(expr.0.args(), env),
+      }),
+    }
+  }
+
+  /// Looks up the variable `index` in `env`.
+  fn lookup_var<'a, Op: Eval>(
+    &mut self,
+    index: DeBruijnIndex,
+    mut env: &Rc<Env<'a, Op>>,
+  ) -> Result<Value<'a, Op>, EvalError> {
+    let mut skip = index.0;
+    loop {
+      env = match &**env {
+        Env::Empty => return Err(EvalError::UnboundVar(index)),
+        Env::Var(value, _) if skip == 0 => return Ok(value.clone()),
+        Env::Fix(body, _) if skip == 0 => {
+          return self.eval_in(*body, Rc::clone(env))
+        }
+        Env::Var(_, next) | Env::Fix(_, next) => {
+          skip -= 1;
+          next
+        }
+        Env::Lib(_, _, next) | Env::LibRec(_, next) => next,
+      }
+    }
+  }
+
+  /// Looks up the library function `id` in `env`.
+  fn lookup_lib<'a, Op: Eval>(
+    &mut self,
+    id: LibId,
+    mut env: &Rc<Env<'a, Op>>,
+  ) -> Result<Value<'a, Op>, EvalError> {
+    loop {
+      env = match &**env {
+        Env::Empty => return Err(EvalError::UnboundLib(id)),
+        Env::Lib(lib, value, _) if *lib == id => return Ok(value.clone()),
+        Env::LibRec(group, next) => {
+          match group.iter().find(|(lib, _)| *lib == id) {
+            Some((_, value)) => return self.eval_in(*value, Rc::clone(env)),
+            None => next,
+          }
+        }
+        Env::Var(_, next) | Env::Fix(_, next) | Env::Lib(_, _, next) => next,
+      }
+    }
+  }
+}
+
+impl<Op: Eval> Expr<Op> {
+  /// Evaluates the closed expression `self` with an [`Interpreter`] whose
+  /// fuel is unlimited.
+  ///
+  /// # Errors
+  /// Returns an error if evaluation fails; see [`Interpreter::eval`].
+  pub fn eval(&self) -> Result<Value<'_, Op>, EvalError> {
+    Interpreter::new().eval(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{EvalError, Interpreter};
+  use crate::{
+    round_trip::parse,
+    simple_lang::{SimpleOp, SimpleValue},
+    teachable::{DeBruijnIndex, Teachable},
+    Expr, LibId,
+  };
+
+  fn symbol(name: &str) -> SimpleValue {
+    SimpleValue::Symbol(name.into())
+  }
+
+  #[test]
+  fn applies_lambdas() {
+    let value = parse("(@ (λ (@ $0 y)) (λ (list $0)))").eval().unwrap();
+    assert_eq!(value.into_prim(), Ok(SimpleValue::List(vec![symbol("y")])));
+  }
+
+  #[test]
+  fn binds_libs() {
+    let l0 = LibId(0);
+    let body = Expr(SimpleOp::apply(Expr(SimpleOp::lib_var(l0)), parse("b")));
+    let expr = Expr(SimpleOp::lib(l0, parse("(λ (list $0))"), body));
+    let value = expr.eval().unwrap();
+    assert_eq!(value.into_prim(), Ok(SimpleValue::List(vec![symbol("b")])));
+  }
+
+  #[test]
+  fn reports_errors() {
+    assert_eq!(
+      parse("$0").eval().unwrap_err(),
+      EvalError::UnboundVar(DeBruijnIndex(0))
+    );
+    assert_eq!(parse("(@ x y)").eval().unwrap_err(), EvalError::NotAFunction);
+    let looping = parse("(fix $0)");
+    assert_eq!(
+      Interpreter::new().with_fuel(100).eval(&looping).unwrap_err(),
+      EvalError::OutOfFuel
+    );
+  }
+}
@@ -1,8 +1,18 @@
-use super::{Arity, AstNode, ParseNodeError};
-use crate::{sexp::Sexp, teachable::Teachable};
+use super::{Arity, ArityError, AstNode, ParseNodeError};
+use crate::{
+  extract::cost::distinct_subterms,
+  sexp::{ParseError, Program, Sexp},
+  teachable::Teachable,
+};
 use egg::{Id, Language, RecExpr};
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, str::FromStr};
+use std::{
+  convert::TryFrom,
+  fmt::{self, Display, Formatter},
+  hash::Hash,
+  str::FromStr,
+};
+use thiserror::Error;
 
 /// An abstract syntax tree with operations `Op`.
 #[derive(
@@ -25,6 +35,17 @@ impl<Op> Expr<Op> {
   pub fn len(&self) -> usize {
     self.0.iter().map(Expr::len).sum::<usize>() + 1
   }
+
+  /// Returns the number of distinct subexpressions of the expression, i.e.
+  /// its number of AST nodes when equal subexpressions are shared.
+  #[must_use]
+  pub fn dag_len(&self) -> usize
+  where
+    Op: Teachable + Clone + Eq + Hash,
+  {
+    let rec_expr: RecExpr<AstNode<Op>> = self.clone().into();
+    distinct_subterms(rec_expr.as_ref(), false)
+  }
 }
 
 impl<'a, Op: FromStr + Arity> TryFrom<Sexp<'a>> for Expr<Op> {
@@ -44,6 +65,130 @@ impl<'a, Op: FromStr + Arity> TryFrom<Sexp<'a>> for Expr<Op> {
   }
 }
 
+/// A position in the input of [`Expr::parse_with`]. Lines and columns both
+/// start at 1, and columns count characters rather than bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextPosition {
+  /// The line of the position.
+  pub line: usize,
+  /// The column of the position.
+  pub column: usize,
+}
+
+impl TextPosition {
+  /// Returns the position at which `part`, which must be a slice of `input`,
+  /// starts.
+  fn of(input: &str, part: &str) -> Self {
+    let offset = (part.as_ptr() as usize)
+      .saturating_sub(input.as_ptr() as usize)
+      .min(input.len());
+    let before = &input[..offset];
+    let line = before.matches('\n').count() + 1;
+    let last_line = before.rfind('\n').map_or(before, |i| &before[i + 1..]);
+    Self { line, column: last_line.chars().count() + 1 }
+  }
+}
+
+impl Display for TextPosition {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "line {}, column {}", self.line, self.column)
+  }
+}
+
+/// An error which can be returned by [`Expr::parse_with`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseExprError<Op, E> {
+  /// The input is not made of well-formed s-expressions.
+  #[error(transparent)]
+  Syntax(#[from] ParseError),
+
+  /// An operation failed to parse.
+  #[error("{position}: invalid operation `{op}`: {error}")]
+  Operation {
+    /// The text of the operation.
+    op: String,
+    /// Where the operation starts in the input.
+    position: TextPosition,
+    /// Why the operation failed to parse.
+    error: E,
+  },
+
+  /// An operation was given the wrong number of arguments.
+  #[error("{position}: {error}")]
+  Arity {
+    /// Where the operation starts in the input.
+    position: TextPosition,
+    /// The operation and the arguments it was given.
+    error: ArityError<Op, Expr<Op>>,
+  },
+}
+
+impl<Op: FromStr + Arity> Expr<Op> {
+  /// Parses an expression written as an s-expression, using the [`FromStr`]
+  /// implementation of `Op` to parse each operation and its [`Arity`] to check
+  /// the number of arguments it is given. Unlike [`Expr::try_from`], errors
+  /// report where in `input` they occur.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `input` is not a single s-expression, if one of its
+  /// operations fails to parse, or if an operation is given the wrong number
+  /// of arguments.
+  pub fn parse_with(input: &str) -> Result<Self, ParseExprError<Op, Op::Err>> {
+    let sexp = Sexp::parse(input)?;
+    Self::from_sexp_in(input, sexp)
+  }
+
+  /// Parses every s-expression of `input` as a separate expression, in the
+  /// same way as [`Expr::parse_with`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `input` contains an invalid s-expression, or if any
+  /// of the expressions fails to parse.
+  pub fn parse_all(
+    input: &str,
+  ) -> Result<Vec<Self>, ParseExprError<Op, Op::Err>> {
+    Program::parse(input)?
+      .0
+      .into_iter()
+      .map(|sexp| Self::from_sexp_in(input, sexp))
+      .collect()
+  }
+
+  /// Converts `sexp`, which was parsed from `input`, into an expression.
+  fn from_sexp_in(
+    input: &str,
+    sexp: Sexp<'_>,
+  ) -> Result<Self, ParseExprError<Op, Op::Err>> {
+    let (op, args) = match sexp {
+      Sexp::Atom(atom) => (atom, Vec::new()),
+      Sexp::List(op, args) => (op, args),
+    };
+    let position = TextPosition::of(input, op);
+    let operation = op.parse().map_err(|error| ParseExprError::Operation {
+      op: op.to_string(),
+      position,
+      error,
+    })?;
+    let args = args
+      .into_iter()
+      .map(|arg| Self::from_sexp_in(input, arg))
+      .collect::<Result<Vec<_>, _>>()?;
+    let node = AstNode::try_new(operation, args)
+      .map_err(|error| ParseExprError::Arity { position, error })?;
+    Ok(Self(node))
+  }
+}
+
+impl<Op: FromStr + Arity> FromStr for Expr<Op> {
+  type Err = ParseExprError<Op, Op::Err>;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Self::parse_with(s)
+  }
+}
+
 impl<Op> From<Expr<Op>> for RecExpr<AstNode<Op>> {
   fn from(expr: Expr<Op>) -> Self {
     fn build<Op>(rec_expr: &mut Vec<AstNode<Op>>, expr: Expr<Op>) {
@@ -0,0 +1,389 @@
+//! Hash-consed expressions, which share structurally equal subexpressions.
+
+use super::{AstNode, Expr};
+use egg::{Analysis, EGraph, Id, RecExpr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+  cmp::Ordering,
+  collections::HashMap,
+  fmt::Debug,
+  hash::{Hash, Hasher},
+  ops::Index,
+};
+
+/// A reference to an expression in an [`Interner`]. References are cheap to
+/// copy, and two references into the same interner are equal if and only if
+/// the expressions they refer to are structurally equal.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct ExprRef(u32);
+
+impl ExprRef {
+  fn index(self) -> usize {
+    self.0 as usize
+  }
+}
+
+/// An arena of hash-consed expressions: each distinct subexpression is
+/// stored once, as a node whose arguments are references to earlier nodes.
+#[derive(Debug, Clone)]
+pub struct Interner<Op> {
+  /// The distinct nodes, each after all of its arguments.
+  nodes: Vec<AstNode<Op, ExprRef>>,
+  /// The reference to each node in `nodes`.
+  refs: HashMap<AstNode<Op, ExprRef>, ExprRef>,
+}
+
+impl<Op> Default for Interner<Op> {
+  fn default() -> Self {
+    Self { nodes: Vec::new(), refs: HashMap::new() }
+  }
+}
+
+impl<Op> Interner<Op> {
+  /// Creates an empty interner.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The number of distinct subexpressions in the interner.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// Returns `true` if nothing has been interned.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  /// The distinct nodes in the interner, each after all of its arguments.
+  pub fn nodes(
+    &self,
+  ) -> impl Iterator<Item = (ExprRef, &AstNode<Op, ExprRef>)> + '_ {
+    self.nodes.iter().enumerate().map(|(i, node)| (Self::make_ref(i), node))
+  }
+
+  /// The number of AST nodes in the expression `expr` refers to, without
+  /// sharing, as for [`Expr::len`].
+  #[must_use]
+  pub fn tree_len(&self, expr: ExprRef) -> usize {
+    let mut lens: Vec<usize> = Vec::with_capacity(expr.index() + 1);
+    for node in &self.nodes[..=expr.index()] {
+      lens.push(node.iter().map(|arg| lens[arg.index()]).sum::<usize>() + 1);
+    }
+    lens[expr.index()]
+  }
+
+  fn make_ref(index: usize) -> ExprRef {
+    ExprRef(u32::try_from(index).expect("too many interned expressions"))
+  }
+}
+
+impl<Op: Clone + Eq + Hash> Interner<Op> {
+  /// Interns `node`, whose arguments must already be in the interner,
+  /// returning a reference to it.
+  ///
+  /// # Panics
+  /// Panics if an argument of `node` is not a reference into this interner.
+  pub fn add(&mut self, node: AstNode<Op, ExprRef>) -> ExprRef {
+    if let Some(&expr) = self.refs.get(&node) {
+      return expr;
+    }
+    assert!(
+      node.iter().all(|arg| arg.index() < self.nodes.len()),
+      "argument not in the interner"
+    );
+    let expr = Self::make_ref(self.nodes.len());
+    self.nodes.push(node.clone());
+    self.refs.insert(node, expr);
+    expr
+  }
+
+  /// Interns `expr` and all of its subexpressions, returning a reference to
+  /// it.
+  pub fn intern(&mut self, expr: &Expr<Op>) -> ExprRef {
+    let (operation, args) = expr.0.as_parts();
+    let args = args.iter().map(|arg| self.intern(arg)).collect();
+    self.add(AstNode { operation: operation.clone(), args })
+  }
+
+  /// Builds the tree `expr` refers to.
+  #[must_use]
+  pub fn expr(&self, expr: ExprRef) -> Expr<Op> {
+    Expr(self[expr].clone().map(|arg| self.expr(arg)))
+  }
+
+  /// Builds the expression `expr` refers to as a [`RecExpr`], in which
+  /// equal subexpressions are shared.
+  #[must_use]
+  pub fn rec_expr(&self, expr: ExprRef) -> RecExpr<AstNode<Op>> {
+    let mut ids: HashMap<ExprRef, Id> = HashMap::new();
+    let mut rec_expr = Vec::new();
+    let mut stack = vec![expr];
+    while let Some(&top) = stack.last() {
+      let pending: Vec<_> = self[top]
+        .iter()
+        .copied()
+        .filter(|arg| !ids.contains_key(arg))
+        .collect();
+      if pending.is_empty() {
+        stack.pop();
+        if !ids.contains_key(&top) {
+          rec_expr.push(self[top].clone().map(|arg| ids[&arg]));
+          ids.insert(top, Id::from(rec_expr.len() - 1));
+        }
+      } else {
+        stack.extend(pending);
+      }
+    }
+    rec_expr.into()
+  }
+}
+
+impl<Op> Index<ExprRef> for Interner<Op> {
+  type Output = AstNode<Op, ExprRef>;
+
+  fn index(&self, expr: ExprRef) -> &Self::Output {
+    &self.nodes[expr.index()]
+  }
+}
+
+// Interners are compared, hashed and serialized by their nodes alone: the
+// map of references is determined by them.
+
+impl<Op: PartialEq> PartialEq for Interner<Op> {
+  fn eq(&self, other: &Self) -> bool {
+    self.nodes == other.nodes
+  }
+}
+
+impl<Op: Eq> Eq for Interner<Op> {}
+
+impl<Op: PartialOrd> PartialOrd for Interner<Op> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    self.nodes.partial_cmp(&other.nodes)
+  }
+}
+
+impl<Op: Ord> Ord for Interner<Op> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.nodes.cmp(&other.nodes)
+  }
+}
+
+impl<Op: Hash> Hash for Interner<Op> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.nodes.hash(state);
+  }
+}
+
+impl<Op: Serialize> Serialize for Interner<Op> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.nodes.serialize(serializer)
+  }
+}
+
+impl<'de, Op> Deserialize<'de> for Interner<Op>
+where
+  Op: Deserialize<'de> + Clone + Eq + Hash,
+{
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    let nodes = Vec::<AstNode<Op, ExprRef>>::deserialize(deserializer)?;
+    let mut interner = Self::new();
+    for node in nodes {
+      if node.iter().any(|arg| arg.index() >= interner.len()) {
+        return Err(de::Error::custom("argument not in the interner"));
+      }
+      interner.add(node);
+    }
+    Ok(interner)
+  }
+}
+
+/// A corpus of groups of equivalent expressions, sharing one [`Interner`].
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(bound(
+  serialize = "Op: Serialize",
+  deserialize = "Op: Deserialize<'de> + Clone + Eq + Hash"
+))]
+pub struct InternedCorpus<Op> {
+  interner: Interner<Op>,
+  groups: Vec<Vec<ExprRef>>,
+  /// How many times each group occurs in the corpus, if not once.
+  #[serde(default)]
+  weights: Option<Vec<usize>>,
+}
+
+impl<Op: Clone + Eq + Hash> InternedCorpus<Op> {
+  /// Interns each group of equivalent expressions of `groups`. Empty
+  /// groups, such as frontiers with no programs, are left out of the
+  /// corpus.
+  #[must_use]
+  pub fn from_groups<I>(groups: I) -> Self
+  where
+    I: IntoIterator<Item = Vec<Expr<Op>>>,
+  {
+    let mut interner = Interner::new();
+    let groups = groups
+      .into_iter()
+      .filter(|group| !group.is_empty())
+      .map(|group| group.iter().map(|expr| interner.intern(expr)).collect())
+      .collect();
+    Self { interner, groups, weights: None }
+  }
+
+  /// Interns each group of equivalent expressions of `groups`, weighted by
+  /// how many times it occurs in the corpus; see
+  /// [`InternedCorpus::with_weights`]. Empty groups are left out of the
+  /// corpus along with their weights.
+  ///
+  /// # Panics
+  /// Panics if a weight is zero.
+  #[must_use]
+  pub fn from_weighted_groups<I>(groups: I) -> Self
+  where
+    I: IntoIterator<Item = (Vec<Expr<Op>>, usize)>,
+  {
+    let (groups, weights): (Vec<_>, Vec<_>) =
+      groups.into_iter().filter(|(group, _)| !group.is_empty()).unzip();
+    Self::from_groups(groups).with_weights(weights)
+  }
+
+  /// Builds the trees of every group of expressions.
+  #[must_use]
+  pub fn expr_groups(&self) -> Vec<Vec<Expr<Op>>> {
+    self
+      .groups
+      .iter()
+      .map(|group| group.iter().map(|&expr| self.interner.expr(expr)).collect())
+      .collect()
+  }
+
+  /// Adds every expression to `egraph`, merging the expressions of each
+  /// group, and returns the e-class of each group. Shared subexpressions
+  /// are only added once.
+  ///
+  /// # Panics
+  /// Panics if a group is empty, which can only happen if the corpus was
+  /// deserialized rather than built with [`InternedCorpus::from_groups`].
+  pub fn add_to_egraph<A>(&self, egraph: &mut EGraph<AstNode<Op>, A>) -> Vec<Id>
+  where
+    Op: Ord + Debug,
+    A: Analysis<AstNode<Op>>,
+  {
+    let mut ids: Vec<Id> = Vec::with_capacity(self.interner.len());
+    for (_, node) in self.interner.nodes() {
+      let id = egraph.add(node.clone().map(|arg| ids[arg.index()]));
+      ids.push(id);
+    }
+    self
+      .groups
+      .iter()
+      .map(|group| {
+        let (&first, rest) = group.split_first().expect("empty group");
+        let root = ids[first.index()];
+        for &expr in rest {
+          egraph.union(root, ids[expr.index()]);
+        }
+        root
+      })
+      .collect()
+  }
+}
+
+impl<Op> InternedCorpus<Op> {
+  /// The interner the expressions are stored in.
+  #[must_use]
+  pub fn interner(&self) -> &Interner<Op> {
+    &self.interner
+  }
+
+  /// The groups of equivalent expressions.
+  #[must_use]
+  pub fn groups(&self) -> &[Vec<ExprRef>] {
+    &self.groups
+  }
+
+  /// Weights each group by how many times it occurs in the corpus, so that
+  /// frequent programs count for more when selecting libraries and measuring
+  /// compression. By default, every group occurs once.
+  ///
+  /// Since [`InternedCorpus::from_groups`] leaves out empty groups, there
+  /// might be fewer groups than it was given; use
+  /// [`InternedCorpus::from_weighted_groups`] to weight the groups as they
+  /// are given.
+  ///
+  /// # Panics
+  /// Panics if there isn't exactly one weight per group, or a weight is
+  /// zero.
+  #[must_use]
+  pub fn with_weights(mut self, weights: Vec<usize>) -> Self {
+    assert_eq!(weights.len(), self.groups.len(), "one weight per group");
+    assert!(weights.iter().all(|&weight| weight > 0), "zero weight");
+    self.weights = Some(weights);
+    self
+  }
+
+  /// The weight of each group, if the corpus is weighted; see
+  /// [`InternedCorpus::with_weights`].
+  #[must_use]
+  pub fn weights(&self) -> Option<&[usize]> {
+    self.weights.as_deref()
+  }
+
+  /// The number of times the group at `index` occurs in the corpus.
+  #[must_use]
+  pub fn weight(&self, index: usize) -> usize {
+    self.weights.as_ref().map_or(1, |weights| weights[index])
+  }
+
+  /// The number of groups.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.groups.len()
+  }
+
+  /// Returns `true` if there are no groups.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.groups.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::InternedCorpus;
+  use crate::round_trip::parse;
+
+  #[test]
+  fn weights_skip_empty_groups() {
+    let corpus = InternedCorpus::from_weighted_groups([
+      (vec![parse("a")], 2),
+      (Vec::new(), 3),
+      (vec![parse("b"), parse("c")], 4),
+    ]);
+    assert_eq!(corpus.len(), 2);
+    assert_eq!(corpus.weights(), Some(&[2, 4][..]));
+    assert_eq!(
+      corpus.expr_groups(),
+      [vec![parse("a")], vec![parse("b"), parse("c")]]
+    );
+  }
+}
@@ -0,0 +1,194 @@
+//! Descriptive names for learned library functions.
+//!
+//! Library functions are identified by [`LibId`]s, which are printed as
+//! `l0`, `l1`, and so on. [`LibNames`] names each library function after
+//! what it does instead: the first distinct operations its body uses,
+//! followed by its arity, as in `map_filter_2`.
+
+use super::Expr;
+use crate::{
+  learn::LibId,
+  teachable::{BindingExpr, Teachable},
+};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt::Display,
+};
+
+/// The number of operations a name is made of.
+const NAME_PARTS: usize = 2;
+
+/// The descriptive names of a set of library functions; see the [module
+/// documentation](self).
+///
+/// Names only depend on the definitions of the library functions, and not on
+/// their [`LibId`]s, so the same library function gets the same name from
+/// run to run. Library functions whose names would be the same are told
+/// apart by a numeric suffix, given in order of their definitions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LibNames {
+  names: BTreeMap<LibId, String>,
+}
+
+impl LibNames {
+  /// Names every library function defined in `expr`.
+  #[must_use]
+  pub fn from_expr<Op>(expr: &Expr<Op>) -> Self
+  where
+    Op: Teachable + Display + Ord,
+  {
+    let mut defs = BTreeMap::new();
+    collect_defs(expr, &mut defs);
+
+    let mut candidates: Vec<_> =
+      defs.into_iter().map(|(lib, def)| (base_name(def), def, lib)).collect();
+    candidates.sort_unstable();
+    let bases: BTreeSet<_> =
+      candidates.iter().map(|(base, ..)| base.clone()).collect();
+
+    let mut taken = BTreeSet::new();
+    let mut names = BTreeMap::new();
+    for (base, _, lib) in candidates {
+      let name = if taken.contains(&base) {
+        (2..)
+          .map(|i| format!("{base}_{i}"))
+          .find(|name| !taken.contains(name) && !bases.contains(name))
+          .unwrap()
+      } else {
+        base
+      };
+      taken.insert(name.clone());
+      names.insert(lib, name);
+    }
+    Self { names }
+  }
+
+  /// Returns the name of the library function `lib`, or [`None`] if it
+  /// wasn't named.
+  #[must_use]
+  pub fn name(&self, lib: LibId) -> Option<&str> {
+    self.names.get(&lib).map(String::as_str)
+  }
+
+  /// Returns the name of the library function `lib`, or its [`LibId`] if it
+  /// wasn't named.
+  #[must_use]
+  pub fn label(&self, lib: LibId) -> String {
+    self.name(lib).map_or_else(|| lib.to_string(), str::to_string)
+  }
+
+  /// Iterates over the library functions and their names, in order of
+  /// [`LibId`].
+  pub fn iter(&self) -> impl Iterator<Item = (LibId, &str)> + '_ {
+    self.names.iter().map(|(&lib, name)| (lib, name.as_str()))
+  }
+}
+
+/// Collects the definition of every library function defined in `expr`.
+fn collect_defs<'a, Op: Teachable>(
+  expr: &'a Expr<Op>,
+  defs: &mut BTreeMap<LibId, &'a Expr<Op>>,
+) {
+  if let Some(
+    BindingExpr::Lib(lib, def, _) | BindingExpr::LibRec(lib, def, _),
+  ) = expr.0.as_binding_expr()
+  {
+    defs.insert(lib, def);
+  }
+  for child in expr.0.iter() {
+    collect_defs(child, defs);
+  }
+}
+
+/// The name of a library function with definition `def`, before it is told
+/// apart from other library functions: the first [`NAME_PARTS`] distinct
+/// operations in its body, in pre-order, and its arity.
+fn base_name<Op: Teachable + Display>(mut def: &Expr<Op>) -> String {
+  let mut arity = 0;
+  loop {
+    match def.0.as_binding_expr() {
+      Some(BindingExpr::Lambda(body)) => {
+        arity += 1;
+        def = body;
+      }
+      Some(BindingExpr::Fix(body)) => def = body,
+      _ => break,
+    }
+  }
+  let mut parts = Vec::new();
+  head_ops(def, &mut parts);
+  if parts.is_empty() {
+    parts.push("lib".to_string());
+  }
+  format!("{}_{arity}", parts.join("_"))
+}
+
+/// Collects the identifiers of the operations used by `expr`, in pre-order,
+/// until there are [`NAME_PARTS`] of them. Binding constructs, such as
+/// applications and variables, and operations whose identifier would be
+/// empty or start with a digit, are skipped.
+fn head_ops<Op: Teachable + Display>(expr: &Expr<Op>, parts: &mut Vec<String>) {
+  if parts.len() >= NAME_PARTS {
+    return;
+  }
+  if expr.0.as_binding_expr().is_none() {
+    let part = identifier(&expr.0.operation().to_string());
+    if part.starts_with(|c: char| c.is_ascii_alphabetic())
+      && !parts.contains(&part)
+    {
+      parts.push(part);
+    }
+  }
+  for child in expr.0.iter() {
+    head_ops(child, parts);
+  }
+}
+
+/// Converts the name of an operation into a lowercase identifier, keeping
+/// only its ASCII letters and digits and separating runs of them with
+/// underscores.
+fn identifier(op: &str) -> String {
+  op.split(|c: char| !c.is_ascii_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(str::to_ascii_lowercase)
+    .collect::<Vec<_>>()
+    .join("_")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::LibNames;
+  use crate::{
+    round_trip::parse, simple_lang::SimpleOp, teachable::Teachable, Expr, LibId,
+  };
+
+  fn lib(id: usize, def: &str, body: Expr<SimpleOp>) -> Expr<SimpleOp> {
+    Expr(SimpleOp::lib(LibId(id), parse(def), body))
+  }
+
+  #[test]
+  fn names_after_ops_and_arity() {
+    let expr = lib(
+      0,
+      "(λ (λ (@ (@ map $1) (@ filter $0))))",
+      lib(1, "(fix (λ (@ fold-left $0)))", parse("x")),
+    );
+    let names = LibNames::from_expr(&expr);
+    assert_eq!(names.name(LibId(0)), Some("map_filter_2"));
+    assert_eq!(names.name(LibId(1)), Some("fold_left_1"));
+    assert_eq!(names.label(LibId(2)), "l2");
+  }
+
+  #[test]
+  fn tells_equal_names_apart() {
+    let expr = lib(
+      3,
+      "(λ (@ g $0))",
+      lib(0, "(λ (@ $0 g))", lib(1, "(@ + 1)", parse("x"))),
+    );
+    let names = LibNames::from_expr(&expr);
+    assert_eq!(names.name(LibId(0)), Some("g_1"));
+    assert_eq!(names.name(LibId(3)), Some("g_1_2"));
+    assert_eq!(names.name(LibId(1)), Some("lib_0"));
+  }
+}
@@ -0,0 +1,390 @@
+//! Expressions with named variables.
+//!
+//! Library learning works on expressions whose bound variables are de Bruijn
+//! indices. [`VarNames`] converts s-expressions in which binders name their
+//! variables into that form, remembering the name each binder had, so that
+//! learned expressions can be printed with the original names (see
+//! [`PrettyWith::with_var_names`](super::PrettyWith::with_var_names)).
+//!
+//! [`Expr::to_named`] and [`NamedExpr::to_debruijn`] convert between the two
+//! forms directly, without going through s-expressions.
+
+use super::{Arity, AstNode, Expr, ParseNodeError};
+use crate::{
+  sexp::Sexp,
+  teachable::{BindingExpr, DeBruijnIndex, Teachable},
+};
+use std::{
+  collections::HashMap,
+  fmt::{self, Display, Formatter},
+  hash::Hash,
+  str::FromStr,
+};
+use thiserror::Error;
+
+/// The names of the variables bound in a set of expressions.
+///
+/// Names are keyed by the binder (the lambda or fixpoint) itself, so they
+/// survive library learning wherever a binder from the input survives
+/// unchanged. If equal binders were given different names, the first name is
+/// kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarNames<Op> {
+  names: HashMap<Expr<Op>, String>,
+}
+
+impl<Op> Default for VarNames<Op> {
+  fn default() -> Self {
+    Self { names: HashMap::new() }
+  }
+}
+
+/// An error when converting an s-expression with named variables.
+#[derive(Debug, Error)]
+pub enum ParseNamedError<Op, E> {
+  /// A `lambda` or `fix` is not of the form `(lambda (x ...) body)`,
+  /// `(lambda x body)`, or `(fix f body)`.
+  #[error("malformed binder: {0}")]
+  Binder(String),
+
+  /// An operation failed to parse or has the wrong number of arguments.
+  #[error(transparent)]
+  Node(ParseNodeError<Op, Expr<Op>, E>),
+}
+
+impl<Op: Eq + Hash> VarNames<Op> {
+  /// Creates an empty set of names.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns the name of the variable bound by `binder`, if it was recorded.
+  #[must_use]
+  pub fn name(&self, binder: &Expr<Op>) -> Option<&str> {
+    self.names.get(binder).map(String::as_str)
+  }
+}
+
+impl<Op> VarNames<Op>
+where
+  Op: FromStr + Arity + Teachable + Clone + Eq + Hash,
+{
+  /// Converts `sexp` into a de Bruijn-indexed expression, recording the
+  /// names of its variables.
+  ///
+  /// In `sexp`, `(lambda (x y) body)` (or `(lambda x body)` for a single
+  /// variable) binds named variables, and `(fix f body)` binds `f` to the
+  /// fixpoint. A bound variable in operator position, as in `(f a b)`, is
+  /// applied to the arguments. Every other s-expression is parsed as by
+  /// [`Expr::try_from`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a binder is malformed, or if an operation fails to
+  /// parse or has the wrong number of arguments.
+  pub fn parse(
+    &mut self,
+    sexp: Sexp<'_>,
+  ) -> Result<Expr<Op>, ParseNamedError<Op, Op::Err>> {
+    self.parse_in(sexp, &mut Vec::new())
+  }
+
+  /// Converts `sexp` in the scope of the named variables `bindings`.
+  fn parse_in<'a>(
+    &mut self,
+    sexp: Sexp<'a>,
+    bindings: &mut Vec<&'a str>,
+  ) -> Result<Expr<Op>, ParseNamedError<Op, Op::Err>> {
+    let (op, args) = match sexp {
+      Sexp::List("lambda", mut args) if args.len() == 2 => {
+        let body = args.pop().unwrap();
+        let vars = match args.pop().unwrap() {
+          Sexp::Atom(var) => vec![var],
+          Sexp::List(var, vars) => {
+            let mut names = vec![var];
+            for var in vars {
+              match var {
+                Sexp::Atom(var) => names.push(var),
+                list @ Sexp::List(..) => {
+                  return Err(ParseNamedError::Binder(list.to_string()))
+                }
+              }
+            }
+            names
+          }
+        };
+        return self.bind(&vars, body, bindings, Op::lambda);
+      }
+      Sexp::List("fix", mut args) if args.len() == 2 => {
+        let body = args.pop().unwrap();
+        let var = match args.pop().unwrap() {
+          Sexp::Atom(var) => var,
+          list @ Sexp::List(..) => {
+            return Err(ParseNamedError::Binder(list.to_string()))
+          }
+        };
+        return self.bind(&[var], body, bindings, Op::fix);
+      }
+      sexp @ Sexp::List("lambda" | "fix", _) => {
+        return Err(ParseNamedError::Binder(sexp.to_string()));
+      }
+      Sexp::Atom(atom) => (atom, Vec::new()),
+      Sexp::List(op, args) => (op, args),
+    };
+
+    let args = args
+      .into_iter()
+      .map(|arg| self.parse_in(arg, bindings))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    match bindings.iter().rev().position(|&var| var == op) {
+      Some(index) => {
+        let var = Expr(Op::var(index));
+        Ok(args.into_iter().fold(var, |fun, arg| Expr(Op::apply(fun, arg))))
+      }
+      None => {
+        let op: Op = op
+          .parse()
+          .map_err(|e| ParseNamedError::Node(ParseNodeError::ParseError(e)))?;
+        let node = AstNode::try_new(op, args)
+          .map_err(|e| ParseNamedError::Node(ParseNodeError::ArityError(e)))?;
+        Ok(Expr(node))
+      }
+    }
+  }
+
+  /// Converts `body` in the scope of `vars`, and wraps it in one binder per
+  /// variable, built by `binder`.
+  fn bind<'a>(
+    &mut self,
+    vars: &[&'a str],
+    body: Sexp<'a>,
+    bindings: &mut Vec<&'a str>,
+    binder: fn(Expr<Op>) -> AstNode<Op, Expr<Op>>,
+  ) -> Result<Expr<Op>, ParseNamedError<Op, Op::Err>> {
+    bindings.extend_from_slice(vars);
+    let body = self.parse_in(body, bindings);
+    bindings.truncate(bindings.len() - vars.len());
+
+    let mut expr = body?;
+    for var in vars.iter().rev() {
+      expr = Expr(binder(expr));
+      self.names.entry(expr.clone()).or_insert_with(|| (*var).to_string());
+    }
+    Ok(expr)
+  }
+}
+
+/// An expression whose bound variables are named instead of de Bruijn
+/// indices, as produced by [`Expr::to_named`].
+///
+/// Only lambdas and fixpoints bind variables, so every other construct,
+/// including library function definitions, applications, and references to
+/// library functions, is kept as an ordinary node.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NamedExpr<Op> {
+  /// A reference to the innermost enclosing binder of this name.
+  Var(String),
+  /// A function of the named variable.
+  Lambda(String, Box<Self>),
+  /// A fixpoint, in whose body the named variable refers to the fixpoint
+  /// itself.
+  Fix(String, Box<Self>),
+  /// Any other operation. A de Bruijn index in such a node is free in the
+  /// whole expression, and counts binders from outside of it.
+  Node(AstNode<Op, Self>),
+}
+
+/// An error when converting a [`NamedExpr`] with a variable which isn't bound
+/// by any enclosing binder.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unbound variable `{0}`")]
+pub struct UnboundVarError(pub String);
+
+impl<Op: Teachable + Clone> Expr<Op> {
+  /// Names the variables bound by each lambda and fixpoint of the
+  /// expression, calling `fresh_name` for the name of each binder in
+  /// pre-order.
+  ///
+  /// A name which is also given to an enclosing binder shadows it, so the
+  /// names only need to differ from those of the enclosing binders whose
+  /// variables are used below. [`NamedExpr::to_debruijn`] converts the
+  /// result back.
+  #[must_use]
+  pub fn to_named<F>(&self, mut fresh_name: F) -> NamedExpr<Op>
+  where
+    F: FnMut() -> String,
+  {
+    self.to_named_in(&mut fresh_name, &mut Vec::new())
+  }
+
+  /// Names the variables of the expression in the scope of the binders named
+  /// `names`, innermost last.
+  fn to_named_in(
+    &self,
+    fresh_name: &mut dyn FnMut() -> String,
+    names: &mut Vec<String>,
+  ) -> NamedExpr<Op> {
+    let mut bind = |body: &Self, names: &mut Vec<String>| {
+      let name = fresh_name();
+      names.push(name.clone());
+      let body = body.to_named_in(fresh_name, names);
+      names.pop();
+      (name, Box::new(body))
+    };
+    match self.0.as_binding_expr() {
+      Some(BindingExpr::Lambda(body)) => {
+        let (name, body) = bind(body, names);
+        NamedExpr::Lambda(name, body)
+      }
+      Some(BindingExpr::Fix(body)) => {
+        let (name, body) = bind(body, names);
+        NamedExpr::Fix(name, body)
+      }
+      Some(BindingExpr::Var(DeBruijnIndex(index))) if index < names.len() => {
+        NamedExpr::Var(names[names.len() - 1 - index].clone())
+      }
+      Some(BindingExpr::Var(DeBruijnIndex(index))) => {
+        NamedExpr::Node(Op::var(index - names.len()))
+      }
+      _ => NamedExpr::Node(AstNode {
+        operation: self.0.operation.clone(),
+        args: self
+          .0
+          .iter()
+          .map(|arg| arg.to_named_in(fresh_name, names))
+          .collect(),
+      }),
+    }
+  }
+}
+
+impl<Op: Teachable + Clone> NamedExpr<Op> {
+  /// Converts the expression back into one whose bound variables are de
+  /// Bruijn indices.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a variable isn't bound by any enclosing binder.
+  pub fn to_debruijn(&self) -> Result<Expr<Op>, UnboundVarError> {
+    self.to_debruijn_in(&mut Vec::new())
+  }
+
+  /// Converts the expression in the scope of the binders named `names`,
+  /// innermost last.
+  fn to_debruijn_in<'a>(
+    &'a self,
+    names: &mut Vec<&'a str>,
+  ) -> Result<Expr<Op>, UnboundVarError> {
+    let expr = match self {
+      Self::Var(name) => {
+        match names.iter().rev().position(|&bound| bound == name) {
+          Some(index) => Expr(Op::var(index)),
+          None => return Err(UnboundVarError(name.clone())),
+        }
+      }
+      Self::Lambda(name, body) | Self::Fix(name, body) => {
+        names.push(name);
+        let body = body.to_debruijn_in(names);
+        names.pop();
+        let body = body?;
+        match self {
+          Self::Lambda(..) => Expr(Op::lambda(body)),
+          _ => Expr(Op::fix(body)),
+        }
+      }
+      Self::Node(node) => match node.as_binding_expr() {
+        Some(BindingExpr::Var(DeBruijnIndex(index))) => {
+          Expr(Op::var(index + names.len()))
+        }
+        _ => Expr(AstNode {
+          operation: node.operation.clone(),
+          args: node
+            .iter()
+            .map(|arg| arg.to_debruijn_in(names))
+            .collect::<Result<_, _>>()?,
+        }),
+      },
+    };
+    Ok(expr)
+  }
+}
+
+impl<Op: Display> Display for NamedExpr<Op> {
+  /// Writes the expression as an s-expression, which [`VarNames::parse`]
+  /// reads back if the [`Display`] implementation of `Op` agrees with its
+  /// [`FromStr`] implementation.
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Var(name) => f.write_str(name),
+      Self::Lambda(name, body) => write!(f, "(lambda {name} {body})"),
+      Self::Fix(name, body) => write!(f, "(fix {name} {body})"),
+      Self::Node(node) if node.is_empty() => write!(f, "{}", node.operation),
+      Self::Node(node) => {
+        write!(f, "({}", node.operation)?;
+        for arg in node {
+          write!(f, " {arg}")?;
+        }
+        f.write_str(")")
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{NamedExpr, UnboundVarError};
+  use crate::{
+    round_trip::parse, sexp::Sexp, simple_lang::SimpleOp, Expr, LibId,
+    Teachable, VarNames,
+  };
+
+  fn counter() -> impl FnMut() -> String {
+    let mut next = 0;
+    move || {
+      next += 1;
+      format!("x{next}")
+    }
+  }
+
+  #[test]
+  fn names_bound_variables() {
+    let expr = parse("(lambda (lambda (@ $1 (@ $0 a))))");
+    let named = expr.to_named(counter());
+    assert_eq!(named.to_string(), "(lambda x1 (lambda x2 (@ x1 (@ x2 a))))");
+    assert_eq!(named.to_debruijn().unwrap(), expr);
+  }
+
+  #[test]
+  fn keeps_free_variables() {
+    let expr = parse("(lambda (@ $0 (lambda (@ $2 $1))))");
+    let named = expr.to_named(counter());
+    assert_eq!(named.to_debruijn().unwrap(), expr);
+  }
+
+  #[test]
+  fn round_trips_libs_and_fixpoints() {
+    let value = parse("(lambda (fix (@ $0 $1)))");
+    let body = parse("(@ (lambda (@ f $0)) (lambda a))");
+    let expr = Expr(SimpleOp::lib(LibId(0), value, body));
+    let named = expr.to_named(counter());
+    assert_eq!(named.to_debruijn().unwrap(), expr);
+  }
+
+  #[test]
+  fn reads_back_printed_expressions() {
+    let expr = parse("(lambda (@ (lambda (@ $0 $1)) $0))");
+    let printed = expr.to_named(counter()).to_string();
+    let sexp = Sexp::parse(&printed).unwrap();
+    let parsed: Expr<SimpleOp> = VarNames::new().parse(sexp).unwrap();
+    assert_eq!(parsed, expr);
+  }
+
+  #[test]
+  fn rejects_unbound_variables() {
+    let named: NamedExpr<SimpleOp> =
+      NamedExpr::Lambda("x".into(), Box::new(NamedExpr::Var("y".into())));
+    assert_eq!(named.to_debruijn(), Err(UnboundVarError("y".into())));
+  }
+}
@@ -45,7 +45,7 @@ where
     match self {
       PartialExpr::Node(node) => {
         let binders = match node.as_binding_expr() {
-          Some(BindingExpr::Lambda(_)) => depth + 1,
+          Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_)) => depth + 1,
           _ => depth,
         };
         let node = node.map(|child| child.fill_with_binders_helper(f, binders));
@@ -75,7 +75,7 @@ where
           f(node, depth)
         } else {
           let binders = match node.as_binding_expr() {
-            Some(BindingExpr::Lambda(_)) => depth + 1,
+            Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_)) => depth + 1,
             _ => depth,
           };
           let node =
@@ -1,7 +1,11 @@
-use std::fmt::{self, Display, Write};
+use std::{
+  fmt::{self, Display, Write},
+  hash::Hash,
+};
 
 use crate::{
-  ast_node::Expr,
+  ast_node::{AstNode, Expr, LibNames, VarNames},
+  learn::LibId,
   teachable::{BindingExpr, Teachable},
 };
 
@@ -19,6 +23,391 @@ where
   }
 }
 
+/// The surface syntax in which [`PrettyWith`] prints expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+  /// S-expressions, like the ones expressions are parsed from.
+  Lisp,
+  /// ML-style syntax, as printed by [`Pretty`].
+  Ml,
+}
+
+/// Options controlling how [`PrettyWith`] prints an expression. By default,
+/// expressions are printed exactly as by [`Pretty`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrettyOptions {
+  /// The surface syntax to print.
+  syntax: Syntax,
+  /// Whether to give bound variables names instead of de Bruijn indices.
+  named_vars: bool,
+  /// Whether to inline library functions which are used only once.
+  inline_single_use: bool,
+}
+
+impl Default for PrettyOptions {
+  fn default() -> Self {
+    Self { syntax: Syntax::Ml, named_vars: true, inline_single_use: false }
+  }
+}
+
+impl PrettyOptions {
+  /// Sets the surface syntax to print.
+  #[must_use]
+  pub fn syntax(mut self, syntax: Syntax) -> Self {
+    self.syntax = syntax;
+    self
+  }
+
+  /// Sets whether to give bound variables names instead of printing their de
+  /// Bruijn indices. Variables are always named in [`Syntax::Ml`].
+  #[must_use]
+  pub fn named_vars(mut self, named_vars: bool) -> Self {
+    self.named_vars = named_vars;
+    self
+  }
+
+  /// Sets whether to replace the only use of a library function with its
+  /// definition, instead of printing the definition separately.
+  #[must_use]
+  pub fn inline_single_use(mut self, inline_single_use: bool) -> Self {
+    self.inline_single_use = inline_single_use;
+    self
+  }
+}
+
+/// A wrapper around [`&'a Expr<Op>`] whose [`Display`] impl pretty-prints the
+/// expression according to a set of [`PrettyOptions`].
+#[derive(Debug, Clone, Copy)]
+pub struct PrettyWith<'a, Op> {
+  expr: &'a Expr<Op>,
+  options: PrettyOptions,
+  var_names: Option<&'a VarNames<Op>>,
+  lib_names: Option<&'a LibNames>,
+}
+
+impl<Op> Expr<Op> {
+  /// Returns a wrapper which pretty-prints the expression according to
+  /// `options`.
+  #[must_use]
+  pub fn pretty_with(&self, options: PrettyOptions) -> PrettyWith<'_, Op> {
+    PrettyWith { expr: self, options, var_names: None, lib_names: None }
+  }
+}
+
+impl<'a, Op> PrettyWith<'a, Op> {
+  /// Names bound variables after the names recorded in `var_names` where
+  /// possible, and the parameters of library functions after the variables
+  /// they are applied to. This only affects [`Syntax::Lisp`] with named
+  /// variables.
+  #[must_use]
+  pub fn with_var_names(mut self, var_names: &'a VarNames<Op>) -> Self {
+    self.var_names = Some(var_names);
+    self
+  }
+
+  /// Refers to library functions by the names in `lib_names`, such as
+  /// those given by [`LibNames::from_expr`], instead of by their
+  /// [`LibId`]s.
+  #[must_use]
+  pub fn with_lib_names(mut self, lib_names: &'a LibNames) -> Self {
+    self.lib_names = Some(lib_names);
+    self
+  }
+}
+
+impl<Op> Display for PrettyWith<'_, Op>
+where
+  Op: Printable + Teachable + Display + Clone + Eq + Hash,
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let inlined;
+    let expr = if self.options.inline_single_use {
+      inlined = inline_single_use(self.expr.clone());
+      &inlined
+    } else {
+      self.expr
+    };
+    match self.options.syntax {
+      Syntax::Ml => {
+        let mut printer = Printer::new(f);
+        printer.lib_names = self.lib_names.cloned().unwrap_or_default();
+        printer.print(expr)
+      }
+      Syntax::Lisp => {
+        let mut printer = LispPrinter {
+          named_vars: self.options.named_vars,
+          var_names: self.var_names,
+          lib_names: self.lib_names,
+          bindings: Vec::new(),
+        };
+        printer.print(expr, f)
+      }
+    }
+  }
+}
+
+/// The state of printing an expression as an S-expression.
+struct LispPrinter<'a, Op> {
+  /// If `false`, the expression is printed as is. Otherwise, bound
+  /// variables are named, and nested lambdas and applications are flattened.
+  named_vars: bool,
+  /// The names to give bound variables, where known.
+  var_names: Option<&'a VarNames<Op>>,
+  /// The names to give library functions, if they are named.
+  lib_names: Option<&'a LibNames>,
+  /// The names of the variables in scope.
+  bindings: Vec<String>,
+}
+
+impl<Op: Display + Teachable + Eq + Hash> LispPrinter<'_, Op> {
+  /// Prints `expr` in the scope of the variables in `self.bindings`.
+  fn print(
+    &mut self,
+    expr: &Expr<Op>,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    match expr.0.as_binding_expr() {
+      Some(BindingExpr::Var(index)) if self.named_vars => {
+        match self.bindings.len().checked_sub(index.0 + 1) {
+          Some(i) => f.write_str(&self.bindings[i]),
+          // A free variable has no name.
+          None => write!(f, "{index}"),
+        }
+      }
+      Some(BindingExpr::Lambda(_)) if self.named_vars => {
+        self.print_lambda(expr, &[], f)
+      }
+      Some(BindingExpr::Fix(body)) if self.named_vars => {
+        let name = self.fresh_name(expr, None, "f");
+        write!(f, "(fix {name} ")?;
+        self.bindings.push(name);
+        self.print(body, f)?;
+        self.bindings.pop();
+        f.write_char(')')
+      }
+      Some(BindingExpr::Apply(..)) if self.named_vars => {
+        let mut fun = expr;
+        let mut args = Vec::new();
+        while let Some(BindingExpr::Apply(inner, arg)) = fun.0.as_binding_expr()
+        {
+          args.push(arg);
+          fun = inner;
+        }
+        f.write_char('(')?;
+        self.print(fun, f)?;
+        for arg in args.into_iter().rev() {
+          f.write_char(' ')?;
+          self.print(arg, f)?;
+        }
+        f.write_char(')')
+      }
+      Some(
+        BindingExpr::Lib(lib, def, body) | BindingExpr::LibRec(lib, def, body),
+      ) if self.named_vars && self.var_names.is_some() => {
+        let mut params = Vec::new();
+        let mut bindings = self.bindings.iter().cloned().map(Some).collect();
+        self.param_names(body, lib, &mut bindings, &mut params);
+        write!(f, "({} ", self.op_name(expr))?;
+        match def.0.as_binding_expr() {
+          Some(BindingExpr::Lambda(_)) => self.print_lambda(def, &params, f)?,
+          _ => self.print(def, f)?,
+        }
+        f.write_char(' ')?;
+        self.print(body, f)?;
+        f.write_char(')')
+      }
+      _ if expr.0.is_empty() => f.write_str(&self.op_name(expr)),
+      _ => {
+        write!(f, "({}", self.op_name(expr))?;
+        for child in expr.0.iter() {
+          f.write_char(' ')?;
+          self.print(child, f)?;
+        }
+        f.write_char(')')
+      }
+    }
+  }
+
+  /// The operation of `expr` as it is printed. Operations which bind or
+  /// refer to a library function are printed with the library function's
+  /// name in place of its [`LibId`], which they are expected to end with.
+  fn op_name(&self, expr: &Expr<Op>) -> String {
+    let op = expr.0.operation().to_string();
+    let lib = match expr.0.as_binding_expr() {
+      Some(
+        BindingExpr::LibVar(lib)
+        | BindingExpr::Lib(lib, ..)
+        | BindingExpr::LibRec(lib, ..),
+      ) => lib,
+      _ => return op,
+    };
+    let name = self.lib_names.and_then(|names| names.name(lib));
+    match (name, op.strip_suffix(&lib.to_string())) {
+      (Some(name), Some(prefix)) => format!("{prefix}{name}"),
+      _ => op,
+    }
+  }
+
+  /// Prints the lambda `expr` and the lambdas directly nested in it as a
+  /// single lambda with several variables. The `i`th variable is named
+  /// `hints[i]` if it is given.
+  fn print_lambda(
+    &mut self,
+    expr: &Expr<Op>,
+    hints: &[Option<String>],
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    let mut body = expr;
+    let mut num_vars = 0;
+    f.write_str("(lambda (")?;
+    while let Some(BindingExpr::Lambda(inner)) = body.0.as_binding_expr() {
+      let hint = hints.get(num_vars).and_then(Option::as_deref);
+      let name = self.fresh_name(body, hint, "x");
+      if num_vars > 0 {
+        f.write_char(' ')?;
+      }
+      f.write_str(&name)?;
+      self.bindings.push(name);
+      num_vars += 1;
+      body = inner;
+    }
+    f.write_str(") ")?;
+    self.print(body, f)?;
+    self.bindings.truncate(self.bindings.len() - num_vars);
+    f.write_char(')')
+  }
+
+  /// Returns a name for the variable bound by `binder`: `hint` if given, or
+  /// else its recorded name, or else `prefix` followed by the binder's
+  /// depth. Names which are already in scope are disambiguated by the
+  /// depth.
+  fn fresh_name(
+    &self,
+    binder: &Expr<Op>,
+    hint: Option<&str>,
+    prefix: &str,
+  ) -> String {
+    let depth = self.bindings.len();
+    let name =
+      hint.or_else(|| self.var_names.and_then(|names| names.name(binder)));
+    match name {
+      Some(name) if self.bindings.iter().any(|other| other == name) => {
+        format!("{name}{depth}")
+      }
+      Some(name) => name.to_string(),
+      None => format!("{prefix}{depth}"),
+    }
+  }
+
+  /// Suggests names for the parameters of the library function `lib` from
+  /// its uses in `expr`, in the scope of the possibly named variables
+  /// `bindings`: a parameter is named after the variable it is applied to,
+  /// if some use applies it to a named variable.
+  fn param_names(
+    &self,
+    expr: &Expr<Op>,
+    lib: LibId,
+    bindings: &mut Vec<Option<String>>,
+    params: &mut Vec<Option<String>>,
+  ) {
+    let mut fun = expr;
+    let mut args = Vec::new();
+    while let Some(BindingExpr::Apply(inner, arg)) = fun.0.as_binding_expr() {
+      args.push(arg);
+      fun = inner;
+    }
+    if matches!(
+      fun.0.as_binding_expr(),
+      Some(BindingExpr::LibVar(other)) if other == lib
+    ) {
+      params.resize(params.len().max(args.len()), None);
+      for (param, arg) in params.iter_mut().zip(args.into_iter().rev()) {
+        if let Some(BindingExpr::Var(index)) = arg.0.as_binding_expr() {
+          let name = bindings
+            .len()
+            .checked_sub(index.0 + 1)
+            .and_then(|i| bindings[i].clone());
+          if param.is_none() {
+            *param = name;
+          }
+        }
+      }
+    }
+
+    match expr.0.as_binding_expr() {
+      Some(BindingExpr::Lambda(body) | BindingExpr::Fix(body)) => {
+        let name = self.var_names.and_then(|names| names.name(expr));
+        bindings.push(name.map(str::to_string));
+        self.param_names(body, lib, bindings, params);
+        bindings.pop();
+      }
+      _ => {
+        for child in expr.0.iter() {
+          self.param_names(child, lib, bindings, params);
+        }
+      }
+    }
+  }
+}
+
+/// Replaces every library function which is used exactly once within the
+/// scope of its definition with its definition.
+fn inline_single_use<Op: Teachable + Clone>(expr: Expr<Op>) -> Expr<Op> {
+  let node = expr.0.map(inline_single_use);
+  if let Some(BindingExpr::Lib(lib, def, body)) = node.as_binding_expr() {
+    if count_uses(body, lib) == 1 {
+      return substitute_lib(body.clone(), lib, def, 0);
+    }
+  }
+  Expr(node)
+}
+
+/// Counts the uses of the library function `lib` in `expr`.
+fn count_uses<Op: Teachable>(expr: &Expr<Op>, lib: LibId) -> usize {
+  match expr.0.as_binding_expr() {
+    Some(BindingExpr::LibVar(other)) => usize::from(other == lib),
+    _ => expr.0.iter().map(|child| count_uses(child, lib)).sum(),
+  }
+}
+
+/// Replaces the uses of the library function `lib` in `expr`, which is
+/// nested within `depth` binders, with its definition `def`.
+fn substitute_lib<Op: Teachable + Clone>(
+  expr: Expr<Op>,
+  lib: LibId,
+  def: &Expr<Op>,
+  depth: usize,
+) -> Expr<Op> {
+  let depth = match expr.0.as_binding_expr() {
+    Some(BindingExpr::LibVar(other)) if other == lib => {
+      return shift(def, depth, 0);
+    }
+    Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_)) => depth + 1,
+    _ => depth,
+  };
+  Expr(expr.0.map(|child| substitute_lib(child, lib, def, depth)))
+}
+
+/// Shifts the variables in `expr` which are free at binder depth `cutoff` up
+/// by `by`.
+fn shift<Op: Teachable + Clone>(
+  expr: &Expr<Op>,
+  by: usize,
+  cutoff: usize,
+) -> Expr<Op> {
+  let cutoff = match expr.0.as_binding_expr() {
+    Some(BindingExpr::Var(index)) if index.0 >= cutoff => {
+      return Expr(Op::var(index.0 + by));
+    }
+    Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_)) => cutoff + 1,
+    _ => cutoff,
+  };
+  Expr(AstNode {
+    operation: expr.0.operation().clone(),
+    args: expr.0.iter().map(|child| shift(child, by, cutoff)).collect(),
+  })
+}
+
 /// Operator precedence
 pub type Precedence = u8;
 
@@ -57,12 +446,20 @@ pub struct Printer<W: Write> {
   bindings: Vec<String>,
   // Current indentation level
   indentation: usize,
+  /// Names of library functions, printed instead of their ids
+  lib_names: LibNames,
 }
 
 impl<W: Write> Printer<W> {
   /// Create a fresh printer for the top-level expression
   fn new(writer: W) -> Self {
-    Self { writer, bindings: vec![], ctx_precedence: 0, indentation: 0 }
+    Self {
+      writer,
+      bindings: vec![],
+      ctx_precedence: 0,
+      indentation: 0,
+      lib_names: LibNames::default(),
+    }
   }
 
   /// Print `expr` into the buffer at the current precedence level
@@ -138,9 +535,15 @@ impl<W: Write> Printer<W> {
               .expect("unbound variable");
             self.writer.write_str(name)
           }
-          BindingExpr::Lib(ix, def, body) => {
+          BindingExpr::Lib(ix, def, body)
+          | BindingExpr::LibRec(ix, def, body) => {
+            let keyword = match binding_expr {
+              BindingExpr::LibRec(..) => "lib rec",
+              _ => "lib",
+            };
             self.with_binding("f", |p| {
-              write!(p.writer, "lib {ix} =")?; // print binding
+              let name = p.lib_names.label(ix);
+              write!(p.writer, "{keyword} {name} =")?; // print binding
 
               p.indented(|p| {
                 p.new_line()?;
@@ -155,8 +558,14 @@ impl<W: Write> Printer<W> {
             })
           }
           BindingExpr::LibVar(ix) => {
-            write!(self.writer, "{ix}")
+            let name = self.lib_names.label(ix);
+            self.writer.write_str(&name)
           }
+          BindingExpr::Fix(body) => self.with_binding("f", |p| {
+            let fresh_var = p.bindings.last().unwrap();
+            write!(p.writer, "fix {fresh_var} -> ")?;
+            p.print_in_context(body, 0)
+          }),
         }
       }
       None => {
@@ -283,3 +692,25 @@ impl<W: Write> Printer<W> {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{Pretty, PrettyOptions, Syntax};
+  use crate::{simple_lang::SimpleOp, teachable::Teachable, Expr, LibId};
+
+  #[test]
+  fn prints_lib_rec() {
+    let l0 = LibId(0);
+    let call =
+      SimpleOp::apply(Expr(SimpleOp::lib_var(l0)), Expr(SimpleOp::var(0)));
+    let def = Expr(SimpleOp::lambda(Expr(call)));
+    let expr = Expr(SimpleOp::lib_rec(l0, def, Expr(SimpleOp::lib_var(l0))));
+
+    let ml = Pretty(&expr).to_string();
+    assert!(ml.starts_with("lib rec l0 ="), "{ml}");
+    let lisp = expr
+      .pretty_with(PrettyOptions::default().syntax(Syntax::Lisp))
+      .to_string();
+    assert!(lisp.starts_with("(librec l0 (lambda (x0) "), "{lisp}");
+  }
+}
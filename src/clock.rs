@@ -0,0 +1,27 @@
+//! Reading the clock for time limits and timings. On targets without a
+//! clock, such as `wasm32-unknown-unknown`, [`Instant::now`] panics, so
+//! without the `std` feature the clock is never read: deadlines never pass,
+//! and everything takes no time.
+
+use std::time::{Duration, Instant};
+
+/// The current time, or `None` without the `std` feature.
+pub(crate) fn now() -> Option<Instant> {
+  cfg!(feature = "std").then(Instant::now)
+}
+
+/// The time elapsed since `start`, or zero if the clock wasn't read.
+pub(crate) fn elapsed(start: Option<Instant>) -> Duration {
+  start.as_ref().map_or(Duration::ZERO, Instant::elapsed)
+}
+
+/// The time `limit` from now, or `None` without the `std` feature or if it
+/// is too far in the future to represent.
+pub(crate) fn deadline(limit: Duration) -> Option<Instant> {
+  now().and_then(|now| now.checked_add(limit))
+}
+
+/// Whether `deadline` has passed, if there is one.
+pub(crate) fn passed(deadline: Option<Instant>) -> bool {
+  deadline.map_or(false, |deadline| Instant::now() >= deadline)
+}
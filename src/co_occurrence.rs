@@ -1,6 +1,9 @@
 //! Co-occurrence analysis on e-graphs.
 //! Determines whether (the nodes from) two e-classes can co-occur in any extracted expression.
-use crate::ast_node::{Arity, AstNode};
+use crate::{
+  ast_node::{Arity, AstNode},
+  learn::LibId,
+};
 use egg::{Analysis, EGraph, Id, Language};
 use std::{
   collections::{HashMap, HashSet},
@@ -27,6 +30,54 @@ impl CoOccurrences {
   }
 }
 
+/// The co-occurrence relation lifted to library functions: two library
+/// functions may co-occur if any of the e-classes matched by their patterns
+/// may co-occur.
+#[derive(Debug, Clone, Default)]
+pub struct LibCoOccurrences {
+  /// Pairs of library functions which may co-occur, with the smaller one
+  /// first.
+  pairs: HashSet<(LibId, LibId)>,
+}
+
+impl LibCoOccurrences {
+  /// Lifts `co_occurrences` to library functions, given the e-classes
+  /// matched by the pattern of each library function.
+  #[must_use]
+  pub fn new(
+    co_occurrences: &CoOccurrences,
+    matches: &[(LibId, Vec<Id>)],
+  ) -> Self {
+    let mut pairs = HashSet::new();
+    for (i, (lib1, classes1)) in matches.iter().enumerate() {
+      for (lib2, classes2) in &matches[i + 1..] {
+        let co_occur = classes1.iter().any(|&a| {
+          classes2.iter().any(|&b| co_occurrences.may_co_occur(a, b))
+        });
+        if co_occur {
+          pairs.insert((*lib1.min(lib2), *lib1.max(lib2)));
+        }
+      }
+    }
+    Self { pairs }
+  }
+
+  /// May library functions `a` and `b` co-occur?
+  #[must_use]
+  pub fn may_co_occur(&self, a: LibId, b: LibId) -> bool {
+    a == b || self.pairs.contains(&(a.min(b), a.max(b)))
+  }
+
+  /// May all of `libs` co-occur with each other?
+  #[must_use]
+  pub fn all_co_occur(&self, libs: &[LibId]) -> bool {
+    libs
+      .iter()
+      .enumerate()
+      .all(|(i, &a)| libs[i + 1..].iter().all(|&b| self.may_co_occur(a, b)))
+  }
+}
+
 /// Builds a co-occurrence relation for a given e-graph.
 /// We assume that the e-graph can have multiple roots (that correspond to different initial programs),
 /// and hence all these roots can co-occur,
@@ -0,0 +1,392 @@
+//! A high-level entry point for library learning, which runs the whole
+//! pipeline of rewriting, anti-unification, beam search, and extraction.
+
+use std::{
+  fmt::{Debug, Display},
+  hash::Hash,
+  sync::Arc,
+  time::Duration,
+};
+
+use egg::{EGraph, RecExpr, Rewrite, Runner};
+use log::{debug, info};
+
+use crate::{
+  ast_node::{Arity, AstNode, Expr, InternedCorpus},
+  co_occurrence::COBuilder,
+  error::Error,
+  extract::{
+    apply_libs_constrained,
+    beam::{LibConstraints, PartialLibCost},
+    cost::{CostModel, UnitCost},
+  },
+  learn::{DiscriminantEq, LearnedLibraryBuilder, LibId},
+  teachable::Teachable,
+};
+
+/// The settings used by [`compress`]. A `Config` is built by starting from
+/// [`Config::default`] and overriding the settings which matter.
+#[derive(Debug)]
+pub struct Config<Op> {
+  /// The domain-specific rewrites to apply before learning libraries.
+  dsrs: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// The number of library selections kept at the root.
+  final_beams: usize,
+  /// The number of library selections kept at every other e-class.
+  inter_beams: usize,
+  /// The number of libraries learned at a time.
+  lps: usize,
+  /// The maximum arity of a library function.
+  max_arity: Option<usize>,
+  /// Whether to learn "library functions" with no arguments.
+  learn_constants: bool,
+  /// Operations at which no library function may be rooted.
+  excluded_root_ops: Vec<Op>,
+  /// If not empty, the only operations at which library functions may be
+  /// rooted.
+  anchor_ops: Vec<Op>,
+  /// The number of times to apply library rewrites.
+  lib_iter_limit: usize,
+  /// The time limit for each run of the e-graph.
+  timeout: Duration,
+  /// The libraries which must or must not be used.
+  lib_constraints: LibConstraints,
+}
+
+impl<Op> Default for Config<Op> {
+  fn default() -> Self {
+    Self {
+      dsrs: Vec::new(),
+      final_beams: 400,
+      inter_beams: 400,
+      lps: 1,
+      max_arity: None,
+      learn_constants: false,
+      excluded_root_ops: Vec::new(),
+      anchor_ops: Vec::new(),
+      lib_iter_limit: 1,
+      timeout: Duration::from_secs(60),
+      lib_constraints: LibConstraints::default(),
+    }
+  }
+}
+
+impl<Op> Config<Op> {
+  /// Sets the domain-specific rewrites to apply before learning libraries.
+  /// By default, there are none.
+  #[must_use]
+  pub fn dsrs<I>(mut self, dsrs: I) -> Self
+  where
+    I: IntoIterator<Item = Rewrite<AstNode<Op>, PartialLibCost>>,
+  {
+    self.dsrs = dsrs.into_iter().collect();
+    self
+  }
+
+  /// Sets the number of library selections kept at the root (`final_beams`)
+  /// and at every other e-class (`inter_beams`).
+  #[must_use]
+  pub fn beams(mut self, final_beams: usize, inter_beams: usize) -> Self {
+    self.final_beams = final_beams;
+    self.inter_beams = inter_beams;
+    self
+  }
+
+  /// Sets the number of libraries learned at a time.
+  #[must_use]
+  pub fn lps(mut self, lps: usize) -> Self {
+    self.lps = lps;
+    self
+  }
+
+  /// Sets the maximum arity of a library function. By default, there is no
+  /// limit.
+  #[must_use]
+  pub fn max_arity(mut self, max_arity: Option<usize>) -> Self {
+    self.max_arity = max_arity;
+    self
+  }
+
+  /// Sets whether to learn "library functions" with no arguments.
+  #[must_use]
+  pub fn learn_constants(mut self, learn_constants: bool) -> Self {
+    self.learn_constants = learn_constants;
+    self
+  }
+
+  /// Sets the operations at which no library function may be rooted, such
+  /// as literals. See [`LearnedLibraryBuilder::exclude_root_op`]. By
+  /// default, there are none.
+  #[must_use]
+  pub fn exclude_root_ops<I>(mut self, ops: I) -> Self
+  where
+    I: IntoIterator<Item = Op>,
+  {
+    self.excluded_root_ops = ops.into_iter().collect();
+    self
+  }
+
+  /// Sets the operations at which every library function must be rooted.
+  /// See [`LearnedLibraryBuilder::anchor_op`]. By default, there are none,
+  /// and library functions may be rooted at any operation.
+  #[must_use]
+  pub fn anchor_ops<I>(mut self, ops: I) -> Self
+  where
+    I: IntoIterator<Item = Op>,
+  {
+    self.anchor_ops = ops.into_iter().collect();
+    self
+  }
+
+  /// Sets the number of times to apply library rewrites.
+  #[must_use]
+  pub fn lib_iter_limit(mut self, lib_iter_limit: usize) -> Self {
+    self.lib_iter_limit = lib_iter_limit;
+    self
+  }
+
+  /// Sets the time limit for each run of the e-graph.
+  #[must_use]
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Sets the libraries the compressed programs must or must not use, such
+  /// as ones defined by hand in the input programs. By default, there are no
+  /// constraints.
+  #[must_use]
+  pub fn lib_constraints(mut self, lib_constraints: LibConstraints) -> Self {
+    self.lib_constraints = lib_constraints;
+    self
+  }
+}
+
+/// The result of [`compress`].
+#[derive(Debug)]
+pub struct CompressionResult<Op> {
+  /// The compressed programs, as a list whose elements are the input
+  /// programs in order, with the learned libraries defined at the top. With
+  /// [`compress_frontiers`], there is one program per frontier.
+  pub expr: Expr<Op>,
+  /// The rewrites introducing each learned library function.
+  pub rewrites: Vec<Rewrite<AstNode<Op>, PartialLibCost>>,
+  /// The size of the input programs, counting only the smallest program of
+  /// each frontier.
+  pub initial_cost: usize,
+  /// The size of the compressed programs, not counting library definitions.
+  pub final_cost: usize,
+}
+
+/// The errors of [`compress`] and [`compress_frontiers`], which are now the
+/// errors of library learning as a whole.
+#[deprecated(note = "use `babble::Error`, which `CompressionError` became")]
+pub type CompressionError = Error;
+
+/// Learns a library from `exprs` and rewrites them to use it.
+///
+/// If nothing in `exprs` can be abstracted, such as when every program is a
+/// single node, no library is learned and the programs are returned as they
+/// are.
+///
+/// # Errors
+///
+/// Returns an error if `exprs` is empty, or if the library constraints can't
+/// be satisfied.
+pub fn compress<Op>(
+  exprs: Vec<Expr<Op>>,
+  config: &Config<Op>,
+) -> Result<CompressionResult<Op>, Error>
+where
+  Op: Arity
+    + Teachable
+    + Debug
+    + Display
+    + Hash
+    + Clone
+    + Ord
+    + Send
+    + Sync
+    + DiscriminantEq
+    + 'static,
+{
+  compress_frontiers(exprs.into_iter().map(|expr| vec![expr]).collect(), config)
+}
+
+/// Like [`compress`], but learns a library from `frontiers`, each of which
+/// is a group of alternative programs solving the same task, of which any one
+/// will do.
+///
+/// The programs of a frontier are merged into a single e-class, so each
+/// frontier only contributes the cost of its cheapest program, both to the
+/// initial cost and to the cost of every library selection in the beam
+/// search. The compressed output contains one program per frontier: whichever
+/// is cheapest once the learned libraries are used. Empty frontiers are
+/// ignored, and have no program in the output.
+///
+/// # Errors
+///
+/// Returns an error if every frontier is empty, or if the library
+/// constraints can't be satisfied.
+pub fn compress_frontiers<Op>(
+  frontiers: Vec<Vec<Expr<Op>>>,
+  config: &Config<Op>,
+) -> Result<CompressionResult<Op>, Error>
+where
+  Op: Arity
+    + Teachable
+    + Debug
+    + Display
+    + Hash
+    + Clone
+    + Ord
+    + Send
+    + Sync
+    + DiscriminantEq
+    + 'static,
+{
+  let frontier_costs: Vec<usize> = frontiers
+    .iter()
+    .filter_map(|frontier| {
+      frontier
+        .iter()
+        .map(|expr| UnitCost.cost_rec(&RecExpr::from(expr.clone())))
+        .min()
+    })
+    .collect();
+  if frontier_costs.is_empty() {
+    return Err(Error::NoPrograms);
+  }
+  let initial_cost =
+    frontier_costs.iter().sum::<usize>() + UnitCost.node_cost(&Op::list());
+  let corpus = InternedCorpus::from_groups(frontiers);
+  let mut egraph = EGraph::new(
+    PartialLibCost::new(config.final_beams, config.inter_beams, config.lps)
+      .with_lib_constraints(config.lib_constraints.clone()),
+  );
+  let roots = corpus.add_to_egraph(&mut egraph);
+  egraph.rebuild();
+  // Learned libraries are numbered after any the input programs define, so
+  // that those can be constrained.
+  let lib_offset = LibId::next_free(&egraph).0;
+
+  info!("Running {} DSRs... ", config.dsrs.len());
+  let aeg = Runner::<_, _, ()>::new(PartialLibCost::empty())
+    .with_egraph(egraph)
+    .with_time_limit(config.timeout)
+    .with_iter_limit(3)
+    .run(&config.dsrs)
+    .egraph;
+
+  info!("Running anti-unification... ");
+  let co_occurs = COBuilder::new(&aeg, &roots).run();
+  let mut learned_lib = LearnedLibraryBuilder::default()
+    .lib_offset(lib_offset)
+    .learn_constants(config.learn_constants)
+    .max_arity(config.max_arity)
+    .exclude_root_ops(config.excluded_root_ops.iter().cloned())
+    .anchor_ops(config.anchor_ops.iter().cloned())
+    .with_co_occurs(co_occurs)
+    .build(&aeg);
+  learned_lib.deduplicate(&aeg);
+  let lib_rewrites: Vec<_> = learned_lib.rewrites().collect();
+  info!("Found {} patterns", lib_rewrites.len());
+
+  info!("Adding libs and running beam search... ");
+  let mut egraph = Runner::<_, _, ()>::new(PartialLibCost::empty())
+    .with_egraph(aeg.clone())
+    .with_iter_limit(config.lib_iter_limit)
+    .with_time_limit(config.timeout)
+    .with_node_limit(1_000_000)
+    .run(lib_rewrites.iter())
+    .egraph;
+  let root = egraph.add(AstNode::new(Op::list(), roots.iter().copied()));
+  let mut root_costs = egraph[egraph.find(root)].data.clone();
+  if !root_costs.retain_satisfying(&config.lib_constraints) {
+    return Err(Error::UnsatisfiableConstraints);
+  }
+  root_costs.sort_by_full_cost();
+
+  // The beam always keeps some lib selection at the root, if only the one
+  // with no libs, unless the constraints rule them all out.
+  let best = root_costs.set.first().ok_or(Error::UnsatisfiableConstraints)?;
+  let rewrites: Vec<_> = best
+    .libs
+    .iter()
+    .filter_map(|lib| learned_lib.lib_index(lib.0))
+    .map(|i| lib_rewrites[i].clone())
+    .collect();
+
+  info!("Extracting... ");
+  let lifted = apply_libs_constrained(
+    aeg,
+    &roots,
+    &rewrites,
+    Arc::new(UnitCost),
+    &config.lib_constraints,
+  )?;
+  let final_cost = UnitCost.cost_rec(&lifted);
+  debug!("final cost: {}", final_cost);
+
+  Ok(CompressionResult {
+    expr: lifted.into(),
+    rewrites,
+    initial_cost,
+    final_cost,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{compress, compress_frontiers, Config};
+  use crate::{
+    round_trip::parse, simple_lang::SimpleOp, util::compression_factor, Error,
+  };
+
+  #[test]
+  fn no_programs() {
+    let config = Config::default();
+    assert!(matches!(
+      compress::<SimpleOp>(Vec::new(), &config),
+      Err(Error::NoPrograms)
+    ));
+    assert!(matches!(
+      compress_frontiers::<SimpleOp>(vec![Vec::new(), Vec::new()], &config),
+      Err(Error::NoPrograms)
+    ));
+  }
+
+  #[test]
+  fn ignores_empty_frontiers() {
+    let config = Config::default();
+    let frontiers = vec![Vec::new(), vec![parse("(@ f x)")], Vec::new()];
+    let result = compress_frontiers(frontiers, &config).unwrap();
+    assert_eq!(result.expr, parse("(list (@ f x))"));
+    assert_eq!(result.initial_cost, result.final_cost);
+  }
+
+  #[test]
+  fn single_node_programs() {
+    let config = Config::default();
+    let result = compress(vec![parse("a"), parse("a")], &config).unwrap();
+    assert!(result.rewrites.is_empty());
+    assert_eq!(result.expr, parse("(list a a)"));
+    assert_eq!(result.initial_cost, result.final_cost);
+  }
+
+  #[test]
+  fn nothing_to_abstract() {
+    let config = Config::default();
+    let exprs = vec![parse("(@ f x)"), parse("(lambda $0)")];
+    let result = compress(exprs, &config).unwrap();
+    assert!(result.rewrites.is_empty());
+    assert_eq!(result.expr, parse("(list (@ f x) (lambda $0))"));
+  }
+
+  #[test]
+  fn compression_factor_of_empty_programs() {
+    assert!((compression_factor(0, 0) - 1.0).abs() < f64::EPSILON);
+    assert!((compression_factor(4, 0) - 1.0).abs() < f64::EPSILON);
+    assert!((compression_factor(4, 2) - 2.0).abs() < f64::EPSILON);
+  }
+}
@@ -0,0 +1,18 @@
+//! Defines the [`ControlFlow`] trait for languages with control-flow
+//! operations, whose arguments library learning treats as natural
+//! parameters.
+
+/// A trait for languages with control-flow operations, like conditionals
+/// and loops.
+///
+/// Implementing this trait is optional. If an operation implements it, a
+/// [`LearnedLibraryBuilder`](crate::LearnedLibraryBuilder) can be asked to
+/// abstract over each argument of a control-flow operation as a whole. For
+/// example, `(if (< x 0) a b)` and `(if (< y 1) a b)` can then be abstracted
+/// into `(if ?cond a b)`, rather than only into `(if (< ?x ?n) a b)`.
+pub trait ControlFlow {
+  /// Returns `true` if the operation is a control-flow operation, whose
+  /// arguments, such as a condition, a branch, or a loop body, should each
+  /// become a parameter of a library function when they differ.
+  fn is_control_flow(&self) -> bool;
+}
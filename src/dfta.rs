@@ -14,6 +14,9 @@ use std::{
 pub(crate) struct Dfta<Op, S> {
   by_operation: BTreeMap<Op, BTreeSet<(Vec<S>, S)>>,
   by_output: BTreeMap<S, BTreeSet<(Op, Vec<S>)>>,
+  /// States of a crossed-over DFTA which pair two applications of the same
+  /// variadic operation to different numbers of inputs.
+  mismatched_arity: BTreeSet<S>,
 }
 
 impl<Op: Debug, S: Debug> Debug for Dfta<Op, S> {
@@ -57,7 +60,11 @@ where
   /// Create an empty DFTA.
   #[must_use]
   pub(crate) fn new() -> Self {
-    Self { by_operation: BTreeMap::new(), by_output: BTreeMap::new() }
+    Self {
+      by_operation: BTreeMap::new(),
+      by_output: BTreeMap::new(),
+      mismatched_arity: BTreeSet::new(),
+    }
   }
 
   #[allow(unused)]
@@ -99,8 +106,11 @@ where
         for (op2, rules2) in &self.by_operation {
           for (inputs2, output2) in rules2 {
             let new_output = (output1.clone(), output2.clone());
-            if op1 == op2 {
-              // ops are the same, can assume args len are the same
+            if op1 == op2 && inputs1.len() != inputs2.len() {
+              // A variadic operation with different numbers of inputs can
+              // only be anti-unified as a whole.
+              new_dfta.mismatched_arity.insert(new_output);
+            } else if op1 == op2 {
               let new_inputs =
                 inputs1.iter().cloned().zip(inputs2.iter().cloned());
               new_dfta.add_rule(
@@ -139,6 +149,13 @@ where
   ) -> Option<&BTreeSet<(Op, Vec<S>)>> {
     self.by_output.get(output)
   }
+
+  /// Returns `true` if `state` pairs two applications of the same variadic
+  /// operation to different numbers of inputs, which have no transition rule
+  /// in a crossed-over DFTA.
+  pub(crate) fn has_mismatched_arity(&self, state: &S) -> bool {
+    self.mismatched_arity.contains(state)
+  }
 }
 
 impl<Op, S> Default for Dfta<Op, S>
@@ -0,0 +1,42 @@
+//! The errors of library learning, shared by [`compress`](crate::compress),
+//! extraction, and the experiments built on top of them.
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::extract::beam::ExtractionError;
+#[cfg(feature = "std")]
+use crate::extract::maxsat::MaxSatError;
+
+/// An error from library learning.
+#[derive(Debug, Error)]
+pub enum Error {
+  /// There are no programs to learn a library from: the input is empty, or
+  /// so is every group of equivalent programs.
+  #[error("no programs to learn a library from")]
+  NoPrograms,
+  /// No library selection satisfies the library constraints.
+  #[error("no library selection satisfies the library constraints")]
+  UnsatisfiableConstraints,
+  /// The rewritten programs couldn't be extracted.
+  #[error(transparent)]
+  Extraction(#[from] ExtractionError),
+  /// The Max-SAT solver choosing the library functions failed.
+  #[cfg(feature = "std")]
+  #[error(transparent)]
+  MaxSat(#[from] MaxSatError),
+  /// A rewritten program doesn't compute the same value as the program it
+  /// was rewritten from, at the given index.
+  #[error(
+    "rewritten program {0} doesn't compute the same value as the original"
+  )]
+  NotPreserved(usize),
+  /// The operation isn't supported, such as running an experiment in a way
+  /// it wasn't designed for.
+  #[error("{0} is not supported")]
+  Unsupported(&'static str),
+  /// Results couldn't be written.
+  #[error(transparent)]
+  Io(#[from] io::Error),
+}
@@ -2,22 +2,41 @@
 //! orderings of learned library sets.
 use egg::{Analysis, CostFunction, DidMerge, EGraph, Id, Language, RecExpr};
 use log::debug;
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
+  any::Any,
+  borrow::Cow,
   cmp::Ordering,
-  collections::{BinaryHeap, HashMap},
-  fmt::Debug,
+  collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+  fmt::{self, Debug, Formatter},
+  sync::{Arc, Mutex, PoisonError},
+  time::{Duration, Instant},
 };
+use thiserror::Error;
+use tracing::{trace_span, Span};
 
+use super::cost::{CostModel, UnitCost};
 use crate::{
   ast_node::{Arity, AstNode},
+  clock,
+  co_occurrence::LibCoOccurrences,
   learn::LibId,
   teachable::{BindingExpr, Teachable},
 };
+#[cfg(not(feature = "std"))]
+use sequential::{FlatMapIter, ParallelSlice};
+
+/// The minimum number of pairs of `LibSel`s in a cross product before we
+/// compute it in parallel. Below this, the overhead of spawning rayon tasks
+/// outweighs the work being done.
+const PAR_CROSS_THRESHOLD: usize = 1024;
 
 /// A `CostSet` is a set of pairs; each pair contains a set of library
 /// functions paired with the cost of the current expression/eclass
 /// without the lib fns, and the cost of the lib fns themselves.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CostSet {
   /// The set of library selections and their associated costs.
   /// Invariant: sorted in ascending order of `expr_cost`, except during
@@ -30,8 +49,16 @@ impl CostSet {
   /// This will always have the value [{} -> (1, 1)].
   #[must_use]
   pub fn intro_op() -> CostSet {
+    Self::intro_cost(1)
+  }
+
+  /// Creates a `CostSet` corresponding to introducing a nullary operation
+  /// with the given cost.
+  /// This will always have the value [{} -> (cost, cost)].
+  #[must_use]
+  pub fn intro_cost(cost: usize) -> CostSet {
     let mut set = Vec::with_capacity(10);
-    set.push(LibSel::intro_op());
+    set.push(LibSel { libs: Vec::new(), expr_cost: cost, full_cost: cost });
     CostSet { set }
   }
 
@@ -41,20 +68,32 @@ impl CostSet {
   /// `LibSel`s have their libraries combined and costs added.
   #[must_use]
   pub fn cross(&self, other: &CostSet, lps: usize) -> CostSet {
-    let mut set = Vec::new();
+    self.cross_with(other, lps, true)
+  }
 
-    for ls1 in &self.set {
-      for ls2 in &other.set {
-        match ls1.combine(ls2, lps) {
-          None => continue,
-          Some(ls) => {
-            if let Err(pos) = set.binary_search(&ls) {
-              set.insert(pos, ls);
-            }
-          }
-        }
-      }
-    }
+  /// Like [`CostSet::cross`], but large cross products are only computed in
+  /// parallel if `parallel` is set.
+  fn cross_with(&self, other: &CostSet, lps: usize, parallel: bool) -> CostSet {
+    let mut set: Vec<LibSel> =
+      if parallel && self.set.len() * other.set.len() >= PAR_CROSS_THRESHOLD {
+        self
+          .set
+          .par_iter()
+          .flat_map_iter(|ls1| {
+            other.set.iter().filter_map(move |ls2| ls1.combine(ls2, lps))
+          })
+          .collect()
+      } else {
+        self
+          .set
+          .iter()
+          .flat_map(|ls1| {
+            other.set.iter().filter_map(move |ls2| ls1.combine(ls2, lps))
+          })
+          .collect()
+      };
+    set.sort_unstable();
+    set.dedup();
 
     CostSet { set }
   }
@@ -62,7 +101,6 @@ impl CostSet {
   /// Combines two `CostSets` by unioning them together.
   /// Used for e.g. different `ENodes` of an `EClass`.
   pub fn combine(&mut self, other: CostSet) {
-    // println!("combine");
     let mut cix = 0;
 
     for elem in other.set {
@@ -78,7 +116,6 @@ impl CostSet {
   /// Performs trivial partial order reduction: if `CostSet` A contains a superset
   /// of the libs of another `CostSet` B, and A has a higher `expr_cost` than B, remove A.
   pub fn unify(&mut self) {
-    // println!("unify");
     let mut i = 0;
 
     while i < self.set.len() {
@@ -98,39 +135,113 @@ impl CostSet {
     }
   }
 
+  /// Removes the `LibSel`s which are dominated by others in this `CostSet`,
+  /// as decided by `strategy`.
+  pub fn unify_with(&mut self, strategy: &PruningStrategy) {
+    match strategy {
+      PruningStrategy::None => {}
+      PruningStrategy::Subset => self.unify(),
+      PruningStrategy::PartialOrder => self.remove_dominated(|ls1, ls2| {
+        ls1.expr_cost <= ls2.expr_cost && ls1.is_subset(ls2)
+      }),
+      PruningStrategy::Custom(dominates) => {
+        self.remove_dominated(|ls1, ls2| dominates(ls1, ls2));
+      }
+    }
+  }
+
+  /// Removes every `LibSel` which is dominated by another, in either
+  /// direction. Of two `LibSel`s which dominate each other, the first is
+  /// kept.
+  fn remove_dominated<F>(&mut self, dominates: F)
+  where
+    F: Fn(&LibSel, &LibSel) -> bool,
+  {
+    let mut i = 0;
+
+    'outer: while i < self.set.len() {
+      let mut j = i + 1;
+
+      while j < self.set.len() {
+        if dominates(&self.set[i], &self.set[j]) {
+          self.set.remove(j);
+        } else if dominates(&self.set[j], &self.set[i]) {
+          self.set.remove(i);
+          continue 'outer;
+        } else {
+          j += 1;
+        }
+      }
+      i += 1;
+    }
+  }
+
   /// Increments the expr and full cost of every `LibSel` in this `CostSet`.
   /// This is done if we e.g. cross all the args of a node, then have to add
   /// the node itself to the cost.
   pub fn inc_cost(&mut self) {
-    // println!("inc_cost");
+    self.add_cost(1);
+  }
+
+  /// Adds `cost` to the expr and full cost of every `LibSel` in this
+  /// `CostSet`.
+  pub fn add_cost(&mut self, cost: usize) {
     for ls in &mut self.set {
-      ls.inc_cost();
+      ls.expr_cost += cost;
+      ls.full_cost += cost;
+    }
+  }
+
+  /// Multiplies the expression cost of every `LibSel` by `weight`, as if the
+  /// expression occurred `weight` times. The cost of the libs is unchanged,
+  /// since each is only defined once.
+  pub fn scale(&mut self, weight: usize) {
+    for ls in &mut self.set {
+      ls.full_cost = ls.full_cost - ls.expr_cost + ls.expr_cost * weight;
+      ls.expr_cost *= weight;
     }
   }
 
   #[must_use]
   pub fn add_lib(&self, lib: LibId, cost: &CostSet, lps: usize) -> CostSet {
-    // println!("add_lib");
-    // To add a lib, we do a modified cross.
-    let mut set = Vec::new();
+    self.add_lib_with(lib, cost, lps, true)
+  }
 
-    for ls1 in &cost.set {
-      if ls1.libs.iter().any(|l| l.0 == lib) {
-        // If this libsel contains the lib we are defining,
-        // we can't use in in the definition.
-        continue;
-      }
-      for ls2 in &self.set {
-        match ls2.add_lib(lib, ls1, lps) {
-          None => continue,
-          Some(ls) => {
-            if let Err(pos) = set.binary_search(&ls) {
-              set.insert(pos, ls);
-            }
-          }
-        }
-      }
-    }
+  /// Like [`CostSet::add_lib`], but large cross products are only computed
+  /// in parallel if `parallel` is set.
+  fn add_lib_with(
+    &self,
+    lib: LibId,
+    cost: &CostSet,
+    lps: usize,
+    parallel: bool,
+  ) -> CostSet {
+    // To add a lib, we do a modified cross.
+    // If a libsel contains the lib we are defining, we can't use it in the
+    // definition.
+    let defs: Vec<&LibSel> = cost
+      .set
+      .iter()
+      .filter(|ls1| ls1.libs.iter().all(|l| l.0 != lib))
+      .collect();
+    let mut set: Vec<LibSel> =
+      if parallel && defs.len() * self.set.len() >= PAR_CROSS_THRESHOLD {
+        defs
+          .par_iter()
+          .flat_map_iter(|ls1| {
+            self.set.iter().filter_map(move |ls2| ls2.add_lib(lib, ls1, lps))
+          })
+          .collect()
+      } else {
+        defs
+          .iter()
+          .flat_map(|ls1| {
+            self.set.iter().filter_map(move |ls2| ls2.add_lib(lib, ls1, lps))
+          })
+          .collect()
+      };
+    set.sort_unstable();
+    set.dedup();
 
     CostSet { set }
   }
@@ -147,13 +258,20 @@ impl CostSet {
   /// n is the beam size. In other words, we preserve n `LibSel`s with
   /// 0 libs, n `LibSel`s with 1 lib, etc.
   pub fn prune(&mut self, n: usize, lps: usize) {
+    self.prune_seeded(n, lps, None);
+  }
+
+  /// Like [`CostSet::prune`], but if `seed` is set, `LibSel`s with the same
+  /// full cost are kept in a pseudo-random order determined by `seed`,
+  /// instead of by expr cost and lib ids.
+  pub fn prune_seeded(&mut self, n: usize, lps: usize, seed: Option<u64>) {
     use std::cmp::Reverse;
 
     let old_set = std::mem::take(&mut self.set);
 
     // First, we create a table from # of libs to a list of LibSels
-    let mut table: HashMap<usize, BinaryHeap<Reverse<LibSelFC>>> =
-      HashMap::new();
+    let mut table: BTreeMap<usize, BinaryHeap<Reverse<LibSelFC>>> =
+      BTreeMap::new();
 
     // We then iterate over all of the LibSels in this set
     for ls in old_set {
@@ -167,7 +285,7 @@ impl CostSet {
 
       let h = table.entry(num_libs).or_default();
 
-      h.push(Reverse(LibSelFC(ls)));
+      h.push(Reverse(LibSelFC(ls, seed)));
     }
 
     // From our table, recombine into a sorted vector
@@ -195,8 +313,99 @@ impl CostSet {
     self.set = set;
   }
 
+  /// Like [`CostSet::prune_seeded`], but for each number of libs, keeps the
+  /// `LibSel`s for which `preferred` holds ahead of all other `LibSel`s,
+  /// regardless of cost.
+  pub fn prune_preferring<F>(
+    &mut self,
+    n: usize,
+    lps: usize,
+    seed: Option<u64>,
+    preferred: F,
+  ) where
+    F: Fn(&LibSel) -> bool,
+  {
+    let (set, others): (Vec<_>, Vec<_>) =
+      std::mem::take(&mut self.set).into_iter().partition(&preferred);
+    self.set = set;
+    self.prune_seeded(n, lps, seed);
+
+    let mut others = CostSet { set: others };
+    others.prune_seeded(n, lps, seed);
+    others
+      .set
+      .sort_unstable_by(|ls1, ls2| LibSelFC::cmp_full_cost(ls1, ls2, seed));
+
+    // Fill up any remaining room for each number of libs.
+    let beams_per_size = std::cmp::max(1, n / lps);
+    let mut kept: HashMap<usize, usize> = HashMap::new();
+    for ls in &self.set {
+      *kept.entry(ls.libs.len()).or_default() += 1;
+    }
+    for ls in others.set {
+      let count = kept.entry(ls.libs.len()).or_default();
+      if *count < beams_per_size {
+        *count += 1;
+        if let Err(pos) = self.set.binary_search(&ls) {
+          self.set.insert(pos, ls);
+        }
+      }
+    }
+  }
+
+  /// Removes the `LibSel`s which use a lib forbidden by `constraints`.
+  pub fn retain_allowed(&mut self, constraints: &LibConstraints) {
+    if !constraints.forbidden.is_empty() {
+      self.set.retain(|ls| constraints.allows(ls));
+    }
+  }
+
+  /// Keeps only the `LibSel`s which satisfy `constraints`, returning `false`
+  /// and leaving this `CostSet` unchanged if there are none.
+  pub fn retain_satisfying(&mut self, constraints: &LibConstraints) -> bool {
+    if self.set.iter().any(|ls| constraints.is_satisfied_by(ls)) {
+      self.set.retain(|ls| constraints.is_satisfied_by(ls));
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Sorts this `CostSet` in ascending order of full cost. Ties are broken
+  /// by expr cost and then by lib ids, so the order (and therefore which
+  /// `LibSel` comes first) never depends on how the set was built.
+  pub fn sort_by_full_cost(&mut self) {
+    self.set.sort_unstable_by(|ls1, ls2| {
+      (ls1.full_cost, ls1.expr_cost, &ls1.libs).cmp(&(
+        ls2.full_cost,
+        ls2.expr_cost,
+        &ls2.libs,
+      ))
+    });
+  }
+
+  /// Returns the Pareto frontier of this `CostSet` with respect to the number
+  /// of libs used and the full cost: for every number of libs, the cheapest
+  /// `LibSel` with that many libs, as long as it is strictly cheaper than
+  /// every `LibSel` with fewer libs.
+  ///
+  /// The result is sorted in ascending order of number of libs (and therefore
+  /// descending order of full cost).
+  #[must_use]
+  pub fn pareto_front(&self) -> Vec<LibSel> {
+    let mut sorted: Vec<_> = self.set.iter().collect();
+    sorted.sort_by_key(|ls| (ls.libs.len(), ls.full_cost, &ls.libs));
+
+    let mut front: Vec<LibSel> = Vec::new();
+    for ls in sorted {
+      if front.last().map_or(true, |best| ls.full_cost < best.full_cost) {
+        front.push(ls.clone());
+      }
+    }
+    front
+  }
+
   pub fn unify2(&mut self) {
-    // println!("unify");
     let mut i = 0;
 
     while i < self.set.len() {
@@ -220,8 +429,9 @@ impl CostSet {
 /// A `LibSel` is a selection of library functions, paired with two
 /// corresponding cost values: the cost of the expression without the library
 /// functions, and the cost of the library functions themselves
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-
+#[derive(
+  Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
 pub struct LibSel {
   // We place this first so that it has prio in the Ord impl
   pub expr_cost: usize,
@@ -361,24 +571,45 @@ impl LibSel {
   }
 }
 
-/// A wrapper around `LibSel`s that orders based on their full cost.
+/// A wrapper around `LibSel`s that orders based on their full cost. Ties are
+/// broken pseudo-randomly if the seed is set.
 #[derive(Debug, Clone, PartialEq, Eq)]
-struct LibSelFC(pub(crate) LibSel);
+struct LibSelFC(pub(crate) LibSel, Option<u64>);
+
+impl LibSelFC {
+  /// Compares `ls1` and `ls2` by full cost, breaking ties by a hash of their
+  /// lib ids if `seed` is set, and then by expr cost and lib ids.
+  fn cmp_full_cost(ls1: &LibSel, ls2: &LibSel, seed: Option<u64>) -> Ordering {
+    let key = |ls: &LibSel| seed.map(|seed| tie_break_key(seed, &ls.libs));
+    ls1
+      .full_cost
+      .cmp(&ls2.full_cost)
+      .then_with(|| key(ls1).cmp(&key(ls2)))
+      .then_with(|| ls1.expr_cost.cmp(&ls2.expr_cost))
+      .then_with(|| ls1.libs.cmp(&ls2.libs))
+  }
+}
 
 impl PartialOrd for LibSelFC {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-    let mut r = self.0.full_cost.cmp(&other.0.full_cost);
-    if !r.is_eq() {
-      return Some(r);
-    }
+    Some(Self::cmp_full_cost(&self.0, &other.0, self.1))
+  }
+}
 
-    r = self.0.expr_cost.cmp(&other.0.expr_cost);
-    if !r.is_eq() {
-      return Some(r);
-    }
+/// A pseudo-random key for the lib selection with the libs `libs`, which
+/// only depends on `seed` and the lib ids.
+fn tie_break_key(seed: u64, libs: &[(LibId, usize)]) -> u64 {
+  libs
+    .iter()
+    .fold(splitmix64(seed), |hash, &(lib, _)| splitmix64(hash ^ lib.0 as u64))
+}
 
-    Some(self.0.libs.cmp(&other.0.libs))
-  }
+/// One step of the SplitMix64 generator, which makes a good mixing function.
+fn splitmix64(x: u64) -> u64 {
+  let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
 }
 
 impl Ord for LibSelFC {
@@ -387,18 +618,240 @@ impl Ord for LibSelFC {
   }
 }
 
+/// A dominance check between lib selections: returns `true` if the first
+/// `LibSel` makes the second redundant.
+pub type Dominates = dyn Fn(&LibSel, &LibSel) -> bool + Send + Sync;
+
+/// How [`CostSet::unify_with`] removes the `LibSel`s which are dominated by
+/// others, before the beam is pruned by cost.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub enum PruningStrategy {
+  /// Keep every `LibSel`, leaving pruning to the beam alone.
+  None,
+  /// Remove a `LibSel` if an earlier one in the `CostSet` uses a subset of
+  /// its libs, as [`CostSet::unify`] does. Since `CostSet`s are sorted by
+  /// expression cost, the earlier one is usually no more expensive. This is
+  /// the default.
+  #[default]
+  Subset,
+  /// Remove a `LibSel` if another one, anywhere in the `CostSet`, uses a
+  /// subset of its libs and has no higher expression cost.
+  PartialOrder,
+  /// Remove a `LibSel` if another one dominates it according to the given
+  /// check. This cannot be serialized.
+  #[serde(skip)]
+  Custom(Arc<Dominates>),
+}
+
+impl Debug for PruningStrategy {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      PruningStrategy::None => write!(f, "None"),
+      PruningStrategy::Subset => write!(f, "Subset"),
+      PruningStrategy::PartialOrder => write!(f, "PartialOrder"),
+      PruningStrategy::Custom(_) => write!(f, "Custom(..)"),
+    }
+  }
+}
+
+/// Libs pinned by the user: libs every solution must use, such as ones
+/// curated by hand, and libs no solution may use. They are enforced both by
+/// the beam search (see [`PartialLibCost::with_lib_constraints`]) and by
+/// extraction (see [`LibExtractor::with_lib_constraints`]).
+///
+/// Required libs count toward the maximum number of libs per lib selection,
+/// so no more libs can be required than that.
+#[derive(
+  Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+pub struct LibConstraints {
+  /// The libs every solution must use.
+  pub required: BTreeSet<LibId>,
+  /// The libs no solution may use.
+  pub forbidden: BTreeSet<LibId>,
+}
+
+impl LibConstraints {
+  /// No constraints: any lib may be used, and none has to be.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Requires every solution to use `lib`.
+  #[must_use]
+  pub fn require(mut self, lib: LibId) -> Self {
+    self.forbidden.remove(&lib);
+    self.required.insert(lib);
+    self
+  }
+
+  /// Forbids every solution from using `lib`.
+  #[must_use]
+  pub fn forbid(mut self, lib: LibId) -> Self {
+    self.required.remove(&lib);
+    self.forbidden.insert(lib);
+    self
+  }
+
+  /// Whether there are no constraints.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.required.is_empty() && self.forbidden.is_empty()
+  }
+
+  /// Whether `ls` uses none of the forbidden libs.
+  #[must_use]
+  pub fn allows(&self, ls: &LibSel) -> bool {
+    ls.libs.iter().all(|(lib, _)| !self.forbidden.contains(lib))
+  }
+
+  /// Whether `ls` uses at least one of the required libs.
+  #[must_use]
+  pub fn uses_required(&self, ls: &LibSel) -> bool {
+    ls.libs.iter().any(|(lib, _)| self.required.contains(lib))
+  }
+
+  /// Whether `ls` uses every required lib and none of the forbidden ones.
+  #[must_use]
+  pub fn is_satisfied_by(&self, ls: &LibSel) -> bool {
+    self.allows(ls)
+      && self
+        .required
+        .iter()
+        .all(|lib| ls.libs.binary_search_by_key(lib, |&(id, _)| id).is_ok())
+  }
+}
+
+/// Telemetry gathered by [`PartialLibCost`] while the beam search runs, if
+/// enabled with [`PartialLibCost::with_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BeamStats {
+  /// The number of cross products computed, including those which introduce
+  /// a lib.
+  pub crosses: usize,
+  /// The number of times two `CostSet`s were combined when merging e-classes.
+  pub combines: usize,
+  /// The number of times dominated lib selections were removed.
+  pub unifies: usize,
+  /// The number of times a `CostSet` was pruned to the beam.
+  pub prunes: usize,
+  /// The time spent computing cross products.
+  pub cross_time: Duration,
+  /// The time spent combining `CostSet`s.
+  pub combine_time: Duration,
+  /// The time spent removing dominated lib selections.
+  pub unify_time: Duration,
+  /// The time spent pruning `CostSet`s to the beam.
+  pub prune_time: Duration,
+  /// The largest number of lib selections each e-class has held, by e-class
+  /// id.
+  pub max_set_sizes: BTreeMap<usize, usize>,
+}
+
+/// A phase of the beam search timed by [`BeamStats`].
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+  Cross,
+  Combine,
+  Unify,
+  Prune,
+}
+
+impl Phase {
+  /// A trace-level span covering a run of this phase.
+  fn span(self) -> Span {
+    match self {
+      Phase::Cross => trace_span!("cross"),
+      Phase::Combine => trace_span!("combine"),
+      Phase::Unify => trace_span!("unify"),
+      Phase::Prune => trace_span!("prune"),
+    }
+  }
+}
+
+impl BeamStats {
+  fn record(&mut self, phase: Phase, elapsed: Duration) {
+    let (count, time) = match phase {
+      Phase::Cross => (&mut self.crosses, &mut self.cross_time),
+      Phase::Combine => (&mut self.combines, &mut self.combine_time),
+      Phase::Unify => (&mut self.unifies, &mut self.unify_time),
+      Phase::Prune => (&mut self.prunes, &mut self.prune_time),
+    };
+    *count += 1;
+    *time += elapsed;
+  }
+
+  /// The largest number of lib selections any e-class has held.
+  #[must_use]
+  pub fn max_set_size(&self) -> usize {
+    self.max_set_sizes.values().copied().max().unwrap_or(0)
+  }
+
+  /// The total time spent in the timed phases.
+  #[must_use]
+  pub fn total_time(&self) -> Duration {
+    self.cross_time + self.combine_time + self.unify_time + self.prune_time
+  }
+}
+
 // --------------------------------
 // --- The actual Analysis part ---
 // --------------------------------
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartialLibCost {
   /// The number of `LibSel`s to keep per `EClass`.
   beam_size: usize,
+  /// The number of `LibSel`s to keep after crossing each argument of a node
+  /// with multiple arguments.
   inter_beam: usize,
-  /// The maximum number of libs per lib selection. Any lib selections with a larger amount will
-  /// be pruned.
+  /// If set, the beam is widened up to this many `LibSel`s when the cost
+  /// distribution is flat; see [`PartialLibCost::with_adaptive_beam`].
+  max_beam: Option<usize>,
+  /// If set, lib selections whose libs may all co-occur are kept ahead of
+  /// other lib selections when pruning. This is not serialized.
+  #[serde(skip)]
+  lib_co_occurrences: Option<Arc<LibCoOccurrences>>,
+  /// The number of libs per lib selection the beam is divided between: when
+  /// pruning, the beam keeps `beam_size / lps` lib selections for each
+  /// number of libs. Unless `max_libs_per_sel` is set, this is also the
+  /// maximum number of libs per lib selection.
   lps: usize,
+  /// The maximum number of libs per lib selection, if different from `lps`.
+  /// Lib selections with more libs are discarded as soon as they are formed.
+  #[serde(default)]
+  max_libs_per_sel: Option<usize>,
+  /// If set, the analysis never uses the rayon thread pool, so that its
+  /// results do not depend on the number of threads available; see
+  /// [`PartialLibCost::with_deterministic`].
+  #[serde(default)]
+  deterministic: bool,
+  /// How dominated lib selections are removed before pruning the beam.
+  #[serde(default)]
+  pruning: PruningStrategy,
+  /// The structure penalty added to the full cost of a lib selection for
+  /// each of its libs; see [`PartialLibCost::with_lib_penalty`].
+  #[serde(default)]
+  lib_penalty: usize,
+  /// The cost model used to compute the cost of each node, stored as an
+  /// `Arc<dyn CostModel<Op>>`. If it is absent (or has the wrong `Op`),
+  /// every node costs 1. The cost model is not serialized.
+  #[serde(skip)]
+  cost_model: Option<Arc<dyn Any + Send + Sync>>,
+  /// Telemetry about the beam search, if enabled; see
+  /// [`PartialLibCost::with_stats`]. It is shared between clones of the
+  /// analysis, and not serialized.
+  #[serde(skip)]
+  stats: Option<Arc<Mutex<BeamStats>>>,
+  /// If set, ties in full cost are broken pseudo-randomly using this seed
+  /// when pruning; see [`PartialLibCost::with_tie_break_seed`].
+  #[serde(default)]
+  seed: Option<u64>,
+  /// The libs lib selections must or must not use; see
+  /// [`PartialLibCost::with_lib_constraints`].
+  #[serde(default)]
+  constraints: LibConstraints,
 }
 
 impl PartialLibCost {
@@ -408,110 +861,446 @@ impl PartialLibCost {
     inter_beam: usize,
     lps: usize,
   ) -> PartialLibCost {
-    PartialLibCost { beam_size, inter_beam, lps }
+    PartialLibCost {
+      beam_size,
+      inter_beam,
+      lps,
+      max_libs_per_sel: None,
+      max_beam: None,
+      lib_co_occurrences: None,
+      deterministic: false,
+      pruning: PruningStrategy::default(),
+      lib_penalty: 0,
+      cost_model: None,
+      stats: None,
+      seed: None,
+      constraints: LibConstraints::default(),
+    }
   }
 
   #[must_use]
   pub fn empty() -> PartialLibCost {
-    PartialLibCost { beam_size: 0, inter_beam: 0, lps: 1 }
+    PartialLibCost {
+      beam_size: 0,
+      inter_beam: 0,
+      lps: 1,
+      max_libs_per_sel: None,
+      max_beam: None,
+      lib_co_occurrences: None,
+      deterministic: false,
+      pruning: PruningStrategy::default(),
+      lib_penalty: 0,
+      cost_model: None,
+      stats: None,
+      seed: None,
+      constraints: LibConstraints::default(),
+    }
   }
-}
 
-impl Default for PartialLibCost {
-  fn default() -> Self {
-    PartialLibCost::empty()
+  /// The number of `LibSel`s kept per `EClass`.
+  #[must_use]
+  pub fn beam_size(&self) -> usize {
+    self.beam_size
   }
-}
 
-impl<Op> Analysis<AstNode<Op>> for PartialLibCost
-where
-  Op: Ord
-    + std::hash::Hash
-    + Debug
-    + Teachable
-    + Arity
-    + Eq
-    + Clone
-    + Send
-    + Sync
-    + 'static,
-{
-  type Data = CostSet;
+  /// The number of `LibSel`s kept after crossing each argument of a node
+  /// with multiple arguments.
+  #[must_use]
+  pub fn inter_beam(&self) -> usize {
+    self.inter_beam
+  }
 
-  fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
-    // println!("merge");
-    // println!("{:?}", to);
-    // println!("{:?}", &from);
+  /// The maximum number of libs in a lib selection; see
+  /// [`PartialLibCost::with_max_libs_per_sel`].
+  #[must_use]
+  pub fn max_libs_per_sel(&self) -> usize {
+    self.max_libs_per_sel.unwrap_or(self.lps)
+  }
+
+  /// Never form lib selections with more than `max_libs_per_sel` libs,
+  /// instead of `lps`, which then only determines how the beam is divided
+  /// between lib selections with different numbers of libs.
+  #[must_use]
+  pub fn with_max_libs_per_sel(
+    mut self,
+    max_libs_per_sel: usize,
+  ) -> PartialLibCost {
+    self.max_libs_per_sel = Some(max_libs_per_sel);
+    self
+  }
+
+  /// Halves the beam sizes, but not below the number of libs per lib
+  /// selection. Returns `false` if the beam could not be narrowed any
+  /// further.
+  pub fn narrow_beam(&mut self) -> bool {
+    let beam_size = std::cmp::max(self.lps, self.beam_size / 2);
+    let inter_beam = std::cmp::max(self.lps, self.inter_beam / 2);
+    let narrowed = beam_size < self.beam_size || inter_beam < self.inter_beam;
+    self.beam_size = beam_size;
+    self.inter_beam = inter_beam;
+    self.max_beam = self.max_beam.map(|max_beam| max_beam / 2);
+    narrowed
+  }
+
+  /// Widen the beam, up to `max_beam` `LibSel`s, wherever the cost
+  /// distribution is flat: instead of arbitrarily discarding `LibSel`s which
+  /// cost exactly as much as the most expensive one kept, keep all of them.
+  #[must_use]
+  pub fn with_adaptive_beam(mut self, max_beam: usize) -> PartialLibCost {
+    self.max_beam = Some(max_beam);
+    self
+  }
+
+  /// Bias pruning toward lib selections whose libs may all co-occur,
+  /// according to `lib_co_occurrences`: such lib selections are kept ahead
+  /// of any others with the same number of libs.
+  #[must_use]
+  pub fn with_lib_co_occurrences(
+    mut self,
+    lib_co_occurrences: LibCoOccurrences,
+  ) -> PartialLibCost {
+    self.lib_co_occurrences = Some(Arc::new(lib_co_occurrences));
+    self
+  }
+
+  /// Make the analysis reproducible bit-for-bit: cross products are always
+  /// computed on the current thread. `CostSet`s are always kept in a total
+  /// order (ties are broken by lib ids), so with this set the resulting
+  /// `CostSet`s are the same from run to run and machine to machine.
+  #[must_use]
+  pub fn with_deterministic(mut self, deterministic: bool) -> PartialLibCost {
+    self.deterministic = deterministic;
+    self
+  }
+
+  /// Whether the analysis is reproducible bit-for-bit; see
+  /// [`PartialLibCost::with_deterministic`].
+  #[must_use]
+  pub fn deterministic(&self) -> bool {
+    self.deterministic
+  }
+
+  /// Use `pruning` to remove dominated lib selections, instead of the
+  /// default [`PruningStrategy::Subset`].
+  #[must_use]
+  pub fn with_pruning(mut self, pruning: PruningStrategy) -> PartialLibCost {
+    self.pruning = pruning;
+    self
+  }
+
+  /// Adds `penalty` to the full cost of a lib selection for each of its
+  /// libs, like DreamCoder's structure penalty, so that fewer, bigger libs
+  /// are preferred over many small ones. By default, there is no penalty.
+  #[must_use]
+  pub fn with_lib_penalty(mut self, penalty: usize) -> PartialLibCost {
+    self.lib_penalty = penalty;
+    self
+  }
+
+  /// The structure penalty per lib; see
+  /// [`PartialLibCost::with_lib_penalty`].
+  #[must_use]
+  pub fn lib_penalty(&self) -> usize {
+    self.lib_penalty
+  }
+
+  /// Break ties between lib selections with the same full cost in a
+  /// pseudo-random order determined by `seed` when pruning the beam, instead
+  /// of by expr cost and lib ids. Different seeds keep different lib
+  /// selections in the beam, which can get the search out of a local
+  /// minimum.
+  #[must_use]
+  pub fn with_tie_break_seed(mut self, seed: u64) -> PartialLibCost {
+    self.seed = Some(seed);
+    self
+  }
+
+  /// Enforce `constraints` on the lib selections: lib selections using a
+  /// forbidden lib are discarded, and when pruning, lib selections using a
+  /// required lib are kept ahead of any others with the same number of libs,
+  /// taking precedence over [`PartialLibCost::with_lib_co_occurrences`]. A
+  /// required lib can only be enforced at the root, by picking a lib
+  /// selection which satisfies `constraints`; see
+  /// [`CostSet::retain_satisfying`]. By default, there are no constraints.
+  #[must_use]
+  pub fn with_lib_constraints(
+    mut self,
+    constraints: LibConstraints,
+  ) -> PartialLibCost {
+    self.constraints = constraints;
+    self
+  }
+
+  /// The libs lib selections must or must not use; see
+  /// [`PartialLibCost::with_lib_constraints`].
+  #[must_use]
+  pub fn lib_constraints(&self) -> &LibConstraints {
+    &self.constraints
+  }
+
+  /// Gather [`BeamStats`] while the analysis runs. This is off by default,
+  /// so that the search pays nothing for it.
+  #[must_use]
+  pub fn with_stats(mut self, stats: bool) -> PartialLibCost {
+    self.stats = stats.then(|| Arc::new(Mutex::new(BeamStats::default())));
+    self
+  }
+
+  /// The telemetry gathered so far, if enabled with
+  /// [`PartialLibCost::with_stats`].
+  #[must_use]
+  pub fn stats(&self) -> Option<BeamStats> {
+    self
+      .stats
+      .as_ref()
+      .map(|stats| stats.lock().unwrap_or_else(PoisonError::into_inner).clone())
+  }
+
+  /// Runs `f` within the span of `phase`, which `tracing` subscribers can
+  /// time, also recording it as a call to `phase` if stats are enabled.
+  /// Without stats, timing is left to the subscriber, if any.
+  fn timed<R>(&self, phase: Phase, f: impl FnOnce() -> R) -> R {
+    let _span = phase.span().entered();
+    let stats = match &self.stats {
+      Some(stats) => stats,
+      None => return f(),
+    };
+    let start = clock::now();
+    let result = f();
+    stats
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .record(phase, clock::elapsed(start));
+    result
+  }
+
+  /// Crosses the `CostSet`s of `children`, each scaled by its weight (see
+  /// [`CostSet::scale`]), and prunes the result as when making a node with
+  /// those children, without adding the cost of the node itself. This is
+  /// how the cost of a list of programs is computed when some programs occur
+  /// more often than others.
+  #[must_use]
+  pub fn cross_weighted<'a, I>(&self, children: I) -> CostSet
+  where
+    I: IntoIterator<Item = (&'a CostSet, usize)>,
+  {
+    let weighted = |(set, weight): (&'a CostSet, usize)| {
+      if weight == 1 {
+        Cow::Borrowed(set)
+      } else {
+        let mut set = set.clone();
+        set.scale(weight);
+        Cow::Owned(set)
+      }
+    };
+    let mut children = children.into_iter().map(weighted);
+    let mut e = match children.next() {
+      Some(first) => first.into_owned(),
+      None => return CostSet::intro_cost(0),
+    };
+
+    for cs in children {
+      e = self.timed(Phase::Cross, || {
+        e.cross_with(&cs, self.max_libs_per_sel(), !self.deterministic)
+      });
+      // Intermediate prune.
+      self.unify(&mut e);
+      self.prune(&mut e, self.inter_beam);
+    }
+
+    self.unify(&mut e);
+    self.prune(&mut e, self.beam_size);
+    e
+  }
+
+  /// Removes dominated `LibSel`s from `set`.
+  fn unify(&self, set: &mut CostSet) {
+    self.timed(Phase::Unify, || set.unify_with(&self.pruning));
+  }
+
+  /// Prunes `set` to a beam of `n` `LibSel`s, or wider in adaptive mode.
+  fn prune(&self, set: &mut CostSet, n: usize) {
+    self.timed(Phase::Prune, || self.prune_untimed(set, n));
+  }
+
+  fn prune_untimed(&self, set: &mut CostSet, n: usize) {
+    set.retain_allowed(&self.constraints);
+    let width = match self.max_beam {
+      Some(max_beam) if n > 0 && set.set.len() > n => {
+        let mut costs: Vec<usize> =
+          set.set.iter().map(|ls| ls.full_cost).collect();
+        costs.sort_unstable();
+        let cutoff = costs[n - 1];
+        let ties = costs.partition_point(|&cost| cost <= cutoff);
+        ties.min(max_beam.max(n))
+      }
+      _ => n,
+    };
+    match &self.lib_co_occurrences {
+      _ if !self.constraints.required.is_empty() => {
+        set.prune_preferring(width, self.lps, self.seed, |ls| {
+          self.constraints.uses_required(ls)
+        });
+      }
+      Some(lib_co_occurrences) => {
+        set.prune_preferring(width, self.lps, self.seed, |ls| {
+          let libs: Vec<_> = ls.libs.iter().map(|&(lib, _)| lib).collect();
+          lib_co_occurrences.all_co_occur(&libs)
+        });
+      }
+      None => set.prune_seeded(width, self.lps, self.seed),
+    }
+  }
+
+  /// Use `cost_model` to compute the cost of expressions instead of their
+  /// size.
+  #[must_use]
+  pub fn with_cost_model<Op: 'static>(
+    mut self,
+    cost_model: Arc<dyn CostModel<Op>>,
+  ) -> PartialLibCost {
+    self.cost_model = Some(Arc::new(cost_model));
+    self
+  }
+
+  /// The cost of a single node with operation `op`.
+  fn node_cost<Op: 'static>(&self, op: &Op) -> usize {
+    self
+      .cost_model
+      .as_ref()
+      .and_then(|model| model.downcast_ref::<Arc<dyn CostModel<Op>>>())
+      .map_or(1, |model| model.node_cost(op))
+  }
+}
+
+impl PartialLibCost {
+  /// Merges the cost set `from` into `to`, as [`Analysis::merge`] does for
+  /// this analysis.
+  pub fn merge_sets(&self, to: &mut CostSet, from: CostSet) -> DidMerge {
     let a0 = to.clone();
 
     // Merging consists of combination, followed by unification and beam
     // pruning.
-    to.combine(from.clone());
-    to.unify();
-    to.prune(self.beam_size, self.lps);
+    let _span =
+      trace_span!("merge", to = to.set.len(), from = from.set.len()).entered();
+    self.timed(Phase::Combine, || to.combine(from.clone()));
+    self.unify(to);
+    self.prune(to, self.beam_size);
 
-    // println!("{:?}", to);
-    // println!("{} {}", &a0 != to, to != &from);
     // TODO: be more efficient with how we do this
     DidMerge(&a0 != to, to != &from)
     // DidMerge(false, false)
   }
 
-  fn make(
-    egraph: &mut EGraph<AstNode<Op>, Self>,
+  /// The cost set of `enode`, as [`Analysis::make`] computes it for this
+  /// analysis, given the cost set `x(id)` of each of its children.
+  #[must_use]
+  pub fn make_set<'a, Op>(
+    &self,
     enode: &AstNode<Op>,
-  ) -> Self::Data {
-    // println!("make");
-    let x = |i: &Id| &egraph[*i].data;
-
-    let self_ref = egraph.analysis;
-
+    x: impl Fn(&Id) -> &'a CostSet,
+  ) -> CostSet
+  where
+    Op: Teachable + 'static,
+  {
     match Teachable::as_binding_expr(enode) {
-      Some(BindingExpr::Lib(id, f, b)) => {
+      Some(BindingExpr::Lib(id, _, _) | BindingExpr::LibRec(id, _, _))
+        if self.constraints.forbidden.contains(&id) =>
+      {
+        // A forbidden lib can't be introduced, so no lib selection can
+        // extract this node.
+        CostSet { set: Vec::new() }
+      }
+      Some(BindingExpr::Lib(id, f, b) | BindingExpr::LibRec(id, f, b)) => {
         // This is a lib binding!
         // cross e1, e2 and introduce a lib!
-        let mut e = x(b).add_lib(id, x(f), self_ref.lps);
-        e.unify();
-        e.prune(self_ref.beam_size, self_ref.lps);
+        let parallel = !self.deterministic;
+        let max_libs = self.max_libs_per_sel();
+        let mut e = self.timed(Phase::Cross, || {
+          if self.lib_penalty > 0 {
+            // The penalty is paid as part of the cost of the lib.
+            let mut def = x(f).clone();
+            def.add_cost(self.lib_penalty);
+            x(b).add_lib_with(id, &def, max_libs, parallel)
+          } else {
+            x(b).add_lib_with(id, x(f), max_libs, parallel)
+          }
+        });
+        self.unify(&mut e);
+        self.prune(&mut e, self.beam_size);
         e
       }
       Some(_) | None => {
         // This is some other operation of some kind.
         // We test the arity of the function
+        let node_cost = self.node_cost(enode.operation());
 
         if enode.is_empty() {
           // 0 args. Return intro.
-          CostSet::intro_op()
+          CostSet::intro_cost(node_cost)
         } else if enode.args().len() == 1 {
           // 1 arg. Get child cost set, inc, and return.
           let mut e = x(&enode.args()[0]).clone();
-          e.inc_cost();
+          e.add_cost(node_cost);
           e
         } else {
           // 2+ args. Cross/unify time!
-          let mut e = x(&enode.args()[0]).clone();
-
-          for cs in &enode.args()[1..] {
-            e = e.cross(x(cs), self_ref.lps);
-            // Intermediate prune.
-            e.unify();
-            e.prune(self_ref.inter_beam, self_ref.lps);
-          }
-
-          e.unify();
-          e.prune(self_ref.beam_size, self_ref.lps);
-          e.inc_cost();
+          let mut e =
+            self.cross_weighted(enode.args().iter().map(|id| (x(id), 1)));
+          e.add_cost(node_cost);
           e
         }
       }
     }
   }
 
-  // For debugging
-  // fn modify(egraph: &mut EGraph<AstNode<Op>, Self>, id: Id) {
-  //     println!("merge {}", id);
-  //     println!("{:?}", &egraph[id].data)
-  // }
+  /// Records that the cost set of the e-class `id` has `size` lib
+  /// selections, if telemetry is enabled.
+  pub(crate) fn record_set_size(&self, id: Id, size: usize) {
+    if let Some(stats) = &self.stats {
+      let mut stats = stats.lock().unwrap_or_else(PoisonError::into_inner);
+      let max = stats.max_set_sizes.entry(id.into()).or_insert(0);
+      *max = (*max).max(size);
+    }
+  }
+}
+
+impl Default for PartialLibCost {
+  fn default() -> Self {
+    PartialLibCost::empty()
+  }
+}
+
+impl<Op> Analysis<AstNode<Op>> for PartialLibCost
+where
+  Op: Ord
+    + std::hash::Hash
+    + Debug
+    + Teachable
+    + Arity
+    + Eq
+    + Clone
+    + Send
+    + Sync
+    + 'static,
+{
+  type Data = CostSet;
+
+  fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+    self.merge_sets(to, from)
+  }
+
+  fn make(
+    egraph: &mut EGraph<AstNode<Op>, Self>,
+    enode: &AstNode<Op>,
+  ) -> Self::Data {
+    egraph.analysis.make_set(enode, |id| &egraph[*id].data)
+  }
+
+  fn modify(egraph: &mut EGraph<AstNode<Op>, Self>, id: Id) {
+    let size = egraph[id].data.set.len();
+    egraph.analysis.record_set_size(id, size);
+  }
 }
 
 /// Library context is a set of library function names.
@@ -541,18 +1330,23 @@ impl LibContext {
   }
 }
 
-type MaybeExpr<Op> = Option<RecExpr<AstNode<Op>>>;
-
-/// This is here for debugging purposes.
-fn display_maybe_expr<
-  Op: Clone + std::fmt::Debug + std::hash::Hash + Ord + std::fmt::Display,
->(
-  maybe_expr: &MaybeExpr<Op>,
-) -> String {
-  match maybe_expr {
-    Some(expr) => expr.pretty(100),
-    _ => "<none>".to_string(),
-  }
+/// A state of extraction: an e-class, and the lib context to extract it in.
+type State = (Id, LibContext);
+
+/// An error when extracting an expression with a [`LibExtractor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ExtractionError {
+  /// Every expression in the e-class is infinite, or uses a library function
+  /// inside its own definition.
+  #[error("e-class {0} has no finite expression")]
+  NoFiniteTerm(Id),
+  /// A required library function is never defined in the e-graph, so no
+  /// expression can use it.
+  #[error("required library function {0} is not defined in the e-graph")]
+  MissingLib(LibId),
+  /// The deadline passed before extraction finished.
+  #[error("ran out of time while extracting")]
+  TimedOut,
 }
 
 /// Extractor that minimizes AST size but ignores the cost of library definitions
@@ -563,6 +1357,14 @@ fn display_maybe_expr<
 /// This is because when extracting inside library definitions,
 /// we are not allowed to use those libraries;
 /// so the best expression is different depending on which library defs we are currently inside.
+///
+/// Extraction doesn't recurse, and terminates even when the e-graph has
+/// cycles: the states reachable from the root are explored with an explicit
+/// stack, visiting each at most once, and there are finitely many of them
+/// (each is an e-class paired with a set of libs). Then their best
+/// expressions are computed with a worklist, and a state is only requeued
+/// when the cost of the best expression of one of its children strictly
+/// decreases, which can only happen finitely often.
 #[derive(Debug)]
 pub struct LibExtractor<
   'a,
@@ -574,18 +1376,24 @@ pub struct LibExtractor<
     + std::fmt::Display,
   N: Analysis<AstNode<Op>>,
 > {
-  /// Remembers the best expression so far for each pair of class id and lib context;
-  /// if an entry is absent, we haven't visited this class in this context yet;
-  /// if an entry is `None`, it's currently under processing, but we have no results for it yet;
-  /// if an entry is `Some(_)`, we have found an expression for it (but it might still be improved).
-  memo: HashMap<Id, HashMap<LibContext, MaybeExpr<Op>>>,
-  /// Current lib context:
-  /// contains all lib ids inside whose definitions we are currently extracting.
-  lib_context: LibContext,
+  /// The best expression found so far for each state, and its key: its
+  /// cost, and whether its root fails to define a required lib, which
+  /// breaks ties in favor of defining required libs. A visited state with no
+  /// entry has no finite expression (yet).
+  memo: HashMap<State, ((usize, bool), RecExpr<AstNode<Op>>)>,
+  /// The states reachable from the roots extracted so far.
+  visited: HashSet<State>,
+  /// The states which have an e-node with each state as a child, which need
+  /// to be revisited when the state's best expression improves.
+  parents: HashMap<State, Vec<State>>,
   /// The egraph to extract from.
   egraph: &'a EGraph<AstNode<Op>, N>,
-  /// This is here for pretty debug messages.
-  indent: usize,
+  /// The cost model to minimize.
+  cost_model: Arc<dyn CostModel<Op>>,
+  /// The libs which must or must not be used.
+  constraints: LibConstraints,
+  /// The time after which extraction gives up, if any.
+  deadline: Option<Instant>,
 }
 
 impl<'a, Op, N> LibExtractor<'a, Op, N>
@@ -603,166 +1411,232 @@ where
   pub fn new(egraph: &'a EGraph<AstNode<Op>, N>) -> Self {
     Self {
       memo: HashMap::new(),
-      lib_context: LibContext::new(),
+      visited: HashSet::new(),
+      parents: HashMap::new(),
       egraph,
-      indent: 0,
+      cost_model: Arc::new(UnitCost),
+      constraints: LibConstraints::default(),
+      deadline: None,
     }
   }
 
-  /// Get best best expression for `id` in the current lib context.
-  fn get_from_memo(&self, id: Id) -> Option<&MaybeExpr<Op>> {
-    self.memo.get(&id)?.get(&self.lib_context)
+  /// Minimize `cost_model` instead of AST size.
+  #[must_use]
+  pub fn with_cost_model(mut self, cost_model: Arc<dyn CostModel<Op>>) -> Self {
+    self.cost_model = cost_model;
+    self
+  }
+
+  /// Never extract a definition of a lib forbidden by `constraints`, and
+  /// where defining a required lib costs nothing extra, define it. Whether
+  /// every required lib ends up used depends on the e-graph; see
+  /// [`super::apply_libs_constrained`]. By default, there are no
+  /// constraints.
+  #[must_use]
+  pub fn with_lib_constraints(mut self, constraints: LibConstraints) -> Self {
+    self.constraints = constraints;
+    self
+  }
+
+  /// Gives up on extraction once `deadline` has passed, after which every
+  /// extraction fails with [`ExtractionError::TimedOut`]. By default,
+  /// extraction runs to completion.
+  #[must_use]
+  pub fn with_deadline(mut self, deadline: Instant) -> Self {
+    self.deadline = Some(deadline);
+    self
   }
 
-  /// Set best best expression for `id` in the current lib context.
-  fn insert_into_memo(&mut self, id: Id, val: MaybeExpr<Op>) {
-    self.memo.entry(id).or_default().insert(self.lib_context.clone(), val);
+  /// Whether the deadline has passed.
+  fn out_of_time(&self) -> bool {
+    clock::passed(self.deadline)
   }
 
   /// Extract the smallest expression for the eclass `id`.
-  /// # Panics
-  /// Panics if extraction fails
-  /// (this should never happen because the e-graph must contain a non-cyclic expression)
-  pub fn best(&mut self, id: Id) -> RecExpr<AstNode<Op>> {
-    // Populate the memo:
-    self.extract(id);
-    // Get the best expression from the memo:
-    self.get_from_memo(id).unwrap().clone().unwrap()
-  }
-
-  /// Expression cost used by this extractor (which is `NoLibCost`)
-  fn cost(expr: &RecExpr<AstNode<Op>>) -> usize {
-    NoLibCost.cost_rec(expr)
-  }
-
-  /// Extract the smallest expression from the eclass id and its descendants
-  /// in the current context, storing results in the memo
-  fn extract(&mut self, id: Id) {
-    self.debug_indented(&format!("extracting eclass {id}"));
-    if let Some(res) = self.get_from_memo(id) {
-      // This node has already been visited in this context (either done or under processing)
-      self.debug_indented(&format!(
-        "visited, memoized value: {}",
-        display_maybe_expr(res)
-      ));
-    } else {
-      // Initialize memo with None to prevent infinite recursion in case of cycles in the egraph
-      self.insert_into_memo(id, None);
-      // Extract a candidate expression from each node
-      for node in self.egraph[id].iter() {
-        match self.extract_node(node) {
-          None => (), // Extraction for this node failed (must be a cycle)
-          Some(cand) => {
-            // Extraction succeeded: check if cand is better than what we have so far
-            match self.get_from_memo(id).unwrap() {
-              // If we already had an expression and it was better, do nothing
-              Some(prev) if Self::cost(prev) <= Self::cost(&cand) => (),
-              // Otherwise, update the memo;
-              // note that updating the memo after each better candidate is found instead of at the end
-              // is slightly suboptimal (because it might cause us to go around some cycles once),
-              // but the code is simpler and it doesn't matter too much.
-              _ => {
-                self.debug_indented(&format!(
-                  "new best for {id}: {} (cost {})",
-                  cand.pretty(100),
-                  Self::cost(&cand)
-                ));
-                self.insert_into_memo(id, Some(cand));
-              }
-            }
-          }
-        }
-      }
+  ///
+  /// # Errors
+  /// Returns an error if the eclass has no finite expression which doesn't
+  /// use a library function inside its own definition, or if the deadline
+  /// passes.
+  pub fn best(
+    &mut self,
+    id: Id,
+  ) -> Result<RecExpr<AstNode<Op>>, ExtractionError> {
+    self.best_in(id, &[])
+  }
+
+  /// Extract the smallest expression for the eclass `id` which doesn't use
+  /// the libs `libs`, such as the definition of one of them.
+  ///
+  /// # Errors
+  /// Returns an error if the eclass has no finite expression which doesn't
+  /// use `libs` or a library function inside its own definition, or if the
+  /// deadline passes.
+  pub fn best_in(
+    &mut self,
+    id: Id,
+    libs: &[LibId],
+  ) -> Result<RecExpr<AstNode<Op>>, ExtractionError> {
+    let mut context = LibContext::new();
+    for &lib in libs {
+      context.add(lib);
     }
+    let root = (self.egraph.find(id), context);
+    // Once out of time, the states are left half explored or half solved,
+    // so every later extraction has to fail too; it does, since the
+    // deadline stays passed.
+    let new_states =
+      self.explore(root.clone()).ok_or(ExtractionError::TimedOut)?;
+    self.solve(new_states).ok_or(ExtractionError::TimedOut)?;
+    self
+      .memo
+      .get(&root)
+      .map(|(_, expr)| expr.clone())
+      .ok_or(ExtractionError::NoFiniteTerm(id))
   }
 
-  /// Extract the smallest expression from `node`.
-  fn extract_node(&mut self, node: &AstNode<Op>) -> MaybeExpr<Op> {
-    self.debug_indented(&format!("extracting node {node:?}"));
-    if let Some(BindingExpr::Lib(lid, _, _)) = node.as_binding_expr() {
-      if self.lib_context.contains(lid) {
-        // This node is a definition of one of the libs, whose definition we are currently extracting:
-        // do not go down this road since it leads to lib definitions using themselves
-        self.debug_indented(&format!("encountered banned lib: {lid}"));
-        return None;
-      }
+  /// Has `(id, lib context)` been visited, in the lib context containing
+  /// `libs`?
+  #[must_use]
+  pub fn is_visited(&self, id: Id, libs: &[LibId]) -> bool {
+    let mut context = LibContext::new();
+    for &lib in libs {
+      context.add(lib);
     }
-    // Otherwise: extract all children
-    let mut child_indexes = vec![];
-    self.extract_children(node, 0, vec![], &mut child_indexes)
+    self.visited.contains(&(self.egraph.find(id), context))
   }
 
-  /// Process the children of `node` starting from index `current`
-  /// and accumulate results in `partial expr`;
-  /// `child_indexes` stores the indexes of already processed children within `partial_expr`,
-  /// so that we can use them in the `AstNode` at the end.
-  fn extract_children(
-    &mut self,
-    node: &AstNode<Op>,
-    current: usize,
-    mut partial_expr: Vec<AstNode<Op>>,
-    child_indexes: &mut Vec<usize>,
-  ) -> MaybeExpr<Op> {
-    if current == node.children().len() {
-      // Done with children: add ourselves to the partial expression and return
-      let child_ids: Vec<Id> =
-        child_indexes.iter().map(|x| (*x).into()).collect();
-      let root = AstNode::new(node.operation().clone(), child_ids);
-      partial_expr.push(root);
-      Some(partial_expr.into())
-    } else {
-      // If this is the first child of a lib node (i.e. lib definition) add this lib to the context:
-      let old_lib_context = self.lib_context.clone();
-      if let Some(BindingExpr::Lib(lid, _, _)) = node.as_binding_expr() {
-        if current == 0 {
-          self.debug_indented(&format!(
-            "processing first child of {node:?}, adding {lid} to context"
-          ));
-          self.lib_context.add(lid);
+  /// The number of states visited so far.
+  #[must_use]
+  pub fn num_visited(&self) -> usize {
+    self.visited.len()
+  }
+
+  /// Visit every state reachable from `root` which hasn't been visited yet,
+  /// recording the parents of each, and return the newly visited states, or
+  /// `None` if the deadline passes first.
+  fn explore(&mut self, root: State) -> Option<Vec<State>> {
+    let mut new_states = Vec::new();
+    let mut stack = vec![root];
+    while let Some(state) = stack.pop() {
+      if self.out_of_time() {
+        return None;
+      }
+      if !self.visited.insert(state.clone()) {
+        continue;
+      }
+      let egraph = self.egraph;
+      for node in egraph[state.0].iter() {
+        for child in self.child_states(node, &state.1).into_iter().flatten() {
+          self.parents.entry(child.clone()).or_default().push(state.clone());
+          stack.push(child);
         }
       }
+      new_states.push(state);
+    }
+    debug!("visited {} new states", new_states.len());
+    Some(new_states)
+  }
 
-      // Process the current child
-      let child = &node.children()[current];
-      self.indent += 1;
-      self.extract(*child);
-      self.indent -= 1;
-      // We need to get the result before restoring the context
-      let child_res = self.get_from_memo(*child).unwrap().clone();
-      // Restore lib context
-      self.lib_context = old_lib_context;
-
-      match child_res {
-        None => None, // Failed to extract a child, so the extraction of this node fails
-        Some(expr) => {
-          // We need to clone the expr because we're going to mutate it (offset child indexes),
-          // and we don't want it to affect the memo result for child.
-          let mut new_expr = expr.as_ref().to_vec();
-          for n in &mut new_expr {
-            // Increment all indexes inside `n` by the current expression length;
-            // this is needed to make a well-formed `RecExpr`
-            Self::offset_children(n, partial_expr.len());
-          }
-          partial_expr.extend(new_expr);
-          child_indexes.push(partial_expr.len() - 1);
-          self.extract_children(node, current + 1, partial_expr, child_indexes)
+  /// Find the best expression of every state in `worklist`, and of every
+  /// state whose best expression depends on them. Returns `None` if the
+  /// deadline passes first.
+  fn solve(&mut self, worklist: Vec<State>) -> Option<()> {
+    let mut queued: HashSet<State> = worklist.iter().cloned().collect();
+    let mut worklist: VecDeque<State> = worklist.into();
+    while let Some(state) = worklist.pop_front() {
+      if self.out_of_time() {
+        return None;
+      }
+      queued.remove(&state);
+      let best = self.egraph[state.0]
+        .iter()
+        .filter_map(|node| {
+          let expr = self.extract_node(node, &state.1)?;
+          let key =
+            (self.cost_model.cost_rec(&expr), !self.defines_required(node));
+          Some((key, expr))
+        })
+        .min_by_key(|(key, _)| *key);
+      let (key, expr) = match best {
+        Some(best) => best,
+        None => continue,
+      };
+      if matches!(self.memo.get(&state), Some((prev, _)) if *prev <= key) {
+        continue;
+      }
+      debug!("new best for {}: {} (cost {})", state.0, expr.pretty(100), key.0);
+      self.memo.insert(state.clone(), (key, expr));
+      for parent in self.parents.get(&state).into_iter().flatten() {
+        if queued.insert(parent.clone()) {
+          worklist.push_back(parent.clone());
         }
       }
     }
+    Some(())
   }
 
-  /// Add `offset` to all children of `node`
-  fn offset_children(node: &mut AstNode<Op>, offset: usize) {
-    for child in node.children_mut() {
-      let child_index: usize = (*child).into();
-      *child = (child_index + offset).into();
+  /// Whether `node` defines a required lib.
+  fn defines_required(&self, node: &AstNode<Op>) -> bool {
+    matches!(
+      node.as_binding_expr(),
+      Some(BindingExpr::Lib(lid, _, _) | BindingExpr::LibRec(lid, _, _))
+        if self.constraints.required.contains(&lid)
+    )
+  }
+
+  /// The states to extract the children of `node` in, when extracting it in
+  /// `context`; or `None` if `node` is the definition of a lib in `context`,
+  /// since lib definitions can't use themselves, or of a forbidden lib.
+  fn child_states(
+    &self,
+    node: &AstNode<Op>,
+    context: &LibContext,
+  ) -> Option<Vec<State>> {
+    let lib = match node.as_binding_expr() {
+      Some(BindingExpr::Lib(lid, _, _) | BindingExpr::LibRec(lid, _, _))
+        if self.constraints.forbidden.contains(&lid) =>
+      {
+        return None;
+      }
+      Some(BindingExpr::Lib(lid, _, _)) => Some(lid),
+      _ => None,
+    };
+    if matches!(lib, Some(lid) if context.contains(lid)) {
+      return None;
     }
+    let children = node.children().iter().enumerate().map(|(i, &child)| {
+      let mut context = context.clone();
+      // The first child of a lib node is its definition.
+      if let (0, Some(lid)) = (i, lib) {
+        context.add(lid);
+      }
+      (self.egraph.find(child), context)
+    });
+    Some(children.collect())
   }
 
-  /// Print a debug message with the current indentation
-  /// TODO: this should be a macro
-  fn debug_indented(&self, msg: &str) {
-    debug!("{:indent$}{msg}", "", indent = 2 * self.indent);
+  /// Build the best expression rooted at `node` in `context` from the best
+  /// expressions of its children, or `None` if some child has none yet.
+  fn extract_node(
+    &self,
+    node: &AstNode<Op>,
+    context: &LibContext,
+  ) -> Option<RecExpr<AstNode<Op>>> {
+    let mut expr = Vec::new();
+    let mut child_ids: Vec<Id> = Vec::new();
+    for child in self.child_states(node, context)? {
+      let (_, child_expr) = self.memo.get(&child)?;
+      let offset = expr.len();
+      expr.extend(child_expr.as_ref().iter().map(|n| {
+        // Shift the indexes inside the child's expression by its position
+        // in the new expression, to make a well-formed `RecExpr`.
+        n.clone().map_children(|id| (usize::from(id) + offset).into())
+      }));
+      child_ids.push((expr.len() - 1).into());
+    }
+    expr.push(AstNode::new(node.operation().clone(), child_ids));
+    Some(expr.into())
   }
 }
 
@@ -781,8 +1655,40 @@ where
     C: FnMut(Id) -> Self::Cost,
   {
     match enode.as_binding_expr() {
-      Some(BindingExpr::Lib(_, _, body)) => costs(*body),
+      Some(BindingExpr::Lib(_, _, body) | BindingExpr::LibRec(_, _, body)) => {
+        costs(*body)
+      }
       _ => enode.fold(1, |sum, id| sum + costs(id)),
     }
   }
 }
+
+/// Sequential stand-ins for the rayon iterators used to compute cross
+/// products, for builds without the `std` feature, where there is no thread
+/// pool.
+#[cfg(not(feature = "std"))]
+mod sequential {
+  use std::{iter::FlatMap, slice};
+
+  pub(super) trait ParallelSlice<T> {
+    fn par_iter(&self) -> slice::Iter<'_, T>;
+  }
+
+  impl<T> ParallelSlice<T> for [T] {
+    fn par_iter(&self) -> slice::Iter<'_, T> {
+      self.iter()
+    }
+  }
+
+  pub(super) trait FlatMapIter: Iterator + Sized {
+    fn flat_map_iter<U, F>(self, f: F) -> FlatMap<Self, U, F>
+    where
+      U: IntoIterator,
+      F: FnMut(Self::Item) -> U,
+    {
+      self.flat_map(f)
+    }
+  }
+
+  impl<I: Iterator> FlatMapIter for I {}
+}
@@ -0,0 +1,126 @@
+//! Running a domain-specific e-class analysis, such as type inference or
+//! constant folding, alongside the beam analysis [`PartialLibCost`].
+//!
+//! An e-graph only has one analysis, so a [`BeamAnd`] pairs
+//! [`PartialLibCost`] with a [`DomainAnalysis`], and the data of each e-class
+//! holds both the beam of lib selections and the domain-specific data. DSRs
+//! over `BeamAnd<A>` can then check domain facts in their conditions, while
+//! the beam data still flows through the e-graph as usual.
+
+use std::fmt::Debug;
+
+use egg::{Analysis, DidMerge, EGraph, Id, Language};
+
+use super::beam::{CostSet, PartialLibCost};
+use crate::{ast_node::AstNode, teachable::Teachable};
+
+/// An e-class analysis which can run alongside [`PartialLibCost`] in a
+/// [`BeamAnd`]. Unlike an [`Analysis`], it computes the data of an e-node
+/// from the data of its children, since the e-graph belongs to the
+/// [`BeamAnd`].
+pub trait DomainAnalysis<Op>: Sized {
+  /// The domain-specific data of each e-class.
+  type Data: Debug;
+
+  /// The data of `enode`, given the data `data(id)` of each of its children.
+  fn make<'a>(
+    &self,
+    enode: &AstNode<Op>,
+    data: &dyn Fn(Id) -> &'a Self::Data,
+  ) -> Self::Data
+  where
+    Self::Data: 'a;
+
+  /// Merges the data `from` into `to`, like [`Analysis::merge`].
+  fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge;
+
+  /// Modifies the e-class `id` after its data changed, for example by adding
+  /// the constant it evaluates to, like [`Analysis::modify`]. By default,
+  /// this does nothing.
+  fn modify(egraph: &mut EGraph<AstNode<Op>, BeamAnd<Self>>, id: Id)
+  where
+    AstNode<Op>: Language,
+    BeamAnd<Self>: Analysis<AstNode<Op>>,
+  {
+    let _ = (egraph, id);
+  }
+}
+
+/// An analysis with no data, for which [`BeamAnd`] behaves like
+/// [`PartialLibCost`] alone.
+impl<Op> DomainAnalysis<Op> for () {
+  type Data = ();
+
+  fn make<'a>(&self, _enode: &AstNode<Op>, _data: &dyn Fn(Id) -> &'a ()) {}
+
+  fn merge(&mut self, _to: &mut (), _from: ()) -> DidMerge {
+    DidMerge(false, false)
+  }
+}
+
+/// The beam analysis [`PartialLibCost`] paired with the domain-specific
+/// analysis `A`.
+#[derive(Debug, Clone, Default)]
+pub struct BeamAnd<A> {
+  /// The beam analysis.
+  pub beam: PartialLibCost,
+  /// The domain-specific analysis.
+  pub domain: A,
+}
+
+impl<A> BeamAnd<A> {
+  /// Runs `domain` alongside the beam analysis `beam`.
+  #[must_use]
+  pub fn new(beam: PartialLibCost, domain: A) -> Self {
+    Self { beam, domain }
+  }
+}
+
+/// The data of an e-class under [`BeamAnd`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamAndData<D> {
+  /// The lib selections of the e-class, as computed by [`PartialLibCost`].
+  pub beam: CostSet,
+  /// The domain-specific data of the e-class.
+  pub domain: D,
+}
+
+impl<Op, A> Analysis<AstNode<Op>> for BeamAnd<A>
+where
+  Op: Ord
+    + std::hash::Hash
+    + Debug
+    + Teachable
+    + Eq
+    + Clone
+    + Send
+    + Sync
+    + 'static,
+  A: DomainAnalysis<Op>,
+{
+  type Data = BeamAndData<A::Data>;
+
+  fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+    let beam = self.beam.merge_sets(&mut to.beam, from.beam);
+    let domain = self.domain.merge(&mut to.domain, from.domain);
+    DidMerge(beam.0 || domain.0, beam.1 || domain.1)
+  }
+
+  fn make(
+    egraph: &mut EGraph<AstNode<Op>, Self>,
+    enode: &AstNode<Op>,
+  ) -> Self::Data {
+    let egraph = &*egraph;
+    let beam =
+      egraph.analysis.beam.make_set(enode, |id| &egraph[*id].data.beam);
+    let domain =
+      egraph.analysis.domain.make(enode, &|id| &egraph[id].data.domain);
+    BeamAndData { beam, domain }
+  }
+
+  fn modify(egraph: &mut EGraph<AstNode<Op>, Self>, id: Id) {
+    let size = egraph[id].data.beam.set.len();
+    egraph.analysis.beam.record_set_size(id, size);
+    A::modify(egraph, id);
+  }
+}
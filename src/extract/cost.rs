@@ -0,0 +1,148 @@
+//! Cost models used to measure the size of expressions during extraction.
+
+use crate::{
+  ast_node::{AstNode, Expr},
+  teachable::{BindingExpr, Teachable},
+};
+use egg::{Id, RecExpr};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Debug,
+  hash::Hash,
+};
+
+/// A cost model assigns a cost to every AST node based on its operation. The
+/// cost of an expression is the sum of the costs of its nodes.
+///
+/// The cost of a library definition is never counted; only the body in which
+/// the library is in scope is.
+pub trait CostModel<Op>: Debug + Send + Sync {
+  /// The cost of a single node with operation `op`, not including the cost of
+  /// its children.
+  fn node_cost(&self, op: &Op) -> usize;
+
+  /// The cost of the expression `expr`, ignoring library definitions.
+  fn cost_rec(&self, expr: &RecExpr<AstNode<Op>>) -> usize
+  where
+    Op: Teachable,
+  {
+    let nodes = expr.as_ref();
+    let mut costs: Vec<usize> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+      let cost = match node.as_binding_expr() {
+        Some(
+          BindingExpr::Lib(_, _, body) | BindingExpr::LibRec(_, _, body),
+        ) => costs[usize::from(*body)],
+        _ => node.iter().fold(self.node_cost(node.operation()), |sum, &id| {
+          sum + costs[usize::from(id)]
+        }),
+      };
+      costs.push(cost);
+    }
+    costs.last().copied().unwrap_or(0)
+  }
+
+  /// The cost of the expression `expr`, ignoring library definitions.
+  fn expr_cost(&self, expr: &Expr<Op>) -> usize
+  where
+    Op: Teachable,
+  {
+    match expr.0.as_binding_expr() {
+      Some(BindingExpr::Lib(_, _, body) | BindingExpr::LibRec(_, _, body)) => {
+        self.expr_cost(body)
+      }
+      _ => {
+        expr.0.iter().fold(self.node_cost(expr.0.operation()), |sum, arg| {
+          sum + self.expr_cost(arg)
+        })
+      }
+    }
+  }
+}
+
+/// The default cost model, in which every node costs 1. Under this model, the
+/// cost of an expression is its size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnitCost;
+
+impl<Op> CostModel<Op> for UnitCost {
+  fn node_cost(&self, _op: &Op) -> usize {
+    1
+  }
+}
+
+/// A cost model which counts every distinct subterm of an expression once:
+/// the cost of an expression is its size as a DAG in which equal subterms are
+/// shared, rather than as a tree.
+///
+/// Every node costs 1, so during beam search this is the same as
+/// [`UnitCost`]; the difference is in extraction and in the final costs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DagSize;
+
+impl<Op: Clone + Eq + Hash> CostModel<Op> for DagSize {
+  fn node_cost(&self, _op: &Op) -> usize {
+    1
+  }
+
+  fn cost_rec(&self, expr: &RecExpr<AstNode<Op>>) -> usize
+  where
+    Op: Teachable,
+  {
+    distinct_subterms(expr.as_ref(), true)
+  }
+
+  fn expr_cost(&self, expr: &Expr<Op>) -> usize
+  where
+    Op: Teachable,
+  {
+    self.cost_rec(&expr.clone().into())
+  }
+}
+
+/// Returns the number of distinct subterms of the expression whose nodes are
+/// `nodes`, with the root last. If `skip_lib_defs` is set, library
+/// definitions are not counted, only the bodies in which they are in scope.
+pub(crate) fn distinct_subterms<Op>(
+  nodes: &[AstNode<Op>],
+  skip_lib_defs: bool,
+) -> usize
+where
+  Op: Teachable + Clone + Eq + Hash,
+{
+  // Hash-cons the nodes, so that equal subterms get the same id.
+  let mut canonical: Vec<Id> = Vec::with_capacity(nodes.len());
+  let mut classes: HashMap<AstNode<Op>, Id> = HashMap::new();
+  let mut unique: Vec<AstNode<Op>> = Vec::new();
+  for node in nodes {
+    let node = node.clone().map(|id| canonical[usize::from(id)]);
+    let id = *classes.entry(node.clone()).or_insert_with(|| {
+      unique.push(node);
+      Id::from(unique.len() - 1)
+    });
+    canonical.push(id);
+  }
+
+  // Count the distinct subterms reachable from the root.
+  let mut count = 0;
+  let mut visited = HashSet::new();
+  let mut stack: Vec<Id> = canonical.last().copied().into_iter().collect();
+  while let Some(id) = stack.pop() {
+    if !visited.insert(id) {
+      continue;
+    }
+    let node = &unique[usize::from(id)];
+    match node.as_binding_expr() {
+      Some(BindingExpr::Lib(_, _, body) | BindingExpr::LibRec(_, _, body))
+        if skip_lib_defs =>
+      {
+        stack.push(*body);
+      }
+      _ => {
+        count += 1;
+        stack.extend(node.iter().copied());
+      }
+    }
+  }
+  count
+}
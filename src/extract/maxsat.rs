@@ -0,0 +1,447 @@
+//! `extract::maxsat` selects library functions by encoding the extraction
+//! problem as a weighted partial Max-SAT instance, which is then solved by an
+//! external Max-SAT solver.
+//!
+//! Every e-class, e-node, and library function gets a boolean variable. The
+//! hard clauses require that the roots are extracted, that every extracted
+//! e-class has an extracted e-node, that every extracted e-node has its
+//! children extracted, and that every library function used is defined. The
+//! soft clauses penalize extracting each e-node by its cost. Since the
+//! variables are shared, a subexpression which occurs multiple times is only
+//! counted once.
+//!
+//! The encoding doesn't rule out cyclic extractions. Instead, if the solver
+//! returns a cyclic extraction, the cycle is forbidden and the solver is run
+//! again.
+
+use std::{
+  collections::{BTreeSet, HashMap},
+  fmt::Debug,
+  fs,
+  io::{self, Write},
+  path::PathBuf,
+  process::Command,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+};
+
+use egg::{Analysis, EGraph, Id, Language};
+use log::debug;
+use thiserror::Error;
+
+use super::cost::{CostModel, UnitCost};
+use crate::{
+  ast_node::AstNode,
+  learn::LibId,
+  teachable::{BindingExpr, Teachable},
+};
+
+/// An error when solving a Max-SAT instance.
+#[derive(Debug, Error)]
+pub enum MaxSatError {
+  /// The solver couldn't be run, or its input couldn't be written.
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  /// The solver reported that the hard clauses can't be satisfied.
+  #[error("the Max-SAT instance is unsatisfiable")]
+  Unsatisfiable,
+  /// The solver didn't report a solution.
+  #[error("the Max-SAT solver did not report a solution: {0}")]
+  NoSolution(String),
+}
+
+/// An external Max-SAT solver, such as `open-wbo` or `EvalMaxSAT`.
+///
+/// The solver is run as `program args... instance.wcnf`. It must read
+/// instances in the pre-2022 format of the Max-SAT Evaluations (with a
+/// `p wcnf` header), and can write solutions in either the old format (`v`
+/// lines listing literals) or the current one (a single `v` line with one bit
+/// per variable).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaxSatSolver {
+  program: PathBuf,
+  args: Vec<String>,
+}
+
+impl MaxSatSolver {
+  /// Creates a solver which runs `program`.
+  #[must_use]
+  pub fn new<P: Into<PathBuf>>(program: P) -> Self {
+    Self { program: program.into(), args: Vec::new() }
+  }
+
+  /// Adds an argument to pass to the solver before the instance file.
+  #[must_use]
+  pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+    self.args.push(arg.into());
+    self
+  }
+
+  /// Solves `instance`, returning the value of every variable.
+  fn solve(&self, instance: &Wcnf) -> Result<Vec<bool>, MaxSatError> {
+    static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+    let path = std::env::temp_dir().join(format!(
+      "babble-{}-{}.wcnf",
+      std::process::id(),
+      INSTANCES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = io::BufWriter::new(fs::File::create(&path)?);
+    instance.write(&mut file)?;
+    file.flush()?;
+    drop(file);
+
+    let output =
+      Command::new(&self.program).args(&self.args).arg(&path).output();
+    fs::remove_file(&path)?;
+    let output = output?;
+
+    instance.parse_solution(&String::from_utf8_lossy(&output.stdout))
+  }
+}
+
+/// A weighted partial Max-SAT instance. Variables are numbered from 1, and
+/// literals are represented as in DIMACS.
+#[derive(Debug, Clone, Default)]
+struct Wcnf {
+  num_vars: usize,
+  hard: Vec<Vec<i64>>,
+  soft: Vec<(usize, Vec<i64>)>,
+}
+
+impl Wcnf {
+  fn new_var(&mut self) -> i64 {
+    self.num_vars += 1;
+    i64::try_from(self.num_vars).unwrap()
+  }
+
+  /// Writes the instance in the pre-2022 format, which every solver we know
+  /// of still accepts.
+  fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let top = self.soft.iter().map(|(weight, _)| weight).sum::<usize>() + 1;
+    writeln!(
+      w,
+      "p wcnf {} {} {top}",
+      self.num_vars,
+      self.hard.len() + self.soft.len()
+    )?;
+
+    let clauses = self
+      .hard
+      .iter()
+      .map(|clause| (top, clause))
+      .chain(self.soft.iter().map(|(weight, clause)| (*weight, clause)));
+    for (weight, clause) in clauses {
+      write!(w, "{weight}")?;
+      for lit in clause {
+        write!(w, " {lit}")?;
+      }
+      writeln!(w, " 0")?;
+    }
+
+    Ok(())
+  }
+
+  fn parse_solution(&self, output: &str) -> Result<Vec<bool>, MaxSatError> {
+    let mut status = None;
+    let mut values = vec![false; self.num_vars + 1];
+    let mut found = false;
+
+    for line in output.lines() {
+      if let Some(s) = line.strip_prefix("s ") {
+        status = Some(s.trim());
+      } else if let Some(v) = line.strip_prefix("v ") {
+        found = true;
+        let v = v.trim();
+        if v.len() == self.num_vars && v.bytes().all(|b| b == b'0' || b == b'1')
+        {
+          for (value, b) in values[1..].iter_mut().zip(v.bytes()) {
+            *value = b == b'1';
+          }
+        } else {
+          for lit in v.split_whitespace() {
+            let lit: i64 = lit.parse().map_err(|_| {
+              MaxSatError::NoSolution(format!("bad literal {lit}"))
+            })?;
+            if lit > 0 {
+              let value = usize::try_from(lit)
+                .ok()
+                .and_then(|var| values.get_mut(var))
+                .ok_or_else(|| {
+                  MaxSatError::NoSolution(format!("bad literal {lit}"))
+                })?;
+              *value = true;
+            }
+          }
+        }
+      }
+    }
+
+    match status {
+      Some("UNSATISFIABLE") => Err(MaxSatError::Unsatisfiable),
+      Some("OPTIMUM FOUND" | "SATISFIABLE") if found => Ok(values),
+      Some(status) => Err(MaxSatError::NoSolution(status.to_string())),
+      None => Err(MaxSatError::NoSolution("no status line".to_string())),
+    }
+  }
+}
+
+/// Selects the library functions to use by solving a Max-SAT instance.
+#[derive(Debug)]
+pub struct MaxSatExtractor<'a, Op, A>
+where
+  Op: Ord + Debug + Clone + std::hash::Hash,
+  A: Analysis<AstNode<Op>>,
+{
+  egraph: &'a EGraph<AstNode<Op>, A>,
+  solver: MaxSatSolver,
+  cost_model: Arc<dyn CostModel<Op>>,
+}
+
+impl<'a, Op, A> MaxSatExtractor<'a, Op, A>
+where
+  Op: Clone + Teachable + Ord + Debug + std::hash::Hash,
+  A: Analysis<AstNode<Op>>,
+{
+  /// Creates an extractor for `egraph` which runs `solver`.
+  #[must_use]
+  pub fn new(egraph: &'a EGraph<AstNode<Op>, A>, solver: MaxSatSolver) -> Self {
+    Self { egraph, solver, cost_model: Arc::new(UnitCost) }
+  }
+
+  /// Minimize `cost_model` instead of AST size.
+  #[must_use]
+  pub fn with_cost_model(mut self, cost_model: Arc<dyn CostModel<Op>>) -> Self {
+    self.cost_model = cost_model;
+    self
+  }
+
+  /// Finds the library functions used in the cheapest extraction of `roots`.
+  /// Library functions are returned in order of their [`LibId`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the solver can't be run or doesn't find a solution.
+  pub fn select_libs(&self, roots: &[Id]) -> Result<Vec<LibId>, MaxSatError> {
+    let mut encoding = self.encode(roots);
+
+    loop {
+      let values = self.solver.solve(&encoding.wcnf)?;
+      let selected = |var: i64| values[usize::try_from(var).unwrap()];
+
+      match encoding.find_cycle(self.egraph, roots, &selected) {
+        Some(cycle) => {
+          debug!("Forbidding cycle of {} e-nodes", cycle.len());
+          let clause = cycle.into_iter().map(|var| -var).collect();
+          encoding.wcnf.hard.push(clause);
+        }
+        None => return Ok(encoding.libs(self.egraph, roots, &selected)),
+      }
+    }
+  }
+
+  fn encode(&self, roots: &[Id]) -> Encoding {
+    let mut wcnf = Wcnf::default();
+
+    let mut classes = HashMap::new();
+    for class in self.egraph.classes() {
+      classes.insert(class.id, wcnf.new_var());
+    }
+
+    let mut nodes = HashMap::new();
+    let mut libs = HashMap::new();
+    let mut defs: HashMap<LibId, Vec<i64>> = HashMap::new();
+    for class in self.egraph.classes() {
+      let class_var = classes[&class.id];
+      let mut node_vars = Vec::with_capacity(class.len());
+
+      for (i, node) in class.iter().enumerate() {
+        let node_var = wcnf.new_var();
+        nodes.insert((class.id, i), node_var);
+        node_vars.push(node_var);
+
+        for &child in node.children() {
+          let child = self.egraph.find(child);
+          wcnf.hard.push(vec![-node_var, classes[&child]]);
+        }
+
+        match node.as_binding_expr() {
+          Some(
+            BindingExpr::Lib(lib, _, _) | BindingExpr::LibRec(lib, _, _),
+          ) => {
+            defs.entry(lib).or_default().push(node_var);
+            // A library definition costs nothing by itself: the cost of its
+            // body is counted once, through the body's own e-nodes.
+            continue;
+          }
+          Some(BindingExpr::LibVar(lib)) => {
+            let lib_var = *libs.entry(lib).or_insert_with(|| wcnf.new_var());
+            wcnf.hard.push(vec![-node_var, lib_var]);
+          }
+          _ => {}
+        }
+
+        let cost = self.cost_model.node_cost(node.operation());
+        if cost > 0 {
+          wcnf.soft.push((cost, vec![-node_var]));
+        }
+      }
+
+      let mut clause = vec![-class_var];
+      clause.extend(node_vars);
+      wcnf.hard.push(clause);
+    }
+
+    // Every library function which is used must be defined somewhere.
+    for (lib, lib_var) in libs {
+      let mut clause = vec![-lib_var];
+      clause.extend(defs.get(&lib).into_iter().flatten());
+      wcnf.hard.push(clause);
+    }
+
+    for &root in roots {
+      wcnf.hard.push(vec![classes[&self.egraph.find(root)]]);
+    }
+
+    Encoding { wcnf, nodes }
+  }
+}
+
+/// A Max-SAT instance, along with the variables of each e-node.
+#[derive(Debug)]
+struct Encoding {
+  wcnf: Wcnf,
+  nodes: HashMap<(Id, usize), i64>,
+}
+
+impl Encoding {
+  /// Returns the variables of the e-nodes in a cycle of selected e-nodes
+  /// reachable from `roots`, if there is one.
+  fn find_cycle<Op, A>(
+    &self,
+    egraph: &EGraph<AstNode<Op>, A>,
+    roots: &[Id],
+    selected: &impl Fn(i64) -> bool,
+  ) -> Option<Vec<i64>>
+  where
+    Op: Ord + Debug + Clone + std::hash::Hash,
+    A: Analysis<AstNode<Op>>,
+  {
+    /// The e-class is on the current path.
+    const ACTIVE: u8 = 1;
+    /// The e-class and its descendants have no cycles.
+    const DONE: u8 = 2;
+
+    fn visit<Op, A>(
+      enc: &Encoding,
+      egraph: &EGraph<AstNode<Op>, A>,
+      class: Id,
+      selected: &impl Fn(i64) -> bool,
+      state: &mut HashMap<Id, u8>,
+      path: &mut Vec<(Id, i64)>,
+    ) -> Option<Vec<i64>>
+    where
+      Op: Ord + Debug + Clone + std::hash::Hash,
+      A: Analysis<AstNode<Op>>,
+    {
+      match state.get(&class) {
+        Some(&DONE) => return None,
+        Some(_) => {
+          let start = path.iter().position(|&(c, _)| c == class).unwrap();
+          return Some(path[start..].iter().map(|&(_, var)| var).collect());
+        }
+        None => {}
+      }
+      state.insert(class, ACTIVE);
+
+      for (i, node) in egraph[class].iter().enumerate() {
+        let var = enc.nodes[&(class, i)];
+        if !selected(var) {
+          continue;
+        }
+        path.push((class, var));
+        for &child in node.children() {
+          let child = egraph.find(child);
+          if let Some(cycle) = visit(enc, egraph, child, selected, state, path)
+          {
+            return Some(cycle);
+          }
+        }
+        path.pop();
+      }
+
+      state.insert(class, DONE);
+      None
+    }
+
+    let mut state = HashMap::new();
+    let mut path = Vec::new();
+    roots.iter().find_map(|&root| {
+      let root = egraph.find(root);
+      visit(self, egraph, root, selected, &mut state, &mut path)
+    })
+  }
+
+  /// Returns the library functions defined by selected e-nodes reachable
+  /// from `roots`.
+  fn libs<Op, A>(
+    &self,
+    egraph: &EGraph<AstNode<Op>, A>,
+    roots: &[Id],
+    selected: &impl Fn(i64) -> bool,
+  ) -> Vec<LibId>
+  where
+    Op: Ord + Debug + Clone + std::hash::Hash + Teachable,
+    A: Analysis<AstNode<Op>>,
+  {
+    let mut libs = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    let mut stack: Vec<Id> =
+      roots.iter().map(|&root| egraph.find(root)).collect();
+
+    while let Some(class) = stack.pop() {
+      if !seen.insert(class) {
+        continue;
+      }
+      for (i, node) in egraph[class].iter().enumerate() {
+        if !selected(self.nodes[&(class, i)]) {
+          continue;
+        }
+        if let Some(
+          BindingExpr::Lib(lib, _, _) | BindingExpr::LibRec(lib, _, _),
+        ) = node.as_binding_expr()
+        {
+          libs.insert(lib);
+        }
+        stack.extend(node.children().iter().map(|&child| egraph.find(child)));
+      }
+    }
+
+    libs.into_iter().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{MaxSatError, Wcnf};
+
+  #[test]
+  fn parses_solutions() {
+    let instance = Wcnf { num_vars: 2, ..Wcnf::default() };
+    let values = instance.parse_solution("s OPTIMUM FOUND\nv 1 -2\n").unwrap();
+    assert_eq!(values, [false, true, false]);
+    let values = instance.parse_solution("s OPTIMUM FOUND\nv 01\n").unwrap();
+    assert_eq!(values, [false, false, true]);
+  }
+
+  #[test]
+  fn rejects_out_of_range_literals() {
+    let instance = Wcnf { num_vars: 2, ..Wcnf::default() };
+    assert!(matches!(
+      instance.parse_solution("s OPTIMUM FOUND\nv 1 3\n"),
+      Err(MaxSatError::NoSolution(_))
+    ));
+  }
+}
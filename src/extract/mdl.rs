@@ -0,0 +1,188 @@
+//! A minimum-description-length objective for choosing between library
+//! selections, in the style of Dream&shy;Coder's compression.
+//!
+//! Under this objective, the cost of programs rewritten to use a library is
+//! the length of a two-part code: the library functions' definitions, plus
+//! the programs encoded with a grammar over the operations and library
+//! functions they use. The grammar is re-estimated from the rewritten
+//! programs themselves, so a library function which is used often is cheap
+//! to refer to, and one which is rarely used mostly adds to the size of the
+//! library.
+
+use std::{collections::HashMap, hash::Hash};
+
+use egg::RecExpr;
+
+use crate::{ast_node::AstNode, teachable::Teachable};
+
+use super::split_libs;
+
+/// How to compare library selections once their programs are extracted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Objective {
+  /// Minimize the cost of the rewritten programs under the cost model.
+  #[default]
+  Size,
+  /// Minimize the [`DescriptionLength`] of the library and the rewritten
+  /// programs.
+  Mdl,
+}
+
+/// A probabilistic grammar over operations, in which every production is
+/// chosen independently of its context. Costs are negative log-likelihoods,
+/// in nats.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pcfg<Op> {
+  /// The negative log-likelihood of each production.
+  costs: HashMap<Op, f64>,
+  /// The negative log-likelihood of a production which was never seen.
+  unseen: f64,
+}
+
+impl<Op: Clone + Eq + Hash> Pcfg<Op> {
+  /// Estimates a grammar from the number of times each production is used by
+  /// `programs`, each of which counts as many times as its weight. Every
+  /// count, including that of a single unseen production, is increased by
+  /// `pseudocount`, so that no production is impossible.
+  ///
+  /// # Panics
+  /// Panics if `pseudocount` is not positive.
+  #[must_use]
+  #[allow(clippy::cast_precision_loss)]
+  pub fn estimate<'a, I>(programs: I, pseudocount: f64) -> Self
+  where
+    Op: 'a,
+    I: IntoIterator<Item = (&'a RecExpr<AstNode<Op>>, usize)>,
+  {
+    assert!(pseudocount > 0.0, "expected a positive pseudocount");
+    let mut counts: HashMap<Op, usize> = HashMap::new();
+    for (program, weight) in programs {
+      for node in program.as_ref() {
+        *counts.entry(node.operation().clone()).or_default() += weight;
+      }
+    }
+    let total = counts.values().sum::<usize>() as f64
+      + pseudocount * (counts.len() + 1) as f64;
+    let cost = |count: f64| (total / (count + pseudocount)).ln();
+    let costs =
+      counts.into_iter().map(|(op, count)| (op, cost(count as f64))).collect();
+    Self { costs, unseen: cost(0.0) }
+  }
+
+  /// The negative log-likelihood of `op`.
+  #[must_use]
+  pub fn cost(&self, op: &Op) -> f64 {
+    self.costs.get(op).copied().unwrap_or(self.unseen)
+  }
+
+  /// The negative log-likelihood of `expr`, the sum of the costs of its
+  /// nodes.
+  #[must_use]
+  pub fn expr_cost(&self, expr: &RecExpr<AstNode<Op>>) -> f64 {
+    expr.as_ref().iter().map(|node| self.cost(node.operation())).sum()
+  }
+
+  /// The number of productions which were seen.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.costs.len()
+  }
+
+  /// Whether no production was seen.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.costs.is_empty()
+  }
+}
+
+/// The description length of a library and of programs rewritten to use it,
+/// in nats; see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DescriptionLength {
+  /// The negative log-likelihood of the programs under the grammar
+  /// re-estimated from them.
+  pub corpus: f64,
+  /// The length of the library functions' definitions, each node of which is
+  /// encoded as one of the grammar's productions chosen uniformly.
+  pub grammar: f64,
+}
+
+impl DescriptionLength {
+  /// Measures `lifted`, a list of programs with the definitions of the
+  /// library functions they use at the top, counting each program as many
+  /// times as its weight, if `weights` are given. The grammar is
+  /// re-estimated with a pseudocount of 1.
+  #[must_use]
+  #[allow(clippy::cast_precision_loss)]
+  pub fn of<Op>(
+    lifted: &RecExpr<AstNode<Op>>,
+    weights: Option<&[usize]>,
+  ) -> Self
+  where
+    Op: Clone + Teachable + Ord + std::fmt::Debug + Hash,
+  {
+    let applied = split_libs(lifted);
+    let weight = |i: usize| weights.map_or(1, |weights| weights[i]);
+    let pcfg = Pcfg::estimate(
+      applied
+        .programs
+        .iter()
+        .enumerate()
+        .map(|(i, program)| (program, weight(i))),
+      1.0,
+    );
+    let corpus = applied
+      .programs
+      .iter()
+      .enumerate()
+      .map(|(i, program)| weight(i) as f64 * pcfg.expr_cost(program))
+      .sum();
+    // Every production of the grammar, plus an unseen one.
+    let choice = ((pcfg.len() + 1) as f64).ln();
+    let grammar = applied
+      .libs
+      .iter()
+      .map(|lib| lib.definition.as_ref().len() as f64 * choice)
+      .sum();
+    Self { corpus, grammar }
+  }
+
+  /// The total description length.
+  #[must_use]
+  pub fn total(&self) -> f64 {
+    self.corpus + self.grammar
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{DescriptionLength, Pcfg};
+  use crate::{round_trip, simple_lang::SimpleOp, AstNode};
+  use egg::RecExpr;
+
+  fn parse(input: &str) -> RecExpr<AstNode<SimpleOp>> {
+    round_trip::parse(input).into()
+  }
+
+  #[test]
+  fn frequent_productions_are_cheaper() {
+    let a = parse("(@ f x)");
+    let b = parse("(@ f y)");
+    let pcfg = Pcfg::estimate([(&a, 1), (&b, 1)], 1.0);
+    assert_eq!(pcfg.len(), 4);
+    let f = pcfg.cost(&"f".parse().unwrap());
+    let x = pcfg.cost(&"x".parse().unwrap());
+    let z = pcfg.cost(&"z".parse().unwrap());
+    assert!(f < x && x < z);
+    assert!((pcfg.expr_cost(&a) - (2.0 * f + x)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn weights_count_programs_repeatedly() {
+    let lifted = parse("(list (@ f x) (@ f y))");
+    let once = DescriptionLength::of(&lifted, None);
+    let twice = DescriptionLength::of(&lifted, Some(&[2, 2]));
+    assert!(once.grammar.abs() < 1e-9);
+    assert!(twice.corpus > once.corpus);
+  }
+}
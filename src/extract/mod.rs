@@ -1,24 +1,165 @@
 //! Extracting expressions with learned libs out of egraphs
 
 pub mod beam;
+pub mod compose;
+pub mod cost;
+#[cfg(feature = "std")]
+pub mod maxsat;
+pub mod mdl;
+pub mod serialize;
 
-use std::collections::HashMap;
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Arc,
+  time::Duration,
+};
 
 use egg::{Analysis, EGraph, Id, Language, RecExpr, Rewrite, Runner};
 
 use crate::{
-  ast_node::{Arity, AstNode},
+  ast_node::{Arity, AstNode, Expr},
+  clock,
   learn::LibId,
   teachable::{BindingExpr, Teachable},
 };
 
+use self::{
+  beam::{ExtractionError, LibConstraints},
+  cost::{CostModel, UnitCost},
+};
+
 /// Given an `egraph` that contains the original expression at `roots`,
 /// and a set of library `rewrites`, extract the programs rewritten using the library.
+///
+/// # Errors
+/// Returns an error if `roots` have no finite expressions in `egraph`, which
+/// can't happen if they are the original programs.
 pub fn apply_libs<Op, A>(
   egraph: EGraph<AstNode<Op>, A>,
   roots: &[Id],
   rewrites: &[Rewrite<AstNode<Op>, A>],
-) -> RecExpr<AstNode<Op>>
+) -> Result<RecExpr<AstNode<Op>>, ExtractionError>
+where
+  Op: Clone
+    + Teachable
+    + Ord
+    + std::fmt::Debug
+    + std::fmt::Display
+    + std::hash::Hash
+    + Arity
+    + Send
+    + Sync,
+  A: Analysis<AstNode<Op>> + Default + Clone,
+{
+  apply_libs_with_cost_model(egraph, roots, rewrites, Arc::new(UnitCost))
+}
+
+/// Same as [`apply_libs`], but extracts the programs which are cheapest
+/// according to `cost_model`.
+///
+/// # Errors
+/// Returns an error if `roots` have no finite expressions in `egraph`, which
+/// can't happen if they are the original programs.
+pub fn apply_libs_with_cost_model<Op, A>(
+  egraph: EGraph<AstNode<Op>, A>,
+  roots: &[Id],
+  rewrites: &[Rewrite<AstNode<Op>, A>],
+  cost_model: Arc<dyn CostModel<Op>>,
+) -> Result<RecExpr<AstNode<Op>>, ExtractionError>
+where
+  Op: Clone
+    + Teachable
+    + Ord
+    + std::fmt::Debug
+    + std::fmt::Display
+    + std::hash::Hash
+    + Arity
+    + Send
+    + Sync,
+  A: Analysis<AstNode<Op>> + Default + Clone,
+{
+  apply_libs_constrained(
+    egraph,
+    roots,
+    rewrites,
+    cost_model,
+    &LibConstraints::default(),
+  )
+}
+
+/// Same as [`apply_libs_with_cost_model`], but respects `constraints`: the
+/// definition of a forbidden library function is never extracted, and the
+/// definition of every required library function is kept in the result,
+/// even where using it doesn't make the programs any cheaper.
+///
+/// # Errors
+/// Returns an error if a required library function is defined nowhere in
+/// `egraph` once `rewrites` are applied, or if `roots` have no finite
+/// expressions without the forbidden library functions, which can't happen
+/// if they are the original programs and don't use them.
+pub fn apply_libs_constrained<Op, A>(
+  egraph: EGraph<AstNode<Op>, A>,
+  roots: &[Id],
+  rewrites: &[Rewrite<AstNode<Op>, A>],
+  cost_model: Arc<dyn CostModel<Op>>,
+  constraints: &LibConstraints,
+) -> Result<RecExpr<AstNode<Op>>, ExtractionError>
+where
+  Op: Clone
+    + Teachable
+    + Ord
+    + std::fmt::Debug
+    + std::fmt::Display
+    + std::hash::Hash
+    + Arity
+    + Send
+    + Sync,
+  A: Analysis<AstNode<Op>> + Default + Clone,
+{
+  apply_libs_until(egraph, roots, rewrites, cost_model, constraints, None)
+}
+
+/// Same as [`apply_libs_constrained`], but gives up once `time_limit` has
+/// passed, both while applying `rewrites` and while extracting.
+///
+/// # Errors
+/// Returns [`ExtractionError::TimedOut`] if the time runs out, and the
+/// errors of [`apply_libs_constrained`] otherwise.
+pub fn apply_libs_within<Op, A>(
+  egraph: EGraph<AstNode<Op>, A>,
+  roots: &[Id],
+  rewrites: &[Rewrite<AstNode<Op>, A>],
+  cost_model: Arc<dyn CostModel<Op>>,
+  constraints: &LibConstraints,
+  time_limit: Duration,
+) -> Result<RecExpr<AstNode<Op>>, ExtractionError>
+where
+  Op: Clone
+    + Teachable
+    + Ord
+    + std::fmt::Debug
+    + std::fmt::Display
+    + std::hash::Hash
+    + Arity
+    + Send
+    + Sync,
+  A: Analysis<AstNode<Op>> + Default + Clone,
+{
+  let limit = Some(time_limit);
+  apply_libs_until(egraph, roots, rewrites, cost_model, constraints, limit)
+}
+
+/// Implements [`apply_libs_constrained`] and [`apply_libs_within`]: with a
+/// `time_limit`, the rewrites and extraction stop once it has passed, and
+/// without one, the rewrites run with egg's default limits.
+fn apply_libs_until<Op, A>(
+  egraph: EGraph<AstNode<Op>, A>,
+  roots: &[Id],
+  rewrites: &[Rewrite<AstNode<Op>, A>],
+  cost_model: Arc<dyn CostModel<Op>>,
+  constraints: &LibConstraints,
+  time_limit: Option<Duration>,
+) -> Result<RecExpr<AstNode<Op>>, ExtractionError>
 where
   Op: Clone
     + Teachable
@@ -31,70 +172,504 @@ where
     + Sync,
   A: Analysis<AstNode<Op>> + Default + Clone,
 {
-  let mut fin = Runner::<_, _, ()>::new(Default::default())
-    .with_egraph(egraph)
-    .run(rewrites.iter())
-    .egraph;
+  let deadline = time_limit.and_then(clock::deadline);
+  let mut runner =
+    Runner::<_, _, ()>::new(Default::default()).with_egraph(egraph);
+  if let Some(time_limit) = time_limit {
+    runner = runner.with_time_limit(time_limit);
+  }
+  let mut fin = runner.run(rewrites.iter()).egraph;
   let root = fin.add(AstNode::new(Op::list(), roots.iter().copied()));
 
-  let mut extractor = beam::LibExtractor::new(&fin);
-  let best = extractor.best(root);
-  lift_libs(&best)
+  let mut extractor = beam::LibExtractor::new(&fin)
+    .with_cost_model(cost_model)
+    .with_lib_constraints(constraints.clone());
+  if let Some(deadline) = deadline {
+    extractor = extractor.with_deadline(deadline);
+  }
+  let mut best = Expr::from(lift_libs(&extractor.best(root)?));
+
+  // Define each required lib the cheapest programs don't use anyway.
+  let defined: HashSet<LibId> =
+    split_libs(&best.clone().into()).libs.iter().map(|lib| lib.id).collect();
+  for &lib in constraints.required.iter().filter(|lib| !defined.contains(lib)) {
+    let definition = fin
+      .classes()
+      .flat_map(|class| class.iter())
+      .find_map(|node| match node.as_binding_expr() {
+        Some(
+          BindingExpr::Lib(id, def, _) | BindingExpr::LibRec(id, def, _),
+        ) if id == lib => Some(*def),
+        _ => None,
+      })
+      .ok_or(ExtractionError::MissingLib(lib))?;
+    let definition = extractor.best_in(definition, &[lib])?;
+    best = Op::lib(lib, Expr::from(definition), best).into();
+  }
+  Ok(lift_libs(&best.into()))
 }
 
-fn build<Op: Clone + Teachable + std::fmt::Debug>(
-  orig: &[AstNode<Op>],
-  cur: Id,
-  mut seen: impl FnMut(LibId, Id),
-) -> AstNode<Op> {
-  match orig[Into::<usize>::into(cur)].as_binding_expr() {
-    Some(BindingExpr::Lib(id, lam, c)) => {
-      seen(id, *lam);
-      build(orig, *c, seen)
-    }
-    _ => orig[Into::<usize>::into(cur)].clone(),
+/// A library function lifted out of a set of programs by
+/// [`apply_libs_per_root`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiftedLib<Op> {
+  /// The id of the library function.
+  pub id: LibId,
+  /// The definition of the library function.
+  pub definition: RecExpr<AstNode<Op>>,
+  /// Whether the library function is bound as one of a group of mutually
+  /// recursive library functions.
+  pub recursive: bool,
+}
+
+/// Programs rewritten to use library functions, with the definitions of those
+/// library functions kept apart. See [`apply_libs_per_root`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedLibs<Op> {
+  /// The library functions used by the programs, in the order
+  /// [`lift_libs`] binds them, outermost first.
+  pub libs: Vec<LiftedLib<Op>>,
+  /// The rewritten programs, in the same order as the roots they came from.
+  pub programs: Vec<RecExpr<AstNode<Op>>>,
+}
+
+/// Same as [`apply_libs`], but returns each rewritten program separately,
+/// aligned with `roots`, along with the definitions of the library functions
+/// they share.
+///
+/// # Errors
+/// Returns an error if `roots` have no finite expressions in `egraph`, which
+/// can't happen if they are the original programs.
+pub fn apply_libs_per_root<Op, A>(
+  egraph: EGraph<AstNode<Op>, A>,
+  roots: &[Id],
+  rewrites: &[Rewrite<AstNode<Op>, A>],
+) -> Result<AppliedLibs<Op>, ExtractionError>
+where
+  Op: Clone
+    + Teachable
+    + Ord
+    + std::fmt::Debug
+    + std::fmt::Display
+    + std::hash::Hash
+    + Arity
+    + Send
+    + Sync,
+  A: Analysis<AstNode<Op>> + Default + Clone,
+{
+  let lifted = apply_libs(egraph, roots, rewrites)?;
+  let applied = split_libs(&lifted);
+  debug_assert_eq!(applied.programs.len(), roots.len());
+  Ok(applied)
+}
+
+/// Splits `expr`, a list of programs with the definitions of the library
+/// functions they use lifted to the top by [`lift_libs`], into those
+/// definitions and the programs.
+#[must_use]
+pub fn split_libs<Op>(expr: &RecExpr<AstNode<Op>>) -> AppliedLibs<Op>
+where
+  Op: Clone + Teachable + Ord + std::fmt::Debug + std::hash::Hash,
+{
+  let nodes = expr.as_ref();
+  let subexpr = |id: Id| {
+    nodes[usize::from(id)].build_recexpr(|id| nodes[usize::from(id)].clone())
+  };
+
+  let mut libs = Vec::new();
+  let mut cur = Id::from(nodes.len() - 1);
+  loop {
+    let (id, definition, body, recursive) =
+      match nodes[usize::from(cur)].as_binding_expr() {
+        Some(BindingExpr::Lib(id, definition, body)) => {
+          (id, *definition, *body, false)
+        }
+        Some(BindingExpr::LibRec(id, definition, body)) => {
+          (id, *definition, *body, true)
+        }
+        _ => break,
+      };
+    libs.push(LiftedLib { id, definition: subexpr(definition), recursive });
+    cur = body;
   }
+
+  let programs =
+    nodes[usize::from(cur)].args().iter().map(|&root| subexpr(root)).collect();
+  AppliedLibs { libs, programs }
 }
 
 /// Given an expression `expr` containing library function definitions, move
-/// those definitions to the top.
+/// those definitions to the top. Library functions whose definitions refer to
+/// each other, directly or indirectly, are bound together as a group of
+/// mutually recursive library functions, innermost.
+///
+/// A definition which refers to a de Bruijn variable bound in `expr` can't
+/// be lifted out of that variable's binder, so it is only lifted up to just
+/// inside it, along with the definitions which refer to it. The variables of
+/// every definition are re-indexed to account for the binders it was lifted
+/// out of.
 #[must_use]
 pub fn lift_libs<Op>(expr: &RecExpr<AstNode<Op>>) -> RecExpr<AstNode<Op>>
 where
   Op: Clone + Teachable + Ord + std::fmt::Debug + std::hash::Hash,
 {
-  let orig: Vec<AstNode<Op>> = expr.as_ref().to_vec();
-  let mut seen = HashMap::new();
+  let mut floating = Vec::new();
+  let body = float_libs(Expr::from(expr.clone()), &mut floating);
+  bind_libs(body, floating).into()
+}
 
-  let rest = orig[orig.len() - 1].build_recexpr(|id| {
-    build(&orig, id, |k, v| {
-      seen.insert(k, v);
-    })
-  });
-  let mut res = rest.as_ref().to_vec();
-
-  // Work queue for functions we still have to do
-  let mut q: Vec<(LibId, Id)> = seen.iter().map(|(k, v)| (*k, *v)).collect();
-
-  // TODO: order based on libs dependency w each other?
-  while let Some((lib, expr)) = q.pop() {
-    let body = res.len() - 1;
-    let value: Vec<_> = orig[Into::<usize>::into(expr)]
-      .build_recexpr(|id| {
-        build(&orig, id, |k, v| {
-          if seen.insert(k, v).is_none() {
-            q.push((k, v));
-          }
-        })
-      })
-      .as_ref()
+/// Inlines the library functions of `expr`, a list of programs with the
+/// definitions of the library functions they use lifted to the top by
+/// [`lift_libs`], which are used fewer than `min_uses` times, by substituting
+/// their definitions for their names. The beam search only estimates the
+/// cost of a lib selection, so it sometimes picks a library function whose
+/// definition costs more than its uses save, such as one used once.
+///
+/// Each such library function is only inlined if `cost` of the result is at
+/// most `cost` of the expression before, so the result never costs more
+/// than `expr`. Recursive library functions are never inlined.
+pub fn inline_rare_libs<Op, F>(
+  expr: &RecExpr<AstNode<Op>>,
+  min_uses: usize,
+  mut cost: F,
+) -> RecExpr<AstNode<Op>>
+where
+  Op: Clone + Teachable + Ord + std::fmt::Debug + std::hash::Hash,
+  F: FnMut(&RecExpr<AstNode<Op>>) -> usize,
+{
+  let mut best = expr.clone();
+  let mut best_cost = cost(&best);
+  'inline: loop {
+    let mut defs = Vec::new();
+    let body = float_libs(Expr::from(best.clone()), &mut defs);
+    let recursive = recursive_libs(&defs);
+    let uses = |lib: LibId| {
+      defs
+        .iter()
+        .map(|(_, def)| def)
+        .chain([&body])
+        .flat_map(lib_vars)
+        .filter(|&used| used == lib)
+        .count()
+    };
+    let rare: Vec<_> = defs
       .iter()
-      .cloned()
-      .map(|x| x.map_children(|x| (usize::from(x) + res.len()).into()))
+      .map(|&(lib, _)| lib)
+      .filter(|lib| !recursive.contains(lib) && uses(*lib) < min_uses)
       .collect();
-    res.extend(value);
-    res.push(Teachable::lib(lib, Id::from(res.len() - 1), Id::from(body)));
+
+    for lib in rare {
+      let def = &defs.iter().find(|&&(other, _)| other == lib).unwrap().1;
+      let others = defs
+        .iter()
+        .filter(|&&(other, _)| other != lib)
+        .map(|(other, other_def)| {
+          (*other, substitute_lib(other_def.clone(), lib, def))
+        })
+        .collect();
+      let inlined: RecExpr<_> =
+        bind_libs(substitute_lib(body.clone(), lib, def), others).into();
+      let inlined_cost = cost(&inlined);
+      if inlined_cost <= best_cost {
+        best = inlined;
+        best_cost = inlined_cost;
+        continue 'inline;
+      }
+    }
+    return best;
   }
+}
+
+/// Replaces the uses of the library function `lib` in `expr` with its
+/// definition `def`, which has no free variables.
+fn substitute_lib<Op: Teachable + Clone>(
+  expr: Expr<Op>,
+  lib: LibId,
+  def: &Expr<Op>,
+) -> Expr<Op> {
+  match expr.0.as_binding_expr() {
+    Some(BindingExpr::LibVar(other)) if other == lib => def.clone(),
+    _ => Expr(expr.0.map(|child| substitute_lib(child, lib, def))),
+  }
+}
+
+/// Removes the library function bindings from `expr`, pushing their
+/// definitions onto `floating` in the order they are found, re-indexed to be
+/// valid outside of `expr`. Bindings which can't be lifted out of a binder in
+/// `expr` are bound again just inside of it.
+fn float_libs<Op: Teachable + Clone>(
+  expr: Expr<Op>,
+  floating: &mut Vec<(LibId, Expr<Op>)>,
+) -> Expr<Op> {
+  if let Some(
+    BindingExpr::Lib(lib, def, body) | BindingExpr::LibRec(lib, def, body),
+  ) = expr.0.as_binding_expr()
+  {
+    let (def, body) = (def.clone(), body.clone());
+    let def = float_libs(def, floating);
+    floating.push((lib, def));
+    return float_libs(body, floating);
+  }
+  if !matches!(
+    expr.0.as_binding_expr(),
+    Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_))
+  ) {
+    return Expr(expr.0.map(|child| float_libs(child, floating)));
+  }
+
+  let mut inner = Vec::new();
+  let node = expr.0.map(|body| float_libs(body, &mut inner));
 
-  res.into()
+  // The definitions which refer to the variable bound here, or to library
+  // functions which have to stay here, have to stay here too.
+  let mut stuck: HashSet<LibId> = HashSet::new();
+  loop {
+    let before = stuck.len();
+    for (lib, def) in &inner {
+      if has_free_var(def, 0) || lib_vars(def).any(|used| stuck.contains(&used))
+      {
+        stuck.insert(*lib);
+      }
+    }
+    if stuck.len() == before {
+      break;
+    }
+  }
+
+  let (kept, lifted): (Vec<_>, Vec<_>) =
+    inner.into_iter().partition(|(lib, _)| stuck.contains(lib));
+  floating.extend(lifted.into_iter().map(|(lib, def)| (lib, unshift(def, 0))));
+  if kept.is_empty() {
+    Expr(node)
+  } else {
+    let mut kept = Some(kept);
+    Expr(node.map(|body| bind_libs(body, kept.take().unwrap_or_default())))
+  }
+}
+
+/// Binds the library functions `defs` around `body`, keeping only the first
+/// definition of each. Library functions are bound in the order of `defs`,
+/// outermost first, except that those whose definitions refer to each other
+/// are bound together as a group of mutually recursive library functions,
+/// innermost.
+fn bind_libs<Op: Teachable + Clone>(
+  body: Expr<Op>,
+  defs: Vec<(LibId, Expr<Op>)>,
+) -> Expr<Op> {
+  let mut seen = HashSet::new();
+  let mut defs: Vec<_> =
+    defs.into_iter().filter(|(lib, _)| seen.insert(*lib)).collect();
+  let recursive = recursive_libs(&defs);
+  defs.sort_by_key(|(lib, _)| recursive.contains(lib));
+  defs.into_iter().rev().fold(body, |body, (lib, def)| {
+    Expr(if recursive.contains(&lib) {
+      Op::lib_rec(lib, def, body)
+    } else {
+      Op::lib(lib, def, body)
+    })
+  })
+}
+
+/// Whether the de Bruijn variable `index`, counted from outside of `expr`,
+/// occurs free in `expr`.
+fn has_free_var<Op: Teachable>(expr: &Expr<Op>, index: usize) -> bool {
+  match expr.0.as_binding_expr() {
+    Some(BindingExpr::Var(var)) => var.0 == index,
+    Some(BindingExpr::Lambda(body) | BindingExpr::Fix(body)) => {
+      has_free_var(body, index + 1)
+    }
+    _ => expr.0.iter().any(|child| has_free_var(child, index)),
+  }
+}
+
+/// Re-indexes `expr`, which is nested within `cutoff` binders of a binder
+/// whose variable it doesn't refer to, for use outside of that binder.
+fn unshift<Op: Teachable + Clone>(expr: Expr<Op>, cutoff: usize) -> Expr<Op> {
+  let cutoff = match expr.0.as_binding_expr() {
+    Some(BindingExpr::Var(var)) if var.0 > cutoff => {
+      return Expr(Op::var(var.0 - 1));
+    }
+    Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_)) => cutoff + 1,
+    _ => cutoff,
+  };
+  Expr(expr.0.map(|child| unshift(child, cutoff)))
+}
+
+/// The library functions referred to in `expr`.
+fn lib_vars<Op: Teachable>(expr: &Expr<Op>) -> impl Iterator<Item = LibId> {
+  let mut libs = Vec::new();
+  let mut stack = vec![expr];
+  while let Some(expr) = stack.pop() {
+    match expr.0.as_binding_expr() {
+      Some(BindingExpr::LibVar(lib)) => libs.push(lib),
+      _ => stack.extend(expr.0.iter()),
+    }
+  }
+  libs.into_iter()
+}
+
+/// Returns the library functions in `defs` whose definitions refer to
+/// themselves, directly or through the definitions of other library
+/// functions in `defs`.
+fn recursive_libs<Op: Teachable>(defs: &[(LibId, Expr<Op>)]) -> HashSet<LibId> {
+  let refs: HashMap<LibId, Vec<LibId>> =
+    defs.iter().map(|(lib, value)| (*lib, lib_vars(value).collect())).collect();
+
+  let mut recursive = HashSet::new();
+  for &(lib, _) in defs {
+    let mut visited = HashSet::new();
+    let mut stack = refs[&lib].clone();
+    while let Some(used) = stack.pop() {
+      if used == lib {
+        recursive.insert(lib);
+        break;
+      }
+      if visited.insert(used) {
+        stack.extend(refs.get(&used).into_iter().flatten().copied());
+      }
+    }
+  }
+  recursive
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    apply_libs_within,
+    beam::{ExtractionError, LibConstraints, LibExtractor},
+    cost::UnitCost,
+    lift_libs, split_libs,
+  };
+  use crate::{
+    round_trip::parse, simple_lang::SimpleOp, teachable::Teachable, AstNode,
+    Expr, LibId,
+  };
+  use egg::EGraph;
+  use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+  };
+
+  /// A call of the library function `lib` on `arg`.
+  fn call(lib: usize, arg: Expr<SimpleOp>) -> Expr<SimpleOp> {
+    Expr(SimpleOp::apply(Expr(SimpleOp::lib_var(LibId(lib))), arg))
+  }
+
+  fn lambda(body: Expr<SimpleOp>) -> Expr<SimpleOp> {
+    Expr(SimpleOp::lambda(body))
+  }
+
+  #[test]
+  fn lift_libs_groups_mutual_recursion() {
+    let programs = Expr(AstNode::new(SimpleOp::List, [call(0, parse("x"))]));
+    let expr = Expr(SimpleOp::lib(
+      LibId(0),
+      lambda(call(1, parse("$0"))),
+      Expr(SimpleOp::lib(
+        LibId(1),
+        lambda(call(0, call(2, parse("$0")))),
+        Expr(SimpleOp::lib(LibId(2), parse("(λ $0)"), programs)),
+      )),
+    ));
+    let libs: Vec<_> = split_libs(&lift_libs(&expr.into()))
+      .libs
+      .into_iter()
+      .map(|lib| (lib.id, lib.recursive))
+      .collect();
+    assert_eq!(libs, [(LibId(2), false), (LibId(0), true), (LibId(1), true)]);
+  }
+
+  #[test]
+  fn lift_libs_under_binders() {
+    let lib = |id, def: Expr<SimpleOp>, body: Expr<SimpleOp>| {
+      Expr(SimpleOp::lib(LibId(id), def, body))
+    };
+    let fix = |body: Expr<SimpleOp>| Expr(SimpleOp::fix(body));
+    let lift = |expr: Expr<SimpleOp>| Expr::from(lift_libs(&expr.into()));
+
+    // A closed definition is lifted out of every binder.
+    assert_eq!(
+      lift(lambda(lib(0, parse("(λ $0)"), call(0, parse("$0"))))),
+      lib(0, parse("(λ $0)"), lambda(call(0, parse("$0"))))
+    );
+    // A definition referring to the outer lambda is lifted out of the inner
+    // one, re-indexed, and bound just inside the outer one.
+    assert_eq!(
+      lift(lambda(lambda(lib(0, parse("(@ f $1)"), call(0, parse("$0")))))),
+      lambda(lib(0, parse("(@ f $0)"), lambda(call(0, parse("$0")))))
+    );
+    // A definition referring to a variable free in the whole expression is
+    // lifted to the top, re-indexed.
+    assert_eq!(
+      lift(lambda(lib(0, parse("(@ f $1)"), parse("$0")))),
+      lib(0, parse("(@ f $0)"), lambda(parse("$0")))
+    );
+    // A definition referring to the variable bound by a fix stays inside of
+    // it, but one referring to an outer lambda is lifted out of the fix.
+    let under_fix = fix(lib(0, parse("(@ f $0)"), call(0, parse("$0"))));
+    assert_eq!(lift(under_fix.clone()), under_fix);
+    assert_eq!(
+      lift(lambda(fix(lib(0, parse("(@ f $1)"), call(0, parse("$0")))))),
+      lambda(lib(0, parse("(@ f $0)"), fix(call(0, parse("$0")))))
+    );
+    // A closed definition using a library function which has to stay inside
+    // a binder stays there too.
+    let stuck = lib(
+      0,
+      parse("(@ f $0)"),
+      lib(1, lambda(call(0, parse("$0"))), call(1, call(2, parse("$0")))),
+    );
+    assert_eq!(
+      lift(lambda(lib(2, parse("(λ $0)"), stuck.clone()))),
+      lib(2, parse("(λ $0)"), lambda(stuck))
+    );
+  }
+
+  #[test]
+  fn extracts_lib_rec() {
+    let expr = Expr(SimpleOp::lib_rec(
+      LibId(0),
+      lambda(call(0, parse("$0"))),
+      call(0, parse("x")),
+    ));
+    let mut egraph = EGraph::<AstNode<SimpleOp>, ()>::default();
+    let root = egraph.add_expr(&expr.clone().into());
+    egraph.rebuild();
+    let best = LibExtractor::new(&egraph).best(root).unwrap();
+    assert_eq!(Expr::from(best), expr);
+
+    let constraints =
+      LibConstraints { forbidden: [LibId(0)].into(), ..LibConstraints::new() };
+    let mut extractor =
+      LibExtractor::new(&egraph).with_lib_constraints(constraints);
+    assert!(matches!(
+      extractor.best(root),
+      Err(ExtractionError::NoFiniteTerm(_))
+    ));
+  }
+
+  #[test]
+  fn extraction_stops_at_deadline() {
+    let mut egraph = EGraph::<AstNode<SimpleOp>, ()>::default();
+    let root = egraph.add_expr(&parse("(list (list x) (list x))").into());
+    egraph.rebuild();
+
+    let mut extractor =
+      LibExtractor::new(&egraph).with_deadline(Instant::now());
+    assert!(matches!(extractor.best(root), Err(ExtractionError::TimedOut)));
+
+    let constraints = LibConstraints::new();
+    let within = |time_limit| {
+      apply_libs_within(
+        egraph.clone(),
+        &[root],
+        &[],
+        Arc::new(UnitCost),
+        &constraints,
+        time_limit,
+      )
+    };
+    assert!(matches!(within(Duration::ZERO), Err(ExtractionError::TimedOut)));
+    assert!(within(Duration::from_secs(60)).is_ok());
+  }
 }
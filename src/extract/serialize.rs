@@ -0,0 +1,71 @@
+//! Exporting e-graphs to the JSON format of the [`egraph-serialize`] crate,
+//! so that they can be visualized with the tools built around it.
+//!
+//! [`egraph-serialize`]: https://github.com/egraphs-good/egraph-serialize
+
+use std::fmt::Display;
+
+use egg::{EGraph, Id};
+use serde_json::{json, Map, Value};
+
+use super::beam::{LibSel, PartialLibCost};
+use crate::ast_node::AstNode;
+
+/// Converts `egraph` to the JSON format of `egraph-serialize`, with the
+/// e-classes `roots` as its roots.
+///
+/// The e-node at index `i` of e-class `c` is named `c.i`, and the children
+/// of an e-node refer to the first e-node of each child e-class. Every
+/// e-node costs 1. The beam of lib selections kept for each e-class is
+/// included in its class data, under `beam`, in ascending order of cost.
+#[must_use]
+pub fn to_json<Op: Display>(
+  egraph: &EGraph<AstNode<Op>, PartialLibCost>,
+  roots: &[Id],
+) -> Value {
+  let mut nodes = Map::new();
+  let mut class_data = Map::new();
+  for class in egraph.classes() {
+    for (i, node) in class.nodes.iter().enumerate() {
+      let children: Vec<_> = node
+        .args()
+        .iter()
+        .map(|&child| format!("{}.0", egraph.find(child)))
+        .collect();
+      nodes.insert(
+        format!("{}.{i}", class.id),
+        json!({
+          "op": node.operation().to_string(),
+          "children": children,
+          "eclass": class.id.to_string(),
+          "cost": 1.0,
+        }),
+      );
+    }
+    let beam: Vec<_> = class.data.set.iter().map(lib_sel_to_json).collect();
+    class_data.insert(class.id.to_string(), json!({ "beam": beam }));
+  }
+  let root_eclasses: Vec<_> =
+    roots.iter().map(|&root| egraph.find(root).to_string()).collect();
+
+  json!({
+    "nodes": nodes,
+    "root_eclasses": root_eclasses,
+    "class_data": class_data,
+  })
+}
+
+/// Converts `lib_sel` to JSON, with the libs as pairs of their name and
+/// cost.
+fn lib_sel_to_json(lib_sel: &LibSel) -> Value {
+  let libs: Vec<_> = lib_sel
+    .libs
+    .iter()
+    .map(|(lib, cost)| json!([lib.to_string(), cost]))
+    .collect();
+  json!({
+    "expr_cost": lib_sel.expr_cost,
+    "full_cost": lib_sel.full_cost,
+    "libs": libs,
+  })
+}
@@ -14,24 +14,40 @@
 //! add the partial expression op(z1, ..., zn) to the set AU(a, b).
 //! If the set AU(a, b) is empty, we add to it the partial expression (a, b).
 use crate::{
-  ast_node::{Arity, AstNode, PartialExpr},
+  ast_node::{Arity, AstNode, Expr, PartialExpr},
+  clock,
   co_occurrence::CoOccurrences,
+  control_flow::ControlFlow,
   dfta::Dfta,
+  progress::{NoProgress, Phase, ProgressSink},
   teachable::{BindingExpr, Teachable},
+  typing::{TypeSignature, Typeable},
   COBuilder,
 };
-use egg::{Analysis, EGraph, Id, Language, Pattern, Rewrite, Searcher, Var};
+use egg::{
+  Analysis, ConditionalApplier, EGraph, Id, Language, Pattern, RecExpr,
+  Rewrite, Runner, Searcher, Subst, Var,
+};
 use itertools::Itertools;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::{BTreeMap, BTreeSet},
+  collections::{
+    hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet,
+  },
   fmt::{Debug, Display},
+  hash::{Hash, Hasher},
   num::ParseIntError,
   str::FromStr,
+  sync::Arc,
+  time::{Duration, Instant},
 };
 use thiserror::Error;
 
+/// The largest number of variables of a pattern for which
+/// [`LearnedLibrary::semantic_dedup`] tries every order of the variables.
+const MAX_PERMUTED_VARS: usize = 4;
+
 /// A library function's name.
 #[derive(
   Debug,
@@ -47,6 +63,33 @@ use thiserror::Error;
 )]
 pub struct LibId(pub usize);
 
+impl LibId {
+  /// The first [`LibId`] after those of every library function defined or
+  /// referred to in `egraph`, from which library functions learned from it
+  /// can be numbered.
+  #[must_use]
+  pub fn next_free<Op, A>(egraph: &EGraph<AstNode<Op>, A>) -> Self
+  where
+    Op: Teachable + Ord + Debug + Clone + Hash,
+    A: Analysis<AstNode<Op>>,
+  {
+    let next = egraph
+      .classes()
+      .flat_map(|class| class.iter())
+      .filter_map(|node| match node.as_binding_expr() {
+        Some(
+          BindingExpr::LibVar(lib)
+          | BindingExpr::Lib(lib, _, _)
+          | BindingExpr::LibRec(lib, _, _),
+        ) => Some(lib.0 + 1),
+        _ => None,
+      })
+      .max()
+      .unwrap_or(0);
+    Self(next)
+  }
+}
+
 impl Display for LibId {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(f, "l{}", self.0)
@@ -100,10 +143,25 @@ pub struct LearnedLibraryBuilder<Op> {
   learn_trivial: bool,
   learn_constants: bool,
   max_arity: Option<usize>,
+  min_size: Option<usize>,
+  max_size: Option<usize>,
   banned_ops: Vec<Op>,
+  excluded_root_ops: Vec<Op>,
+  anchor_ops: Vec<Op>,
   roots: Vec<Id>,
   co_occurences: Option<CoOccurrences>,
   dfta: bool,
+  type_check: Option<fn(&PartialExpr<Op, Var>) -> bool>,
+  control_flow: Option<fn(&Op) -> bool>,
+  lib_offset: usize,
+  learn_recursive: bool,
+  learn_lets: bool,
+  learn_higher_order: bool,
+  previous: Option<LearnedLibrary<Op, (Id, Id)>>,
+  time_limit: Option<Duration>,
+  deepen_arity: Option<Duration>,
+  candidate_budget: Option<usize>,
+  progress: Option<Arc<dyn ProgressSink>>,
 }
 
 impl<Op> Default for LearnedLibraryBuilder<Op> {
@@ -112,10 +170,25 @@ impl<Op> Default for LearnedLibraryBuilder<Op> {
       learn_trivial: false,
       learn_constants: false,
       max_arity: None,
+      min_size: None,
+      max_size: None,
       banned_ops: vec![],
+      excluded_root_ops: vec![],
+      anchor_ops: vec![],
       roots: vec![],
       co_occurences: None,
       dfta: true,
+      type_check: None,
+      control_flow: None,
+      lib_offset: 0,
+      learn_recursive: false,
+      learn_lets: false,
+      learn_higher_order: false,
+      previous: None,
+      time_limit: None,
+      deepen_arity: None,
+      candidate_budget: None,
+      progress: None,
     }
   }
 }
@@ -131,6 +204,7 @@ where
     + Display
     + std::hash::Hash
     + DiscriminantEq
+    + Teachable
     + 'static,
 {
   #[must_use]
@@ -151,6 +225,25 @@ where
     self
   }
 
+  /// Only learn library functions whose bodies have at least `size` nodes,
+  /// not counting their arguments. By default, there is no minimum beyond
+  /// what it takes for a library function to compress anything.
+  #[must_use]
+  pub fn min_size(mut self, size: Option<usize>) -> Self {
+    self.min_size = size;
+    self
+  }
+
+  /// Only learn library functions whose bodies have at most `size` nodes,
+  /// not counting their arguments. Larger anti-unifications are discarded
+  /// as soon as they are found, so this also speeds up anti-unification. By
+  /// default, there is no limit.
+  #[must_use]
+  pub fn max_size(mut self, size: Option<usize>) -> Self {
+    self.max_size = size;
+    self
+  }
+
   #[must_use]
   pub fn ban_op(mut self, op: Op) -> Self {
     self.banned_ops.push(op);
@@ -163,6 +256,47 @@ where
     self
   }
 
+  /// Never learn library functions whose bodies are rooted at `op`, such as
+  /// a literal. Unlike [`ban_op`](Self::ban_op), `op` may still appear
+  /// deeper inside a body. Pairs of e-classes which can't produce any other
+  /// root are not anti-unified at all.
+  #[must_use]
+  pub fn exclude_root_op(mut self, op: Op) -> Self {
+    self.excluded_root_ops.push(op);
+    self
+  }
+
+  /// Never learn library functions whose bodies are rooted at any of the
+  /// operations in `iter`; see [`exclude_root_op`](Self::exclude_root_op).
+  #[must_use]
+  pub fn exclude_root_ops(
+    mut self,
+    iter: impl IntoIterator<Item = Op>,
+  ) -> Self {
+    self.excluded_root_ops.extend(iter);
+    self
+  }
+
+  /// Only learn library functions whose bodies are rooted at `op` or at
+  /// another anchor operation, such as a domain's `map`. Pairs of e-classes
+  /// which contain no anchor operation in common are not anti-unified at
+  /// all, except as part of a pair which does. By default, there are no
+  /// anchors, and library functions may be rooted at any operation.
+  #[must_use]
+  pub fn anchor_op(mut self, op: Op) -> Self {
+    self.anchor_ops.push(op);
+    self
+  }
+
+  /// Only learn library functions whose bodies are rooted at one of the
+  /// anchor operations, which include those in `iter`; see
+  /// [`anchor_op`](Self::anchor_op).
+  #[must_use]
+  pub fn anchor_ops(mut self, iter: impl IntoIterator<Item = Op>) -> Self {
+    self.anchor_ops.extend(iter);
+    self
+  }
+
   #[must_use]
   pub fn with_roots(mut self, roots: Vec<Id>) -> Self {
     self.roots = roots;
@@ -181,6 +315,119 @@ where
     self
   }
 
+  /// Number the learned library functions starting from `offset`, so that
+  /// they don't clash with library functions already present in the egraph.
+  #[must_use]
+  pub fn lib_offset(mut self, offset: usize) -> Self {
+    self.lib_offset = offset;
+    self
+  }
+
+  /// Also learn recursive library functions, defined as fixpoints, from
+  /// anti-unifications which occur nested within themselves. They are found
+  /// when the library is [deduplicated](LearnedLibrary::deduplicate).
+  #[must_use]
+  pub fn learn_recursive(mut self, recursive: bool) -> Self {
+    self.learn_recursive = recursive;
+    self
+  }
+
+  /// Bind subterms which repeat within the body of a learned library
+  /// function to locals, so that the function's definition, and hence its
+  /// cost, reflects the sharing.
+  #[must_use]
+  pub fn learn_lets(mut self, lets: bool) -> Self {
+    self.learn_lets = lets;
+    self
+  }
+
+  /// Also learn library functions which take functions as arguments, by
+  /// letting anti-unification abstract over a pair of different lambdas as
+  /// a whole instead of only over the parts of their bodies which differ.
+  /// For example, `(map (lambda (+ $0 1)) xs)` and `(map (lambda (* $0 2))
+  /// ys)` can then be abstracted into `(map ?f ?xs)`.
+  #[must_use]
+  pub fn learn_higher_order(mut self, higher_order: bool) -> Self {
+    self.learn_higher_order = higher_order;
+    self
+  }
+
+  /// Reuse the anti-unifications found by `previous`, a library learned
+  /// with the same settings from an earlier e-graph, for every pair of
+  /// e-classes which represents the same terms in both e-graphs. Only the
+  /// remaining pairs are anti-unified from scratch.
+  ///
+  /// Co-occurrence is only rechecked for the reused pairs themselves, so
+  /// reused anti-unifications may include some whose subterms no longer
+  /// co-occur.
+  #[must_use]
+  pub fn with_previous(
+    mut self,
+    previous: LearnedLibrary<Op, (Id, Id)>,
+  ) -> Self {
+    self.previous = Some(previous);
+    self
+  }
+
+  /// Stop anti-unifying once `limit` has passed, keeping the library
+  /// functions found so far. By default, there is no time limit.
+  #[must_use]
+  pub fn time_limit(mut self, limit: Duration) -> Self {
+    self.time_limit = Some(limit);
+    self
+  }
+
+  /// Learn library functions in rounds of increasing maximum arity, starting
+  /// at 1. Each round raises the maximum arity by one, but only keeps the new
+  /// library functions which generalize a function of the previous round
+  /// that compresses the e-graph. The rounds stop at the
+  /// [`max_arity`](Self::max_arity), once a round finds nothing which
+  /// compresses, or once `budget` has passed.
+  ///
+  /// The rounds don't reuse the anti-unifications of a library passed to
+  /// [`with_previous`](Self::with_previous).
+  #[must_use]
+  pub fn deepen_arity(mut self, budget: Duration) -> Self {
+    self.deepen_arity = Some(budget);
+    self
+  }
+
+  /// Only anti-unify the `budget` pairs of e-classes whose anti-unifications
+  /// are estimated to match most often, instead of every pair. This trades
+  /// completeness for a tractable runtime on very large e-graphs.
+  ///
+  /// A pair is estimated to match more often the more pairs of e-nodes with
+  /// the same operation its e-classes contain, and the more e-nodes refer to
+  /// either e-class. Pairs which can't co-occur are never chosen. The
+  /// descendants of a chosen pair are still anti-unified as needed, whether
+  /// or not they are chosen themselves. By default, there is no budget.
+  #[must_use]
+  pub fn candidate_budget(mut self, budget: Option<usize>) -> Self {
+    self.candidate_budget = budget;
+    self
+  }
+
+  /// Also abstract over each differing argument of the operations for which
+  /// `is_control_flow` returns `true` as a whole, in addition to over the
+  /// parts of the arguments which differ. By default, no operations are
+  /// treated as control flow.
+  #[must_use]
+  pub fn split_control_flow(
+    mut self,
+    is_control_flow: Option<fn(&Op) -> bool>,
+  ) -> Self {
+    self.control_flow = is_control_flow;
+    self
+  }
+
+  /// Report the progress of co-occurrence analysis and anti-unification to
+  /// `progress`. By default, progress is only logged.
+  #[must_use]
+  pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+    self.progress = Some(progress);
+    self
+  }
+
   pub fn build<A>(
     self,
     egraph: &EGraph<AstNode<Op>, A>,
@@ -189,23 +436,80 @@ where
     A: Analysis<AstNode<Op>> + Clone,
     AstNode<Op>: Language,
   {
+    let deadline = self.time_limit.and_then(clock::deadline);
+    let progress = self.progress.as_deref().unwrap_or(&NoProgress);
     let roots = &self.roots;
     debug!("Computing co-occurences");
     let co_occurs = self.co_occurences.unwrap_or_else(|| {
+      let start = clock::now();
+      progress.phase_started(Phase::CoOccurrence);
       let co_ext = COBuilder::new(egraph, roots);
-      co_ext.run()
+      let co_occurs = co_ext.run();
+      progress.phase_finished(Phase::CoOccurrence, clock::elapsed(start));
+      co_occurs
     });
 
     debug!("Constructing learned libraries");
-    LearnedLibrary::new(
-      egraph,
-      self.learn_trivial,
-      self.learn_constants,
-      self.max_arity,
-      self.banned_ops,
-      co_occurs,
-      self.dfta,
-    )
+    let start = clock::now();
+    progress.phase_started(Phase::AntiUnification);
+    let learn = |max_arity, previous| {
+      LearnedLibrary::new(
+        egraph,
+        self.learn_trivial,
+        self.learn_constants,
+        max_arity,
+        self.min_size,
+        self.max_size,
+        self.banned_ops.clone(),
+        RootOps::new(&self.excluded_root_ops, &self.anchor_ops),
+        co_occurs.clone(),
+        self.dfta,
+        self.type_check,
+        self.control_flow,
+        self.lib_offset,
+        self.learn_recursive,
+        self.learn_lets,
+        self.learn_higher_order,
+        previous,
+        deadline,
+        self.candidate_budget,
+        progress,
+      )
+    };
+    let mut learned_lib = match self.deepen_arity {
+      Some(budget) => LearnedLibrary::deepen_arity(
+        egraph,
+        self.max_arity,
+        budget,
+        |max_arity| learn(Some(max_arity), None),
+      ),
+      None => learn(self.max_arity, self.previous),
+    };
+    progress.phase_finished(Phase::AntiUnification, clock::elapsed(start));
+    learned_lib
+  }
+}
+
+impl<Op: Typeable> LearnedLibraryBuilder<Op> {
+  /// Only learn library functions which are well-typed according to the
+  /// [`Typeable`] implementation of `Op`.
+  #[must_use]
+  pub fn well_typed(mut self, well_typed: bool) -> Self {
+    self.type_check = if well_typed { Some(Op::well_typed) } else { None };
+    self
+  }
+}
+
+impl<Op: ControlFlow> LearnedLibraryBuilder<Op> {
+  /// Also abstract over each differing argument of a control-flow operation
+  /// as a whole, according to the [`ControlFlow`] implementation of `Op`, so
+  /// that conditions, branches, and loop bodies become parameters of library
+  /// functions. See [`split_control_flow`](Self::split_control_flow).
+  #[must_use]
+  pub fn learn_control_flow(mut self, control_flow: bool) -> Self {
+    self.control_flow =
+      if control_flow { Some(Op::is_control_flow) } else { None };
+    self
   }
 }
 
@@ -224,18 +528,56 @@ pub struct LearnedLibrary<Op, T> {
   aus_by_state: BTreeMap<T, BTreeSet<PartialExpr<Op, T>>>,
   /// A set of all the antiunifications discovered.
   aus: BTreeSet<PartialExpr<Op, Var>>,
+  /// The normalized antiunifications found so far while enumerating, which
+  /// become `aus` once enumeration is done.
+  table: PatternTable<Op>,
   /// Whether to learn "trivial" anti-unifications.
   learn_trivial: bool,
   /// Whether to also learn "library functions" which take no arguments.
   learn_constants: bool,
   /// Maximum arity of functions to learn.
   max_arity: Option<usize>,
+  /// Minimum number of nodes in the body of functions to learn.
+  min_size: Option<usize>,
+  /// Maximum number of nodes in the body of functions to learn.
+  max_size: Option<usize>,
   /// Operations that must never appear in learned abstractions.
   banned_ops: Vec<Op>,
+  /// The operations at which learned abstractions may be rooted.
+  root_ops: RootOps<Op>,
   /// Data about which e-classes can co-occur.
   co_occurrences: CoOccurrences,
+  /// If set, anti-unifications for which this returns `false` are discarded.
+  type_check: Option<fn(&PartialExpr<Op, Var>) -> bool>,
+  /// If set, the differing arguments of operations for which this returns
+  /// `true` are also abstracted over as a whole.
+  control_flow: Option<fn(&Op) -> bool>,
+  /// The [`LibId`] of the first learned library function.
+  lib_offset: usize,
+  /// Whether to learn recursive library functions.
+  learn_recursive: bool,
+  /// Whether to bind repeated subterms of library functions to locals.
+  learn_lets: bool,
+  /// Whether anti-unification may abstract over whole lambdas.
+  learn_higher_order: bool,
+  /// The anti-unifications which occur nested within themselves, along with
+  /// the variable at which they do.
+  recursive: BTreeMap<PartialExpr<Op, Var>, Var>,
+  /// The fingerprint of each e-class in the e-graph the library was learned
+  /// from; see [`fingerprints`].
+  fingerprints: BTreeMap<Id, u64>,
+  /// Anti-unifications of DFTA states carried over from a previous library,
+  /// which have not been enumerated yet.
+  reusable: BTreeMap<T, BTreeSet<PartialExpr<Op, T>>>,
+  /// Whether anti-unification stopped early because it ran out of time.
+  timed_out: bool,
+  /// The e-classes at which each anti-unification matched in the e-graph
+  /// the library was last deduplicated against; see
+  /// [`LearnedLibrary::match_sites`].
+  match_sites: BTreeMap<PartialExpr<Op, Var>, BTreeSet<Id>>,
 }
 
+#[cfg(feature = "std")]
 #[allow(unused)]
 fn mem_usage_of<F, X, L>(label: L, f: F) -> (X, usize)
 where
@@ -259,30 +601,66 @@ where
     + Send
     + Display
     + DiscriminantEq
+    + Teachable
     + std::hash::Hash
     + 'static,
   AstNode<Op>: Language,
 {
   /// Constructs a [`LearnedLibrary`] from an [`EGraph`] by antiunifying pairs of
   /// enodes to find their common structure.
+  #[allow(clippy::too_many_arguments)]
   fn new<A: Analysis<AstNode<Op>> + Clone>(
     egraph: &'a EGraph<AstNode<Op>, A>,
     learn_trivial: bool,
     learn_constants: bool,
     max_arity: Option<usize>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
     banned_ops: Vec<Op>,
+    root_ops: RootOps<Op>,
     co_occurrences: CoOccurrences,
     dfta: bool,
+    type_check: Option<fn(&PartialExpr<Op, Var>) -> bool>,
+    control_flow: Option<fn(&Op) -> bool>,
+    lib_offset: usize,
+    learn_recursive: bool,
+    learn_lets: bool,
+    learn_higher_order: bool,
+    previous: Option<Self>,
+    deadline: Option<Instant>,
+    candidate_budget: Option<usize>,
+    progress: &dyn ProgressSink,
   ) -> Self {
+    let fingerprints = fingerprints(egraph);
+    let reusable = previous
+      .map_or_else(BTreeMap::new, |previous| previous.translate(&fingerprints));
+    debug!("Reusing anti-unifications of {} states", reusable.len());
+
     let mut learned_lib = Self {
       aus_by_state: BTreeMap::new(),
       aus: BTreeSet::new(),
+      table: PatternTable::default(),
       learn_trivial,
       learn_constants,
       max_arity,
+      min_size,
+      max_size,
       banned_ops,
+      root_ops,
       co_occurrences,
+      type_check,
+      control_flow,
+      lib_offset,
+      learn_recursive,
+      learn_lets,
+      learn_higher_order,
+      recursive: BTreeMap::new(),
+      fingerprints,
+      reusable,
+      timed_out: false,
+      match_sites: BTreeMap::new(),
     };
+    let out_of_time = || clock::passed(deadline);
 
     if dfta {
       let dfta = Dfta::from(egraph);
@@ -290,7 +668,23 @@ where
       debug!("crossed over dfta");
 
       // for each e-class pair
-      for &state in dfta.output_states() {
+      let states = dfta
+        .output_states()
+        .copied()
+        .filter(|&state| learned_lib.root_ops.may_root(egraph, state));
+      let states: Vec<_> = match candidate_budget {
+        Some(budget) => {
+          sample_states(egraph, &learned_lib.co_occurrences, states, budget)
+        }
+        None => states.collect(),
+      };
+      let total = states.len();
+      for (done, state) in states.into_iter().enumerate() {
+        if out_of_time() {
+          learned_lib.timed_out = true;
+          break;
+        }
+        progress.candidates(Phase::AntiUnification, done, Some(total));
         learned_lib.enumerate_over_dfta(&dfta, state);
       }
     } else {
@@ -299,15 +693,124 @@ where
       let eclass_pairs = classes
         .iter()
         .cartesian_product(classes.iter())
-        .map(|(ecls1, ecls2)| (egraph.find(*ecls1), egraph.find(*ecls2)));
-
-      for (ecls1, ecls2) in eclass_pairs {
+        .map(|(ecls1, ecls2)| (egraph.find(*ecls1), egraph.find(*ecls2)))
+        .filter(|&state| learned_lib.root_ops.may_root(egraph, state));
+
+      let eclass_pairs: Vec<_> = match candidate_budget {
+        Some(budget) => sample_states(
+          egraph,
+          &learned_lib.co_occurrences,
+          eclass_pairs,
+          budget,
+        ),
+        None => eclass_pairs.collect(),
+      };
+      let total = eclass_pairs.len();
+      for (done, (ecls1, ecls2)) in eclass_pairs.into_iter().enumerate() {
+        if out_of_time() {
+          learned_lib.timed_out = true;
+          break;
+        }
+        progress.candidates(Phase::AntiUnification, done, Some(total));
         learned_lib.enumerate_over_egraph(egraph, (ecls1, ecls2));
       }
     }
+    learned_lib.reusable.clear();
+    learned_lib.aus = learned_lib.table.take_kept();
 
     learned_lib
   }
+
+  /// Learns a library in rounds of increasing maximum arity, calling `learn`
+  /// with each maximum arity; see [`LearnedLibraryBuilder::deepen_arity`].
+  fn deepen_arity<A, F>(
+    egraph: &EGraph<AstNode<Op>, A>,
+    max_arity: Option<usize>,
+    budget: Duration,
+    mut learn: F,
+  ) -> Self
+  where
+    A: Analysis<AstNode<Op>>,
+    F: FnMut(usize) -> Self,
+  {
+    let deadline = clock::deadline(budget);
+    let mut arity = 1;
+    let mut learned_lib = learn(arity);
+    let mut aus = learned_lib.aus.clone();
+    // The library functions of the current arity which compress.
+    let mut frontier: Vec<_> = aus
+      .iter()
+      .filter(|au| au.unique_holes().len() == arity && compresses(egraph, au))
+      .cloned()
+      .collect();
+
+    while !frontier.is_empty()
+      && max_arity.map_or(true, |max_arity| arity < max_arity)
+      && !learned_lib.timed_out
+      && !clock::passed(deadline)
+    {
+      arity += 1;
+      debug!(
+        "Raising the maximum arity to {arity} for {} patterns",
+        frontier.len()
+      );
+      learned_lib = learn(arity);
+      let deeper: Vec<_> = learned_lib
+        .aus
+        .iter()
+        .filter(|au| {
+          au.unique_holes().len() == arity
+            && frontier.iter().any(|lower| generalizes(au, lower))
+        })
+        .cloned()
+        .collect();
+      frontier =
+        deeper.iter().filter(|au| compresses(egraph, au)).cloned().collect();
+      aus.extend(deeper);
+    }
+
+    learned_lib.aus = aus;
+    learned_lib
+  }
+
+  /// Translates the anti-unifications of this library's DFTA states to the
+  /// e-graph whose e-classes have the given fingerprints, dropping those of
+  /// states which do not exist in it.
+  fn translate(
+    self,
+    fingerprints: &BTreeMap<Id, u64>,
+  ) -> BTreeMap<(Id, Id), BTreeSet<PartialExpr<Op, (Id, Id)>>> {
+    let classes: HashMap<u64, Id> = fingerprints
+      .iter()
+      .map(|(&id, &fingerprint)| (fingerprint, id))
+      .collect();
+    let translate_class =
+      |id: Id| self.fingerprints.get(&id).and_then(|f| classes.get(f)).copied();
+    let translate_state =
+      |(a, b): (Id, Id)| Some((translate_class(a)?, translate_class(b)?));
+
+    self
+      .aus_by_state
+      .into_iter()
+      // A state without anti-unifications was never enumerated, because its
+      // e-classes could not co-occur.
+      .filter(|(_, aus)| !aus.is_empty())
+      .filter_map(|(state, aus)| {
+        let state = translate_state(state)?;
+        let translatable = aus.iter().all(|au| {
+          au.unique_holes().into_iter().all(|&s| translate_state(s).is_some())
+        });
+        if !translatable {
+          return None;
+        }
+        let aus = aus
+          .into_iter()
+          .map(|au| au.fill(|s| PartialExpr::Hole(translate_state(s).unwrap())))
+          .collect();
+        Some((state, aus))
+      })
+      .collect()
+  }
 }
 
 impl<Op, T> LearnedLibrary<Op, T>
@@ -348,23 +851,87 @@ where
   pub fn rewrites<A: Analysis<AstNode<Op>>>(
     &self,
   ) -> impl Iterator<Item = Rewrite<AstNode<Op>, A>> + '_ {
-    self.aus.iter().enumerate().map(|(i, au)| {
-      let searcher: Pattern<_> = au.clone().into();
-      let applier: Pattern<_> = reify(LibId(i), au.clone()).into();
+    self.patterns().enumerate().map(|(i, (searcher, applier, recursive))| {
       let name = format!("anti-unify {i}");
       debug!("Found rewrite \"{name}\":\n{searcher} => {applier}");
+      lib_rewrite(name, searcher, applier, recursive)
+    })
+  }
 
-      // Both patterns contain the same variables, so this can never fail.
-      Rewrite::new(name, searcher, applier).unwrap_or_else(|_| unreachable!())
+  /// Left- and right-hand sides of library rewrites. For a recursive library
+  /// function, also returns the variable within whose binding the left-hand
+  /// side must match for the rewrite to apply; see [`lib_rewrite`].
+  ///
+  /// Library functions whose bodies refer to each other, directly or
+  /// indirectly, are bound as members of a group of mutually recursive
+  /// library functions.
+  pub fn patterns(&self) -> impl Iterator<Item = LibPatterns<Op>> + '_ {
+    let mutual = self.mutually_recursive();
+    self.aus.iter().enumerate().map(move |(i, au)| {
+      let searcher: Pattern<_> = au.clone().into();
+      let recursive = self.recursive.get(au).copied();
+      let lib = match recursive {
+        Some(var) => reify_recursive(self.lib_id(i), au.clone(), var),
+        None => reify(self.lib_id(i), au.clone()),
+      };
+      let lib = if self.learn_lets { bind_lib_locals(lib) } else { lib };
+      let lib =
+        if mutual.contains(&self.lib_id(i)) { bind_rec(lib) } else { lib };
+      (searcher, lib.into(), recursive)
     })
   }
 
+  /// The learned library functions which refer to themselves through the
+  /// bodies of learned library functions, including their own.
+  fn mutually_recursive(&self) -> BTreeSet<LibId> {
+    let refs: BTreeMap<LibId, BTreeSet<LibId>> = self
+      .aus
+      .iter()
+      .enumerate()
+      .map(|(i, au)| {
+        let mut libs = BTreeSet::new();
+        collect_lib_vars(au, &mut libs);
+        libs.retain(|&lib| self.lib_index(lib).is_some());
+        (self.lib_id(i), libs)
+      })
+      .filter(|(_, libs)| !libs.is_empty())
+      .collect();
+
+    refs
+      .keys()
+      .copied()
+      .filter(|&lib| {
+        let mut seen = BTreeSet::new();
+        let mut pending: Vec<LibId> = refs[&lib].iter().copied().collect();
+        while let Some(next) = pending.pop() {
+          if next == lib {
+            return true;
+          }
+          if seen.insert(next) {
+            pending.extend(refs.get(&next).into_iter().flatten().copied());
+          }
+        }
+        false
+      })
+      .collect()
+  }
+
   /// Right-hand sides of library rewrites.
   pub fn libs(&self) -> impl Iterator<Item = Pattern<AstNode<Op>>> + '_ {
-    self.aus.iter().enumerate().map(|(i, au)| {
-      let applier: Pattern<_> = reify(LibId(i), au.clone()).into();
-      applier
-    })
+    self.patterns().map(|(_, applier, _)| applier)
+  }
+
+  /// The [`LibId`] of the `i`th learned library function.
+  #[must_use]
+  pub fn lib_id(&self, i: usize) -> LibId {
+    LibId(self.lib_offset + i)
+  }
+
+  /// The index of the library function named `lib` among the learned
+  /// library functions, if it is one of them.
+  #[must_use]
+  pub fn lib_index(&self, lib: LibId) -> Option<usize> {
+    lib.0.checked_sub(self.lib_offset).filter(|&i| i < self.aus.len())
   }
 
   pub fn for_each_anti_unification<F>(&mut self, f: F)
@@ -374,6 +941,18 @@ where
     self.aus = self.aus.iter().map(f).collect();
   }
 
+  /// The e-classes at which each library rewrite's left-hand side matches,
+  /// in the same order as [`LearnedLibrary::patterns`]. These are recorded
+  /// by [`LearnedLibrary::deduplicate`], which searches the e-graph for
+  /// every pattern anyway, and are `None` for patterns added or changed
+  /// since. Passing them to [`apply_lib_rewrites_at`] along with the
+  /// rewrites avoids searching the whole e-graph again.
+  pub fn match_sites(&self) -> impl Iterator<Item = Option<Vec<Id>>> + '_ {
+    self.aus.iter().map(|au| {
+      self.match_sites.get(au).map(|sites| sites.iter().copied().collect())
+    })
+  }
+
   /// The raw anti-unifications that we have collected
   pub fn anti_unifications(
     &self,
@@ -395,6 +974,13 @@ where
     self.aus.len()
   }
 
+  /// Whether anti-unification ran out of time before every pair of e-classes
+  /// was anti-unified.
+  #[must_use]
+  pub fn timed_out(&self) -> bool {
+    self.timed_out
+  }
+
   /// If two candidate patterns (stored in `nontrivial_aus`) have the same set of matches,
   /// only preserve the smaller one of them.
   /// Here a match is a pair of the e-class where the match was found
@@ -406,19 +992,38 @@ where
   /// For example, after running a DSR (+ ?x ?y) => (+ ?y ?x),
   /// for any learned pattern containing (+ ?x0 ?x1), there will be an equivalent pattern containing (+ ?x1 ?x0),
   /// which will be eliminated here.
+  ///
+  /// Before searching the e-graph, alpha-equivalent patterns, which differ
+  /// only in the names or order of their variables, are collapsed into one
+  /// without searching for their matches.
   pub fn deduplicate<A: Analysis<AstNode<Op>>>(
     &mut self,
     egraph: &EGraph<AstNode<Op>, A>,
   ) {
+    // The anti-unifications found by `LearnedLibrary::new` are already
+    // normalized, but ones added by `extend` might not be.
+    let num_aus = self.aus.len();
+    self.aus = std::mem::take(&mut self.aus)
+      .into_iter()
+      .map(|au| normalize(au).0)
+      .collect();
+    debug!("Pruned {} alpha-equivalent patterns", num_aus - self.aus.len());
+
     // The algorithm is simply to iterate over all patterns,
     // and save their matches in a dictionary indexed by the match set.
     let mut cache: BTreeMap<Vec<Match>, PartialExpr<Op, Var>> = BTreeMap::new();
+    self.match_sites.clear();
     for au in &self.aus {
       let pattern: Pattern<_> = au.clone().into();
       // A key in `cache` is a set of matches
       // represented as a sorted vector.
       let mut key = vec![];
-      for m in pattern.search(egraph) {
+      let matches = pattern.search(egraph);
+      self.match_sites.insert(
+        au.clone(),
+        matches.iter().map(|m| egraph.find(m.eclass)).collect(),
+      );
+      for m in matches {
         for sub in m.substs {
           let actuals: Vec<_> =
             pattern.vars().iter().map(|v| sub[*v]).collect();
@@ -441,12 +1046,175 @@ where
       }
     }
     self.aus = cache.values().cloned().collect();
+    let aus = &self.aus;
+    self.match_sites.retain(|au, _| aus.contains(au));
+
+    if self.learn_recursive {
+      self.find_recursive(egraph);
+    }
+  }
+
+  /// Only keeps the patterns which match within every one of `corpora`,
+  /// each given by the roots of its programs in `egraph`, so that only
+  /// library functions common to all of the corpora are learned, rather than
+  /// library functions of their union.
+  pub fn retain_common<A: Analysis<AstNode<Op>>>(
+    &mut self,
+    egraph: &EGraph<AstNode<Op>, A>,
+    corpora: &[&[Id]],
+  ) {
+    let reachable: Vec<_> =
+      corpora.iter().map(|roots| reachable(egraph, roots)).collect();
+    let num_aus = self.aus.len();
+    self.aus.retain(|au| {
+      let pattern: Pattern<_> = au.clone().into();
+      let classes: HashSet<Id> = pattern
+        .search(egraph)
+        .into_iter()
+        .map(|m| egraph.find(m.eclass))
+        .collect();
+      reachable.iter().all(|corpus| !corpus.is_disjoint(&classes))
+    });
+    let aus = &self.aus;
+    self.recursive.retain(|au, _| aus.contains(au));
+    debug!(
+      "Pruned {} patterns which don't match in every corpus",
+      num_aus - self.aus.len()
+    );
+  }
+
+  /// Merges patterns which are equal modulo `rewrites`, such as patterns
+  /// which differ in the order of the arguments of a commutative operation
+  /// or by eta-expansion, keeping the smallest of each.
+  ///
+  /// Each pattern is instantiated by replacing its variables with distinct
+  /// constants, in every order if it has at most four variables. Two
+  /// patterns with the same number of variables are merged if their
+  /// instances are in the same e-classes after running `rewrites` on them
+  /// for `iter_limit` iterations, so the library functions they define are
+  /// equal up to the order of their arguments. Unlike
+  /// [`LearnedLibrary::deduplicate`], this doesn't depend on where the
+  /// patterns match in the e-graph they were learned from.
+  pub fn semantic_dedup<A: Analysis<AstNode<Op>>>(
+    &mut self,
+    rewrites: &[Rewrite<AstNode<Op>, A>],
+    analysis: A,
+    iter_limit: usize,
+  ) {
+    let mut runner =
+      Runner::<_, _, ()>::new(analysis).with_iter_limit(iter_limit);
+    let instances: Vec<_> = self
+      .aus
+      .iter()
+      .map(|au| {
+        let vars = Pattern::from(au.clone()).vars();
+        let orders = if vars.len() <= MAX_PERMUTED_VARS {
+          vars.iter().copied().permutations(vars.len()).collect()
+        } else {
+          vec![vars.clone()]
+        };
+        let ids: Vec<Id> = orders
+          .into_iter()
+          .map(|order| {
+            let instance = au.clone().fill(|var| -> PartialExpr<Op, Var> {
+              let index = order
+                .iter()
+                .position(|&v| v == var)
+                .unwrap_or_else(|| unreachable!());
+              PartialExpr::Node(Op::lib_var(LibId(usize::MAX - index)))
+            });
+            let instance: RecExpr<_> = Expr::try_from(instance)
+              .unwrap_or_else(|_| unreachable!())
+              .into();
+            runner.egraph.add_expr(&instance)
+          })
+          .collect();
+        (au, vars.len(), ids)
+      })
+      .collect();
+    runner.egraph.rebuild();
+    let runner = runner.run(rewrites);
+    debug!("Stopped semantic deduplication: {:?}", runner.stop_reason);
+
+    let mut classes: BTreeMap<(usize, Vec<Id>), &PartialExpr<Op, Var>> =
+      BTreeMap::new();
+    for (au, arity, ids) in instances {
+      let mut key: Vec<Id> =
+        ids.into_iter().map(|id| runner.egraph.find(id)).collect();
+      key.sort_unstable();
+      key.dedup();
+      match classes.get(&(arity, key.clone())) {
+        Some(&kept) if kept.size() <= au.size() => {
+          debug!(
+            "Pruning pattern {}\n as equivalent to {}",
+            Pattern::from(au.clone()),
+            Pattern::from(kept.clone())
+          );
+        }
+        _ => {
+          classes.insert((arity, key), au);
+        }
+      }
+    }
+    let aus: BTreeSet<_> = classes.into_values().cloned().collect();
+    debug!("Merged {} equivalent patterns", self.aus.len() - aus.len());
+    self.recursive.retain(|au, _| aus.contains(au));
+    self.aus = aus;
+  }
+
+  /// Finds the anti-unifications which occur nested within themselves: those
+  /// which match an e-class while binding one of their variables to an
+  /// e-class they also match, as `(f ?x)` does in `(f (f x))`. The e-class
+  /// may be the matched one itself, if the e-graph has a cycle.
+  ///
+  /// Only anti-unifications without binders are considered.
+  fn find_recursive<A: Analysis<AstNode<Op>>>(
+    &mut self,
+    egraph: &EGraph<AstNode<Op>, A>,
+  ) {
+    self.recursive.clear();
+    for au in &self.aus {
+      if !is_first_order(au) {
+        continue;
+      }
+      let pattern: Pattern<_> = au.clone().into();
+      let matches = pattern.search(egraph);
+      let sites: HashSet<Id> =
+        matches.iter().map(|m| egraph.find(m.eclass)).collect();
+      let var = matches.iter().find_map(|m| {
+        m.substs.iter().find_map(|subst| {
+          pattern
+            .vars()
+            .into_iter()
+            .find(|&var| sites.contains(&egraph.find(subst[var])))
+        })
+      });
+      if let Some(var) = var {
+        debug!("Found recursive pattern {pattern} in {var}");
+        self.recursive.insert(au.clone(), var);
+      }
+    }
+  }
+}
+
+impl<Op: Typeable, T> LearnedLibrary<Op, T> {
+  /// Returns the type signature of each learned library function, or
+  /// [`None`] if the function's signature could not be inferred.
+  pub fn lib_signatures(
+    &self,
+  ) -> impl Iterator<Item = (LibId, Option<TypeSignature<Op::Type>>)> + '_ {
+    self
+      .aus
+      .iter()
+      .enumerate()
+      .map(|(i, au)| (LibId(self.lib_offset + i), Op::type_signature(au)))
   }
 }
 
 impl<Op> LearnedLibrary<Op, (Id, Id)>
 where
-  Op: Arity + Clone + Debug + Ord + DiscriminantEq + std::hash::Hash,
+  Op:
+    Arity + Clone + Debug + Ord + DiscriminantEq + Teachable + std::hash::Hash,
 {
   /// Computes the antiunifications of `state` in the DFTA `dfta`.
   fn enumerate_over_dfta(
@@ -473,16 +1241,29 @@ where
       return;
     }
 
+    if let Some(aus) = self.reusable.remove(&state) {
+      // A previous library already enumerated this state.
+      self.filter_aus(aus, state);
+      return;
+    }
+
     let mut aus: BTreeSet<PartialExpr<Op, (Id, Id)>> = BTreeSet::new();
 
     let mut same = false;
-    let mut different = false;
+    // A variadic operation applied to lists of different lengths can only be
+    // abstracted over as a whole, making the list an argument.
+    // TODO: A hole standing for the remaining children of a variadic
+    // operation would keep their common prefix, but egg patterns have a fixed
+    // number of children per node, so it needs a searcher of its own.
+    let mut different = dfta.has_mismatched_arity(&state);
+    let mut functions = false;
 
     // if there is a rule that produces this state
     if let Some(rules) = dfta.get_by_output(&state) {
       for ((op1, op2), inputs) in rules {
         if op1 == op2 {
           same = true;
+          functions |= is_lambda(op1);
           if inputs.is_empty() {
             aus.insert(AstNode::leaf(op1.clone()).into());
           } else {
@@ -499,7 +1280,7 @@ where
             // `antiunifications_by_state[s1] × ... × antiunifications_by_state[sn]`
             let new_aus = inputs
               .iter()
-              .map(|input| self.aus_by_state[input].iter().cloned())
+              .map(|&input| self.input_aus(op1, input))
               .multi_cartesian_product()
               .map(|inputs| {
                 PartialExpr::from(AstNode::new(op1.clone(), inputs))
@@ -518,7 +1299,11 @@ where
       }
     }
 
-    if same && different {
+    // A pair of different lambdas may be abstracted over as a whole, making
+    // it an argument of the library function.
+    let higher_order =
+      self.learn_higher_order && functions && state.0 != state.1;
+    if (same && different) || higher_order {
       aus.insert(PartialExpr::Hole(state));
     }
 
@@ -545,16 +1330,26 @@ where
       return;
     }
 
+    if let Some(aus) = self.reusable.remove(&state) {
+      // A previous library already enumerated this state.
+      self.filter_aus(aus, state);
+      return;
+    }
+
     let mut aus: BTreeSet<PartialExpr<Op, (Id, Id)>> = BTreeSet::new();
 
     let mut same = false;
     let mut different = false;
+    let mut functions = false;
 
     let ops1 = egraph[state.0].nodes.iter().map(AstNode::as_parts);
     for (op1, args1) in ops1 {
       for (op2, args2) in egraph[state.1].nodes.iter().map(AstNode::as_parts) {
-        if op1 == op2 {
+        // A variadic operation applied to lists of different lengths can
+        // only be abstracted over as a whole, making the list an argument.
+        if op1 == op2 && args1.len() == args2.len() {
           same = true;
+          functions |= is_lambda(op1);
           if args1.is_empty() && args2.is_empty() {
             aus.insert(AstNode::leaf(op1.clone()).into());
           } else {
@@ -568,7 +1363,7 @@ where
 
             let new_aus = inputs
               .iter()
-              .map(|input| self.aus_by_state[input].iter().cloned())
+              .map(|&input| self.input_aus(op1, input))
               .multi_cartesian_product()
               .map(|inputs| {
                 PartialExpr::from(AstNode::new(op1.clone(), inputs))
@@ -587,18 +1382,42 @@ where
       }
     }
 
-    if same && different {
+    // A pair of different lambdas may be abstracted over as a whole, making
+    // it an argument of the library function.
+    let higher_order =
+      self.learn_higher_order && functions && state.0 != state.1;
+    if (same && different) || higher_order {
       aus.insert(PartialExpr::Hole(state));
     }
 
     self.filter_aus(aus, state);
   }
 
+  /// The anti-unifications of `input`, an argument of `op`. If `op` is a
+  /// control-flow operation and the e-classes of `input` differ, these
+  /// include abstracting over the argument as a whole.
+  fn input_aus(
+    &self,
+    op: &Op,
+    input: (Id, Id),
+  ) -> impl Iterator<Item = PartialExpr<Op, (Id, Id)>> + Clone + '_ {
+    let whole = self
+      .control_flow
+      .filter(|is_control_flow| input.0 != input.1 && is_control_flow(op))
+      .map(|_| PartialExpr::Hole(input));
+    self.aus_by_state[&input].iter().cloned().chain(whole)
+  }
+
   fn filter_aus(
     &mut self,
     mut aus: BTreeSet<PartialExpr<Op, (Id, Id)>>,
     state: (Id, Id),
   ) {
+    // Anti-unifications containing one which is too large are too large
+    // themselves, so there is no need to keep them around.
+    if let Some(max_size) = self.max_size {
+      aus.retain(|au| au.num_nodes() <= max_size);
+    }
     if aus.is_empty() {
       aus.insert(PartialExpr::Hole(state));
     } else {
@@ -611,14 +1430,20 @@ where
 
       let learn_constants = self.learn_constants;
       let learn_trivial = self.learn_trivial;
+      let min_size = self.min_size.unwrap_or(0);
       let banned_ops = &self.banned_ops;
+      let root_ops = &self.root_ops;
+      let type_check = self.type_check;
 
-      let nontrivial_aus = aus
+      let candidates = aus
         .iter()
         .filter(|au| learn_constants || au.has_holes())
-        .cloned()
-        .map(normalize)
-        .filter_map(|(au, num_vars)| {
+        .filter(|au| au.num_nodes() >= min_size);
+      for au in candidates {
+        // Most anti-unifications are derived again from many other states.
+        // Those are recognized without normalizing them, and whether to keep
+        // them was decided the first time.
+        self.table.intern(au, |au, num_vars| {
           // Here we filter out rewrites that don't actually simplify
           // anything. We say that an AU rewrite simplifies an
           // expression if it replaces that expression with a function
@@ -643,23 +1468,23 @@ where
           // if size(e[x_1/e_1, ..., x_n/e_n]) > 2n + 1. This
           // corresponds to an anti-unification containing at least n
           // + 1 nodes.
-          if learn_trivial
+          let simplifies = learn_trivial
             || num_vars < au.num_holes()
-            || au.num_nodes() > num_vars + 1
-          {
-            Some(au)
-          } else {
-            None
-          }
-        })
-        .filter(|au| match au {
-          PartialExpr::Node(ast_node) => !banned_ops
-            .iter()
-            .any(|op| ast_node.operation().discriminant_eq(op)),
-          PartialExpr::Hole(_) => true,
+            || au.num_nodes() > num_vars + 1;
+          let allowed = match au {
+            PartialExpr::Node(ast_node) => {
+              !banned_ops
+                .iter()
+                .any(|op| ast_node.operation().discriminant_eq(op))
+                && root_ops.allows(ast_node.operation())
+            }
+            PartialExpr::Hole(_) => true,
+          };
+          simplifies
+            && allowed
+            && type_check.map_or(true, |well_typed| well_typed(au))
         });
-
-      self.aus.extend(nontrivial_aus);
+      }
     }
 
     if aus.len() > 10_000 {
@@ -673,6 +1498,186 @@ where
   }
 }
 
+/// The operations at which learned library functions may be rooted; see
+/// [`LearnedLibraryBuilder::exclude_root_op`] and
+/// [`LearnedLibraryBuilder::anchor_op`].
+#[derive(Debug, Clone)]
+struct RootOps<Op> {
+  /// Operations at which no library function may be rooted.
+  excluded: Vec<Op>,
+  /// If not empty, the only operations at which library functions may be
+  /// rooted.
+  anchors: Vec<Op>,
+}
+
+impl<Op: Clone + DiscriminantEq> RootOps<Op> {
+  fn new(excluded: &[Op], anchors: &[Op]) -> Self {
+    Self { excluded: excluded.to_vec(), anchors: anchors.to_vec() }
+  }
+
+  /// Whether library functions may be rooted at `op`.
+  fn allows(&self, op: &Op) -> bool {
+    !self.excluded.iter().any(|excluded| op.discriminant_eq(excluded))
+      && (self.anchors.is_empty()
+        || self.anchors.iter().any(|anchor| op.discriminant_eq(anchor)))
+  }
+
+  /// Whether anti-unifying the e-classes of `state` can produce a library
+  /// function with an allowed root, which takes an operation the e-classes
+  /// have in common.
+  fn may_root<A>(
+    &self,
+    egraph: &EGraph<AstNode<Op>, A>,
+    state: (Id, Id),
+  ) -> bool
+  where
+    Op: Eq,
+    A: Analysis<AstNode<Op>>,
+    AstNode<Op>: Language,
+  {
+    if self.excluded.is_empty() && self.anchors.is_empty() {
+      return true;
+    }
+    egraph[state.0].iter().any(|node1| {
+      let op = node1.operation();
+      self.allows(op)
+        && egraph[state.1].iter().any(|node2| node2.operation() == op)
+    })
+  }
+}
+
+/// The e-classes reachable from `roots` in `egraph`, including the roots.
+fn reachable<Op, A>(
+  egraph: &EGraph<AstNode<Op>, A>,
+  roots: &[Id],
+) -> HashSet<Id>
+where
+  A: Analysis<AstNode<Op>>,
+  AstNode<Op>: Language,
+{
+  let mut reachable = HashSet::new();
+  let mut stack: Vec<Id> =
+    roots.iter().map(|&root| egraph.find(root)).collect();
+  while let Some(id) = stack.pop() {
+    if reachable.insert(id) {
+      for node in egraph[id].iter() {
+        stack.extend(node.children().iter().map(|&child| egraph.find(child)));
+      }
+    }
+  }
+  reachable
+}
+
+/// Returns the `budget` pairs of e-classes among `states` whose
+/// anti-unifications are estimated to match most often; see
+/// [`LearnedLibraryBuilder::candidate_budget`]. The estimate for a pair is the
+/// number of pairs of its e-nodes with the same operation and number of
+/// arguments, times the number of e-nodes which refer to either e-class.
+fn sample_states<Op, A>(
+  egraph: &EGraph<AstNode<Op>, A>,
+  co_occurrences: &CoOccurrences,
+  states: impl Iterator<Item = (Id, Id)>,
+  budget: usize,
+) -> Vec<(Id, Id)>
+where
+  Op: Eq,
+  A: Analysis<AstNode<Op>>,
+  AstNode<Op>: Language,
+{
+  let mut uses: HashMap<Id, usize> = HashMap::new();
+  for class in egraph.classes() {
+    for node in &class.nodes {
+      for &child in node.children() {
+        *uses.entry(egraph.find(child)).or_default() += 1;
+      }
+    }
+  }
+  let uses_of = |id: Id| uses.get(&id).copied().unwrap_or(0);
+
+  let mut scored: Vec<_> = states
+    .filter(|&(a, b)| co_occurrences.may_co_occur(a, b))
+    .map(|(a, b)| {
+      let matching = egraph[a]
+        .nodes
+        .iter()
+        .cartesian_product(&egraph[b].nodes)
+        .filter(|(node1, node2)| {
+          node1.operation() == node2.operation()
+            && node1.args().len() == node2.args().len()
+        })
+        .count();
+      let score = matching.saturating_mul(1 + uses_of(a) + uses_of(b));
+      (score, (a, b))
+    })
+    .collect();
+  scored.sort_unstable_by(|(score1, state1), (score2, state2)| {
+    score2.cmp(score1).then(state1.cmp(state2))
+  });
+  scored.dedup_by_key(|(_, state)| *state);
+  scored.truncate(budget);
+  debug!("Sampled {} pairs of e-classes", scored.len());
+  scored.into_iter().map(|(_, state)| state).collect()
+}
+
+/// Computes a fingerprint of each e-class in `egraph` which depends only on
+/// the terms the e-class represents, not on its [`Id`], so that the same
+/// e-class can be recognized in a different e-graph. E-classes which are part
+/// of or reach a cycle have no fingerprint.
+fn fingerprints<Op, A>(egraph: &EGraph<AstNode<Op>, A>) -> BTreeMap<Id, u64>
+where
+  Op: Hash,
+  A: Analysis<AstNode<Op>>,
+  AstNode<Op>: Language,
+{
+  let mut fingerprints = HashMap::new();
+  for class in egraph.classes() {
+    fingerprint(egraph, class.id, &mut fingerprints);
+  }
+  fingerprints
+    .into_iter()
+    .filter_map(|(id, fingerprint)| Some((id, fingerprint?)))
+    .collect()
+}
+
+/// Computes the fingerprint of the e-class `id`, memoizing the fingerprints
+/// of it and its descendants in `fingerprints`.
+fn fingerprint<Op, A>(
+  egraph: &EGraph<AstNode<Op>, A>,
+  id: Id,
+  fingerprints: &mut HashMap<Id, Option<u64>>,
+) -> Option<u64>
+where
+  Op: Hash,
+  A: Analysis<AstNode<Op>>,
+  AstNode<Op>: Language,
+{
+  let id = egraph.find(id);
+  if let Some(&fingerprint) = fingerprints.get(&id) {
+    return fingerprint;
+  }
+  // Until the fingerprint is known, any cycle back to this e-class finds
+  // that it has none.
+  fingerprints.insert(id, None);
+
+  let mut node_hashes = Vec::new();
+  for node in egraph[id].iter() {
+    let (op, args) = node.as_parts();
+    let mut hasher = DefaultHasher::new();
+    op.hash(&mut hasher);
+    for &arg in args {
+      fingerprint(egraph, arg, fingerprints)?.hash(&mut hasher);
+    }
+    node_hashes.push(hasher.finish());
+  }
+  node_hashes.sort_unstable();
+
+  let mut hasher = DefaultHasher::new();
+  node_hashes.hash(&mut hasher);
+  let fingerprint = hasher.finish();
+  fingerprints.insert(id, Some(fingerprint));
+  Some(fingerprint)
+}
+
 /// Replaces the metavariables in an anti-unification with pattern variables.
 /// Normalizing alpha-equivalent anti-unifications produces identical
 /// anti-unifications. Returns a pair of the anti-unification and the number of
@@ -698,6 +1703,132 @@ fn normalize<Op, T: Eq>(
   (normalized, metavars.len())
 }
 
+/// Anti-unifications hash-consed up to alpha-equivalence, so that one which
+/// is derived from many pairs of e-classes is normalized and filtered once.
+#[derive(Debug, Clone)]
+struct PatternTable<Op> {
+  /// The normalized anti-unifications, and whether each is kept.
+  patterns: Vec<(PartialExpr<Op, Var>, bool)>,
+  /// The indices into `patterns` of the anti-unifications with each
+  /// [`canonical_hash`].
+  by_hash: HashMap<u64, Vec<usize>>,
+  /// The number of anti-unifications which were already in the table.
+  hits: usize,
+}
+
+impl<Op> Default for PatternTable<Op> {
+  fn default() -> Self {
+    Self { patterns: Vec::new(), by_hash: HashMap::new(), hits: 0 }
+  }
+}
+
+impl<Op: Clone + Hash + Eq + Teachable> PatternTable<Op> {
+  /// Adds `au` to the table, unless an alpha-equivalent anti-unification is
+  /// already in it. A new anti-unification is normalized and kept if `keep`
+  /// returns `true` for it and its number of unique variables.
+  fn intern<T, F>(&mut self, au: &PartialExpr<Op, T>, keep: F)
+  where
+    T: Clone + Eq,
+    F: FnOnce(&PartialExpr<Op, Var>, usize) -> bool,
+  {
+    let indices = self.by_hash.entry(canonical_hash(au)).or_default();
+    if indices.iter().any(|&i| alpha_eq(au, &self.patterns[i].0)) {
+      self.hits += 1;
+      return;
+    }
+    let (normalized, num_vars) = normalize(au.clone());
+    let kept = keep(&normalized, num_vars);
+    indices.push(self.patterns.len());
+    self.patterns.push((normalized, kept));
+  }
+
+  /// Empties the table, returning the anti-unifications which are kept.
+  fn take_kept(&mut self) -> BTreeSet<PartialExpr<Op, Var>>
+  where
+    Op: Ord,
+  {
+    debug!(
+      "Merged {} duplicate anti-unifications while enumerating",
+      self.hits
+    );
+    let patterns = std::mem::take(self).patterns;
+    patterns.into_iter().filter_map(|(au, kept)| kept.then_some(au)).collect()
+  }
+}
+
+/// A hash of `au` which is the same for alpha-equivalent anti-unifications:
+/// each hole is hashed as the position of its first occurrence.
+fn canonical_hash<Op: Hash, T: Eq>(au: &PartialExpr<Op, T>) -> u64 {
+  fn go<'a, Op: Hash, T: Eq>(
+    au: &'a PartialExpr<Op, T>,
+    holes: &mut Vec<&'a T>,
+    hasher: &mut DefaultHasher,
+  ) {
+    match au {
+      PartialExpr::Node(node) => {
+        let (op, args) = node.as_parts();
+        0_u8.hash(hasher);
+        op.hash(hasher);
+        args.len().hash(hasher);
+        for arg in args {
+          go(arg, holes, hasher);
+        }
+      }
+      PartialExpr::Hole(hole) => {
+        let index = holes.iter().position(|&other| other == hole);
+        let index = index.unwrap_or_else(|| {
+          holes.push(hole);
+          holes.len() - 1
+        });
+        1_u8.hash(hasher);
+        index.hash(hasher);
+      }
+    }
+  }
+
+  let mut hasher = DefaultHasher::new();
+  go(au, &mut Vec::new(), &mut hasher);
+  hasher.finish()
+}
+
+/// Whether `au` is alpha-equivalent to `pattern`, i.e., whether normalizing
+/// `au` would produce `pattern`, without normalizing it.
+fn alpha_eq<Op: Eq, T: Eq>(
+  au: &PartialExpr<Op, T>,
+  pattern: &PartialExpr<Op, Var>,
+) -> bool {
+  fn go<'a, Op: Eq, T: Eq>(
+    au: &'a PartialExpr<Op, T>,
+    pattern: &PartialExpr<Op, Var>,
+    renaming: &mut Vec<(&'a T, Var)>,
+  ) -> bool {
+    match (au, pattern) {
+      (PartialExpr::Node(node), PartialExpr::Node(other)) => {
+        node.operation() == other.operation()
+          && node.args().len() == other.args().len()
+          && node
+            .args()
+            .iter()
+            .zip(other.args())
+            .all(|(arg, other)| go(arg, other, renaming))
+      }
+      (PartialExpr::Hole(hole), PartialExpr::Hole(var)) => {
+        match renaming.iter().find(|(other, _)| *other == hole) {
+          Some((_, renamed)) => renamed == var,
+          None if renaming.iter().any(|(_, other)| other == var) => false,
+          None => {
+            renaming.push((hole, *var));
+            true
+          }
+        }
+      }
+      _ => false,
+    }
+  }
+
+  go(au, pattern, &mut Vec::new())
+}
+
 #[allow(dead_code)]
 fn patternize<Op>(au: &PartialExpr<Op, (Id, Id)>) -> Pattern<AstNode<Op>>
 where
@@ -713,6 +1844,60 @@ where
   .into()
 }
 
+/// Returns `true` if `op` is the operation of a lambda.
+fn is_lambda<Op: Teachable + PartialEq>(op: &Op) -> bool {
+  Op::lambda(()).operation() == op
+}
+
+/// Whether the library function abstracting `au` would make the programs in
+/// `egraph` smaller: every e-class it matches saves the nodes of `au`, less
+/// the function and its applications, and the function's definition costs
+/// the nodes of `au` and its lambdas once.
+fn compresses<Op, A>(
+  egraph: &EGraph<AstNode<Op>, A>,
+  au: &PartialExpr<Op, Var>,
+) -> bool
+where
+  Op: Clone,
+  AstNode<Op>: Language,
+  A: Analysis<AstNode<Op>>,
+{
+  let pattern: Pattern<_> = au.clone().into();
+  let uses = pattern.search(egraph).len();
+  let arity = au.unique_holes().len();
+  let size = au.num_nodes();
+  uses * size.saturating_sub(arity + 1) > size + arity
+}
+
+/// Whether `specific` is an instance of `general`, i.e. whether filling the
+/// holes of `general` can produce `specific`.
+fn generalizes<Op: PartialEq>(
+  general: &PartialExpr<Op, Var>,
+  specific: &PartialExpr<Op, Var>,
+) -> bool {
+  fn instantiate<'a, Op: PartialEq>(
+    general: &PartialExpr<Op, Var>,
+    specific: &'a PartialExpr<Op, Var>,
+    subst: &mut HashMap<Var, &'a PartialExpr<Op, Var>>,
+  ) -> bool {
+    match (general, specific) {
+      (PartialExpr::Hole(var), _) => {
+        *subst.entry(*var).or_insert(specific) == specific
+      }
+      (PartialExpr::Node(general), PartialExpr::Node(specific)) => {
+        general.operation() == specific.operation()
+          && general.args().len() == specific.args().len()
+          && general
+            .iter()
+            .zip(specific.iter())
+            .all(|(general, specific)| instantiate(general, specific, subst))
+      }
+      (PartialExpr::Node(_), PartialExpr::Hole(_)) => false,
+    }
+  }
+  instantiate(general, specific, &mut HashMap::new())
+}
+
 /// Converts an anti-unification into a partial expression which defines a new
 /// named function and applies it to the metavariables in the anti-unification.
 /// The new function reifies the anti-unification, replacing metavariables by
@@ -732,6 +1917,13 @@ where
 /// ```
 ///
 /// assuming `name` is "foo".
+///
+/// A metavariable under `k` binders becomes a function of the `k` variables
+/// they bind, and its argument is wrapped in `k` lambdas, so free variables of
+/// the argument keep referring to the same binders. This also holds when the
+/// metavariable stands for a whole lambda (see
+/// [`LearnedLibraryBuilder::learn_higher_order`]), which the library function
+/// then receives as a function argument.
 #[must_use]
 fn reify<Op, T>(ix: LibId, au: PartialExpr<Op, T>) -> PartialExpr<Op, T>
 where
@@ -808,3 +2000,391 @@ where
 
   PartialExpr::Node(BindingExpr::Lib(ix, fun, body).into())
 }
+
+/// Like [`reify`], but for an anti-unification `au` which occurs nested
+/// within itself at the variable `var`. The library function is defined as a
+/// fixpoint, which passes itself to its argument for `var`: that argument is
+/// a continuation, which computes the nested expression and may call the
+/// library function again to do so. Since the continuation decides when to
+/// stop, the library function also describes finitely nested expressions.
+///
+/// Where the rewrite applies, the continuation ignores the library function
+/// and returns the e-class bound to `var`, so that the rewrite doesn't
+/// depend on how deeply `au` is nested there.
+fn reify_recursive<Op>(
+  ix: LibId,
+  au: PartialExpr<Op, Var>,
+  var: Var,
+) -> PartialExpr<Op, Var>
+where
+  Op: Arity + Clone + Teachable,
+{
+  // Pass the library function, by name for now, to the continuation.
+  let au = au.fill(|hole| {
+    if hole == var {
+      Op::apply(PartialExpr::Hole(hole), Op::lib_var(ix).into()).into()
+    } else {
+      PartialExpr::Hole(hole)
+    }
+  });
+
+  let (fun, body) = match reify(ix, au) {
+    PartialExpr::Node(node) => match node.as_binding_expr() {
+      Some(BindingExpr::Lib(_, fun, body)) => (fun.clone(), body.clone()),
+      _ => unreachable!(),
+    },
+    PartialExpr::Hole(_) => unreachable!(),
+  };
+
+  // The continuation passed for `var` is `(λ (λ $1)) ?var`, the constant
+  // function returning `?var`. Applying a closed combinator, rather than
+  // wrapping `?var` in a lambda, leaves the free variables of `?var` alone.
+  let constant: PartialExpr<Op, Var> = PartialExpr::Node(Op::lambda(
+    PartialExpr::Node(Op::lambda(PartialExpr::Node(Op::var(1)))),
+  ));
+  let body = body.fill(|hole| {
+    if hole == var {
+      Op::apply(constant.clone(), PartialExpr::Hole(hole)).into()
+    } else {
+      PartialExpr::Hole(hole)
+    }
+  });
+
+  // Since `fun` is closed, a reference to the library function under `n`
+  // binders becomes a reference to the fixpoint, `$n`.
+  let fun =
+    fun.map_leaves_with_binders(|node, binders| match node.as_binding_expr() {
+      Some(BindingExpr::LibVar(lib)) if lib == ix => Op::var(binders).into(),
+      _ => node.into(),
+    });
+
+  PartialExpr::Node(Op::lib(ix, Op::fix(fun).into(), body))
+}
+
+/// Binds the library function introduced by `lib`, the result of [`reify`]
+/// or [`reify_recursive`], as a member of a group of mutually recursive
+/// library functions.
+fn bind_rec<Op>(lib: PartialExpr<Op, Var>) -> PartialExpr<Op, Var>
+where
+  Op: Clone + Teachable,
+{
+  match lib {
+    PartialExpr::Node(node) => match node.as_binding_expr() {
+      Some(BindingExpr::Lib(ix, fun, body)) => {
+        Op::lib_rec(ix, fun.clone(), body.clone()).into()
+      }
+      _ => unreachable!(),
+    },
+    PartialExpr::Hole(_) => unreachable!(),
+  }
+}
+
+/// Adds the library functions referred to in `au` to `libs`.
+fn collect_lib_vars<Op: Teachable, T>(
+  au: &PartialExpr<Op, T>,
+  libs: &mut BTreeSet<LibId>,
+) {
+  if let PartialExpr::Node(node) = au {
+    if let Some(BindingExpr::LibVar(lib)) = node.as_binding_expr() {
+      libs.insert(lib);
+    }
+    for child in node.iter() {
+      collect_lib_vars(child, libs);
+    }
+  }
+}
+
+/// Binds the subterms which repeat in the definition of the library function
+/// introduced by `lib`, the result of [`reify`] or [`reify_recursive`], to
+/// locals. A local is bound by a `let`, which is encoded as the application
+/// of a lambda to the local's value, `(apply (lambda body) value)`.
+fn bind_lib_locals<Op>(lib: PartialExpr<Op, Var>) -> PartialExpr<Op, Var>
+where
+  Op: Arity + Clone + Ord + Teachable,
+{
+  match lib {
+    PartialExpr::Node(node) => match node.as_binding_expr() {
+      Some(BindingExpr::Lib(ix, fun, body)) => {
+        Op::lib(ix, bind_locals(fun.clone()), body.clone()).into()
+      }
+      _ => unreachable!(),
+    },
+    PartialExpr::Hole(_) => unreachable!(),
+  }
+}
+
+/// Binds the subterms which repeat in the body of the function `fun` to
+/// locals, skipping over the lambdas and fixpoint binding its arguments.
+fn bind_locals<Op>(fun: PartialExpr<Op, Var>) -> PartialExpr<Op, Var>
+where
+  Op: Arity + Clone + Ord + Teachable,
+{
+  if let PartialExpr::Node(node) = &fun {
+    match node.as_binding_expr() {
+      Some(BindingExpr::Lambda(body)) => {
+        return Op::lambda(bind_locals(body.clone())).into();
+      }
+      Some(BindingExpr::Fix(body)) => {
+        return Op::fix(bind_locals(body.clone())).into();
+      }
+      _ => {}
+    }
+  }
+
+  // Sharing `k` copies of a subterm of size `n` replaces them with `k`
+  // variables, at the cost of one copy of the subterm, a lambda, and an
+  // application, so it saves `(k - 1) * (n - 1) - 3`.
+  let mut counts = BTreeMap::new();
+  count_subterms(&fun, &mut counts);
+  let shared = counts
+    .into_iter()
+    .map(|(subterm, count)| ((count - 1) * (subterm.size() - 1), subterm))
+    .filter(|&(savings, _)| savings > 3)
+    .max_by_key(|&(savings, _)| savings)
+    .map(|(_, subterm)| subterm.clone());
+
+  match shared {
+    Some(value) => {
+      let body = bind_local(fun, &value, 0);
+      Op::apply(Op::lambda(bind_locals(body)).into(), value).into()
+    }
+    None => fun,
+  }
+}
+
+/// Counts the occurrences of each subterm of `expr` which is not nested
+/// within a binder in `expr`.
+fn count_subterms<'a, Op, T>(
+  expr: &'a PartialExpr<Op, T>,
+  counts: &mut BTreeMap<&'a PartialExpr<Op, T>, usize>,
+) where
+  Op: Ord + Teachable,
+  T: Ord,
+{
+  if let PartialExpr::Node(node) = expr {
+    *counts.entry(expr).or_default() += 1;
+    if !matches!(
+      node.as_binding_expr(),
+      Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_))
+    ) {
+      for child in node.iter() {
+        count_subterms(child, counts);
+      }
+    }
+  }
+}
+
+/// Replaces the occurrences of `value` in `expr` which are not nested within
+/// a binder in `expr` with a new local, bound just outside of `expr`. `expr`
+/// is nested within `depth` binders, and its free variables are shifted to
+/// account for the new local.
+fn bind_local<Op>(
+  expr: PartialExpr<Op, Var>,
+  value: &PartialExpr<Op, Var>,
+  depth: usize,
+) -> PartialExpr<Op, Var>
+where
+  Op: Arity + Clone + Ord + Teachable,
+{
+  if depth == 0 && &expr == value {
+    return Op::var(0).into();
+  }
+  match expr {
+    PartialExpr::Node(node) => {
+      let (free_var, binds) = match node.as_binding_expr() {
+        Some(BindingExpr::Var(index)) if index.0 >= depth => {
+          (Some(index.0), false)
+        }
+        Some(BindingExpr::Lambda(_) | BindingExpr::Fix(_)) => (None, true),
+        _ => (None, false),
+      };
+      match free_var {
+        Some(index) => Op::var(index + 1).into(),
+        None => {
+          let depth = depth + usize::from(binds);
+          PartialExpr::Node(node.map(|child| bind_local(child, value, depth)))
+        }
+      }
+    }
+    PartialExpr::Hole(_) => expr,
+  }
+}
+
+/// Returns `true` if `au` contains no binders or bound variables.
+fn is_first_order<Op: Teachable, T>(au: &PartialExpr<Op, T>) -> bool {
+  match au {
+    PartialExpr::Node(node) => {
+      !matches!(
+        node.as_binding_expr(),
+        Some(
+          BindingExpr::Lambda(_) | BindingExpr::Var(_) | BindingExpr::Fix(_)
+        )
+      ) && node.iter().all(is_first_order)
+    }
+    PartialExpr::Hole(_) => true,
+  }
+}
+
+/// The left- and right-hand sides of the rewrite introducing a library
+/// function, and, for a recursive library function, the variable within
+/// whose binding the left-hand side occurs nested.
+pub type LibPatterns<Op> =
+  (Pattern<AstNode<Op>>, Pattern<AstNode<Op>>, Option<Var>);
+
+/// Builds the rewrite `searcher => applier` which introduces a library
+/// function. If `recursive` is `Some(var)`, the rewrite only applies where
+/// `searcher` also matches the e-class bound to `var`.
+///
+/// # Panics
+///
+/// Panics if `applier` contains variables which are not in `searcher`.
+pub fn lib_rewrite<Op, A>(
+  name: String,
+  searcher: Pattern<AstNode<Op>>,
+  applier: Pattern<AstNode<Op>>,
+  recursive: Option<Var>,
+) -> Rewrite<AstNode<Op>, A>
+where
+  Op: Ord + Debug + Clone + std::hash::Hash + Send + Sync + 'static,
+  A: Analysis<AstNode<Op>>,
+{
+  // Both patterns contain the same variables, so this can never fail.
+  match recursive {
+    Some(var) => {
+      let nested = searcher.clone();
+      let condition =
+        move |egraph: &mut EGraph<AstNode<Op>, A>, _: Id, subst: &Subst| {
+          let class = egraph.find(subst[var]);
+          nested.search_eclass(egraph, class).is_some()
+        };
+      Rewrite::new(name, searcher, ConditionalApplier { condition, applier })
+    }
+    None => Rewrite::new(name, searcher, applier),
+  }
+  .unwrap_or_else(|_| unreachable!())
+}
+
+/// Applies each of `rewrites` wherever it matches among the e-classes in the
+/// corresponding element of `sites`, then rebuilds `egraph`, returning the
+/// number of matches which changed it. Like one iteration of a [`Runner`],
+/// every rewrite is searched for before any is applied, but only the given
+/// e-classes are searched, such as the match sites recorded by
+/// [`LearnedLibrary::match_sites`], rather than the whole e-graph.
+///
+/// # Panics
+///
+/// Panics if `rewrites` and `sites` have different lengths.
+pub fn apply_lib_rewrites_at<Op, A>(
+  egraph: &mut EGraph<AstNode<Op>, A>,
+  rewrites: &[Rewrite<AstNode<Op>, A>],
+  sites: &[Vec<Id>],
+) -> usize
+where
+  Op: Ord + Debug + Clone + std::hash::Hash,
+  A: Analysis<AstNode<Op>>,
+{
+  assert_eq!(rewrites.len(), sites.len(), "one set of sites per rewrite");
+  let matches: Vec<_> = rewrites
+    .iter()
+    .zip(sites)
+    .map(|(rewrite, sites)| {
+      sites
+        .iter()
+        .filter_map(|&id| {
+          rewrite.searcher.search_eclass(egraph, egraph.find(id))
+        })
+        .collect::<Vec<_>>()
+    })
+    .collect();
+  let applied = rewrites
+    .iter()
+    .zip(&matches)
+    .map(|(rewrite, matches)| rewrite.apply(egraph, matches).len())
+    .sum();
+  egraph.rebuild();
+  applied
+}
+
+#[cfg(test)]
+mod tests {
+  use super::LearnedLibraryBuilder;
+  use crate::{round_trip::parse, simple_lang::SimpleOp, AstNode, PartialExpr};
+  use egg::{EGraph, Pattern, Searcher, Var};
+
+  fn pattern(input: &str) -> PartialExpr<SimpleOp, Var> {
+    input.parse::<Pattern<AstNode<SimpleOp>>>().unwrap().into()
+  }
+
+  #[test]
+  fn learns_fixpoints_from_finite_nesting() {
+    let expr = parse("(list (list (list x)))");
+    let mut egraph = EGraph::<AstNode<SimpleOp>, ()>::default();
+    let root = egraph.add_expr(&expr.into());
+    egraph.rebuild();
+
+    let mut learned_lib = LearnedLibraryBuilder::default()
+      .learn_trivial(true)
+      .learn_recursive(true)
+      .with_roots(vec![root])
+      .build(&egraph);
+    learned_lib.deduplicate(&egraph);
+    let fixpoints: Vec<_> = learned_lib
+      .patterns()
+      .filter(|(_, _, recursive)| recursive.is_some())
+      .map(|(_, applier, _)| applier.to_string())
+      .collect();
+    assert!(!fixpoints.is_empty());
+    assert!(fixpoints.iter().all(|lib| lib.contains("fix")));
+  }
+
+  #[test]
+  fn deduplicates_alpha_equivalent_extensions() {
+    let empty = EGraph::<AstNode<SimpleOp>, ()>::default();
+    let mut egraph = empty.clone();
+    egraph.add_expr(&parse("(list (@ (@ f a) b) (@ (@ f c) d))").into());
+    egraph.rebuild();
+
+    // Nothing is learned from the empty e-graph, so the library only has the
+    // anti-unifications it is extended with.
+    let mut learned_lib = LearnedLibraryBuilder::default().build(&empty);
+    learned_lib
+      .extend([pattern("(@ (@ f ?b) ?a)"), pattern("(@ (@ f ?a) ?b)")]);
+    learned_lib.deduplicate(&egraph);
+    // Both are collapsed into their normal form before searching, rather
+    // than one of them being kept for having the same matches.
+    let aus: Vec<_> = learned_lib.anti_unifications().cloned().collect();
+    assert_eq!(aus, [pattern("(@ (@ f ?x0) ?x1)")]);
+  }
+
+  #[test]
+  fn abstracts_lists_of_different_lengths() {
+    for dfta in [false, true] {
+      let mut egraph = EGraph::<AstNode<SimpleOp>, ()>::default();
+      let roots: Vec<_> = ["(@ sum (list 1 2))", "(@ sum (list 1 2 3))"]
+        .into_iter()
+        .map(|program| egraph.add_expr(&parse(program).into()))
+        .collect();
+      egraph.rebuild();
+
+      let learned_lib = LearnedLibraryBuilder::default()
+        .learn_trivial(true)
+        .with_dfta(dfta)
+        .with_roots(roots.clone())
+        .build(&egraph);
+      // Zipping the children of the lists would truncate the longer one,
+      // giving `(@ sum (list 1 2))`, which only matches the first program.
+      let libs: Vec<_> = learned_lib.libs().collect();
+      assert!(
+        libs.iter().all(|lib| !lib.to_string().contains("sum (list")),
+        "with dfta {dfta}: {libs:?}"
+      );
+      assert!(
+        libs.iter().any(|lib| lib.to_string().starts_with("(@ sum ?")
+          && roots
+            .iter()
+            .all(|&root| lib.search_eclass(&egraph, root).is_some())),
+        "with dfta {dfta}: {libs:?}"
+      );
+    }
+  }
+}
@@ -16,24 +16,47 @@
 #![allow(clippy::non_canonical_partial_ord_impl)]
 
 mod ast_node;
+mod clock;
 mod co_occurrence;
+mod compress;
+mod control_flow;
 mod dfta;
+mod error;
 pub mod extract;
 mod learn;
+pub mod progress;
+mod provenance;
+#[cfg(feature = "std")]
 pub mod rewrites;
+pub mod round_trip;
 pub mod sexp;
 pub mod simple_lang;
+pub mod string_lang;
 mod teachable;
+mod typing;
 pub mod util;
+pub mod web;
 
 pub use ast_node::{
-  combine_exprs, Arity, AstNode, Expr, PartialExpr, Precedence, Pretty,
-  Printable, Printer,
+  combine_exprs, Args, Arity, ArityError, AstNode, Closure, Eval, EvalError,
+  Expr, ExprRef, InternedCorpus, Interner, Interpreter, LibNames, NamedExpr,
+  ParseExprError, ParseNamedError, PartialExpr, Precedence, Pretty,
+  PrettyOptions, PrettyWith, Printable, Printer, Syntax, TextPosition,
+  UnboundVarError, UnknownOpError, Value, VarNames,
 };
-pub use co_occurrence::{COBuilder, CoOccurrences};
+pub use co_occurrence::{COBuilder, CoOccurrences, LibCoOccurrences};
+#[allow(deprecated)]
+pub use compress::{
+  compress, compress_frontiers, CompressionError, CompressionResult, Config,
+};
+pub use control_flow::ControlFlow;
+pub use error::Error;
 pub use learn::{
-  DiscriminantEq, LearnedLibrary, LearnedLibraryBuilder, LibId, ParseLibIdError,
+  apply_lib_rewrites_at, lib_rewrite, DiscriminantEq, LearnedLibrary,
+  LearnedLibraryBuilder, LibId, LibPatterns, ParseLibIdError,
 };
+pub use provenance::Provenance;
 pub use teachable::{
   BindingExpr, DeBruijnIndex, ParseDeBruijnIndexError, Teachable,
 };
+pub use typing::{TypeSignature, Typeable};
@@ -0,0 +1,187 @@
+//! Reporting the progress of library learning, for embedders which can't
+//! watch the log.
+
+use std::{fmt::Debug, time::Duration};
+
+/// A phase of library learning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Phase {
+  /// A round of library learning, out of `rounds`.
+  Round {
+    /// The index of the round, starting from 0.
+    round: usize,
+    /// The total number of rounds.
+    rounds: usize,
+  },
+  /// Running the domain-specific rewrites.
+  Saturation,
+  /// Computing which e-classes may co-occur.
+  CoOccurrence,
+  /// Anti-unifying pairs of e-classes to find candidate library functions.
+  AntiUnification,
+  /// Adding the candidate library functions to the e-graph and searching
+  /// for the best selection of them.
+  BeamSearch,
+  /// Extracting the programs rewritten with the selected library functions.
+  Extraction,
+}
+
+/// A receiver of progress reports from library learning. Every method does
+/// nothing by default.
+///
+/// Reports may come from several threads at once, if several experiments
+/// share a sink.
+pub trait ProgressSink: Debug + Send + Sync {
+  /// Called when `phase` starts.
+  fn phase_started(&self, phase: Phase) {
+    let _ = phase;
+  }
+
+  /// Called when `phase` finishes, `elapsed` after it started.
+  fn phase_finished(&self, phase: Phase, elapsed: Duration) {
+    let _ = (phase, elapsed);
+  }
+
+  /// Called with the size of the e-graph, in e-nodes and e-classes, when it
+  /// changes significantly during `phase`.
+  fn egraph_size(&self, phase: Phase, nodes: usize, classes: usize) {
+    let _ = (phase, nodes, classes);
+  }
+
+  /// Called as candidates are processed during `phase`: `done` of them have
+  /// been processed, out of `total` if it is known. Sinks can estimate the
+  /// time left in the phase from these reports.
+  fn candidates(&self, phase: Phase, done: usize, total: Option<usize>) {
+    let _ = (phase, done, total);
+  }
+}
+
+/// A [`ProgressSink`] which ignores every report.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+#[cfg(feature = "std")]
+pub use self::stderr::StderrProgress;
+
+#[cfg(feature = "std")]
+mod stderr {
+  use super::{Phase, ProgressSink};
+  use std::{
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+  };
+
+  /// The width of the progress bar, in characters.
+  const BAR_WIDTH: usize = 30;
+
+  /// The minimum time between redraws of the progress bar.
+  const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+  /// A [`ProgressSink`] which draws a progress bar for the current phase on
+  /// standard error, with an estimate of the time left in it.
+  #[derive(Debug, Default)]
+  pub struct StderrProgress {
+    state: Mutex<State>,
+  }
+
+  #[derive(Debug, Default)]
+  struct State {
+    /// The current phase and when it started.
+    phase: Option<(Phase, Instant)>,
+    /// When the progress bar was last drawn.
+    last_drawn: Option<Instant>,
+  }
+
+  impl StderrProgress {
+    /// Creates a progress bar.
+    #[must_use]
+    pub fn new() -> Self {
+      Self::default()
+    }
+  }
+
+  impl ProgressSink for StderrProgress {
+    fn phase_started(&self, phase: Phase) {
+      let mut state = self.state.lock().unwrap();
+      state.phase = Some((phase, Instant::now()));
+      state.last_drawn = None;
+      eprint!("\r\x1b[K{}...", describe(phase));
+    }
+
+    fn phase_finished(&self, phase: Phase, elapsed: Duration) {
+      let mut state = self.state.lock().unwrap();
+      state.phase = None;
+      eprintln!(
+        "\r\x1b[K{} done in {:.2}s",
+        describe(phase),
+        elapsed.as_secs_f64()
+      );
+    }
+
+    fn egraph_size(&self, phase: Phase, nodes: usize, classes: usize) {
+      let _state = self.state.lock().unwrap();
+      eprintln!(
+        "\r\x1b[K{}: e-graph has {nodes} e-nodes in {classes} e-classes",
+        describe(phase)
+      );
+    }
+
+    #[allow(
+      clippy::cast_precision_loss,
+      clippy::cast_possible_truncation,
+      clippy::cast_sign_loss
+    )]
+    fn candidates(&self, phase: Phase, done: usize, total: Option<usize>) {
+      let mut state = self.state.lock().unwrap();
+      let now = Instant::now();
+      if state.last_drawn.map_or(false, |last| now - last < REDRAW_INTERVAL) {
+        return;
+      }
+      state.last_drawn = Some(now);
+
+      let mut stderr = io::stderr().lock();
+      let _ = write!(stderr, "\r\x1b[K{}: ", describe(phase));
+      match total {
+        Some(total) if total > 0 => {
+          let fraction = (done as f64 / total as f64).min(1.0);
+          let filled = (fraction * BAR_WIDTH as f64) as usize;
+          let _ = write!(
+            stderr,
+            "[{}{}] {done}/{total}",
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled)
+          );
+          let started = match state.phase {
+            Some((current, started)) if current == phase => Some(started),
+            _ => None,
+          };
+          if let Some(started) = started.filter(|_| done > 0) {
+            let elapsed = started.elapsed().as_secs_f64();
+            let left = elapsed / fraction - elapsed;
+            let _ = write!(stderr, ", about {left:.0}s left");
+          }
+        }
+        _ => {
+          let _ = write!(stderr, "{done}");
+        }
+      }
+      let _ = stderr.flush();
+    }
+  }
+
+  /// A short description of `phase`.
+  fn describe(phase: Phase) -> String {
+    match phase {
+      Phase::Round { round, rounds } => format!("round {}/{rounds}", round + 1),
+      Phase::Saturation => "running DSRs".to_string(),
+      Phase::CoOccurrence => "co-occurrence analysis".to_string(),
+      Phase::AntiUnification => "anti-unification".to_string(),
+      Phase::BeamSearch => "beam search".to_string(),
+      Phase::Extraction => "extraction".to_string(),
+    }
+  }
+}
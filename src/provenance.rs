@@ -0,0 +1,68 @@
+//! Which of the input programs the e-classes of an e-graph come from.
+
+use crate::ast_node::AstNode;
+use egg::{Analysis, EGraph, Id, Language, Searcher};
+use std::collections::{BTreeSet, HashMap};
+
+/// A map from each e-class of an e-graph to the programs it is reachable
+/// from, given by their roots. An e-class reachable from a program is part
+/// of at least one of the expressions the e-graph represents for that
+/// program, so a pattern matching it may be used to rewrite that program.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+  /// The indices of the roots from which each canonical e-class is
+  /// reachable.
+  programs: HashMap<Id, BTreeSet<usize>>,
+}
+
+impl Provenance {
+  /// Computes the programs each e-class of `egraph` is reachable from, where
+  /// program `i` is rooted at `roots[i]`.
+  #[must_use]
+  pub fn new<Op, A>(egraph: &EGraph<AstNode<Op>, A>, roots: &[Id]) -> Self
+  where
+    A: Analysis<AstNode<Op>>,
+    AstNode<Op>: Language,
+  {
+    let mut programs: HashMap<Id, BTreeSet<usize>> = HashMap::new();
+    for (i, &root) in roots.iter().enumerate() {
+      let mut stack = vec![egraph.find(root)];
+      while let Some(id) = stack.pop() {
+        if programs.entry(id).or_default().insert(i) {
+          for node in egraph[id].iter() {
+            stack
+              .extend(node.children().iter().map(|&child| egraph.find(child)));
+          }
+        }
+      }
+    }
+    Self { programs }
+  }
+
+  /// The indices of the programs `class` is reachable from, which must be
+  /// canonical.
+  pub fn programs(&self, class: Id) -> impl Iterator<Item = usize> + '_ {
+    self.programs.get(&class).into_iter().flatten().copied()
+  }
+
+  /// The indices of the programs in which `searcher`, such as the pattern of
+  /// a library function, matches in `egraph`, the e-graph this was computed
+  /// from.
+  #[must_use]
+  pub fn of_matches<Op, A, S>(
+    &self,
+    egraph: &EGraph<AstNode<Op>, A>,
+    searcher: &S,
+  ) -> BTreeSet<usize>
+  where
+    A: Analysis<AstNode<Op>>,
+    AstNode<Op>: Language,
+    S: Searcher<AstNode<Op>, A> + ?Sized,
+  {
+    searcher
+      .search(egraph)
+      .into_iter()
+      .flat_map(|matches| self.programs(egraph.find(matches.eclass)))
+      .collect()
+  }
+}
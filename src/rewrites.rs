@@ -6,12 +6,51 @@
 //!
 //! ```text
 //! one_plus_one: (+ 1 1) => 2
-//! commutative: (+ ?x ?y) => (+ ?y ?x)
+//! commutative: (+ ?x ?y) <=> (+ ?y ?x)
+//! div_self: (/ ?x ?x) => 1 if distinct(?x, 0)
 //! ```
+//!
+//! A rule written with `<=>` is applied in both directions; the reverse rule
+//! is named after the forward one with `-rev` appended, like the rules defined
+//! by [`egg::rewrite!`].
+//!
+//! A rule may be followed by `if` and a comma-separated list of conditions,
+//! all of which must hold for the rule to apply:
+//!
+//! - `equal(?x, ?y)`: `?x` and `?y` are in the same e-class.
+//! - `distinct(?x, ?y)`: `?x` and `?y` are in different e-classes.
+//! - `arity(?x, n)`: the e-class of `?x` contains an e-node with `n`
+//!   children.
+//! - `not_free_in(?x, $n)`: no term in the e-class of `?x` refers to the de
+//!   Bruijn index `$n`, counted from where `?x` is, so `?x` doesn't depend on
+//!   the variable it would refer to.
+//!
+//! The arguments of `equal` and `distinct` may also be patterns, such as `0`
+//! above, which are added to the e-graph if they are not already in it. Text
+//! after the last ` if ` which starts like a condition, with a name followed
+//! by an opening parenthesis, must be a valid list of conditions; otherwise,
+//! it is part of the patterns.
+//!
+//! The [`validate`] module can be used to check that the rewrites in a file
+//! are sound, and the [`infer`] module can be used to propose rewrites for a
+//! domain from a corpus of its programs.
 
-use anyhow::anyhow;
-use egg::{Analysis, FromOp, Language, Pattern, Rewrite};
-use std::{error::Error, fs, io::ErrorKind, path::Path};
+use crate::{
+  ast_node::AstNode,
+  teachable::{BindingExpr, DeBruijnIndex, Teachable},
+};
+use anyhow::{anyhow, bail};
+use egg::{
+  Analysis, Condition, ConditionalApplier, EGraph, FromOp, Id, Language,
+  Pattern, Rewrite, Subst, Var,
+};
+use std::{
+  collections::HashSet, error::Error, fmt::Debug, fs, hash::Hash,
+  io::ErrorKind, path::Path,
+};
+
+pub mod infer;
+pub mod validate;
 
 /// Returns all the rewrites in the specified file.
 ///
@@ -19,12 +58,15 @@ use std::{error::Error, fs, io::ErrorKind, path::Path};
 /// This function will return an error if the file doesn't exist or can't be opened.
 ///
 /// It will also return an error if it could not parse the file.
-pub fn from_file<L, A, P>(path: P) -> anyhow::Result<Vec<Rewrite<L, A>>>
+pub fn from_file<Op, A, P>(
+  path: P,
+) -> anyhow::Result<Vec<Rewrite<AstNode<Op>, A>>>
 where
-  L: Language + FromOp + Sync + Send + 'static,
-  A: Analysis<L>,
+  Op: Teachable + Ord + Debug + Clone + Hash + Sync + Send + 'static,
+  AstNode<Op>: FromOp,
+  <AstNode<Op> as FromOp>::Error: Send + Sync + Error,
+  A: Analysis<AstNode<Op>>,
   P: AsRef<Path>,
-  L::Error: Send + Sync + Error,
 {
   let contents = fs::read_to_string(path)?;
   parse(&contents)
@@ -37,14 +79,15 @@ where
 /// This function will return an error if the file exists but can't be opened.
 ///
 /// It will also return an error if it could not parse the file.
-pub fn try_from_file<L, A, P>(
+pub fn try_from_file<Op, A, P>(
   path: P,
-) -> anyhow::Result<Option<Vec<Rewrite<L, A>>>>
+) -> anyhow::Result<Option<Vec<Rewrite<AstNode<Op>, A>>>>
 where
-  L: Language + FromOp + Sync + Send + 'static,
-  A: Analysis<L>,
+  Op: Teachable + Ord + Debug + Clone + Hash + Sync + Send + 'static,
+  AstNode<Op>: FromOp,
+  <AstNode<Op> as FromOp>::Error: Send + Sync + Error,
+  A: Analysis<AstNode<Op>>,
   P: AsRef<Path>,
-  L::Error: Send + Sync + Error,
 {
   Ok(match fs::read_to_string(path) {
     Ok(contents) => Some(parse(&contents)?),
@@ -59,11 +102,12 @@ where
 ///
 /// # Errors
 /// This function will return an error if the rewrites file is invalid.
-pub fn parse<L, A>(file: &str) -> anyhow::Result<Vec<Rewrite<L, A>>>
+pub fn parse<Op, A>(file: &str) -> anyhow::Result<Vec<Rewrite<AstNode<Op>, A>>>
 where
-  L: Language + FromOp + Sync + Send + 'static,
-  A: Analysis<L>,
-  L::Error: Send + Sync + Error,
+  Op: Teachable + Ord + Debug + Clone + Hash + Sync + Send + 'static,
+  AstNode<Op>: FromOp,
+  <AstNode<Op> as FromOp>::Error: Send + Sync + Error,
+  A: Analysis<AstNode<Op>>,
 {
   let mut rewrites = Vec::new();
   for line in file
@@ -76,14 +120,336 @@ where
   {
     let (name, rewrite) =
       line.split_once(':').ok_or(anyhow!("missing colon"))?;
-    let (lhs, rhs) =
-      rewrite.split_once("=>").ok_or(anyhow!("missing arrow"))?;
     let name = name.trim();
-    let lhs = lhs.trim();
-    let rhs = rhs.trim();
-    let lhs: Pattern<L> = lhs.parse()?;
-    let rhs: Pattern<L> = rhs.parse()?;
-    rewrites.push(Rewrite::new(name, lhs, rhs).map_err(|e| anyhow!("{}", e))?);
+
+    // Patterns may themselves contain an operation named `if`, so only treat
+    // the last ` if ` as the start of the conditions if a condition follows.
+    let (rewrite, conditions) = match rewrite.rsplit_once(" if ") {
+      Some((lhs_rhs, conditions)) if starts_condition(conditions) => {
+        (lhs_rhs, parse_conditions(conditions)?)
+      }
+      _ => (rewrite, Vec::new()),
+    };
+
+    let (lhs, rhs, bidirectional) = match rewrite.split_once("<=>") {
+      Some((lhs, rhs)) => (lhs, rhs, true),
+      None => {
+        let (lhs, rhs) =
+          rewrite.split_once("=>").ok_or(anyhow!("missing arrow"))?;
+        (lhs, rhs, false)
+      }
+    };
+    let lhs: Pattern<L> = lhs.trim().parse()?;
+    let rhs: Pattern<L> = rhs.trim().parse()?;
+
+    if bidirectional {
+      rewrites.push(conditional_rewrite(
+        &format!("{name}-rev"),
+        rhs.clone(),
+        lhs.clone(),
+        conditions.clone(),
+      )?);
+    }
+    rewrites.push(conditional_rewrite(name, lhs, rhs, conditions)?);
   }
   Ok(rewrites)
 }
+
+/// Builds the rewrite `lhs => rhs`, which only applies if all `conditions`
+/// hold.
+fn conditional_rewrite<Op, A>(
+  name: &str,
+  lhs: Pattern<AstNode<Op>>,
+  rhs: Pattern<AstNode<Op>>,
+  conditions: Vec<RewriteCondition<AstNode<Op>>>,
+) -> anyhow::Result<Rewrite<AstNode<Op>, A>>
+where
+  Op: Teachable + Ord + Debug + Clone + Hash + Sync + Send + 'static,
+  A: Analysis<AstNode<Op>>,
+{
+  if conditions.is_empty() {
+    Rewrite::new(name, lhs, rhs)
+  } else {
+    let condition = Conditions(conditions);
+    Rewrite::new(name, lhs, ConditionalApplier { condition, applier: rhs })
+  }
+  .map_err(|e| anyhow!("{}", e))
+}
+
+/// A condition on a rewrite, which must hold for the rewrite to apply.
+#[derive(Debug, Clone)]
+enum RewriteCondition<L> {
+  /// The two patterns are in the same e-class.
+  Equal(Pattern<L>, Pattern<L>),
+  /// The two patterns are in different e-classes.
+  Distinct(Pattern<L>, Pattern<L>),
+  /// The e-class of the variable contains an e-node with this many children.
+  Arity(Var, usize),
+  /// No term in the e-class of the variable refers to the de Bruijn index.
+  NotFreeIn(Var, DeBruijnIndex),
+}
+
+/// A conjunction of [`RewriteCondition`]s.
+#[derive(Debug, Clone)]
+struct Conditions<L>(Vec<RewriteCondition<L>>);
+
+impl<Op, A> Condition<AstNode<Op>, A> for Conditions<AstNode<Op>>
+where
+  Op: Teachable + Ord + Debug + Clone + Hash,
+  A: Analysis<AstNode<Op>>,
+{
+  fn check(
+    &self,
+    egraph: &mut EGraph<AstNode<Op>, A>,
+    _eclass: Id,
+    subst: &Subst,
+  ) -> bool {
+    self.0.iter().all(|condition| match condition {
+      RewriteCondition::Equal(p1, p2) => {
+        let id1 = egraph.add_instantiation(&p1.ast, subst);
+        let id2 = egraph.add_instantiation(&p2.ast, subst);
+        egraph.find(id1) == egraph.find(id2)
+      }
+      RewriteCondition::Distinct(p1, p2) => {
+        let id1 = egraph.add_instantiation(&p1.ast, subst);
+        let id2 = egraph.add_instantiation(&p2.ast, subst);
+        egraph.find(id1) != egraph.find(id2)
+      }
+      RewriteCondition::Arity(var, arity) => {
+        egraph[subst[*var]].iter().any(|node| node.len() == *arity)
+      }
+      RewriteCondition::NotFreeIn(var, index) => {
+        !may_refer_to(egraph, subst[*var], **index)
+      }
+    })
+  }
+
+  fn vars(&self) -> Vec<Var> {
+    self
+      .0
+      .iter()
+      .flat_map(|condition| match condition {
+        RewriteCondition::Equal(p1, p2)
+        | RewriteCondition::Distinct(p1, p2) => {
+          let mut vars = p1.vars();
+          vars.extend(p2.vars());
+          vars
+        }
+        RewriteCondition::Arity(var, _)
+        | RewriteCondition::NotFreeIn(var, _) => vec![*var],
+      })
+      .collect()
+  }
+}
+
+/// Whether some term in the e-class `id` of `egraph` may refer to the de
+/// Bruijn index `index`, counted from the root of the term.
+fn may_refer_to<Op, A>(
+  egraph: &EGraph<AstNode<Op>, A>,
+  id: Id,
+  index: usize,
+) -> bool
+where
+  Op: Teachable + Ord + Debug + Clone + Hash,
+  A: Analysis<AstNode<Op>>,
+{
+  // The index grows under each binder, so a cycle through a binder could
+  // raise it forever. No term refers to an index larger than the largest
+  // variable in the e-classes reachable from `id`, though.
+  let mut reachable = HashSet::new();
+  let mut stack = vec![egraph.find(id)];
+  let mut max_var = None;
+  while let Some(id) = stack.pop() {
+    if reachable.insert(id) {
+      for node in egraph[id].iter() {
+        if let Some(BindingExpr::Var(var)) = node.as_binding_expr() {
+          max_var = max_var.max(Some(var.0));
+        }
+        stack.extend(node.iter().map(|&child| egraph.find(child)));
+      }
+    }
+  }
+  let max_var = match max_var {
+    Some(max_var) => max_var,
+    None => return false,
+  };
+
+  let mut visited = HashSet::new();
+  let mut stack = vec![(egraph.find(id), index)];
+  while let Some((id, index)) = stack.pop() {
+    if index > max_var || !visited.insert((id, index)) {
+      continue;
+    }
+    for node in egraph[id].iter() {
+      match node.as_binding_expr() {
+        Some(BindingExpr::Var(var)) if var.0 == index => return true,
+        Some(BindingExpr::Lambda(body) | BindingExpr::Fix(body)) => {
+          stack.push((egraph.find(*body), index + 1));
+        }
+        _ => {
+          stack.extend(node.iter().map(|&child| (egraph.find(child), index)));
+        }
+      }
+    }
+  }
+  false
+}
+
+/// Whether `s` starts like a condition: with its name, followed by an
+/// opening parenthesis.
+fn starts_condition(s: &str) -> bool {
+  s.trim_start().split_once('(').map_or(false, |(name, _)| {
+    !name.is_empty()
+      && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+  })
+}
+
+/// Parses a comma-separated list of conditions, such as
+/// `distinct(?x, 0), arity(?y, 2)`.
+fn parse_conditions<L>(s: &str) -> anyhow::Result<Vec<RewriteCondition<L>>>
+where
+  L: Language + FromOp + 'static,
+  L::Error: Send + Sync + Error,
+{
+  let mut conditions = Vec::new();
+  let mut rest = s.trim();
+  while !rest.is_empty() {
+    let (name, after_name) =
+      rest.split_once('(').ok_or(anyhow!("missing arguments"))?;
+    let (args, after_args) = split_args(after_name)?;
+    let condition = match (name.trim(), args.as_slice()) {
+      ("equal", [p1, p2]) => RewriteCondition::Equal(p1.parse()?, p2.parse()?),
+      ("distinct", [p1, p2]) => {
+        RewriteCondition::Distinct(p1.parse()?, p2.parse()?)
+      }
+      ("arity", [var, arity]) => {
+        let var = var.parse().map_err(|e: String| anyhow!(e))?;
+        RewriteCondition::Arity(var, arity.parse()?)
+      }
+      ("not_free_in", [var, index]) => {
+        let var = var.parse().map_err(|e: String| anyhow!(e))?;
+        RewriteCondition::NotFreeIn(var, index.parse()?)
+      }
+      (name, _) => bail!("unknown condition {name} with {} args", args.len()),
+    };
+    conditions.push(condition);
+
+    rest = after_args.trim_start();
+    if let Some(after_comma) = rest.strip_prefix(',') {
+      rest = after_comma.trim_start();
+    } else if !rest.is_empty() {
+      bail!("expected a comma between conditions");
+    }
+  }
+  Ok(conditions)
+}
+
+/// Splits the arguments of a condition, which follow its opening
+/// parenthesis, at top-level commas. Returns the arguments and the rest of
+/// the string after the closing parenthesis.
+fn split_args(s: &str) -> anyhow::Result<(Vec<&str>, &str)> {
+  let mut args = Vec::new();
+  let mut depth = 0;
+  let mut start = 0;
+  for (i, c) in s.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ')' if depth > 0 => depth -= 1,
+      ')' => {
+        args.push(s[start..i].trim());
+        return Ok((args, &s[i + 1..]));
+      }
+      ',' if depth == 0 => {
+        args.push(s[start..i].trim());
+        start = i + 1;
+      }
+      _ => {}
+    }
+  }
+  bail!("missing closing parenthesis")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse;
+  use crate::{ast_node::AstNode, simple_lang::SimpleOp};
+  use egg::{EGraph, Rewrite, Runner};
+
+  fn rules(file: &str) -> Vec<Rewrite<AstNode<SimpleOp>, ()>> {
+    parse(file).unwrap()
+  }
+
+  /// Whether the rewrites in `file` show that `lhs` and `rhs` are equal.
+  fn proves(file: &str, lhs: &str, rhs: &str) -> bool {
+    let mut egraph = EGraph::default();
+    let lhs = egraph.add_expr(&lhs.parse().unwrap());
+    let rhs = egraph.add_expr(&rhs.parse().unwrap());
+    let runner = Runner::default().with_egraph(egraph).run(&rules(file));
+    runner.egraph.find(lhs) == runner.egraph.find(rhs)
+  }
+
+  #[test]
+  fn parses_bidirectional_rules() {
+    let file = "swap: (list ?x (list ?y)) <=> (list ?y (list ?x))";
+    let names: Vec<_> =
+      rules(file).iter().map(|rule| rule.name.to_string()).collect();
+    assert_eq!(names, ["swap-rev", "swap"]);
+    assert!(proves(file, "(list (list a) b)", "(list (list b) a)"));
+  }
+
+  #[test]
+  fn checks_equal() {
+    let file = "dup: (list ?x ?y) => ?x if equal(?x, ?y)";
+    assert!(proves(file, "(list a a)", "a"));
+    assert!(!proves(file, "(list a b)", "a"));
+  }
+
+  #[test]
+  fn checks_distinct() {
+    let file = "first: (list ?x ?y) => ?x if distinct(?x, ?y), distinct(?y, c)";
+    assert!(proves(file, "(list a b)", "a"));
+    assert!(!proves(file, "(list a a)", "a"));
+    assert!(!proves(file, "(list a c)", "a"));
+  }
+
+  #[test]
+  fn checks_arity() {
+    let file = "unwrap: (list ?x) => ?x if arity(?x, 2)";
+    assert!(proves(file, "(list (list a b))", "(list a b)"));
+    assert!(!proves(file, "(list (list a))", "(list a)"));
+  }
+
+  #[test]
+  fn checks_not_free_in() {
+    let file = "eta: (λ (@ ?f $0)) => ?f if not_free_in(?f, $0)";
+    assert!(proves(file, "(λ (@ g $0))", "g"));
+    assert!(proves(file, "(λ (@ (λ $0) $0))", "(λ $0)"));
+    assert!(!proves(file, "(λ (@ (@ g $0) $0))", "(@ g $0)"));
+    assert!(!proves(file, "(λ (@ (λ $1) $0))", "(λ $1)"));
+  }
+
+  #[test]
+  fn allows_if_in_patterns() {
+    let file = "drop: (list if ?x) => ?x";
+    assert!(proves(file, "(list if a)", "a"));
+  }
+
+  #[test]
+  fn rejects_malformed_conditions() {
+    for condition in [
+      "equal(?x)",
+      "same(?x, ?y)",
+      "equal(?x, ?y",
+      "equal(?x, ?y) distinct(?x, ?y)",
+      "arity(?x, two)",
+      "arity(x, 2)",
+      "not_free_in(?x, 0)",
+    ] {
+      let file = format!("bad: (list ?x ?y) => ?x if {condition}");
+      assert!(
+        parse::<SimpleOp, ()>(&file).is_err(),
+        "accepted condition {condition}"
+      );
+    }
+  }
+}
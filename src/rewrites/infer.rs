@@ -0,0 +1,448 @@
+//! Proposing rewrites for a domain by observational equivalence.
+//!
+//! Given an evaluator for the operations of a language (see [`Eval`]) and a
+//! way to draw random values (see [`RandomValue`]), an [`Inferrer`]
+//! enumerates small patterns built from the subexpressions of a
+//! corpus, evaluates each of them on the same random instantiations of their
+//! variables, and proposes a rewrite between every two patterns which agree
+//! on all of them.
+//!
+//! The patterns are the generalizations of the corpus' subexpressions: any
+//! subexpression below the root may be replaced by a variable, with equal
+//! subexpressions replaced by the same variable. Patterns which cannot be
+//! evaluated on any instantiation, such as those containing binders, are
+//! discarded.
+//!
+//! Like [`validate`](super::validate), this is random testing, so the
+//! proposed rewrites are only likely to be sound. They are meant to be
+//! reviewed and written to a rewrites file with [`to_file`], which
+//! [`from_file`](super::from_file) can read back.
+
+use super::validate::{eval_op, splitmix64, RandomValue};
+use crate::ast_node::{Arity, AstNode, Eval, Expr};
+use egg::{ENodeOrVar, Id, PatternAst, RecExpr, Var};
+use std::{
+  collections::{BTreeSet, HashMap, HashSet},
+  fmt::{self, Debug, Display, Formatter},
+  fs,
+  hash::Hash,
+  io,
+  path::Path,
+};
+
+/// A rewrite proposed by an [`Inferrer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InferredRewrite<Op> {
+  /// The name of the rewrite.
+  pub name: String,
+  /// The left-hand side of the rewrite.
+  pub lhs: PatternAst<AstNode<Op>>,
+  /// The right-hand side of the rewrite, which is never larger than the
+  /// left-hand side.
+  pub rhs: PatternAst<AstNode<Op>>,
+  /// Whether the rewrite also holds from right to left, i.e. whether both
+  /// sides have the same variables.
+  pub bidirectional: bool,
+}
+
+impl<Op> Display for InferredRewrite<Op>
+where
+  Op: Display + Ord + Debug + Clone + Hash,
+{
+  /// Writes the rewrite as a line of a rewrites file.
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let arrow = if self.bidirectional { "<=>" } else { "=>" };
+    write!(f, "{}: {} {arrow} {}", self.name, self.lhs, self.rhs)
+  }
+}
+
+/// Proposes rewrites by testing patterns drawn from a corpus for
+/// observational equivalence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inferrer {
+  max_size: usize,
+  max_vars: usize,
+  samples: usize,
+  seed: u64,
+}
+
+impl Default for Inferrer {
+  fn default() -> Self {
+    Self { max_size: 5, max_vars: 3, samples: 32, seed: 0 }
+  }
+}
+
+impl Inferrer {
+  /// Creates an inferrer which considers patterns of up to 5 nodes and 3
+  /// variables, tested on 32 instantiations.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the maximum number of nodes, including variables, of the patterns
+  /// which are considered.
+  #[must_use]
+  pub fn max_size(mut self, max_size: usize) -> Self {
+    self.max_size = max_size;
+    self
+  }
+
+  /// Sets the maximum number of distinct variables of the patterns which are
+  /// considered.
+  #[must_use]
+  pub fn max_vars(mut self, max_vars: usize) -> Self {
+    self.max_vars = max_vars;
+    self
+  }
+
+  /// Sets the number of instantiations on which the patterns are compared.
+  #[must_use]
+  pub fn samples(mut self, samples: usize) -> Self {
+    self.samples = samples;
+    self
+  }
+
+  /// Sets the seed from which the instantiations are generated. Inferring
+  /// rewrites from the same corpus with the same seed always gives the same
+  /// results.
+  #[must_use]
+  pub fn seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+
+  /// Proposes rewrites between the patterns drawn from `corpus`.
+  ///
+  /// Each rewrite goes from a pattern to the smallest pattern which is
+  /// observationally equivalent to it, provided it doesn't introduce
+  /// variables. Rewrites which are the same up to renaming of their
+  /// variables are only proposed once.
+  #[must_use]
+  pub fn infer<Op>(&self, corpus: &[Expr<Op>]) -> Vec<InferredRewrite<Op>>
+  where
+    Op: RandomValue + Arity + Ord + Debug + Clone + Hash,
+  {
+    let mut patterns = BTreeSet::new();
+    let mut seen = HashSet::new();
+    for expr in corpus {
+      self.collect_patterns(expr, &mut seen, &mut patterns);
+    }
+
+    // Every pattern is evaluated on the same instantiations, so patterns
+    // which differ only in the names of their variables are kept apart.
+    let mut state = self.seed;
+    let instantiations: Vec<Vec<Op::Value>> = (0..self.samples)
+      .map(|_| {
+        (0..self.max_vars)
+          .map(|_| Op::random_value(splitmix64(&mut state)))
+          .collect()
+      })
+      .collect();
+
+    let mut classes: Vec<(Vec<Option<Op::Value>>, Vec<Term<Op>>)> = Vec::new();
+    for pattern in patterns {
+      let fingerprint: Vec<Option<Op::Value>> =
+        instantiations.iter().map(|values| pattern.eval(values)).collect();
+      if fingerprint.iter().all(Option::is_none) {
+        continue;
+      }
+      match classes.iter_mut().find(|(other, _)| *other == fingerprint) {
+        Some((_, members)) => members.push(pattern),
+        None => classes.push((fingerprint, vec![pattern])),
+      }
+    }
+
+    let mut rules = BTreeSet::new();
+    for (_, mut members) in classes {
+      members.sort_by_key(|term| (term.size(), term.clone()));
+      let (representative, others) = match members.split_first() {
+        Some(split) => split,
+        None => continue,
+      };
+      let rhs_vars = representative.vars();
+      for lhs in others {
+        let lhs_vars = lhs.vars();
+        if !rhs_vars.is_subset(&lhs_vars) {
+          continue;
+        }
+        rules.insert(canonical_rule(lhs, representative, lhs_vars == rhs_vars));
+      }
+    }
+
+    rules
+      .into_iter()
+      .enumerate()
+      .map(|(i, (lhs, rhs, bidirectional))| InferredRewrite {
+        name: format!("inferred-{i}"),
+        lhs: lhs.to_pattern(),
+        rhs: rhs.to_pattern(),
+        bidirectional,
+      })
+      .collect()
+  }
+
+  /// Adds the generalizations of every subexpression of `expr` to
+  /// `patterns`, along with each renaming of their variables. Subexpressions
+  /// in `seen` have already been handled.
+  fn collect_patterns<Op>(
+    &self,
+    expr: &Expr<Op>,
+    seen: &mut HashSet<Expr<Op>>,
+    patterns: &mut BTreeSet<Term<Op>>,
+  ) where
+    Op: Ord + Clone + Hash,
+  {
+    if !seen.insert(expr.clone()) {
+      return;
+    }
+    for arg in expr.0.iter() {
+      self.collect_patterns(arg, seen, patterns);
+    }
+
+    for generalization in generalize(expr, true, self.max_size) {
+      let (term, vars) = generalization.canonicalize();
+      if vars > self.max_vars {
+        continue;
+      }
+      for permutation in permutations(self.max_vars, vars) {
+        patterns.insert(term.rename(&permutation));
+      }
+    }
+  }
+}
+
+/// Returns the generalizations of `expr` with at most `budget` nodes. The
+/// root is only replaced by a variable if `root` is false.
+fn generalize<'a, Op>(
+  expr: &'a Expr<Op>,
+  root: bool,
+  budget: usize,
+) -> Vec<Shape<'a, Op>>
+where
+  Op: Clone,
+{
+  let mut shapes = Vec::new();
+  if !root {
+    shapes.push(Shape::Hole(expr));
+  }
+  let args = expr.0.args();
+  // Each argument takes at least one node.
+  if budget < args.len() + 1 {
+    return shapes;
+  }
+
+  let mut partial: Vec<(Vec<Shape<'a, Op>>, usize)> = vec![(Vec::new(), 1)];
+  for (i, arg) in args.iter().enumerate() {
+    let remaining_args = args.len() - i - 1;
+    let mut next = Vec::new();
+    for (prefix, used) in partial {
+      let arg_budget = budget - used - remaining_args;
+      for shape in generalize(arg, false, arg_budget) {
+        let size = shape.size();
+        let mut prefix = prefix.clone();
+        prefix.push(shape);
+        next.push((prefix, used + size));
+      }
+    }
+    partial = next;
+  }
+  shapes.extend(
+    partial
+      .into_iter()
+      .map(|(args, _)| Shape::Node(expr.0.operation().clone(), args)),
+  );
+  shapes
+}
+
+/// A generalization of an expression, in which some subexpressions are left
+/// as holes to be replaced by variables.
+#[derive(Clone)]
+enum Shape<'a, Op> {
+  Hole(&'a Expr<Op>),
+  Node(Op, Vec<Shape<'a, Op>>),
+}
+
+impl<Op> Shape<'_, Op> {
+  fn size(&self) -> usize {
+    match self {
+      Shape::Hole(_) => 1,
+      Shape::Node(_, args) => 1 + args.iter().map(Shape::size).sum::<usize>(),
+    }
+  }
+}
+
+impl<Op: Clone + Eq + Hash> Shape<'_, Op> {
+  /// Replaces the holes by variables, numbered in order of first occurrence,
+  /// with equal subexpressions replaced by the same variable. Returns the
+  /// resulting term and its number of variables.
+  fn canonicalize(&self) -> (Term<Op>, usize) {
+    fn go<'a, Op: Clone + Eq + Hash>(
+      shape: &Shape<'a, Op>,
+      holes: &mut HashMap<&'a Expr<Op>, usize>,
+    ) -> Term<Op> {
+      match shape {
+        Shape::Hole(expr) => {
+          let next = holes.len();
+          Term::Var(*holes.entry(*expr).or_insert(next))
+        }
+        Shape::Node(op, args) => Term::Node(
+          op.clone(),
+          args.iter().map(|arg| go(arg, holes)).collect(),
+        ),
+      }
+    }
+
+    let mut holes = HashMap::new();
+    let term = go(self, &mut holes);
+    (term, holes.len())
+  }
+}
+
+/// A pattern whose variables are numbered.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Term<Op> {
+  Var(usize),
+  Node(Op, Vec<Term<Op>>),
+}
+
+impl<Op: Clone> Term<Op> {
+  fn size(&self) -> usize {
+    match self {
+      Term::Var(_) => 1,
+      Term::Node(_, args) => 1 + args.iter().map(Term::size).sum::<usize>(),
+    }
+  }
+
+  fn vars(&self) -> BTreeSet<usize> {
+    let mut vars = BTreeSet::new();
+    self.for_each_var(&mut |var| {
+      vars.insert(var);
+    });
+    vars
+  }
+
+  fn for_each_var(&self, f: &mut impl FnMut(usize)) {
+    match self {
+      Term::Var(var) => f(*var),
+      Term::Node(_, args) => args.iter().for_each(|arg| arg.for_each_var(f)),
+    }
+  }
+
+  /// Replaces each variable `i` by variable `renaming[i]`.
+  fn rename(&self, renaming: &[usize]) -> Self {
+    self.map_vars(&mut |var| renaming[var])
+  }
+
+  fn map_vars(&self, f: &mut impl FnMut(usize) -> usize) -> Self {
+    match self {
+      Term::Var(var) => Term::Var(f(*var)),
+      Term::Node(op, args) => {
+        Term::Node(op.clone(), args.iter().map(|arg| arg.map_vars(f)).collect())
+      }
+    }
+  }
+
+  /// Evaluates the term with variable `i` replaced by `values[i]`.
+  fn eval(&self, values: &[Op::Value]) -> Option<Op::Value>
+  where
+    Op: Eval,
+  {
+    match self {
+      Term::Var(var) => values.get(*var).cloned(),
+      Term::Node(op, args) => {
+        if AstNode::new(op.clone(), args).as_binding_expr().is_some() {
+          return None;
+        }
+        let args = args
+          .iter()
+          .map(|arg| arg.eval(values))
+          .collect::<Option<Vec<_>>>()?;
+        eval_op(op, args)
+      }
+    }
+  }
+
+  fn to_pattern(&self) -> PatternAst<AstNode<Op>>
+  where
+    Op: Arity + Ord + Debug + Hash,
+  {
+    fn go<Op: Arity + Ord + Debug + Clone + Hash>(
+      term: &Term<Op>,
+      pattern: &mut PatternAst<AstNode<Op>>,
+    ) -> Id {
+      match term {
+        Term::Var(var) => pattern.add(ENodeOrVar::Var(var_name(*var))),
+        Term::Node(op, args) => {
+          let args: Vec<Id> = args.iter().map(|arg| go(arg, pattern)).collect();
+          pattern.add(ENodeOrVar::ENode(AstNode::new(op.clone(), args)))
+        }
+      }
+    }
+
+    let mut pattern = RecExpr::default();
+    go(self, &mut pattern);
+    pattern
+  }
+}
+
+/// Renames the variables of the rule `lhs => rhs` in order of first
+/// occurrence, so that rules which are the same up to renaming are equal.
+fn canonical_rule<Op: Clone>(
+  lhs: &Term<Op>,
+  rhs: &Term<Op>,
+  bidirectional: bool,
+) -> (Term<Op>, Term<Op>, bool) {
+  let mut renaming = HashMap::new();
+  let mut rename = |var| {
+    let next = renaming.len();
+    *renaming.entry(var).or_insert(next)
+  };
+  let lhs = lhs.map_vars(&mut rename);
+  let rhs = rhs.map_vars(&mut rename);
+  (lhs, rhs, bidirectional)
+}
+
+/// Returns every injective map from `0..vars` to `0..max_vars`.
+fn permutations(max_vars: usize, vars: usize) -> Vec<Vec<usize>> {
+  let mut permutations = vec![Vec::new()];
+  for _ in 0..vars {
+    let mut next = Vec::new();
+    for prefix in permutations {
+      for var in (0..max_vars).filter(|var| !prefix.contains(var)) {
+        let mut permutation = prefix.clone();
+        permutation.push(var);
+        next.push(permutation);
+      }
+    }
+    permutations = next;
+  }
+  permutations
+}
+
+/// The name of the pattern variable numbered `var`: `?a`, `?b`, and so on.
+fn var_name(var: usize) -> Var {
+  let name = match u8::try_from(var) {
+    Ok(var) if var < 26 => format!("?{}", char::from(b'a' + var)),
+    _ => format!("?x{var}"),
+  };
+  name.parse().expect("pattern variable names start with ?")
+}
+
+/// Writes `rewrites` to the file at `path`, one per line, in the format read
+/// by [`from_file`](super::from_file).
+///
+/// # Errors
+/// This function will return an error if the file can't be written.
+pub fn to_file<Op, P>(
+  rewrites: &[InferredRewrite<Op>],
+  path: P,
+) -> io::Result<()>
+where
+  Op: Display + Ord + Debug + Clone + Hash,
+  P: AsRef<Path>,
+{
+  let contents: String =
+    rewrites.iter().map(|rewrite| format!("{rewrite}\n")).collect();
+  fs::write(path, contents)
+}
@@ -0,0 +1,228 @@
+//! Checking the soundness of rewrites by random testing.
+//!
+//! Given an evaluator for the operations of a language (see [`Eval`]) and a
+//! way to draw random values (see [`RandomValue`]), a [`Validator`]
+//! instantiates the variables of each rewrite with random
+//! values, evaluates both sides of the rewrite, and reports the rewrites for
+//! which they disagree.
+//!
+//! Patterns are evaluated first-order: each operation is applied to the
+//! values of its arguments, and patterns containing binding constructs are
+//! undefined. Instantiations for which the left-hand side cannot be
+//! evaluated (because they are not well-typed, for instance) are discarded. The conditions of
+//! conditional rewrites are not taken into account, so a conditional rewrite
+//! may be reported even if it never applies to the counterexample.
+
+use crate::ast_node::{AstNode, Eval, Interpreter, Value};
+use egg::{Analysis, ENodeOrVar, PatternAst, Rewrite, Symbol, Var};
+use log::warn;
+use std::{
+  fmt::{self, Debug, Display, Formatter},
+  hash::Hash,
+};
+
+/// An extension of [`Eval`] for languages whose rewrites can be checked by
+/// random testing.
+pub trait RandomValue: Eval {
+  /// Returns a random value, determined by `seed`. Values of every type in the
+  /// language should be returned for some seeds.
+  fn random_value(seed: u64) -> Self::Value;
+}
+
+/// An instantiation of a rewrite's variables for which its two sides evaluate
+/// to different values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample<V> {
+  /// The name of the rewrite.
+  pub rule: Symbol,
+  /// The value of each variable of the rewrite.
+  pub subst: Vec<(Var, V)>,
+  /// The value of the left-hand side.
+  pub lhs: V,
+  /// The value of the right-hand side, or [`None`] if it could not be
+  /// evaluated.
+  pub rhs: Option<V>,
+}
+
+impl<V: Debug> Display for Counterexample<V> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: with", self.rule)?;
+    for (var, value) in &self.subst {
+      write!(f, " {var} = {value:?}")?;
+    }
+    write!(f, ", the left-hand side is {:?}", self.lhs)?;
+    match &self.rhs {
+      Some(rhs) => write!(f, " but the right-hand side is {rhs:?}"),
+      None => write!(f, " but the right-hand side is undefined"),
+    }
+  }
+}
+
+/// Checks rewrites for soundness by evaluating them on random
+/// instantiations of their variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validator {
+  samples: usize,
+  max_attempts: usize,
+  seed: u64,
+}
+
+impl Default for Validator {
+  fn default() -> Self {
+    Self { samples: 100, max_attempts: 1000, seed: 0 }
+  }
+}
+
+impl Validator {
+  /// Creates a validator which tests each rewrite on 100 instantiations.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the number of instantiations on which each rewrite is tested.
+  #[must_use]
+  pub fn samples(mut self, samples: usize) -> Self {
+    self.samples = samples;
+    self
+  }
+
+  /// Sets the number of random instantiations tried per rewrite, including
+  /// the ones which are discarded because the left-hand side is undefined.
+  #[must_use]
+  pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+    self.max_attempts = max_attempts;
+    self
+  }
+
+  /// Sets the seed from which the instantiations are generated. Validating
+  /// the same rewrites with the same seed always gives the same results.
+  #[must_use]
+  pub fn seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+
+  /// Tests each of `rewrites`, returning a counterexample for each rewrite
+  /// which is found to be unsound.
+  ///
+  /// Rewrites whose sides are not patterns, or for which no instantiation
+  /// with a defined left-hand side is found, are skipped with a warning.
+  pub fn validate<Op, A>(
+    &self,
+    rewrites: &[Rewrite<AstNode<Op>, A>],
+  ) -> Vec<Counterexample<Op::Value>>
+  where
+    Op: RandomValue + Ord + Debug + Clone + Hash,
+    A: Analysis<AstNode<Op>>,
+  {
+    let mut state = self.seed;
+    rewrites
+      .iter()
+      .filter_map(|rewrite| self.validate_rewrite(rewrite, &mut state))
+      .collect()
+  }
+
+  /// Tests a single rewrite, using and updating the random `state`.
+  fn validate_rewrite<Op, A>(
+    &self,
+    rewrite: &Rewrite<AstNode<Op>, A>,
+    state: &mut u64,
+  ) -> Option<Counterexample<Op::Value>>
+  where
+    Op: RandomValue + Ord + Debug + Clone + Hash,
+    A: Analysis<AstNode<Op>>,
+  {
+    let (lhs, rhs) = match (
+      rewrite.searcher.get_pattern_ast(),
+      rewrite.applier.get_pattern_ast(),
+    ) {
+      (Some(lhs), Some(rhs)) => (lhs, rhs),
+      _ => {
+        warn!("cannot validate {}: it is not a pattern rewrite", rewrite.name);
+        return None;
+      }
+    };
+    let vars = rewrite.searcher.vars();
+
+    let mut tested = 0;
+    for _ in 0..self.max_attempts {
+      if tested == self.samples {
+        break;
+      }
+      let subst: Vec<(Var, Op::Value)> = vars
+        .iter()
+        .map(|&var| (var, Op::random_value(splitmix64(state))))
+        .collect();
+      let lhs_value = match evaluate(lhs, &subst) {
+        Some(value) => value,
+        None => continue,
+      };
+      tested += 1;
+      let rhs_value = evaluate(rhs, &subst);
+      if rhs_value.as_ref() != Some(&lhs_value) {
+        return Some(Counterexample {
+          rule: rewrite.name,
+          subst,
+          lhs: lhs_value,
+          rhs: rhs_value,
+        });
+      }
+    }
+
+    if tested == 0 {
+      warn!(
+        "cannot validate {}: no instantiation with a defined left-hand side",
+        rewrite.name
+      );
+    }
+    None
+  }
+}
+
+/// Evaluates `pattern` with its variables replaced by their values in
+/// `subst`. Returns [`None`] if any part of the pattern is undefined.
+fn evaluate<Op: Eval>(
+  pattern: &PatternAst<AstNode<Op>>,
+  subst: &[(Var, Op::Value)],
+) -> Option<Op::Value> {
+  let mut values: Vec<Op::Value> = Vec::with_capacity(pattern.as_ref().len());
+  for node in pattern.as_ref() {
+    let value = match node {
+      ENodeOrVar::Var(var) => {
+        subst.iter().find(|(v, _)| v == var).map(|(_, value)| value.clone())?
+      }
+      ENodeOrVar::ENode(node) => {
+        if node.as_binding_expr().is_some() {
+          return None;
+        }
+        let args: Vec<Op::Value> =
+          node.iter().map(|&id| values[usize::from(id)].clone()).collect();
+        eval_op(node.operation(), args)?
+      }
+    };
+    values.push(value);
+  }
+  values.pop()
+}
+
+/// Applies `op`, which must not be a binding construct, to `args`. Returns
+/// [`None`] if the operation is not defined on `args`, e.g. if they have the
+/// wrong types.
+pub(super) fn eval_op<Op: Eval>(
+  op: &Op,
+  args: Vec<Op::Value>,
+) -> Option<Op::Value> {
+  let args: Vec<Value<'_, Op>> = args.into_iter().map(Value::Prim).collect();
+  Interpreter::new().eval_op(op, &args).ok()?.into_prim().ok()
+}
+
+/// The `SplitMix64` generator: advances `state` and returns the next random
+/// number.
+pub(super) fn splitmix64(state: &mut u64) -> u64 {
+  *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  let mut z = *state;
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
+}
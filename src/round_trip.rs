@@ -0,0 +1,96 @@
+//! Helpers for checking that the [`Display`] and [`FromStr`] implementations
+//! of a language's operations agree, so that expressions printed as
+//! s-expressions can be parsed back with [`Expr::parse_with`].
+
+use crate::ast_node::{Arity, AstNode, Expr};
+use itertools::Itertools;
+use std::{
+  fmt::{Debug, Display},
+  str::FromStr,
+};
+
+/// Parses `input` as an expression of
+/// [`SimpleOp`](crate::simple_lang::SimpleOp), for tests.
+///
+/// # Panics
+///
+/// Panics if `input` fails to parse.
+#[cfg(test)]
+pub(crate) fn parse(input: &str) -> Expr<crate::simple_lang::SimpleOp> {
+  Expr::parse_with(input).unwrap()
+}
+
+/// Prints `expr` as an s-expression, writing each operation with its
+/// [`Display`] implementation.
+#[must_use]
+pub fn to_sexp<Op: Display>(expr: &Expr<Op>) -> String {
+  let node = &expr.0;
+  if node.is_empty() {
+    node.operation().to_string()
+  } else {
+    format!("({} {})", node.operation(), node.iter().map(to_sexp).join(" "))
+  }
+}
+
+/// Checks that printing `expr` with [`to_sexp`] and parsing the result with
+/// [`Expr::parse_with`] gives back `expr`.
+///
+/// # Panics
+///
+/// Panics if the printed expression fails to parse, or parses to a different
+/// expression.
+pub fn assert_round_trip<Op>(expr: &Expr<Op>)
+where
+  Op: Display + FromStr + Arity + Debug + PartialEq,
+  Op::Err: Display,
+{
+  let printed = to_sexp(expr);
+  match Expr::<Op>::parse_with(&printed) {
+    Ok(parsed) => {
+      assert_eq!(&parsed, expr, "`{printed}` parses to a different expression")
+    }
+    Err(error) => panic!("`{printed}` fails to parse: {error}"),
+  }
+}
+
+/// Checks [`assert_round_trip`] for every expression at most `max_depth` deep
+/// which is built from `ops`, each applied to its minimum number of
+/// arguments. The number of such expressions grows exponentially with
+/// `max_depth`, so keep it small.
+///
+/// # Panics
+///
+/// Panics if any of the expressions fails to round-trip.
+pub fn assert_round_trips<Op>(ops: &[Op], max_depth: usize)
+where
+  Op: Display + FromStr + Arity + Clone + Debug + PartialEq,
+  Op::Err: Display,
+{
+  for expr in exprs_up_to(ops, max_depth) {
+    assert_round_trip(&expr);
+  }
+}
+
+/// Returns every expression at most `depth` deep built from `ops`, each
+/// applied to its minimum number of arguments.
+fn exprs_up_to<Op: Arity + Clone>(ops: &[Op], depth: usize) -> Vec<Expr<Op>> {
+  if depth == 0 {
+    return Vec::new();
+  }
+  let smaller = exprs_up_to(ops, depth - 1);
+  let mut exprs = Vec::new();
+  for op in ops {
+    let arity = op.min_arity();
+    if arity == 0 {
+      exprs.push(Expr(AstNode::new(op.clone(), Vec::new())));
+    } else {
+      exprs.extend(
+        (0..arity)
+          .map(|_| smaller.iter().cloned())
+          .multi_cartesian_product()
+          .map(|args| Expr(AstNode::new(op.clone(), args))),
+      );
+    }
+  }
+  exprs
+}
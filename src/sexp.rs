@@ -153,6 +153,10 @@ mod parse {
   #[cfg(test)]
   mod test {
     use super::*;
+    use crate::{
+      round_trip::assert_round_trips, simple_lang::SimpleOp, Expr,
+      ParseExprError, TextPosition,
+    };
 
     #[test]
     fn test_comment() {
@@ -238,6 +242,29 @@ mod parse {
         Ok(("", vec![Sexp::Atom("a"), Sexp::Atom("b"), Sexp::Atom("c")]))
       );
     }
+
+    #[test]
+    fn test_expr_round_trip() {
+      let ops: Vec<SimpleOp> =
+        ["@", "λ", "a", "$0"].iter().map(|op| op.parse().unwrap()).collect();
+      assert_round_trips(&ops, 3);
+    }
+
+    #[test]
+    fn test_expr_parse_error() {
+      let error = Expr::<SimpleOp>::parse_with("(λ\n  (@ a))").unwrap_err();
+      assert!(matches!(
+        error,
+        ParseExprError::Arity {
+          position: TextPosition { line: 2, column: 4 },
+          ..
+        }
+      ));
+      assert!(matches!(
+        Expr::<SimpleOp>::parse_with("(a"),
+        Err(ParseExprError::Syntax(_))
+      ));
+    }
   }
 }
 
@@ -2,15 +2,18 @@
 
 use std::{
   convert::Infallible,
-  fmt::{self, Display, Formatter},
+  fmt::{self, Display, Formatter, Write},
   str::FromStr,
 };
 
 use egg::Symbol;
 
 use crate::{
-  ast_node::{Arity, AstNode},
-  learn::{LibId, ParseLibIdError},
+  ast_node::{
+    Args, Arity, AstNode, Eval, EvalError, Expr, Precedence, Printable,
+    Printer, Value,
+  },
+  learn::{DiscriminantEq, LibId, ParseLibIdError},
   teachable::{BindingExpr, DeBruijnIndex, Teachable},
 };
 
@@ -27,8 +30,12 @@ pub enum SimpleOp {
   Symbol(Symbol),
   /// An anonymous function
   Lambda,
+  /// A fixpoint, for recursive functions
+  Fix,
   /// A library function binding
   Lib(LibId),
+  /// A binding of a library function in a mutually recursive group
+  LibRec(LibId),
   /// A list of expressions
   List,
 }
@@ -37,8 +44,8 @@ impl Arity for SimpleOp {
   fn min_arity(&self) -> usize {
     match self {
       Self::Var(_) | Self::Symbol(_) => 0,
-      Self::Lambda | Self::LibVar(_) | Self::List => 1,
-      Self::Apply | Self::Lib(_) => 2,
+      Self::Lambda | Self::Fix | Self::LibVar(_) | Self::List => 1,
+      Self::Apply | Self::Lib(_) | Self::LibRec(_) => 2,
     }
   }
 }
@@ -48,9 +55,13 @@ impl Display for SimpleOp {
     let s = match self {
       Self::Apply => "@",
       Self::Lambda => "λ",
+      Self::Fix => "fix",
       Self::Lib(libid) => {
         return write!(f, "lib {libid}");
       }
+      Self::LibRec(libid) => {
+        return write!(f, "librec {libid}");
+      }
       Self::LibVar(libid) => {
         return write!(f, "l{libid}");
       }
@@ -73,6 +84,7 @@ impl FromStr for SimpleOp {
     let op = match input {
       "apply" | "@" => Self::Apply,
       "lambda" | "λ" => Self::Lambda,
+      "fix" => Self::Fix,
       "list" => Self::List,
       input => input
         .parse()
@@ -84,6 +96,12 @@ impl FromStr for SimpleOp {
             .ok_or(ParseLibIdError::NoLeadingL)
             .and_then(|x| x.parse().map(Self::Lib))
         })
+        .or_else(|_| {
+          input
+            .strip_prefix("librec ")
+            .ok_or(ParseLibIdError::NoLeadingL)
+            .and_then(|x| x.parse().map(Self::LibRec))
+        })
         .unwrap_or_else(|_| Self::Symbol(input.into())),
     };
     Ok(op)
@@ -94,11 +112,15 @@ impl Teachable for SimpleOp {
   fn from_binding_expr<T>(binding_expr: BindingExpr<T>) -> AstNode<Self, T> {
     match binding_expr {
       BindingExpr::Lambda(body) => AstNode::new(Self::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(Self::Fix, [body]),
       BindingExpr::Apply(fun, arg) => AstNode::new(Self::Apply, [fun, arg]),
       BindingExpr::Var(index) => AstNode::leaf(Self::Var(index)),
       BindingExpr::Lib(ix, bound_value, body) => {
         AstNode::new(Self::Lib(ix), [bound_value, body])
       }
+      BindingExpr::LibRec(ix, bound_value, body) => {
+        AstNode::new(Self::LibRec(ix), [bound_value, body])
+      }
       BindingExpr::LibVar(ix) => AstNode::new(Self::LibVar(ix), []),
     }
   }
@@ -106,11 +128,15 @@ impl Teachable for SimpleOp {
   fn as_binding_expr<T>(node: &AstNode<Self, T>) -> Option<BindingExpr<&T>> {
     let binding_expr = match node.as_parts() {
       (Self::Lambda, [body]) => BindingExpr::Lambda(body),
+      (Self::Fix, [body]) => BindingExpr::Fix(body),
       (Self::Apply, [fun, arg]) => BindingExpr::Apply(fun, arg),
       (Self::Var(index), []) => BindingExpr::Var(*index),
       (Self::Lib(ix), [bound_value, body]) => {
         BindingExpr::Lib(*ix, bound_value, body)
       }
+      (Self::LibRec(ix), [bound_value, body]) => {
+        BindingExpr::LibRec(*ix, bound_value, body)
+      }
       (Self::LibVar(ix), []) => BindingExpr::LibVar(*ix),
       _ => return None,
     };
@@ -121,3 +147,59 @@ impl Teachable for SimpleOp {
     Self::List
   }
 }
+
+impl Printable for SimpleOp {
+  fn precedence(&self) -> Precedence {
+    match self {
+      Self::Var(_) | Self::LibVar(_) | Self::Symbol(_) => 60,
+      Self::List => 50,
+      Self::Apply => 40,
+      Self::Lambda | Self::Fix | Self::Lib(_) | Self::LibRec(_) => 10,
+    }
+  }
+
+  fn print_naked<W: Write>(
+    expr: &Expr<Self>,
+    printer: &mut Printer<W>,
+  ) -> fmt::Result {
+    match (expr.0.operation(), expr.0.args()) {
+      (Self::List, ts) => {
+        let elem = |p: &mut Printer<W>, i: usize| p.print_in_context(&ts[i], 0);
+        printer.in_brackets(|p| p.indented(|p| p.vsep(elem, ts.len(), ",")))
+      }
+      (op, []) => write!(printer.writer, "{op}"),
+      (op, _) => write!(printer.writer, "{op} ???"),
+    }
+  }
+}
+
+impl DiscriminantEq for SimpleOp {
+  fn discriminant_eq(&self, other: &Self) -> bool {
+    self.eq(other)
+  }
+}
+
+/// The value of a [`SimpleOp`] expression, in which symbols are
+/// uninterpreted: the symbols it evaluates to, collected into lists.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SimpleValue {
+  /// A symbol.
+  Symbol(Symbol),
+  /// A list of values.
+  List(Vec<SimpleValue>),
+}
+
+impl Eval for SimpleOp {
+  type Value = SimpleValue;
+
+  fn eval<'a>(
+    &self,
+    mut args: Args<'_, 'a, Self>,
+  ) -> Result<Value<'a, Self>, EvalError> {
+    match self {
+      Self::Symbol(sym) => Ok(Value::Prim(SimpleValue::Symbol(*sym))),
+      Self::List => Ok(Value::Prim(SimpleValue::List(args.prims()?))),
+      op => Err(EvalError::Op(format!("malformed {op}"))),
+    }
+  }
+}
@@ -0,0 +1,357 @@
+//! Defines [`StringOp`], a language of FlashFill-style string transformations
+//! which can be used with babble.
+//!
+//! Programs are s-expressions, usually functions of the input string, like
+//! `(lambda x (concat (substr x 0 3) "-"))`. String literals are written in
+//! double quotes. Since s-expression atoms can't contain whitespace,
+//! parentheses, or semicolons, those are written as `\s` (space), `\t`, `\n`,
+//! or `\xHH` for any other ASCII character, and a backslash as `\\`.
+
+use std::{
+  fmt::{self, Display, Formatter, Write},
+  str::FromStr,
+};
+
+use egg::Symbol;
+use thiserror::Error;
+
+use crate::{
+  ast_node::{
+    Arity, AstNode, Expr, ParseNamedError, Precedence, Printable, Printer,
+    VarNames,
+  },
+  learn::{DiscriminantEq, LibId, ParseLibIdError},
+  sexp::{ParseError, Program},
+  teachable::{BindingExpr, DeBruijnIndex, Teachable},
+};
+
+/// String transformations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StringOp {
+  /// A string literal, without its quotes and escapes
+  Str(Symbol),
+  /// An integer literal, used as an index into a string or list
+  Int(i32),
+  /// The concatenation of two strings
+  Concat,
+  /// The substring of a string from a start index to an end index, where
+  /// negative indices count from the end
+  Substr,
+  /// A string with every occurrence of a second string replaced by a third
+  Replace,
+  /// The list of substrings of a string separated by a second string
+  Split,
+  /// The element of a list at an index, where negative indices count from
+  /// the end
+  Nth,
+  /// The strings of a list joined by a separator
+  Join,
+  /// A string in upper case
+  Upper,
+  /// A string in lower case
+  Lower,
+  /// A function application
+  Apply,
+  /// A de Bruijn-indexed variable
+  Var(DeBruijnIndex),
+  /// An anonymous function
+  Lambda,
+  /// A fixpoint, for recursive functions
+  Fix,
+  /// A library function binding
+  Lib(LibId),
+  /// A binding of a library function in a mutually recursive group
+  LibRec(LibId),
+  /// A reference to a lib var
+  LibVar(LibId),
+  /// A list
+  List,
+}
+
+impl Arity for StringOp {
+  fn min_arity(&self) -> usize {
+    match self {
+      Self::Str(_)
+      | Self::Int(_)
+      | Self::Var(_)
+      | Self::LibVar(_)
+      | Self::List => 0,
+      Self::Upper | Self::Lower | Self::Lambda | Self::Fix => 1,
+      Self::Concat
+      | Self::Split
+      | Self::Nth
+      | Self::Join
+      | Self::Apply
+      | Self::Lib(_)
+      | Self::LibRec(_) => 2,
+      Self::Substr | Self::Replace => 3,
+    }
+  }
+
+  fn max_arity(&self) -> Option<usize> {
+    match self {
+      Self::List => None,
+      other => Some(other.min_arity()),
+    }
+  }
+}
+
+impl Display for StringOp {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      Self::Concat => "concat",
+      Self::Substr => "substr",
+      Self::Replace => "replace",
+      Self::Split => "split",
+      Self::Nth => "nth",
+      Self::Join => "join",
+      Self::Upper => "upper",
+      Self::Lower => "lower",
+      Self::Apply => "@",
+      Self::Lambda => "λ",
+      Self::Fix => "fix",
+      Self::List => "list",
+      Self::Lib(ix) => {
+        return write!(f, "lib {ix}");
+      }
+      Self::LibRec(ix) => {
+        return write!(f, "librec {ix}");
+      }
+      Self::LibVar(ix) => {
+        return write!(f, "{ix}");
+      }
+      Self::Var(index) => {
+        return write!(f, "{index}");
+      }
+      Self::Int(i) => {
+        return write!(f, "{i}");
+      }
+      Self::Str(s) => {
+        f.write_char('"')?;
+        for c in s.as_str().chars() {
+          match c {
+            ' ' => f.write_str("\\s")?,
+            '\t' => f.write_str("\\t")?,
+            '\n' => f.write_str("\\n")?,
+            '\\' => f.write_str("\\\\")?,
+            '"' | '(' | ')' | ';' | '\r' => {
+              write!(f, "\\x{:02x}", u32::from(c))?;
+            }
+            c => f.write_char(c)?,
+          }
+        }
+        return f.write_char('"');
+      }
+    };
+    f.write_str(s)
+  }
+}
+
+/// An error when parsing a [`StringOp`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseStringOpError {
+  /// A string literal has an invalid escape sequence.
+  #[error("invalid escape in string literal {0}")]
+  InvalidEscape(String),
+
+  /// The input is not the name of an operation or a literal.
+  #[error("unknown operation {0}")]
+  UnknownOp(String),
+}
+
+impl FromStr for StringOp {
+  type Err = ParseStringOpError;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    let op = match input {
+      "concat" => Self::Concat,
+      "substr" => Self::Substr,
+      "replace" => Self::Replace,
+      "split" => Self::Split,
+      "nth" => Self::Nth,
+      "join" => Self::Join,
+      "upper" => Self::Upper,
+      "lower" => Self::Lower,
+      "apply" | "@" => Self::Apply,
+      "lambda" | "λ" => Self::Lambda,
+      "fix" => Self::Fix,
+      "list" => Self::List,
+      input => {
+        if let Some(literal) =
+          input.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+        {
+          return unescape(literal)
+            .map(|s| Self::Str(s.into()))
+            .ok_or_else(|| ParseStringOpError::InvalidEscape(input.into()));
+        }
+        input
+          .parse()
+          .map(Self::Var)
+          .or_else(|_| input.parse().map(Self::Int))
+          .or_else(|_| input.parse().map(Self::LibVar))
+          .or_else(|_| {
+            input
+              .strip_prefix("lib ")
+              .ok_or(ParseLibIdError::NoLeadingL)
+              .and_then(|x| x.parse().map(Self::Lib))
+          })
+          .or_else(|_| {
+            input
+              .strip_prefix("librec ")
+              .ok_or(ParseLibIdError::NoLeadingL)
+              .and_then(|x| x.parse().map(Self::LibRec))
+          })
+          .map_err(|_| ParseStringOpError::UnknownOp(input.into()))?
+      }
+    };
+    Ok(op)
+  }
+}
+
+/// Replaces the escape sequences in the contents of a string literal,
+/// returning `None` if one is invalid.
+fn unescape(literal: &str) -> Option<String> {
+  let mut s = String::with_capacity(literal.len());
+  let mut chars = literal.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      s.push(c);
+      continue;
+    }
+    let escaped = match chars.next()? {
+      's' => ' ',
+      't' => '\t',
+      'n' => '\n',
+      '\\' => '\\',
+      'x' => {
+        let hex: String = chars.by_ref().take(2).collect();
+        let code = u8::from_str_radix(&hex, 16).ok()?;
+        if !code.is_ascii() {
+          return None;
+        }
+        char::from(code)
+      }
+      _ => return None,
+    };
+    s.push(escaped);
+  }
+  Some(s)
+}
+
+impl Teachable for StringOp {
+  fn from_binding_expr<T>(binding_expr: BindingExpr<T>) -> AstNode<Self, T> {
+    match binding_expr {
+      BindingExpr::Lambda(body) => AstNode::new(Self::Lambda, [body]),
+      BindingExpr::Fix(body) => AstNode::new(Self::Fix, [body]),
+      BindingExpr::Apply(fun, arg) => AstNode::new(Self::Apply, [fun, arg]),
+      BindingExpr::Var(index) => AstNode::leaf(Self::Var(index)),
+      BindingExpr::Lib(ix, bound_value, body) => {
+        AstNode::new(Self::Lib(ix), [bound_value, body])
+      }
+      BindingExpr::LibRec(ix, bound_value, body) => {
+        AstNode::new(Self::LibRec(ix), [bound_value, body])
+      }
+      BindingExpr::LibVar(ix) => AstNode::leaf(Self::LibVar(ix)),
+    }
+  }
+
+  fn as_binding_expr<T>(node: &AstNode<Self, T>) -> Option<BindingExpr<&T>> {
+    let binding_expr = match node.as_parts() {
+      (Self::Lambda, [body]) => BindingExpr::Lambda(body),
+      (Self::Fix, [body]) => BindingExpr::Fix(body),
+      (Self::Apply, [fun, arg]) => BindingExpr::Apply(fun, arg),
+      (&Self::Var(index), []) => BindingExpr::Var(index),
+      (Self::Lib(ix), [bound_value, body]) => {
+        BindingExpr::Lib(*ix, bound_value, body)
+      }
+      (Self::LibRec(ix), [bound_value, body]) => {
+        BindingExpr::LibRec(*ix, bound_value, body)
+      }
+      (Self::LibVar(ix), []) => BindingExpr::LibVar(*ix),
+      _ => return None,
+    };
+    Some(binding_expr)
+  }
+
+  fn list() -> Self {
+    Self::List
+  }
+}
+
+impl Printable for StringOp {
+  fn precedence(&self) -> Precedence {
+    match self {
+      Self::Str(_) | Self::Int(_) | Self::Var(_) | Self::LibVar(_) => 60,
+      Self::List => 50,
+      Self::Concat
+      | Self::Substr
+      | Self::Replace
+      | Self::Split
+      | Self::Nth
+      | Self::Join
+      | Self::Upper
+      | Self::Lower
+      | Self::Apply => 40,
+      Self::Lambda | Self::Fix | Self::Lib(_) | Self::LibRec(_) => 10,
+    }
+  }
+
+  fn print_naked<W: Write>(
+    expr: &Expr<Self>,
+    printer: &mut Printer<W>,
+  ) -> fmt::Result {
+    match (expr.0.operation(), expr.0.args()) {
+      (Self::List, ts) => {
+        let elem = |p: &mut Printer<W>, i: usize| p.print_in_context(&ts[i], 0);
+        printer.in_brackets(|p| p.indented(|p| p.vsep(elem, ts.len(), ",")))
+      }
+      (op, []) => write!(printer.writer, "{op}"),
+      (op, args) => {
+        write!(printer.writer, "{op}")?;
+        for arg in args {
+          printer.writer.write_char(' ')?;
+          printer.print(arg)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+impl DiscriminantEq for StringOp {
+  fn discriminant_eq(&self, other: &Self) -> bool {
+    self.eq(other)
+  }
+}
+
+/// An error when parsing a program of string transformations.
+#[derive(Debug, Error)]
+pub enum ParseProgramError {
+  /// The input is not a sequence of s-expressions.
+  #[error(transparent)]
+  Sexp(#[from] ParseError),
+
+  /// An s-expression is not a valid string transformation.
+  #[error(transparent)]
+  Expr(#[from] ParseNamedError<StringOp, ParseStringOpError>),
+}
+
+/// Parses each s-expression in `input` as a string transformation, in which
+/// variables are named as described by [`VarNames::parse`]. Returns the
+/// expressions and the names of their variables.
+///
+/// # Errors
+///
+/// Returns an error if `input` is not a sequence of s-expressions, or if one
+/// of them is not a valid string transformation.
+pub fn parse_programs(
+  input: &str,
+) -> Result<(Vec<Expr<StringOp>>, VarNames<StringOp>), ParseProgramError> {
+  let mut names = VarNames::new();
+  let exprs = Program::parse(input)?
+    .0
+    .into_iter()
+    .map(|sexp| names.parse(sexp))
+    .collect::<Result<_, _>>()?;
+  Ok((exprs, names))
+}
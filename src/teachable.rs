@@ -1,6 +1,7 @@
 //! Defines the [`Teachable`] trait for languages that support library learning.
 
 use crate::{ast_node::AstNode, learn::LibId};
+use serde::{Deserialize, Serialize};
 use std::{
   fmt::{self, Debug, Display, Formatter},
   hash::Hash,
@@ -55,16 +56,31 @@ where
     Self::from_binding_expr(BindingExpr::Lib(name, value, body))
   }
 
+  /// Creates an expression defining the library function `name` as `value`
+  /// in `body`, as part of a group of mutually recursive library functions;
+  /// see [`BindingExpr::LibRec`].
+  #[must_use]
+  fn lib_rec<T>(name: LibId, value: T, body: T) -> AstNode<Self, T> {
+    Self::from_binding_expr(BindingExpr::LibRec(name, value, body))
+  }
+
   /// Creates a named variable referencing a library function.
   #[must_use]
   fn lib_var<T>(name: LibId) -> AstNode<Self, T> {
     Self::from_binding_expr(BindingExpr::LibVar(name))
   }
+
+  /// Creates an AST node representing the fixpoint of `body`, in which the
+  /// de Bruijn index `$0` refers to the fixpoint itself.
+  #[must_use]
+  fn fix<T>(body: T) -> AstNode<Self, T> {
+    Self::from_binding_expr(BindingExpr::Fix(body))
+  }
 }
 
 /// A simplified language containing just the constructs necessary for library
-/// learning: functions, applications, let-expressions, and both named and de
-/// Bruijn-indexed variables.
+/// learning: functions, applications, let-expressions, recursive
+/// let-expressions, fixpoints, and both named and de Bruijn-indexed variables.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum BindingExpr<T> {
   /// A de Bruijn index
@@ -77,6 +93,16 @@ pub enum BindingExpr<T> {
   Apply(T, T),
   /// An expression defining a named library function within a certain scope
   Lib(LibId, T, T),
+  /// An expression defining a named library function which is part of a
+  /// group of mutually recursive library functions. A group is a chain of
+  /// `LibRec`s, each the body of the previous one, and the definition of
+  /// each can refer to every library function in the group, including
+  /// itself.
+  LibRec(LibId, T, T),
+  /// A fixpoint, used to define recursive functions. Like a lambda, it binds
+  /// a de Bruijn-indexed variable in its body, which refers to the fixpoint
+  /// itself.
+  Fix(T),
 }
 
 impl<Op, T> From<BindingExpr<T>> for AstNode<Op, T>
@@ -104,7 +130,18 @@ where
 /// representation of the de Bruijn index is a dollar sign ($) followed by the
 /// integer index, (e.g. `$12`) and its [`Debug`], [`Display`], and [`FromStr`]
 /// implementations reflect that.
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[derive(
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Default,
+  Serialize,
+  Deserialize,
+)]
 pub struct DeBruijnIndex(pub usize);
 
 impl Deref for DeBruijnIndex {
@@ -0,0 +1,76 @@
+//! Defines the [`Typeable`] trait for languages with a type system, which can
+//! be used to restrict library learning to well-typed library functions.
+
+use crate::ast_node::PartialExpr;
+use egg::Var;
+use std::fmt::{self, Debug, Display, Formatter};
+
+/// A trait for languages whose expressions can be assigned types.
+///
+/// Implementing this trait is optional. If an operation implements it, a
+/// [`LearnedLibraryBuilder`](crate::LearnedLibraryBuilder) can be asked to
+/// discard anti-unifications which are not well-typed, and the learned
+/// library functions can be annotated with their type signatures.
+pub trait Typeable
+where
+  Self: Sized,
+{
+  /// The type of types in the language.
+  type Type: Clone + Debug + Display + PartialEq;
+
+  /// Infers the type signature of the anti-unification `au`, treating each of
+  /// its pattern variables as an argument. Returns [`None`] if `au` is not
+  /// well-typed.
+  ///
+  /// The pattern variables of a normalized anti-unification are named
+  /// `?x0`, `?x1`, etc. The `i`th argument type of the returned signature
+  /// should be the type of `?xi`.
+  fn type_signature(
+    au: &PartialExpr<Self, Var>,
+  ) -> Option<TypeSignature<Self::Type>>;
+
+  /// Returns `true` if the anti-unification `au` is well-typed.
+  #[must_use]
+  fn well_typed(au: &PartialExpr<Self, Var>) -> bool {
+    Self::type_signature(au).is_some()
+  }
+}
+
+/// The type signature of a library function: the types of its arguments and
+/// the type of its result.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct TypeSignature<T> {
+  /// The types of the function's arguments, in order.
+  pub args: Vec<T>,
+  /// The type of the function's result.
+  pub ret: T,
+}
+
+impl<T> TypeSignature<T> {
+  /// Creates a new type signature.
+  #[must_use]
+  pub fn new(args: Vec<T>, ret: T) -> Self {
+    Self { args, ret }
+  }
+
+  /// The number of arguments the function takes.
+  #[must_use]
+  pub fn arity(&self) -> usize {
+    self.args.len()
+  }
+}
+
+impl<T: Display> Debug for TypeSignature<T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    <Self as Display>::fmt(self, f)
+  }
+}
+
+impl<T: Display> Display for TypeSignature<T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    for arg in &self.args {
+      write!(f, "{arg} -> ")?;
+    }
+    write!(f, "{}", self.ret)
+  }
+}
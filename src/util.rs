@@ -1,6 +1,11 @@
-/// Calculates the efficiency of a compression algorithm.
+/// Calculates the efficiency of a compression algorithm. If either cost is
+/// zero, as for an empty corpus, nothing was compressed, and this is 1.
 #[must_use]
 #[allow(clippy::cast_precision_loss)]
 pub fn compression_factor(start_cost: usize, end_cost: usize) -> f64 {
-  start_cost as f64 / end_cost as f64
+  if start_cost == 0 || end_cost == 0 {
+    1.0
+  } else {
+    start_cost as f64 / end_cost as f64
+  }
 }
@@ -0,0 +1,69 @@
+//! A string-based entry point to library learning, for embedding babble in
+//! environments such as a web page. Its arguments and results are plain
+//! strings and integers, so it can be exported by `wasm-bindgen` as is.
+
+use crate::{
+  ast_node::{AstNode, Expr, PrettyOptions, Syntax},
+  compress::{compress, Config},
+  sexp::{Program, Sexp},
+  simple_lang::SimpleOp,
+  teachable::Teachable,
+};
+
+/// Learns a library from `programs`, a sequence of s-expressions, and
+/// returns the compressed programs as a single s-expression which defines
+/// the learned library functions. At most `beam_size` library selections are
+/// kept at each e-class during the beam search.
+///
+/// The programs are in the language of [`SimpleOp`], except that a symbol
+/// applied to arguments, like `(f x y)`, is curried into `(@ (@ f x) y)`.
+///
+/// # Errors
+///
+/// Returns a description of the problem if `programs` is not a sequence of
+/// valid s-expressions, if it contains no programs, or if library learning
+/// fails.
+pub fn learn_library(
+  programs: &str,
+  beam_size: usize,
+  max_arity: Option<usize>,
+) -> Result<String, String> {
+  let exprs = parse_programs(programs)?;
+  let config =
+    Config::default().beams(beam_size, beam_size).max_arity(max_arity);
+  let result = compress(exprs, &config).map_err(|e| e.to_string())?;
+  let options = PrettyOptions::default().syntax(Syntax::Lisp);
+  Ok(result.expr.pretty_with(options).to_string())
+}
+
+/// Parses `programs`, a sequence of s-expressions, in the syntax accepted by
+/// [`learn_library`].
+///
+/// # Errors
+///
+/// Returns a description of the problem if `programs` is not a sequence of
+/// valid s-expressions, or if it contains no programs.
+pub fn parse_programs(programs: &str) -> Result<Vec<Expr<SimpleOp>>, String> {
+  let Program(sexps) = Program::parse(programs).map_err(ToString::to_string)?;
+  if sexps.is_empty() {
+    return Err("no programs to learn from".to_string());
+  }
+  sexps.into_iter().map(to_expr).collect()
+}
+
+/// Converts `sexp` into an expression, currying applications of symbols.
+fn to_expr(sexp: Sexp<'_>) -> Result<Expr<SimpleOp>, String> {
+  let (op, args) = match sexp {
+    Sexp::Atom(atom) => (atom, Vec::new()),
+    Sexp::List(op, args) => (op, args),
+  };
+  let args = args.into_iter().map(to_expr).collect::<Result<Vec<_>, _>>()?;
+  let op: SimpleOp = op.parse().unwrap_or_else(|never| match never {});
+  match op {
+    SimpleOp::Symbol(_) => {
+      let fun = Expr(AstNode::leaf(op));
+      Ok(args.into_iter().fold(fun, |fun, arg| Expr(SimpleOp::apply(fun, arg))))
+    }
+    _ => AstNode::try_new(op, args).map(Expr).map_err(ToString::to_string),
+  }
+}